@@ -0,0 +1,52 @@
+/* C interface of redshift-rs, see src/ffi.rs */
+
+#ifndef REDSHIFT_RS_H
+#define REDSHIFT_RS_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Periods passed to the period callback */
+#define REDSHIFT_PERIOD_NONE       0
+#define REDSHIFT_PERIOD_DAY        1
+#define REDSHIFT_PERIOD_NIGHT      2
+#define REDSHIFT_PERIOD_TRANSITION 3
+
+/* Results of the functions returning int, besides 0 for success */
+#define REDSHIFT_ERROR    (-1)
+#define REDSHIFT_PANICKED (-2)
+
+typedef struct redshift redshift;
+typedef void (*redshift_period_cb)(int period, void *user_data);
+
+/* Start the named gamma method, or pick one when method is NULL.
+   NULL on failure. */
+redshift *redshift_init(const char *method);
+
+/* Set the location the schedule is computed for, in degrees. */
+int redshift_set_location(redshift *r, double lat, double lon);
+
+/* Apply a temperature in Kelvin right away, regardless of the schedule.
+   The brightness is between 0.1 and 1.0. */
+int redshift_set_temperature(redshift *r, int temp, double brightness);
+
+/* Apply the scheduled temperature for the current time, calling the
+   period callback, on this thread, if the period changed. */
+int redshift_update(redshift *r);
+
+/* Set or, with a NULL callback, clear the period callback. user_data
+   must stay valid while the callback is set. */
+void redshift_set_period_callback(redshift *r, redshift_period_cb cb, void *user_data);
+
+/* Restore the ramps found when the method was started. */
+int redshift_restore(redshift *r);
+
+/* Restore the ramps and free the handle, which must not be used again. */
+void redshift_shutdown(redshift *r);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,267 @@
+//! Ambient light
+//!
+//! With an `[ambient-light]` section in the config file, the daemon
+//! reads the ambient light sensor through iio-sensor-proxy, and dims
+//! the display in a dark room on top of the schedule, optionally
+//! warming it too:
+//!
+//! ```text
+//! [ambient-light]
+//! brightness=0:0.6,50:0.85,300:1.0   ; lux:factor, scaling the brightness
+//! temperature=0:-800,100:0           ; lux:Kelvin, added to the temperature
+//! smoothing=20s                      ; time constant, 10s by default
+//! ```
+//!
+//! Each mapping is a list of `LUX:VALUE` points, in increasing lux.
+//! Between two points the value is interpolated linearly, below the
+//! first and above the last it stays at theirs. Without `brightness`
+//! the brightness follows the schedule alone, and likewise the
+//! temperature without `temperature`. A temperature held through a
+//! control interface is left as it is.
+//!
+//! Readings are smoothed exponentially, so that a hand passing over
+//! the sensor or a light switched on doesn't make the display jump:
+//! after `smoothing` the display has come about two thirds of the way
+//! to the new level.
+//!
+//! iio-sensor-proxy is a service on the system bus, which only builds
+//! with the `dbus` feature can talk to. It announces every new light
+//! level with `PropertiesChanged`, which is when the level is read.
+
+use std::time::Instant;
+
+use chan;
+
+use config::Section;
+use transition::Curve;
+use super::{parse_duration, Result, RedshiftError};
+
+/// Smoothing time constant without a `smoothing` setting, in seconds
+const DEFAULT_SMOOTHING_S: f64 = 10.0;
+
+/// Lowest brightness the light level dims to
+const MIN_BRIGHTNESS: f64 = 0.1;
+
+/// Relative difference from the latest reading at which the smoothed
+/// level counts as settled
+const SETTLED: f64 = 0.01;
+
+/// Settings of the `[ambient-light]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Factor of the brightness by lux
+    pub brightness: Option<Curve>,
+    /// Kelvin added to the temperature by lux
+    pub temperature: Option<Curve>,
+    /// Time constant of the smoothing, in seconds
+    pub smoothing: f64,
+}
+
+impl Options {
+    pub fn from_config(section: &Section) -> Result<Options> {
+        let curve = |key: &str| section.get(key).map_or(Ok(None), |v| Curve::parse(v).map(Some));
+        let options = Options {
+            brightness: curve("brightness")?,
+            temperature: curve("temperature")?,
+            smoothing: section.get("smoothing").map_or(Ok(DEFAULT_SMOOTHING_S), parse_duration)?,
+        };
+        if options.brightness.is_none() && options.temperature.is_none() {
+            return Err(RedshiftError::MalformedConfig(
+                format!("[{}]: neither brightness nor temperature given", section.name)));
+        }
+        Ok(options)
+    }
+}
+
+/// The light level the daemon follows, smoothed over time
+pub struct Ambient {
+    options: Options,
+    /// The latest reading, in lux
+    reading: Option<f64>,
+    smoothed: f64,
+    updated: Instant,
+}
+
+impl Ambient {
+    pub fn new(options: Options) -> Ambient {
+        Ambient {
+            options: options,
+            reading: None,
+            smoothed: 0.0,
+            updated: Instant::now(),
+        }
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+    }
+
+    /// Take in a new reading of the sensor
+    ///
+    /// The first reading is followed right away, there being nothing
+    /// to smooth it with.
+    pub fn set_reading(&mut self, lux: f64) {
+        self.advance();
+        if self.reading.is_none() {
+            debug!("Ambient light: {:.0} lux", lux);
+            self.smoothed = lux;
+        }
+        self.reading = Some(lux);
+    }
+
+    /// Bring the smoothed level up to the current time
+    pub fn advance(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.updated;
+        self.smooth(elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9);
+        self.updated = now;
+    }
+
+    fn smooth(&mut self, secs: f64) {
+        if let Some(reading) = self.reading {
+            let keep = (-secs / self.options.smoothing.max(1e-3)).exp();
+            self.smoothed = reading + (self.smoothed - reading) * keep;
+        }
+    }
+
+    /// The smoothed level in lux, once the sensor was read
+    pub fn level(&self) -> Option<f64> {
+        self.reading.map(|_| self.smoothed)
+    }
+
+    /// Whether the smoothed level has caught up with the readings
+    pub fn settled(&self) -> bool {
+        self.reading.map_or(true, |r| (self.smoothed - r).abs() <= SETTLED * r.abs().max(1.0))
+    }
+
+    /// Factor of the scheduled brightness at the current level
+    pub fn brightness_factor(&self) -> f64 {
+        match (self.reading, &self.options.brightness) {
+            (Some(_), &Some(ref curve)) => curve.at(self.smoothed),
+            _ => 1.0,
+        }
+    }
+
+    /// Kelvin added to the scheduled temperature at the current level
+    pub fn temperature_offset(&self) -> i32 {
+        match (self.reading, &self.options.temperature) {
+            (Some(_), &Some(ref curve)) => curve.at(self.smoothed) as i32,
+            _ => 0,
+        }
+    }
+
+    /// Scale a brightness by the current level
+    pub fn dim(&self, brightness: f64) -> f64 {
+        (brightness * self.brightness_factor()).max(MIN_BRIGHTNESS).min(1.0)
+    }
+}
+
+/// Follow the light level of the ambient light sensor
+///
+/// The channel receives the level in lux whenever it changes. `None`
+/// if the sensor can't be read.
+#[cfg(feature = "dbus")]
+pub fn watch() -> Option<chan::Receiver<f64>> {
+    sensor::watch()
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn watch() -> Option<chan::Receiver<f64>> {
+    warn!("The ambient light sensor can't be read, support for D-Bus is not compiled in");
+    None
+}
+
+#[cfg(feature = "dbus")]
+mod sensor {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use chan;
+    use dbus::Message;
+    use dbus::blocking::Connection;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+    use dbus::message::SignalArgs;
+
+    const SENSOR_PROXY: &'static str = "net.hadess.SensorProxy";
+    const PATH: &'static str = "/net/hadess/SensorProxy";
+    const TIMEOUT_MS: u64 = 1000;
+    /// Longest wait for a signal in one go, in milliseconds
+    const WAIT_MS: u64 = 3_600_000;
+
+    /// Claim the sensor and read the light level whenever
+    /// iio-sensor-proxy announces a new one
+    fn follow(conn: &Connection, tx: chan::Sender<f64>) -> Result<(), String> {
+        let proxy = conn.with_proxy(SENSOR_PROXY, PATH, Duration::from_millis(TIMEOUT_MS));
+        if !proxy.get::<bool>(SENSOR_PROXY, "HasAmbientLight").unwrap_or(false) {
+            return Err("no sensor found by iio-sensor-proxy".to_owned());
+        }
+        if proxy.get::<String>(SENSOR_PROXY, "LightLevelUnit").ok().as_ref().map(|u| &u[..]) == Some("vendor") {
+            warn!("The ambient light sensor reports levels in its own unit rather than lux, \
+                   the [ambient-light] mappings may need adjusting");
+        }
+        let changed = Arc::new(AtomicBool::new(true));
+        let flag = changed.clone();
+        let rule = PropertiesPropertiesChanged::match_rule(None, Some(&PATH.into())).static_clone();
+        conn.add_match(rule, move |p: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            if p.interface_name == SENSOR_PROXY && p.changed_properties.contains_key("LightLevel") {
+                flag.store(true, Ordering::SeqCst);
+            }
+            true
+        }).map_err(|e| e.to_string())?;
+        // The sensor is read for as long as the connection is open
+        proxy.method_call::<(), _, _, _>(SENSOR_PROXY, "ClaimLight", ()).map_err(|e| e.to_string())?;
+        info!("Following the ambient light sensor");
+        let mut last = None;
+        loop {
+            if changed.swap(false, Ordering::SeqCst) {
+                let lux = proxy.get::<f64>(SENSOR_PROXY, "LightLevel")
+                    .map_err(|e| format!("lost the sensor: {}", e))?;
+                if Some(lux) != last {
+                    last = Some(lux);
+                    tx.send(lux);
+                }
+            }
+            conn.process(Duration::from_millis(WAIT_MS)).map_err(|e| e.to_string())?;
+        }
+    }
+
+    pub fn watch() -> Option<chan::Receiver<f64>> {
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let result = Connection::new_system().map_err(|e| e.to_string())
+                .and_then(|conn| follow(&conn, tx));
+            if let Err(e) = result {
+                warn!("Not following the ambient light: {}", e);
+            }
+        });
+        Some(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn smoothing() {
+        let mut ambient = Ambient::new(Options {
+            brightness: Some(Curve::parse("0:0.5,100:1.0").unwrap()),
+            temperature: None,
+            smoothing: 10.0,
+        });
+        assert_eq!(ambient.brightness_factor(), 1.0);
+        ambient.set_reading(0.0);
+        assert_eq!(ambient.dim(0.8), 0.4);
+        assert!(ambient.settled());
+
+        ambient.reading = Some(100.0);
+        ambient.smooth(10.0);
+        assert!((ambient.smoothed - 100.0 * (1.0 - (-1.0f64).exp())).abs() < 1e-9);
+        assert!(!ambient.settled());
+        ambient.smooth(60.0);
+        assert!(ambient.settled());
+        assert_eq!(ambient.temperature_offset(), 0);
+    }
+}
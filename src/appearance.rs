@@ -0,0 +1,169 @@
+//! Profiles by dark or light appearance
+//!
+//! An `[appearance]` section switches to a profile while the desktop
+//! is set to a dark or a light appearance, for those who flip their
+//! whole environment with it, e.g. to stay warmer in the dark:
+//!
+//! ```text
+//! [appearance]
+//! dark=dim      ; a [profile:dim] section
+//!
+//! [profile:dim]
+//! temp-day=5000
+//! temp-night=3000
+//! ```
+//!
+//! The appearance is the `color-scheme` setting of the Settings portal
+//! of xdg-desktop-portal, which GNOME, KDE and others keep in line
+//! with their own toggle. It is followed through the portal's
+//! `SettingChanged` signal, in builds with the `dbus` feature. The
+//! profile is applied on top of the one selected otherwise, like the
+//! one of the `[battery]` section.
+
+use chan;
+
+use config::{Config, Section};
+use super::{Result, RedshiftError};
+
+/// The appearance preferred by the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    NoPreference,
+    Dark,
+    Light,
+}
+
+impl Scheme {
+    /// The value of the `color-scheme` setting of the portal
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn from_portal(value: u32) -> Scheme {
+        match value {
+            1 => Scheme::Dark,
+            2 => Scheme::Light,
+            _ => Scheme::NoPreference,
+        }
+    }
+}
+
+/// The profiles of the `[appearance]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    dark: Option<String>,
+    light: Option<String>,
+}
+
+impl Options {
+    pub fn from_config(section: &Section, config: &Config) -> Result<Options> {
+        let mut options = Options { dark: None, light: None };
+        for e in &section.entries {
+            config.profile(&e.1)?;
+            match &e.0[..] {
+                "dark" => options.dark = Some(e.1.clone()),
+                "light" => options.light = Some(e.1.clone()),
+                key => return Err(RedshiftError::MalformedConfig(
+                    format!("[{}] {}: expected dark or light", section.name, key))),
+            }
+        }
+        Ok(options)
+    }
+
+    /// The profile for the appearance, if any
+    pub fn profile(&self, scheme: Scheme) -> Option<&str> {
+        match scheme {
+            Scheme::Dark => self.dark.as_ref().map(|p| &p[..]),
+            Scheme::Light => self.light.as_ref().map(|p| &p[..]),
+            Scheme::NoPreference => None,
+        }
+    }
+}
+
+/// Follow the appearance of the desktop
+///
+/// The channel receives the appearance at first and then on every
+/// change. `None` if it can't be followed.
+#[cfg(feature = "dbus")]
+pub fn watch() -> Option<chan::Receiver<Scheme>> {
+    portal::watch()
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn watch() -> Option<chan::Receiver<Scheme>> {
+    warn!("The appearance of the desktop can't be followed, support for D-Bus is not compiled in");
+    None
+}
+
+#[cfg(feature = "dbus")]
+mod portal {
+    use std::thread;
+    use std::time::Duration;
+
+    use chan;
+    use dbus::Message;
+    use dbus::arg::{RefArg, Variant};
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+
+    use super::Scheme;
+
+    const PORTAL: &'static str = "org.freedesktop.portal.Desktop";
+    const PORTAL_PATH: &'static str = "/org/freedesktop/portal/desktop";
+    const SETTINGS: &'static str = "org.freedesktop.portal.Settings";
+    const NAMESPACE: &'static str = "org.freedesktop.appearance";
+    const KEY: &'static str = "color-scheme";
+
+    const TIMEOUT_MS: u64 = 1000;
+
+    /// The number in a value, which `Read` wraps in a second variant
+    fn number(value: &RefArg) -> Option<u32> {
+        value.as_u64().map(|n| n as u32)
+            .or_else(|| value.as_iter()?.next().and_then(|inner| number(inner)))
+    }
+
+    fn follow(conn: &Connection, tx: chan::Sender<Scheme>) -> Result<(), ::dbus::Error> {
+        let proxy = conn.with_proxy(PORTAL, PORTAL_PATH, Duration::from_millis(TIMEOUT_MS));
+        let (value,): (Variant<Box<RefArg>>,) = proxy.method_call(SETTINGS, "Read", (NAMESPACE, KEY))?;
+        tx.send(Scheme::from_portal(number(&*value.0).unwrap_or(0)));
+
+        let rule = MatchRule::new_signal(SETTINGS, "SettingChanged");
+        conn.add_match(rule, move |(namespace, key, value): (String, String, Variant<Box<RefArg>>),
+                                   _: &Connection, _: &Message| {
+            if namespace == NAMESPACE && key == KEY {
+                tx.send(Scheme::from_portal(number(&*value.0).unwrap_or(0)));
+            }
+            true
+        })?;
+        loop {
+            conn.process(Duration::from_millis(60_000))?;
+        }
+    }
+
+    pub fn watch() -> Option<chan::Receiver<Scheme>> {
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let result = Connection::new_session().and_then(|conn| follow(&conn, tx));
+            if let Err(e) = result {
+                warn!("Not following the appearance of the desktop: {}", e);
+            }
+        });
+        Some(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profiles() {
+        let config = "[appearance]\ndark=dim\n[profile:dim]\ntemp-night=3000\n".parse::<Config>().unwrap();
+        let options = Options::from_config(config.section("appearance").unwrap(), &config).unwrap();
+        assert_eq!(options.profile(Scheme::from_portal(1)), Some("dim"));
+        assert_eq!(options.profile(Scheme::from_portal(2)), None);
+        assert_eq!(options.profile(Scheme::from_portal(0)), None);
+
+        let config = "[appearance]\ndusk=dim\n[profile:dim]\n".parse::<Config>().unwrap();
+        assert!(Options::from_config(config.section("appearance").unwrap(), &config).is_err());
+        let config = "[appearance]\ndark=missing\n".parse::<Config>().unwrap();
+        assert!(Options::from_config(config.section("appearance").unwrap(), &config).is_err());
+    }
+}
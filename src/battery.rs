@@ -0,0 +1,172 @@
+//! Battery power
+//!
+//! On a laptop, a `[battery]` section in the config file switches to a
+//! profile while running on battery, such as a dimmer and warmer one
+//! that saves power, and back when plugged in again:
+//!
+//! ```text
+//! [battery]
+//! profile=powersave    ; a [profile:powersave] section
+//! below=30             ; only below 30% charge, any charge by default
+//! ```
+//!
+//! The profile is applied on top of the one selected otherwise, so it
+//! only needs the settings that differ on battery. Command-line
+//! arguments still take precedence.
+//!
+//! The power state is read from UPower on the system bus, so the
+//! section is refused by builds without the `dbus` feature. It is read
+//! again when UPower announces a change of `OnBattery` or of the
+//! charge.
+
+use chan;
+
+use config::Section;
+use super::{Result, RedshiftError};
+
+/// The power supply of the machine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Power {
+    pub on_battery: bool,
+    /// Charge of the battery, in percent
+    pub percentage: f64,
+}
+
+/// Settings of the `[battery]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// The profile to switch to
+    pub profile: String,
+    /// Charge in percent below which to switch, at any charge if `None`
+    pub below: Option<f64>,
+}
+
+impl Options {
+    pub fn from_config(section: &Section) -> Result<Options> {
+        let profile = section.get("profile").ok_or_else(|| RedshiftError::MalformedConfig(
+            format!("[{}] profile: missing", section.name)))?;
+        let below = section.parse::<f64>("below")?;
+        if below.map_or(false, |b| !(0.0..=100.0).contains(&b)) {
+            return Err(RedshiftError::MalformedConfig(
+                format!("[{}] below: {} is not a percentage", section.name, below.unwrap())));
+        }
+        Ok(Options {
+            profile: profile.to_owned(),
+            below: below,
+        })
+    }
+
+    /// Whether the profile is wanted with the given power supply
+    pub fn applies(&self, power: &Power) -> bool {
+        power.on_battery && self.below.map_or(true, |b| power.percentage < b)
+    }
+}
+
+/// Follow the power supply
+///
+/// The channel receives the power state whenever it changes. `None` if
+/// it can't be followed.
+#[cfg(feature = "dbus")]
+pub fn watch() -> Option<chan::Receiver<Power>> {
+    upower::watch()
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn watch() -> Option<chan::Receiver<Power>> {
+    warn!("The power supply can't be followed, support for D-Bus is not compiled in");
+    None
+}
+
+#[cfg(feature = "dbus")]
+mod upower {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use chan;
+    use dbus::Message;
+    use dbus::blocking::Connection;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+    use dbus::message::SignalArgs;
+
+    use super::Power;
+
+    const UPOWER: &'static str = "org.freedesktop.UPower";
+    const PATH: &'static str = "/org/freedesktop/UPower";
+    /// The combined state of all the batteries
+    const DISPLAY_DEVICE: &'static str = "/org/freedesktop/UPower/devices/DisplayDevice";
+    const DEVICE: &'static str = "org.freedesktop.UPower.Device";
+    const TIMEOUT_MS: u64 = 1000;
+    /// Longest wait for a signal in one go, in milliseconds
+    const WAIT_MS: u64 = 3_600_000;
+
+    fn power(conn: &Connection) -> Result<Power, ::dbus::Error> {
+        let timeout = Duration::from_millis(TIMEOUT_MS);
+        let on_battery = conn.with_proxy(UPOWER, PATH, timeout).get::<bool>(UPOWER, "OnBattery")?;
+        let percentage = conn.with_proxy(UPOWER, DISPLAY_DEVICE, timeout)
+            .get::<f64>(DEVICE, "Percentage")?;
+        Ok(Power { on_battery: on_battery, percentage: percentage })
+    }
+
+    /// Read the power state at first and whenever UPower announces a
+    /// change of it
+    fn follow(conn: &Connection, tx: chan::Sender<Power>) -> Result<(), ::dbus::Error> {
+        let changed = Arc::new(AtomicBool::new(true));
+        for &(path, property) in &[(PATH, "OnBattery"), (DISPLAY_DEVICE, "Percentage")] {
+            let changed = changed.clone();
+            let rule = PropertiesPropertiesChanged::match_rule(None, Some(&path.into())).static_clone();
+            conn.add_match(rule, move |p: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+                if p.changed_properties.contains_key(property) ||
+                   p.invalidated_properties.iter().any(|i| i == property) {
+                    changed.store(true, Ordering::SeqCst);
+                }
+                true
+            })?;
+        }
+        let mut last = None;
+        loop {
+            if changed.swap(false, Ordering::SeqCst) {
+                let p = power(conn)?;
+                if Some(p) != last {
+                    last = Some(p);
+                    tx.send(p);
+                }
+            }
+            conn.process(Duration::from_millis(WAIT_MS))?;
+        }
+    }
+
+    pub fn watch() -> Option<chan::Receiver<Power>> {
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let result = Connection::new_system().and_then(|conn| follow(&conn, tx));
+            if let Err(e) = result {
+                warn!("Not following the power supply: {}", e);
+            }
+        });
+        Some(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::Config;
+
+    #[test]
+    fn profile_applies() {
+        let config: Config = "[battery]\nprofile=powersave\nbelow=30".parse().unwrap();
+        let options = Options::from_config(config.section("battery").unwrap()).unwrap();
+        assert_eq!(options.profile, "powersave");
+        assert!(options.applies(&Power { on_battery: true, percentage: 20.0 }));
+        assert!(!options.applies(&Power { on_battery: true, percentage: 50.0 }));
+        assert!(!options.applies(&Power { on_battery: false, percentage: 20.0 }));
+
+        let options = Options { below: None, ..options };
+        assert!(options.applies(&Power { on_battery: true, percentage: 50.0 }));
+
+        let config: Config = "[battery]\nbelow=30".parse().unwrap();
+        assert!(Options::from_config(config.section("battery").unwrap()).is_err());
+    }
+}
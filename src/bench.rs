@@ -0,0 +1,116 @@
+//! Benchmark
+//!
+//! `redshift-rs bench` times how long it takes to compute the ramps of
+//! a setting at the common ramp sizes, and to apply a setting with the
+//! gamma method, to compare builds and machines:
+//!
+//! ```text
+//! $ redshift-rs bench
+//! Ramp generation (200 iterations)
+//!     size   per ramp
+//!      256     10.2µs
+//!     1024     40.8µs
+//!     4096    163.0µs
+//!    16384    652.1µs
+//! Method randr, 2 CRTCs (50 iterations)
+//!     per update 1.21ms, per CRTC 0.60ms
+//! ```
+//!
+//! The method is selected and connected to as with `-m`, `-d` and
+//! `--wayland-display`, given before the subcommand, as in
+//! `redshift-rs -m randr bench`. Applying alternates between two settings too
+//! close to tell apart, which every method has to apply in full. The
+//! original ramps are restored afterwards. `--no-method` only times
+//! the ramps.
+
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+
+use colorramp;
+use gamma;
+use transition::ColorSetting;
+use super::{Result, RedshiftError};
+
+/// Ramp sizes to time, from those of most graphics cards to those of
+/// high bit depth panels
+const SIZES: &'static [usize] = &[256, 1024, 4096, 16384];
+
+const DEFAULT_ITERATIONS: u32 = 200;
+
+/// Updates of the method per iteration of the ramps, which are much
+/// slower
+const METHOD_FRACTION: u32 = 4;
+
+pub fn run(matches: &ArgMatches, sub: &ArgMatches) -> Result<i32> {
+    let iterations = match sub.value_of("iterations") {
+        Some(n) => n.parse::<u32>().ok().filter(|n| *n > 0)
+            .ok_or_else(|| RedshiftError::MalformedArgument(format!("iterations: {}", n)))?,
+        None => DEFAULT_ITERATIONS,
+    };
+
+    println!("Ramp generation ({} iterations)", iterations);
+    println!("    {:>5} {:>10}", "size", "per ramp");
+    for &size in SIZES {
+        let elapsed = time(iterations, |i| { colorramp::ramps(&setting(i), size); Ok(()) })?;
+        println!("    {:>5} {:>10}", size, format_duration(elapsed / iterations));
+    }
+
+    if sub.is_present("no-method") {
+        return Ok(0);
+    }
+    let options = gamma::Options {
+        display: matches.value_of("display").map(ToOwned::to_owned),
+        wayland_display: matches.value_of("wayland-display").map(ToOwned::to_owned),
+        ..gamma::Options::default()
+    };
+    let method = matches.value_of("method").filter(|m| *m != "list");
+    let mut method = gamma::RestoreGuard::new(gamma::init_gamma_method(method, &options)?);
+    method.start()?;
+    let crtcs = method.outputs().len();
+    let iterations = (iterations / METHOD_FRACTION).max(1);
+    let elapsed = time(iterations, |i| method.set_temperature(&setting(i)))? / iterations;
+    println!("Method {}, {} CRTCs ({} iterations)", method.name(), crtcs, iterations);
+    println!("    per update {}, per CRTC {}", format_duration(elapsed),
+             format_duration(elapsed / crtcs.max(1) as u32));
+    method.restore()?;
+    Ok(0)
+}
+
+/// One of two settings that look the same, by iteration
+fn setting(i: u32) -> ColorSetting {
+    ColorSetting {
+        temp: if i % 2 == 0 { 6500 } else { 6490 },
+        ..ColorSetting::neutral()
+    }
+}
+
+/// Run `f` the given number of times, with the iteration
+fn time<F: FnMut(u32) -> Result<()>>(iterations: u32, mut f: F) -> Result<Duration> {
+    let started = Instant::now();
+    for i in 0..iterations {
+        f(i)?;
+    }
+    Ok(started.elapsed())
+}
+
+fn format_duration(d: Duration) -> String {
+    let us = d.as_secs() as f64 * 1e6 + d.subsec_nanos() as f64 / 1e3;
+    if us < 1000.0 {
+        format!("{:.1}µs", us)
+    } else {
+        format!("{:.2}ms", us / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::new(0, 10_200)), "10.2µs");
+        assert_eq!(format_duration(Duration::new(0, 1_210_000)), "1.21ms");
+        assert_ne!(setting(0), setting(1));
+    }
+}
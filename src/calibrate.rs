@@ -0,0 +1,219 @@
+//! Interactive calibration
+//!
+//! `redshift-rs calibrate` applies a temperature and a brightness
+//! right away and changes them with the arrow keys, to find the night
+//! setting, or the day setting with `--day`, by looking at the screen:
+//!
+//! ```text
+//! $ redshift-rs calibrate
+//! Night: ↑↓ temperature, ←→ brightness, Enter to save, q to quit
+//!   3300K  brightness 0.90
+//! ```
+//!
+//! Up and down change the temperature by 100K, or 500K with Page Up
+//! and Page Down, and left and right the brightness by 0.05. It starts
+//! from the setting of the configuration file. Enter writes
+//! `temp-night` and `brightness-night` (or `-day`) into its
+//! `[redshift]` section, keeping the rest of the file as it is, and
+//! then restores the display, as quitting does.
+//!
+//! The method is selected as with `-m`, `-d` and `--wayland-display`
+//! before the subcommand.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use libc;
+
+use config::{self, Config};
+use gamma;
+use paths;
+use preset::Presets;
+use term;
+use transition::ColorSetting;
+use super::{check_temperature, Result, RedshiftError};
+use super::{DEFAULT_BRIGHTNESS, DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP, MIN_TEMP, MAX_TEMP};
+
+/// Kelvin per press of up and down, and of page up and page down
+const TEMP_STEP: i32 = 100;
+const TEMP_PAGE: i32 = 500;
+const BRIGHTNESS_STEP: f64 = 0.05;
+const MIN_BRIGHTNESS: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Left,
+    Right,
+    Enter,
+    Quit,
+    Other,
+}
+
+/// The keys in what was read from the terminal
+fn keys(input: &[u8]) -> Vec<Key> {
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < input.len() {
+        let (key, len) = match &input[i..] {
+            [0x1b, b'[', b'A', ..] => (Key::Up, 3),
+            [0x1b, b'[', b'B', ..] => (Key::Down, 3),
+            [0x1b, b'[', b'C', ..] => (Key::Right, 3),
+            [0x1b, b'[', b'D', ..] => (Key::Left, 3),
+            [0x1b, b'[', b'5', b'~', ..] => (Key::PageUp, 4),
+            [0x1b, b'[', b'6', b'~', ..] => (Key::PageDown, 4),
+            [b'\r', ..] | [b'\n', ..] => (Key::Enter, 1),
+            // Escape alone, q, Ctrl-C and Ctrl-D
+            [0x1b] | [b'q', ..] | [0x03, ..] | [0x04, ..] => (Key::Quit, 1),
+            _ => (Key::Other, 1),
+        };
+        keys.push(key);
+        i += len;
+    }
+    keys
+}
+
+/// The terminal without line editing, echo and signals, as it was
+/// again when dropped
+struct RawTerminal {
+    saved: libc::termios,
+}
+
+impl RawTerminal {
+    fn new() -> io::Result<RawTerminal> {
+        unsafe {
+            let mut saved: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(RawTerminal { saved: saved })
+        }
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+pub fn run(matches: &ArgMatches, sub: &ArgMatches) -> Result<i32> {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return Err(RedshiftError::MalformedArgument("calibrate needs a terminal".to_owned()));
+    }
+    let path = matches.value_of("config").map(PathBuf::from).or_else(config::default_path)
+        .ok_or_else(|| RedshiftError::MalformedArgument("no configuration file, $HOME is not set".to_owned()))?;
+    let config = if path.is_file() { Config::from_file(&path)? } else { Config::empty() };
+    let mut presets = Presets::builtin();
+    if let Some(section) = config.section("presets") {
+        presets.extend(section)?;
+    }
+    let (period, default_temp) = if sub.is_present("day") {
+        ("day", DEFAULT_DAY_TEMP)
+    } else {
+        ("night", DEFAULT_NIGHT_TEMP)
+    };
+    let (temp_key, brightness_key) = (format!("temp-{}", period), format!("brightness-{}", period));
+    let section = config.section("redshift");
+    let mut temp = match section.and_then(|s| s.get(&temp_key)) {
+        Some(t) => check_temperature(presets.resolve(t)?)?,
+        None => default_temp,
+    };
+    let mut brightness = section.map_or(Ok(None), |s| s.parse::<f64>(&brightness_key))?
+        .unwrap_or(DEFAULT_BRIGHTNESS).clamp(MIN_BRIGHTNESS, 1.0);
+
+    let options = gamma::Options {
+        display: matches.value_of("display").map(ToOwned::to_owned),
+        wayland_display: matches.value_of("wayland-display").map(ToOwned::to_owned),
+        ..gamma::Options::default()
+    };
+    let method = matches.value_of("method").filter(|m| *m != "list");
+    let mut method = gamma::RestoreGuard::new(gamma::init_gamma_method(method, &options)?);
+    method.start()?;
+
+    let colors = term::colors(term::STDOUT);
+    let mut stdout = io::stdout();
+    println!("{}: ↑↓ temperature, ←→ brightness, Enter to save, q to quit",
+             if period == "day" { "Day" } else { "Night" });
+    let terminal = RawTerminal::new().map_err(|e| RedshiftError::io("the terminal", e))?;
+    let mut buf = [0u8; 32];
+    let save = 'keys: loop {
+        method.set_temperature(&ColorSetting { temp: temp, brightness: brightness, ..ColorSetting::neutral() })?;
+        let swatch = if colors { term::swatch(temp) } else { String::new() };
+        print!("\r\x1b[K  {}K  brightness {:.2} {}", temp, brightness, swatch);
+        let _ = stdout.flush();
+
+        let n = io::stdin().read(&mut buf).map_err(|e| RedshiftError::io("the terminal", e))?;
+        if n == 0 {
+            break false;
+        }
+        for key in keys(&buf[..n]) {
+            match key {
+                Key::Up => temp += TEMP_STEP,
+                Key::Down => temp -= TEMP_STEP,
+                Key::PageUp => temp += TEMP_PAGE,
+                Key::PageDown => temp -= TEMP_PAGE,
+                Key::Right => brightness += BRIGHTNESS_STEP,
+                Key::Left => brightness -= BRIGHTNESS_STEP,
+                Key::Enter => break 'keys true,
+                Key::Quit => break 'keys false,
+                Key::Other => {}
+            }
+        }
+        temp = temp.clamp(MIN_TEMP, MAX_TEMP);
+        // In steps of the brightness, without the rounding errors
+        brightness = (brightness.clamp(MIN_BRIGHTNESS, 1.0) * 100.0).round() / 100.0;
+    };
+    drop(terminal);
+    println!();
+    method.restore()?;
+
+    if save {
+        let contents = if path.is_file() {
+            fs::read_to_string(&path).map_err(|e| RedshiftError::io(path.display(), e))?
+        } else {
+            String::new()
+        };
+        let contents = config::set_value(&contents, "redshift", &temp_key, &temp.to_string());
+        let contents = config::set_value(&contents, "redshift", &brightness_key, &format!("{:.2}", brightness));
+        if let Some(dir) = path.parent() {
+            paths::ensure_dir(dir).map_err(|e| RedshiftError::io(dir.display(), e))?;
+        }
+        fs::write(&path, contents).map_err(|e| RedshiftError::io(path.display(), e))?;
+        println!("Saved {}={} and {}={:.2} to {}", temp_key, temp, brightness_key, brightness, path.display());
+        // The files of the .d directory go over it
+        let saved = Config::from_file(&path)?;
+        if saved.section("redshift").and_then(|s| s.get(&temp_key)) != Some(&temp.to_string()[..]) {
+            warn!("{} is set again in an included file or the .d directory, which takes precedence", temp_key);
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_sequences() {
+        assert_eq!(keys(b"\x1b[A\x1b[Dx\x1b[6~\r"),
+                   [Key::Up, Key::Left, Key::Other, Key::PageDown, Key::Enter]);
+        assert_eq!(keys(b"\x1b"), [Key::Quit]);
+        assert_eq!(keys(b"\x03"), [Key::Quit]);
+    }
+}
@@ -0,0 +1,359 @@
+//! Command-line interface
+//!
+//! Definition of the argument parser. Values are validated here so
+//! that malformed input is reported by clap together with the usage
+//! line, the actual conversion into settings happens in `Args::parse`.
+
+use std::io;
+
+use clap::{App, AppSettings, Arg, ArgGroup, Shell, SubCommand};
+
+use gamma;
+use i18n;
+use location;
+use offset;
+use super::{parse_brightness, parse_duration, parse_gamma, parse_time_of_day};
+use super::{Result, RedshiftError};
+
+const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+
+const USAGE: &'static str = "\
+    redshift-rs [OPTIONS]
+    redshift-rs ctl <COMMAND>
+    redshift-rs completions <SHELL>
+    redshift-rs bench [--no-method]
+    redshift-rs preview <FROM..TO> [--step <KELVIN>] [--dwell <DURATION>]
+    redshift-rs calibrate [--day]
+    redshift-rs (-h | --help)
+    redshift-rs (-V | --version)";
+
+const AFTER_HELP: &'static str = "\
+The neutral temperature is 6500K. Using this value will not change the
+color temperature of the display. Setting the color temperature to a
+value higher than this results in more blue light, and setting a lower
+value will result in more red light.
+
+Temperatures can also be given as preset names: candle (1900K), tungsten
+(2700K), halogen (3400K), daylight (5500K), neutral (6500K) and overcast
+(7000K). More presets can be defined in the [presets] section of the
+configuration file.
+
+Use `-m list` to see the available adjustment methods and `-l list` to
+see the available location providers.
+
+EXAMPLES:
+    redshift-rs -l 55.7:12.6 -t 5700:3600    Run continually at the given location
+    redshift-rs -O 4000                      Set 4000K once and exit
+    redshift-rs -t daylight:candle           Use presets for the day and night temperatures
+    redshift-rs -p -P reading                Print parameters using the 'reading' profile
+    redshift-rs -p --follow                  Print a line on every change of the schedule
+    redshift-rs -x                           Remove any adjustments
+    redshift-rs -a -500                      Make a running instance 500K warmer
+    redshift-rs --simulate-day 2m            Print a day's adjustments in two minutes
+    redshift-rs ctl set-temp candle          Make a running instance hold 1900K";
+
+lazy_static! {
+    static ref ABOUT: String = format!(
+        "\n{}\n\nA Rust clone of the original Redshift written in C by Jon Lund Steffensen.",
+        i18n::message("Set color temperature of display according to time of day.\n", &[]));
+
+    /// Values accepted by `-m`, including the methods that were left
+    /// out at compile time, which fail with a hint when started
+    static ref METHOD_NAMES: Vec<&'static str> = {
+        let mut names = gamma::methods();
+        names.extend(gamma::disabled_methods());
+        names.push("list");
+        names
+    };
+
+    /// Provider names accepted by `-l`, besides coordinates
+    static ref PROVIDER_NAMES: Vec<&'static str> = {
+        let mut names = location::providers();
+        names.extend(location::disabled_providers());
+        names.push("list");
+        names
+    };
+}
+
+/// Turn a parse function into a clap validator
+fn validate<T, F>(parse: F) -> impl Fn(String) -> ::std::result::Result<(), String>
+    where F: Fn(&str) -> Result<T>
+{
+    move |v| parse(&v).map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn validate_list_or<T, F>(parse: F) -> impl Fn(String) -> ::std::result::Result<(), String>
+    where F: Fn(&str) -> Result<T>
+{
+    move |v| if v == "list" { Ok(()) } else { validate(&parse)(v) }
+}
+
+pub fn app<'app>() -> App<'app, 'app> {
+    build(false)
+}
+
+/// Build the argument parser
+///
+/// The location argument accepts free-form coordinates, so provider
+/// names can only be listed as possible values when the parser is
+/// used to generate completions, not when validating input.
+fn build<'app>(completion: bool) -> App<'app, 'app> {
+    let arg = |name| Arg::with_name(name).long(name);
+
+    let location = arg("location")
+        .short("l")
+        .value_name("LAT:LON|PROVIDER")
+        .help("Your current location, or a location provider (use 'list' to see available providers)");
+    let location = if completion {
+        location.possible_values(&PROVIDER_NAMES)
+    } else {
+        // Providers left out at compile time fail later, with a hint
+        location.validator(validate_list_or(|v| if location::is_provider(v) ||
+                                            location::disabled_providers().contains(&v) {
+            Ok(())
+        } else {
+            v.parse::<location::Location>().map(|_| ())
+        }))
+    };
+
+    App::new("redshift-rs")
+        .author("Thomas Jespersen <laumann.thomas@gmail.com>")
+        .version(VERSION)
+        .about(&ABOUT[..])
+        .usage(USAGE)
+        .after_help(AFTER_HELP)
+        .setting(AppSettings::UnifiedHelpMessage)
+        .setting(AppSettings::ColorNever)
+        .arg(arg("config")
+             .short("c")
+             .value_name("FILE")
+             .help("Load settings from specified configuration file"))
+        .arg(arg("profile")
+             .short("P")
+             .value_name("NAME")
+             .help("Use the named profile from the configuration file"))
+        .arg(arg("brightness")
+             .short("b")
+             .value_name("DAY:NIGHT")
+             .validator(validate(parse_brightness))
+             .help("Screen brightness to apply (between 0.1 and 1.0)"))
+        .arg(arg("brightness-only")
+             .conflicts_with("temperature")
+             .help("Only dim on the schedule, leaving the color temperature neutral"))
+        .arg(arg("method")
+             .short("m")
+             .value_name("METHOD")
+             .possible_values(&METHOD_NAMES)
+             .hide_possible_values(true)
+             .help("Method to use to set color temperature (use 'list' to see available methods)"))
+        .arg(location)
+        .arg(arg("display")
+             .value_name("DISPLAY")
+             .help("X display to connect to, instead of $DISPLAY, or several separated by commas"))
+        .arg(arg("wayland-display")
+             .value_name("SOCKET")
+             .help("Wayland socket to connect to, instead of $WAYLAND_DISPLAY"))
+        .arg(arg("temperature")
+             .short("t")
+             .value_name("DAY:NIGHT")
+             .help("Set day/night color temperatures, 1000K to 25000K (in Kelvin, mireds as in 250mired, or preset names)"))
+        .arg(arg("gamma")
+             .short("g")
+             .value_name("R:G:B")
+             .validator(validate(parse_gamma))
+             .help("Additional gamma correction to apply"))
+        .arg(arg("contrast")
+             .value_name("DAY:NIGHT")
+             .help("Contrast at day and night, from 0.1 to 2.0"))
+        .arg(arg("matrix")
+             .value_name("MATRIX")
+             .help("Color matrix, nine values row by row (only the diagonal is applied)"))
+        .arg(arg("grayscale")
+             .help("Turn the colors gray by their luminance (needs a method that mixes channels, like hyprland)"))
+        .arg(arg("color-vision")
+             .value_name("FILTER")
+             .possible_values(&["none", "protanopia", "deuteranopia", "tritanopia",
+                                "simulate-protanopia", "simulate-deuteranopia", "simulate-tritanopia"])
+             .help("Correct the colors for a color vision deficiency, or simulate one \
+                    (needs a method that mixes channels, like hyprland)"))
+        .arg(arg("invert")
+             .value_name("WHEN")
+             .possible_values(&["never", "night", "always"])
+             .help("Invert the colors, at night or always, for dark backgrounds"))
+        .arg(arg("format")
+             .value_name("FORMAT")
+             .possible_values(&["text", "json"])
+             .help("Output format of print mode"))
+        .arg(arg("no-transition").short("r").help("Disable temperature transitions"))
+        .arg(arg("print").short("p").help("Print parameters and exit"))
+        .arg(arg("oneshot").short("o").help("One shot mode (do not continually adjust color temperature)"))
+        .arg(arg("oneshot-manual").short("O")
+             .help("One shot manual mode (set color temperature, in Kelvin, mireds or a preset name)")
+             .value_name("TEMP"))
+        .arg(arg("reset").short("x").help("Reset mode (remove adjustments from screen)"))
+        .arg(arg("adjust").short("a")
+             .value_name("DELTA")
+             .allow_hyphen_values(true)
+             .validator(validate(offset::Adjustment::parse))
+             .help("Shift the scheduled temperature by DELTA (e.g. +500 or -500), or 'reset'"))
+        .arg(arg("list-outputs")
+             .help("List the outputs with their monitors and whether they are adjusted, and exit"))
+        .arg(arg("diagnose")
+             .help("Try every gamma method and location provider, and print a report for bug reports"))
+        .group(ArgGroup::with_name("mode")
+               .args(&["print", "oneshot", "oneshot-manual", "reset", "adjust", "list-outputs", "diagnose"]))
+        .arg(arg("dry-run")
+             .help("Compute and print every adjustment without touching the display"))
+        .arg(arg("simulate-day")
+             .value_name("DURATION")
+             .validator(validate(parse_duration))
+             .conflicts_with("mode")
+             .help("Dry run through a full day in the given time (e.g. 2m), then exit"))
+        .arg(arg("pause-fullscreen")
+             .conflicts_with("mode")
+             .help("Pause the adjustments while a fullscreen window is active"))
+        .arg(arg("pause-locked")
+             .conflicts_with("mode")
+             .help("Pause the adjustments while the session is locked"))
+        .arg(arg("follow")
+             .requires("print")
+             .help("With -p, keep running and print a line whenever the period, the temperature \
+                    or the enabled state changes"))
+        .arg(arg("status-stream")
+             .conflicts_with("mode")
+             .help("Print a JSON line for Waybar or i3status on every change"))
+        .arg(arg("replace")
+             .conflicts_with("mode")
+             .help("Take over from an instance already running on the same display"))
+        .arg(arg("verbose").short("v")
+             .multiple(true)
+             .help("Verbose output (repeat for more detail)"))
+        .arg(arg("quiet").short("q")
+             .conflicts_with("verbose")
+             .help("Only print errors"))
+        .arg(arg("log-target")
+             .value_name("TARGET")
+             .possible_values(&["stderr", "syslog", "journald"])
+             .help("Where to write log messages"))
+        .subcommand(SubCommand::with_name("ctl")
+                    .about("Control a running instance")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(SubCommand::with_name("status")
+                                .about("Print the current status")
+                                .arg(Arg::with_name("format").long("format")
+                                     .value_name("FORMAT")
+                                     .possible_values(&["text", "json"])
+                                     .help("Output format")))
+                    .subcommand(SubCommand::with_name("toggle")
+                                .about("Toggle between enabled and disabled"))
+                    .subcommand(SubCommand::with_name("enable")
+                                .about("Enable the adjustments"))
+                    .subcommand(SubCommand::with_name("disable")
+                                .about("Disable the adjustments"))
+                    .subcommand(SubCommand::with_name("snooze")
+                                .about("Disable the adjustments for a while, e.g. 20m")
+                                .arg(Arg::with_name("duration")
+                                     .required(true)
+                                     .value_name("DURATION")
+                                     .validator(validate(parse_duration))))
+                    .subcommand(SubCommand::with_name("set-temp")
+                                .about("Hold a color temperature, or 'reset' to resume the schedule")
+                                .arg(Arg::with_name("temperature")
+                                     .required(true)
+                                     .value_name("TEMP")
+                                     .help("Temperature in Kelvin, mireds or a preset name"))
+                                .arg(Arg::with_name("until")
+                                     .long("until")
+                                     .value_name("HH:MM")
+                                     .validator(validate(parse_time_of_day))
+                                     .help("Resume the schedule the next time the clock shows HH:MM")))
+                    .subcommand(SubCommand::with_name("night-vision")
+                                .about("Show red only, to keep the eyes adapted to the dark")
+                                .arg(Arg::with_name("state")
+                                     .value_name("STATE")
+                                     .possible_values(&["on", "off"])
+                                     .help("Switch on or off, instead of toggling")))
+                    .subcommand(SubCommand::with_name("grayscale")
+                                .about("Turn the colors gray by their luminance")
+                                .arg(Arg::with_name("state")
+                                     .value_name("STATE")
+                                     .possible_values(&["on", "off"])
+                                     .help("Switch on or off, instead of toggling")))
+                    .subcommand(SubCommand::with_name("reload")
+                                .about("Read the configuration file again"))
+                    .subcommand(SubCommand::with_name("profile")
+                                .about("Switch to the named profile")
+                                .arg(Arg::with_name("name")
+                                     .required(true)
+                                     .value_name("NAME")
+                                     .help("Profile from the configuration file"))))
+        .subcommand(SubCommand::with_name("bench")
+                    .about("Time computing ramps and applying them with the gamma method")
+                    .arg(arg("iterations")
+                         .value_name("N")
+                         .help("Number of ramps to compute at each size, 200 by default"))
+                    .arg(arg("no-method")
+                         .help("Only time computing ramps, leave the display alone")))
+        .subcommand(SubCommand::with_name("preview")
+                    .about("Sweep through a range of temperatures, to pick one by eye")
+                    .arg(Arg::with_name("range")
+                         .required(true)
+                         .value_name("FROM..TO")
+                         .help("Temperatures in Kelvin, mireds or preset names, such as 6500..3000"))
+                    .arg(arg("step")
+                         .value_name("KELVIN")
+                         .help("Difference between the temperatures, 250K by default"))
+                    .arg(arg("dwell")
+                         .value_name("DURATION")
+                         .help("How long each temperature is shown, 2s by default")))
+        .subcommand(SubCommand::with_name("calibrate")
+                    .about("Adjust the night setting with the arrow keys and save it to the configuration file")
+                    .arg(arg("day")
+                         .help("Adjust the day setting instead")))
+        .subcommand(SubCommand::with_name("ramps")
+                    .about("Print the gamma ramps computed for a setting")
+                    .setting(AppSettings::Hidden)
+                    .arg(Arg::with_name("temperature")
+                         .required(true)
+                         .value_name("TEMP")
+                         .help("Temperature in Kelvin, mireds or a preset name"))
+                    .arg(arg("brightness")
+                         .short("b")
+                         .value_name("BRIGHTNESS")
+                         .validator(validate(parse_brightness))
+                         .help("Brightness (between 0.1 and 1.0)"))
+                    .arg(arg("gamma")
+                         .short("g")
+                         .value_name("R:G:B")
+                         .validator(validate(parse_gamma))
+                         .help("Gamma correction"))
+                    .arg(arg("size")
+                         .value_name("N")
+                         .help("Number of entries of each ramp, 256 by default"))
+                    .arg(arg("all")
+                         .help("Print every entry rather than a sample")))
+        .subcommand(SubCommand::with_name("install-service")
+                    .about("Install a systemd user service (a launchd agent on macOS) running redshift-rs")
+                    .arg(arg("enable")
+                         .help("Enable and start the service right away"))
+                    .arg(arg("force")
+                         .help("Replace an existing service file"))
+                    .arg(arg("print")
+                         .conflicts_with_all(&["enable", "force"])
+                         .help("Print the service file instead of installing it")))
+        .subcommand(SubCommand::with_name("completions")
+                    .about("Generate a shell completion script")
+                    .arg(Arg::with_name("shell")
+                         .required(true)
+                         .possible_values(&Shell::variants())
+                         .help("The shell to generate completions for")))
+}
+
+/// Write the completion script for the named shell to stdout
+pub fn completions(shell: &str) -> Result<i32> {
+    let shell = shell.parse::<Shell>()
+        .map_err(|e| RedshiftError::MalformedArgument(e))?;
+    build(true).gen_completions_to("redshift-rs", shell, &mut io::stdout());
+    Ok(0)
+}
@@ -0,0 +1,56 @@
+//! Sources of the current time
+//!
+//! The schedule is computed for the time given by a `Clock`, rather
+//! than by asking the system directly, so that tests can move the time
+//! along with a `TestClock` and check transitions and period changes
+//! without waiting for them.
+
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait Clock {
+    /// The current time in seconds since the epoch
+    fn now(&self) -> f64;
+}
+
+/// The system time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9)
+            .unwrap_or(0.0)
+    }
+}
+
+/// A clock that only moves when told to
+///
+/// Clones share the time, so one clone can be handed to the code
+/// under test while another moves it along.
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    t: Arc<Mutex<f64>>,
+}
+
+impl TestClock {
+    /// A clock standing at `t` seconds since the epoch
+    pub fn new(t: f64) -> TestClock {
+        TestClock { t: Arc::new(Mutex::new(t)) }
+    }
+
+    pub fn set(&self, t: f64) {
+        *self.t.lock().unwrap() = t;
+    }
+
+    pub fn advance(&self, secs: f64) {
+        *self.t.lock().unwrap() += secs;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> f64 {
+        *self.t.lock().unwrap()
+    }
+}
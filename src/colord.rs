@@ -0,0 +1,169 @@
+//! Calibrations of the profiles assigned by colord
+//!
+//! Desktops that manage color keep the ICC profile of each display in
+//! colord, as assigned in their settings or by a calibration tool.
+//! With `colord=1` in the config file the calibration curves of those
+//! profiles are applied on top of every setting, as `icc` in an
+//! `[output:NAME]` section does, to the outputs that have no `icc` of
+//! their own:
+//!
+//! ```text
+//! [redshift]
+//! colord=1
+//! ```
+//!
+//! colord only keeps the profiles; loading their calibration into the
+//! ramps is left to a program of the session, such as GNOME's color
+//! plugin or xiccd. Those keep doing so alongside the daemon, and
+//! whenever they do the calibration is either lost or, composed with
+//! the ramps of the daemon, applied twice, so the daemon warns about
+//! them. Profiles without calibration curves are skipped. colord is
+//! asked over the system bus, with the `dbus` feature, and asked again
+//! on reload.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use icc::Calibration;
+use outputs;
+
+/// Names of the programs of the session that load the calibration of
+/// colord into the ramps, and how they are called
+const LOADERS: [(&'static str, &'static str); 2] = [
+    ("gsd-color", "GNOME's color plugin"),
+    ("xiccd", "xiccd"),
+];
+
+/// The output names of the displays known to colord, with the file of
+/// the default profile of each
+#[cfg(feature = "dbus")]
+pub fn profiles() -> Result<Vec<(String, PathBuf)>, String> {
+    manager::profiles().map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn profiles() -> Result<Vec<(String, PathBuf)>, String> {
+    Err("support for D-Bus is not compiled in".to_owned())
+}
+
+/// Load the calibrations of the profiles, skipping those without one
+pub fn calibrations(profiles: Vec<(String, PathBuf)>) -> Vec<(String, Arc<Calibration>)> {
+    profiles.into_iter()
+        .filter_map(|(output, path)| match Calibration::load(&path) {
+            Ok(calibration) => Some((output, Arc::new(calibration))),
+            Err(e) => {
+                info!("Not calibrating {} from colord: {}", output, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Give the calibrations to the outputs without one of their own,
+/// adding settings for the outputs without a section
+pub fn calibrate(outputs: &mut Vec<outputs::Options>, calibrations: Vec<(String, Arc<Calibration>)>,
+                 temperatures: (i32, i32)) {
+    for (name, calibration) in calibrations {
+        match outputs.iter_mut().find(|o| o.name == name) {
+            Some(output) => {
+                if output.calibration.is_none() {
+                    output.temperatures.get_or_insert(temperatures);
+                    output.calibration = Some(calibration);
+                }
+            }
+            None => outputs.push(outputs::Options {
+                name: name,
+                enabled: true,
+                temperatures: Some(temperatures),
+                calibration: Some(calibration),
+            }),
+        }
+    }
+}
+
+/// The programs running that load the calibration of colord too
+pub fn loaders() -> Vec<&'static str> {
+    let running: Vec<String> = fs::read_dir("/proc").into_iter()
+        .flat_map(|dir| dir.filter_map(|e| e.ok()))
+        .filter_map(|e| fs::read_to_string(e.path().join("comm")).ok())
+        .map(|comm| comm.trim().to_owned())
+        .collect();
+    LOADERS.iter()
+        .filter(|&&(comm, _)| running.iter().any(|r| r == comm))
+        .map(|&(_, name)| name)
+        .collect()
+}
+
+#[cfg(feature = "dbus")]
+mod manager {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use dbus;
+    use dbus::blocking::Connection;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+
+    const COLORD: &'static str = "org.freedesktop.ColorManager";
+    const COLORD_PATH: &'static str = "/org/freedesktop/ColorManager";
+    const DEVICE: &'static str = "org.freedesktop.ColorManager.Device";
+    const PROFILE: &'static str = "org.freedesktop.ColorManager.Profile";
+    /// The metadata of a display device with its RandR output name
+    const OUTPUT_NAME: &'static str = "XRANDR_name";
+
+    const TIMEOUT_MS: u64 = 1000;
+
+    pub fn profiles() -> Result<Vec<(String, PathBuf)>, dbus::Error> {
+        let conn = Connection::new_system()?;
+        let timeout = Duration::from_millis(TIMEOUT_MS);
+        let (devices,): (Vec<dbus::Path<'static>>,) = conn.with_proxy(COLORD, COLORD_PATH, timeout)
+            .method_call(COLORD, "GetDevicesByKind", ("display",))?;
+        let mut profiles = vec![];
+        for device in devices {
+            let proxy = conn.with_proxy(COLORD, device, timeout);
+            let metadata: HashMap<String, String> = proxy.get(DEVICE, "Metadata")?;
+            let output = match metadata.get(OUTPUT_NAME) {
+                Some(output) => output.clone(),
+                None => continue,
+            };
+            // The first profile is the default one
+            let assigned: Vec<dbus::Path<'static>> = proxy.get(DEVICE, "Profiles")?;
+            if let Some(profile) = assigned.into_iter().next() {
+                let filename: String = conn.with_proxy(COLORD, profile, timeout).get(PROFILE, "Filename")?;
+                if !filename.is_empty() {
+                    profiles.push((output, PathBuf::from(filename)));
+                }
+            }
+        }
+        Ok(profiles)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn calibrated_outputs() {
+        let calibration = Arc::new(Calibration::from_curves([vec![0, 65535], vec![0, 65535], vec![0, 65535]]));
+        let own = Arc::new(Calibration::from_curves([vec![0, 32768], vec![0, 65535], vec![0, 65535]]));
+        let output = |name: &str, calibration: Option<Arc<Calibration>>| outputs::Options {
+            name: name.to_owned(),
+            enabled: true,
+            temperatures: None,
+            calibration: calibration,
+        };
+        let mut options = vec![output("DP-1", Some(own.clone())), output("DP-2", None)];
+        calibrate(&mut options, vec![("DP-1".to_owned(), calibration.clone()),
+                                     ("DP-2".to_owned(), calibration.clone()),
+                                     ("eDP-1".to_owned(), calibration.clone())],
+                  (6500, 3500));
+        assert_eq!(options.len(), 3);
+        assert_eq!(options[0], output("DP-1", Some(own)));
+        assert_eq!(options[1].calibration, Some(calibration.clone()));
+        assert_eq!(options[1].temperatures, Some((6500, 3500)));
+        assert_eq!(options[2].name, "eDP-1");
+        assert_eq!(options[2].temperatures, Some((6500, 3500)));
+    }
+}
@@ -1,8 +1,42 @@
-use transition;
+//! Gamma ramps for color settings
+//!
+//! The display looks up each channel of every pixel in the ramp of
+//! that channel, so ramps can scale and bend the channels but never mix
+//! them. Whatever a `ColorSetting` does has to follow from that:
+//! whitepoint, brightness and gamma are all per channel. Effects that
+//! mix the channels, like turning the output into grayscale by its
+//! luminance or correcting color vision deficiencies (see `vision`),
+//! go into `ColorSetting::filter`, which ramps leave out entirely.
+//! Only methods with a color transformation matrix from the display
+//! server apply it, see `GammaMethod::mixes_channels`.
+//!
+//! For the same reason only the diagonal of `ColorSetting::matrix` is
+//! applied, scaling each channel by its factor on itself. The other
+//! entries, the share of each channel in the others, are left out, so
+//! a matrix that mostly tints or corrects the channels separately works
+//! as intended, while one that swaps or mixes them does not.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use transition::{self, ColorSetting};
+use super::{MAX_TEMP, MIN_TEMP, Result, RedshiftError};
+
+/// Red, green and blue gamma ramps of an output
+pub type Ramps = (Vec<u16>, Vec<u16>, Vec<u16>);
 
 /**
- * Generate color ramps from the given color setting and ramp size,
- * and modify the given rgb gamma ramps.
+ * Apply the given color setting to the given rgb gamma ramps, in
+ * place. `ramps` builds on this, starting from the identity.
+ *
+ * Only the first `size` entries are modified, or all of them if a
+ * ramp is shorter. Temperatures outside of MIN_TEMP to MAX_TEMP, the
+ * range of the whitepoint table, are clamped to it.
  */
 pub fn fill(gamma_r: &mut [u16],
             gamma_g: &mut [u16],
@@ -10,41 +44,301 @@ pub fn fill(gamma_r: &mut [u16],
             setting: &transition::ColorSetting,
             size: usize)
 {
-    let alpha = (setting.temp as f64 % 100.0) / 100.0;
-    let temp_index = (((setting.temp - 1000)/100)*3) as usize;
+    let white_points = white_point(setting.temp);
+    let u16_max1 = u16::max_value() as f64 + 1.0;
+    let plain = !setting.invert && setting.contrast == 1.0;
 
-    let white_points = interpolate_color(alpha,
-                                         &BLACKBODY_COLOR[temp_index..temp_index+3],
-                                         &BLACKBODY_COLOR[temp_index+3..temp_index+6]);
+    // One channel at a time, in loops without branches that the
+    // compiler can vectorize. This runs for every output on every step
+    // of a fade, with up to 4096 entries per ramp.
+    for (c, ramp) in [gamma_r, gamma_g, gamma_b].iter_mut().enumerate() {
+        let len = size.min(ramp.len());
+        let ramp = &mut ramp[..len];
+        let scale = setting.brightness * white_points[c] * setting.matrix[c][c];
+        let exponent = setting.gamma[c].recip();
+        if plain && exponent == 1.0 {
+            // Only scaling, by far the most common
+            for v in ramp.iter_mut() {
+                *v = (*v as f64 * scale) as u16;
+            }
+            continue;
+        }
 
-    let mut gammas = [gamma_r, gamma_g, gamma_b];
+        let (flip, slope) = if setting.invert { (1.0, -1.0) } else { (0.0, 1.0) };
+        for v in ramp.iter_mut() {
+            let y = flip + slope * (*v as f64 / u16_max1);
+            let y = ((y - 0.5) * setting.contrast + 0.5).max(0.0).min(1.0);
+            *v = ((y * scale).powf(exponent) * u16_max1) as u16;
+        }
+    }
+}
 
-    // Compute gamma, based on other gamma value
-    let u16_max1 = u16::max_value() as f64 + 1.0;
-    let compute_gamma = |g: u16, c: usize| {
-        let y = g as f64 / u16_max1;
-        let f = (y * setting.brightness * white_points[c]).powf(setting.gamma[c].recip());
-        (f * u16_max1) as u16
-    };
-
-    for i in 0..size {
-        for g in 0..3 {
-            gammas[g][i] = compute_gamma(gammas[g][i], g);
+/// The ramps of the given size for a setting
+///
+/// Each ramp starts out as the identity, see `identity`, gets the
+/// setting applied with `fill`, and then its calibration, if any. The
+/// result only depends on the setting, the size and the whitepoint
+/// table in use, so the ramps can be compared, cached and computed
+/// ahead of time.
+///
+/// The methods of C Redshift start from i/size * 65536 instead, which
+/// stops at 65280 for 256 entries, so its ramps are up to 1/256 darker
+/// towards the top end. Applied to the same ramp the results are
+/// within 1 of each other, as C Redshift's whitepoints are floats.
+pub fn ramps(setting: &ColorSetting, size: usize) -> Ramps {
+    let (mut r, mut g, mut b) = (vec![0; size], vec![0; size], vec![0; size]);
+    identity(&mut r);
+    identity(&mut g);
+    identity(&mut b);
+    fill(&mut r, &mut g, &mut b, setting, size);
+    if let Some(ref calibration) = setting.calibration {
+        calibration.apply(&mut r, 0);
+        calibration.apply(&mut g, 1);
+        calibration.apply(&mut b, 2);
+    }
+    (r, g, b)
+}
+
+/// Ramps computed for the settings applied most recently
+///
+/// Applying a setting again, such as after the screen powered back on,
+/// or to several outputs with ramps of the same size, reuses the ramps
+/// computed the first time. During a fade every setting is new, and
+/// only the last few are kept.
+pub struct Cache {
+    capacity: usize,
+    /// Of the whitepoints the entries were computed with
+    generation: usize,
+    /// Most recently used first
+    entries: VecDeque<(ColorSetting, usize, Ramps)>,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity: capacity,
+            generation: GENERATION.load(Ordering::SeqCst),
+            entries: VecDeque::with_capacity(capacity + 1),
+        }
+    }
+
+    /// The ramps of the given size for a setting, applied to the
+    /// identity
+    pub fn ramps(&mut self, setting: &ColorSetting, size: usize) -> &Ramps {
+        let generation = GENERATION.load(Ordering::SeqCst);
+        if generation != self.generation {
+            self.entries.clear();
+            self.generation = generation;
         }
+        match self.entries.iter().position(|e| e.0 == *setting && e.1 == size) {
+            Some(0) => {}
+            Some(i) => {
+                let entry = self.entries.remove(i).unwrap();
+                self.entries.push_front(entry);
+            }
+            None => {
+                self.entries.push_front((setting.clone(), size, ramps(setting, size)));
+                self.entries.truncate(self.capacity.max(1));
+            }
+        }
+        &self.entries[0].2
     }
 }
 
-fn interpolate_color<'a>(a: f64, c1: &'a[f64], c2: &'a[f64]) -> [f64; 3] {
-    [(1.0-a)*c1[0] + a*c2[0],
-     (1.0-a)*c1[1] + a*c2[1],
-     (1.0-a)*c1[2] + a*c2[2]]
+/// Fill a ramp with the identity, which leaves the colors unchanged
+///
+/// Ramps take 16-bit values whatever their size, from 0 for the first
+/// entry to the maximum for the last, so that white stays at full
+/// intensity also with 256 entries. Outputs with a higher bit depth
+/// have more entries, not larger values.
+pub fn identity(ramp: &mut [u16]) {
+    let max = u16::max_value() as f64;
+    let last = (ramp.len().max(2) - 1) as f64;
+    for (i, v) in ramp.iter_mut().enumerate() {
+        *v = (i as f64 / last * max).round() as u16;
+    }
+}
+
+/// Rounding thresholds of the dither phases, ordered so that a value
+/// between two levels alternates between them as evenly as possible
+const DITHER_THRESHOLDS: [f64; 4] = [0.125, 0.625, 0.375, 0.875];
+
+/// Number of phases a dither cycles through
+pub const DITHER_PHASES: usize = 4;
+
+/// Quantize a ramp to the bit depth of a panel, rounding each entry up
+/// or down depending on the phase
+///
+/// An 8-bit panel only shows 256 levels of each channel, and strongly
+/// scaled ramps put many neighbouring entries on the same level, which
+/// shows as bands in gradients. Applied with successive phases, an
+/// entry a quarter of the way between two levels shows the upper one
+/// in one phase of four, which averages out to the level in between.
+/// Neighbouring entries are a phase apart, so that they don't all step
+/// at once.
+pub fn dither(ramp: &mut [u16], bits: u32, phase: usize) {
+    let levels = ((1u32 << bits.max(1).min(16)) - 1) as f64;
+    let max = u16::max_value() as f64;
+    for (i, v) in ramp.iter_mut().enumerate() {
+        let x = *v as f64 / max * levels;
+        let threshold = DITHER_THRESHOLDS[(phase + i) % DITHER_PHASES];
+        let level = if x - x.floor() > threshold { x.ceil() } else { x.floor() };
+        *v = (level / levels * max).round() as u16;
+    }
+}
+
+/// Number of temperatures in the built-in whitepoint table
+const TABLE_LEN: usize = 242;
+
+/// Whitepoints of temperatures at regular steps
+///
+/// The built-in table can be replaced with `set_whitepoints`, e.g. by
+/// one measured for a display. Such a table is read from a file with
+/// a temperature and the red, green and blue factors on each line, at
+/// the same number of Kelvin apart:
+///
+/// ```text
+/// # K    red  green  blue
+/// 1000   1.0  0.18   0.0
+/// 1500   1.0  0.42   0.05
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Whitepoints {
+    start: i32,
+    step: i32,
+    points: Vec<[f64; 3]>,
+}
+
+lazy_static! {
+    static ref WHITEPOINTS: RwLock<Arc<Whitepoints>> = RwLock::new(Arc::new(Whitepoints::builtin()));
+}
+
+/// Changes every time the whitepoints are replaced, invalidating the
+/// ramps computed with the old ones
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies the whitepoints in use, to tell whether ramps computed
+/// earlier are still current
+pub fn whitepoints_generation() -> usize {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Use another whitepoint table from now on
+pub fn set_whitepoints(whitepoints: Whitepoints) {
+    *WHITEPOINTS.write().unwrap() = Arc::new(whitepoints);
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+impl Whitepoints {
+    /// The table provided by Ingo Thies
+    pub fn builtin() -> Whitepoints {
+        Whitepoints {
+            start: MIN_TEMP,
+            step: 100,
+            points: BLACKBODY_COLOR.chunks(3).map(|c| [c[0], c[1], c[2]]).collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Whitepoints> {
+        let mut contents = String::new();
+        File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| RedshiftError::io(path.display(), e))?;
+        contents.parse().map_err(|e| match e {
+            RedshiftError::MalformedConfig(msg) =>
+                RedshiftError::MalformedConfig(format!("{}: {}", path.display(), msg)),
+            e => e,
+        })
+    }
+
+    /// The whitepoint of a temperature, interpolated from the table
+    ///
+    /// A Catmull-Rom spline through the neighbouring entries has no
+    /// kinks at the entries, unlike straight lines between them, so
+    /// that the hue doesn't change in visible steps every 100K of a
+    /// slow fade. Temperatures beyond the table get its ends.
+    fn white_point(&self, temp: i32) -> [f64; 3] {
+        let end = self.start + self.step * (self.points.len() as i32 - 1);
+        let temp = temp.max(MIN_TEMP).min(MAX_TEMP).max(self.start).min(end);
+        let index = ((temp - self.start) / self.step) as isize;
+        let t = ((temp - self.start) % self.step) as f64 / self.step as f64;
+
+        // The spline needs an entry on either side, repeat the ends
+        let last = self.points.len() as isize - 1;
+        let entry = |i: isize| &self.points[i.max(0).min(last) as usize];
+        let (p0, p1, p2, p3) = (entry(index - 1), entry(index), entry(index + 1), entry(index + 2));
+
+        let mut white = [0.0; 3];
+        for c in 0..3 {
+            let v = 0.5 * (2.0 * p1[c] +
+                           (p2[c] - p0[c]) * t +
+                           (2.0 * p0[c] - 5.0 * p1[c] + 4.0 * p2[c] - p3[c]) * t * t +
+                           (3.0 * p1[c] - p0[c] - 3.0 * p2[c] + p3[c]) * t * t * t);
+            // The spline may overshoot the table slightly
+            white[c] = v.max(0.0).min(1.0);
+        }
+        white
+    }
+}
+
+impl FromStr for Whitepoints {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<Whitepoints> {
+        let malformed = |n: usize, msg: &str| Err(RedshiftError::MalformedConfig(
+            format!("line {}: {}", n + 1, msg)));
+        let mut temps = vec![];
+        let mut points = vec![];
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 4 {
+                return malformed(n, "expected a temperature and three factors");
+            }
+            let temp = match fields[0].trim_end_matches('K').parse::<i32>() {
+                Ok(t) => t,
+                Err(_) => return malformed(n, "invalid temperature"),
+            };
+            let mut point = [0.0; 3];
+            for c in 0..3 {
+                match fields[c + 1].parse::<f64>() {
+                    Ok(v) if (0.0..=1.0).contains(&v) => point[c] = v,
+                    _ => return malformed(n, "factors must be between 0 and 1"),
+                }
+            }
+            if temps.len() >= 2 && temp - temps[temps.len() - 1] != temps[1] - temps[0] {
+                return malformed(n, "temperatures must be the same number of Kelvin apart");
+            }
+            if temps.last().map_or(false, |&last| temp <= last) {
+                return malformed(n, "temperatures must be ascending");
+            }
+            temps.push(temp);
+            points.push(point);
+        }
+        if temps.len() < 2 {
+            return Err(RedshiftError::MalformedConfig(
+                "whitepoints: at least two temperatures are needed".to_owned()));
+        }
+        Ok(Whitepoints {
+            start: temps[0],
+            step: temps[1] - temps[0],
+            points: points,
+        })
+    }
+}
+
+/// The whitepoint of a temperature, from the whitepoints in use
+pub fn white_point(temp: i32) -> [f64; 3] {
+    WHITEPOINTS.read().unwrap().white_point(temp)
 }
 
 /* Whitepoint values for temperatures at 100K intervals.
    These will be interpolated for the actual temperature.
    This table was provided by Ingo Thies, 2013. See
    the file README-colorramp for more information. */
-const BLACKBODY_COLOR: [f64; 726] = [
+const BLACKBODY_COLOR: [f64; TABLE_LEN * 3] = [
 	1.00000000,  0.18172716,  0.00000000, /* 1000K */
 	1.00000000,  0.25503671,  0.00000000, /* 1100K */
 	1.00000000,  0.30942099,  0.00000000, /* 1200K */
@@ -288,3 +582,369 @@ const BLACKBODY_COLOR: [f64; 726] = [
 	0.62774186,  0.75306977,  1.00000000, /* 25000K */
 	0.62740336,  0.75282962,  1.00000000  /* 25100K */
 ];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use transition::ColorSetting;
+
+    fn ramps(temp: i32) -> [Vec<u16>; 3] {
+        ramps_for(&ColorSetting { temp: temp, ..ColorSetting::neutral() })
+    }
+
+    fn ramps_for(setting: &ColorSetting) -> [Vec<u16>; 3] {
+        let mut ramps = [vec![0; 256], vec![0; 256], vec![0; 256]];
+        for ramp in ramps.iter_mut() {
+            identity(ramp);
+        }
+        {
+            let (r, rest) = ramps.split_at_mut(1);
+            let (g, b) = rest.split_at_mut(1);
+            fill(&mut r[0], &mut g[0], &mut b[0], setting, 256);
+        }
+        ramps
+    }
+
+    #[test]
+    fn whole_table() {
+        for temp in (MIN_TEMP..MAX_TEMP + 1).step_by(50) {
+            ramps(temp);
+        }
+        // The warmest leaves no blue, the coolest is more blue than red
+        let warm = ramps(MIN_TEMP);
+        assert_eq!(warm[2][255], 0);
+        let cool = ramps(MAX_TEMP);
+        assert!(cool[0][255] < cool[2][255]);
+
+        assert_eq!(ramps(MIN_TEMP - 500), warm);
+        assert_eq!(ramps(MAX_TEMP + 500), cool);
+    }
+
+    #[test]
+    fn smooth_white_point() {
+        // Passes through the entries of the table
+        assert_eq!(white_point(6500), [1.0, 1.0, 1.0]);
+        assert_eq!(white_point(1000)[..], BLACKBODY_COLOR[0..3]);
+
+        // Without kinks: the slope changes little from one Kelvin to
+        // the next, also across the entries
+        let mut last = white_point(2000);
+        let mut last_slope: Option<f64> = None;
+        for temp in 2001..3000 {
+            let white = white_point(temp);
+            let slope = white[1] - last[1];
+            if let Some(s) = last_slope {
+                assert!((slope - s).abs() < 1e-6, "kink at {}K", temp);
+            }
+            last_slope = Some(slope);
+            last = white;
+        }
+    }
+
+    #[test]
+    fn brightness() {
+        let full = ramps(6500);
+        let dimmed = ramps_for(&ColorSetting { brightness: 0.5, ..ColorSetting::neutral() });
+        for c in 0..3 {
+            assert_eq!(full[c][255], 65535);
+            assert_eq!(dimmed[c][255], 65535 / 2);
+            assert_eq!(dimmed[c][0], 0);
+        }
+    }
+
+    #[test]
+    fn channel_gamma() {
+        let full = ramps(6500);
+        let red = ramps_for(&ColorSetting { gamma: [2.0, 1.0, 1.0], ..ColorSetting::neutral() });
+        assert!(red[0][64] > full[0][64]);
+        assert_eq!(red[1], full[1]);
+        assert_eq!(red[2], full[2]);
+    }
+
+    #[test]
+    fn contrast() {
+        let full = ramps(6500);
+        let reduced = ramps_for(&ColorSetting { contrast: 0.5, ..ColorSetting::neutral() });
+        for c in 0..3 {
+            assert!(reduced[c][0] > 16000 && reduced[c][255] < 49500);
+            // Pivoting around mid-gray
+            let expected = (full[c][128] as i32 - 32768) / 2 + 32768;
+            assert!((reduced[c][128] as i32 - expected).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn inverted() {
+        let full = ramps(3500);
+        let inverted = ramps_for(&ColorSetting {
+            temp: 3500, brightness: 0.5, invert: true, ..ColorSetting::neutral()
+        });
+        for c in 0..3 {
+            // Black gets the dimmed and shifted white, and vice versa
+            assert!((inverted[c][0] as i32 - full[c][255] as i32 / 2).abs() < 256);
+            assert!(inverted[c][255] < inverted[c][128]);
+        }
+    }
+
+    #[test]
+    fn fast_path() {
+        // Scaling alone gives the same ramps as the full computation,
+        // which a gamma of almost 1.0 goes through
+        let fast = ramps(4500);
+        let full = ramps_for(&ColorSetting {
+            temp: 4500, gamma: [1.0 + 1e-12; 3], ..ColorSetting::neutral()
+        });
+        for c in 0..3 {
+            for (a, b) in fast[c].iter().zip(full[c].iter()) {
+                assert!((*a as i32 - *b as i32).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn cache() {
+        let mut cache = Cache::new(2);
+        let warm = ColorSetting { temp: 3500, ..ColorSetting::neutral() };
+        let first = cache.ramps(&warm, 256) as *const Ramps;
+        assert_eq!(cache.ramps(&warm, 256).0, ramps(3500)[0]);
+        assert_eq!(cache.ramps(&warm, 256) as *const Ramps, first);
+
+        // Another size is another entry
+        assert_eq!(cache.ramps(&warm, 1024).0.len(), 1024);
+        assert_eq!(cache.entries.len(), 2);
+        // The least recently used goes first
+        cache.ramps(&warm, 256);
+        cache.ramps(&ColorSetting::neutral(), 256);
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.entries.iter().all(|e| e.1 == 256));
+    }
+
+    #[test]
+    fn ramp_sizes() {
+        let warm = ColorSetting { temp: 3500, ..ColorSetting::neutral() };
+        let mut cache = Cache::new(1);
+        for &size in &[1, 2, 17, 256, 1023, 1024, 2048, 4096] {
+            let mut ramp = vec![0; size];
+            identity(&mut ramp);
+            assert_eq!(ramp[0], 0);
+            if size > 1 {
+                assert_eq!(ramp[size - 1], 65535);
+                assert!(ramp.windows(2).all(|w| w[0] < w[1]));
+            }
+
+            // Neutral leaves the identity, up to rounding
+            let neutral = cache.ramps(&ColorSetting::neutral(), size).clone();
+            for (a, b) in neutral.0.iter().zip(ramp.iter()) {
+                assert!((*a as i32 - *b as i32).abs() <= 1);
+            }
+            let (r, g, b) = cache.ramps(&warm, size).clone();
+            assert_eq!((r.len(), g.len(), b.len()), (size, size, size));
+            assert!(r[size - 1] >= g[size - 1] && g[size - 1] >= b[size - 1]);
+        }
+
+        // A size beyond the ramps given leaves none out
+        let (mut r, mut g, mut b) = (vec![65535; 4], vec![65535; 4], vec![65535; 4]);
+        fill(&mut r, &mut g, &mut b, &warm, 256);
+        assert!(b.iter().all(|&v| v < 65535));
+    }
+
+    #[test]
+    fn custom_whitepoints() {
+        let table: Whitepoints = "
+# K    red  green  blue
+1000   1.0  0.2    0.0
+3000   1.0  0.6    0.4K
+".replace("0.4K", "0.4").parse().unwrap();
+        assert_eq!(table.white_point(1000), [1.0, 0.2, 0.0]);
+        assert_eq!(table.white_point(3000), [1.0, 0.6, 0.4]);
+        // Beyond the table
+        assert_eq!(table.white_point(6500), [1.0, 0.6, 0.4]);
+        let middle = table.white_point(2000);
+        assert!(middle[1] > 0.2 && middle[1] < 0.6);
+
+        assert_eq!(Whitepoints::builtin().white_point(4321), white_point(4321));
+        assert!("1000 1 1 1".parse::<Whitepoints>().is_err());
+        assert!("1000 1 1 1\n2000 1 1".parse::<Whitepoints>().is_err());
+        assert!("1000 1 1 1\n2000 1 1 2".parse::<Whitepoints>().is_err());
+        assert!("2000 1 1 1\n1000 1 1 1".parse::<Whitepoints>().is_err());
+        assert!("1000 1 1 1\n2000 1 1 1\n2500 1 1 1".parse::<Whitepoints>().is_err());
+    }
+
+    #[test]
+    fn matrix() {
+        let plain = ramps(5000);
+        // Only the diagonal applies
+        let tinted = ramps_for(&ColorSetting {
+            temp: 5000,
+            matrix: [[1.0, 0.3, 0.0], [0.2, 0.5, 0.0], [0.0, 0.0, 0.8]],
+            ..ColorSetting::neutral()
+        });
+        assert_eq!(tinted[0], plain[0]);
+        for (c, factor) in [(1, 0.5), (2, 0.8)].iter() {
+            let expected = (plain[*c][255] as f64 * factor) as i32;
+            assert!((tinted[*c][255] as i32 - expected).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn night_vision() {
+        let red = ramps_for(&ColorSetting::night_vision(0.5));
+        assert!(red[1].iter().chain(red[2].iter()).all(|&v| v == 0));
+        assert!((red[0][255] as i32 - 65535 / 2).abs() <= 1);
+    }
+
+    #[test]
+    fn dithering() {
+        let mut ramp = vec![0; 256];
+        identity(&mut ramp);
+        fill_one(&mut ramp, 0.3);
+        let mut sums = vec![0.0; ramp.len()];
+        for phase in 0..DITHER_PHASES {
+            let mut dithered = ramp.clone();
+            dither(&mut dithered, 8, phase);
+            for (i, v) in dithered.iter().enumerate() {
+                // Only levels the panel can show
+                assert_eq!(*v % 257, 0);
+                sums[i] += *v as f64 / DITHER_PHASES as f64;
+            }
+        }
+        // On average within an eighth of a level
+        for (sum, v) in sums.iter().zip(ramp.iter()) {
+            assert!((sum - *v as f64).abs() <= 257.0 / 8.0 + 1.0);
+        }
+    }
+
+    fn fill_one(ramp: &mut [u16], scale: f64) {
+        for v in ramp.iter_mut() {
+            *v = (*v as f64 * scale) as u16;
+        }
+    }
+
+    /// Samples of every 17th entry of the ramps of C Redshift 1.12 for
+    /// temperature, brightness and gamma, starting from the ramps its
+    /// RandR method builds, i/size * 65536
+    const C_REFERENCE: [(i32, f64, [f64; 3], [[u16; 16]; 3]); 10] = [
+        (1000, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 790, 1581, 2372, 3163, 3954, 4745, 5536, 6327, 7117, 7908, 8699, 9490, 10281, 11072, 11863],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+]),
+        (2000, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 2365, 4731, 7097, 9463, 11828, 14194, 16560, 18926, 21291, 23657, 26023, 28389, 30754, 33120, 35486],
+            [0, 377, 755, 1133, 1511, 1888, 2266, 2644, 3022, 3399, 3777, 4155, 4533, 4910, 5288, 5666],
+]),
+        (3500, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 3394, 6788, 10182, 13576, 16970, 20364, 23758, 27152, 30546, 33940, 37334, 40728, 44122, 47516, 50910],
+            [0, 2378, 4756, 7134, 9512, 11890, 14268, 16646, 19024, 21402, 23780, 26158, 28536, 30914, 33292, 35670],
+]),
+        (4500, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 3780, 7560, 11340, 15120, 18900, 22681, 26461, 30241, 34021, 37801, 41581, 45362, 49142, 52922, 56702],
+            [0, 3206, 6413, 9620, 12827, 16034, 19241, 22448, 25655, 28862, 32069, 35276, 38483, 41690, 44897, 48104],
+]),
+        (5500, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 4084, 8169, 12253, 16338, 20422, 24507, 28591, 32676, 36760, 40845, 44929, 49014, 53098, 57183, 61267],
+            [0, 3835, 7670, 11506, 15341, 19177, 23012, 26848, 30683, 34518, 38354, 42189, 46025, 49860, 53696, 57531],
+]),
+        (6500, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+]),
+        (10000, 1.0, [1.0, 1.0, 1.0], [
+            [0, 3437, 6875, 10312, 13750, 17187, 20625, 24063, 27500, 30938, 34375, 37813, 41251, 44688, 48126, 51563],
+            [0, 3764, 7528, 11292, 15056, 18820, 22584, 26348, 30112, 33876, 37640, 41405, 45169, 48933, 52697, 56461],
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+]),
+        (3500, 0.75, [1.0, 1.0, 1.0], [
+            [0, 3264, 6528, 9792, 13056, 16320, 19584, 22848, 26112, 29376, 32640, 35904, 39168, 42432, 45696, 48960],
+            [0, 2545, 5091, 7636, 10182, 12727, 15273, 17818, 20364, 22909, 25455, 28000, 30546, 33091, 35637, 38182],
+            [0, 1783, 3567, 5350, 7134, 8917, 10701, 12484, 14268, 16051, 17835, 19618, 21402, 23185, 24969, 26752],
+]),
+        (4000, 0.875, [0.75, 0.875, 1.25], [
+            [0, 1474, 3716, 6381, 9365, 12610, 16080, 19749, 23598, 27611, 31776, 36082, 40520, 45084, 49766, 54562],
+            [0, 2045, 4516, 7179, 9974, 12871, 15853, 18907, 22024, 25198, 28422, 31693, 35007, 38360, 41750, 45176],
+            [0, 4755, 8279, 11452, 14416, 17233, 19939, 22556, 25100, 27580, 30005, 32382, 34717, 37013, 39273, 41502],
+]),
+        (6500, 1.0, [2.0, 1.0, 0.5], [
+            [0, 16888, 23883, 29251, 33776, 37763, 41367, 44682, 47767, 50664, 53405, 56011, 58502, 60891, 63190, 65407],
+            [0, 4352, 8704, 13056, 17408, 21760, 26112, 30464, 34816, 39168, 43520, 47872, 52224, 56576, 60928, 65280],
+            [0, 289, 1156, 2601, 4624, 7225, 10404, 14161, 18496, 23409, 28900, 34969, 41616, 48841, 56644, 65025],
+]),
+    ];
+
+    #[test]
+    fn c_reference() {
+        // At multiples of 100K the whitepoint is the table entry, which
+        // C Redshift interpolated linearly in between
+        for &(temp, brightness, gamma, ref expected) in C_REFERENCE.iter() {
+            let initial: Vec<u16> = (0..256).map(|i| (i as f64 / 256.0 * 65536.0) as u16).collect();
+            let (mut r, mut g, mut b) = (initial.clone(), initial.clone(), initial);
+            let setting = ColorSetting {
+                temp: temp, brightness: brightness, gamma: gamma, ..ColorSetting::neutral()
+            };
+            fill(&mut r, &mut g, &mut b, &setting, 256);
+            for (c, ramp) in [r, g, b].iter().enumerate() {
+                for (n, &v) in expected[c].iter().enumerate() {
+                    let actual = ramp[n * 17];
+                    assert!((actual as i32 - v as i32).abs() <= 1,
+                            "{}K channel {} entry {}: {} instead of {}", temp, c, n * 17, actual, v);
+                }
+            }
+        }
+    }
+
+    /// Samples of every 17th entry of C Redshift 1.12's colorramp_fill
+    /// applied to the identity `ramps` starts from, with 256 entries
+    const C_IDENTITY_REFERENCE: [(i32, f64, [f64; 3], [[u16; 16]; 3]); 3] = [
+        (3500, 1.0, [1.0, 1.0, 1.0], [
+            [0, 4369, 8738, 13107, 17476, 21845, 26214, 30583, 34952, 39321, 43690, 48059, 52428, 56797, 61166, 65535],
+            [0, 3407, 6814, 10221, 13629, 17036, 20443, 23850, 27258, 30665, 34072, 37480, 40887, 44294, 47701, 51109],
+            [0, 2387, 4774, 7161, 9549, 11936, 14323, 16711, 19098, 21485, 23873, 26260, 28647, 31035, 33422, 35809],
+]),
+        (4000, 0.875, [0.75, 0.875, 1.25], [
+            [0, 1482, 3735, 6414, 9413, 12676, 16164, 19852, 23721, 27755, 31941, 36270, 40731, 45319, 50026, 54846],
+            [0, 2054, 4537, 7211, 10018, 12928, 15924, 18991, 22122, 25310, 28549, 31834, 35163, 38531, 41937, 45377],
+            [0, 4770, 8305, 11488, 14461, 17287, 20002, 22627, 25178, 27666, 30099, 32484, 34825, 37128, 39396, 41632],
+]),
+        (6500, 1.0, [2.0, 1.0, 0.5], [
+            [0, 16921, 23930, 29308, 33842, 37836, 41448, 44769, 47860, 50763, 53509, 56121, 58616, 61010, 63313, 65535],
+            [0, 4369, 8738, 13107, 17476, 21845, 26214, 30583, 34952, 39321, 43690, 48059, 52428, 56797, 61166, 65535],
+            [0, 291, 1165, 2621, 4660, 7281, 10485, 14271, 18640, 23592, 29126, 35242, 41941, 49223, 57087, 65534],
+]),
+    ];
+
+    #[test]
+    fn c_reference_ramps() {
+        for &(temp, brightness, gamma, ref expected) in C_IDENTITY_REFERENCE.iter() {
+            let setting = ColorSetting {
+                temp: temp, brightness: brightness, gamma: gamma, ..ColorSetting::neutral()
+            };
+            let (r, g, b) = super::ramps(&setting, 256);
+            for (c, ramp) in [r, g, b].iter().enumerate() {
+                for (n, &v) in expected[c].iter().enumerate() {
+                    let actual = ramp[n * 17];
+                    assert!((actual as i32 - v as i32).abs() <= 1,
+                            "{}K channel {} entry {}: {} instead of {}", temp, c, n * 17, actual, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pure_ramps() {
+        let setting = ColorSetting { temp: 4200, brightness: 0.8, ..ColorSetting::neutral() };
+        let (r, g, b) = super::ramps(&setting, 1024);
+        assert_eq!((r.clone(), g.clone(), b.clone()), super::ramps(&setting, 1024));
+        let mut filled = [vec![0; 1024], vec![0; 1024], vec![0; 1024]];
+        for ramp in filled.iter_mut() {
+            identity(ramp);
+        }
+        let [ref mut fr, ref mut fg, ref mut fb] = filled;
+        fill(fr, fg, fb, &setting, 1024);
+        assert_eq!((&r, &g, &b), (&*fr, &*fg, &*fb));
+    }
+}
@@ -0,0 +1,404 @@
+//! Configuration file
+//!
+//! The configuration file uses the same INI-like format as the
+//! original Redshift: `[section]` headers followed by `key=value`
+//! pairs. Lines starting with `;` or `#` are comments.
+//!
+//! Global settings live in the `[redshift]` section. Named profiles
+//! are given as `[profile:NAME]` sections, each of which can override
+//! the temperatures, brightness and gamma of the global settings.
+//! Settings of a single output are given as `[output:NAME]` sections.
+//!
+//! Other files are read in with `include=PATH` lines, anywhere in the
+//! file, relative to the including file:
+//!
+//! ```text
+//! include=shared.conf
+//!
+//! [redshift]
+//! temp-night=3000
+//! ```
+//!
+//! After the file itself, the files ending in `.conf` of the directory
+//! named like it with `.d` appended are read in name order, such as
+//! `~/.config/redshift-rs/redshift.conf.d/50-laptop.conf`. Everything
+//! is merged in the order it is read: a section given in several files
+//! is one section, and of a key given more than once the last value
+//! wins. So the lines after an `include` override those of the included
+//! file, and the `.d` directory overrides them all, which keeps
+//! settings of one machine apart from a base shared between machines.
+//!
+//! The settings of the `[redshift]` section can also be given in the
+//! environment, named in upper case with `REDSHIFT_RS_` in front and
+//! underscores for dashes, such as `REDSHIFT_RS_TEMP_DAY=5500`.
+//! `REDSHIFT_RS_METHOD` stands for `adjustment-method`. They go over
+//! the files and the profiles, but not over the command line.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use paths;
+use super::{Result, RedshiftError};
+
+const PROFILE_PREFIX: &'static str = "profile:";
+const OUTPUT_PREFIX: &'static str = "output:";
+
+const INCLUDE: &'static str = "include";
+const ENV_PREFIX: &'static str = "REDSHIFT_RS_";
+/// Name of the section of the settings from the environment, in errors
+const ENV_SECTION: &'static str = "environment";
+/// How deep includes can be nested, to catch loops
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A `[section]` with its key-value pairs in file order
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<(String, String)>,
+}
+
+impl Section {
+    fn new(name: &str) -> Section {
+        Section {
+            name: name.to_owned(),
+            entries: vec![],
+        }
+    }
+
+    /// The settings given in the environment
+    pub fn from_env() -> Section {
+        Section::from_vars(env::vars())
+    }
+
+    fn from_vars<I: Iterator<Item=(String, String)>>(vars: I) -> Section {
+        let mut section = Section::new(ENV_SECTION);
+        for (name, value) in vars {
+            if !name.starts_with(ENV_PREFIX) || name.len() == ENV_PREFIX.len() {
+                continue;
+            }
+            let key = name[ENV_PREFIX.len()..].to_lowercase().replace('_', "-");
+            let key = if key == "method" { "adjustment-method".to_owned() } else { key };
+            section.entries.push((key, value.trim().to_owned()));
+        }
+        section
+    }
+
+    /// Look up the value of a key
+    ///
+    /// If a key is given multiple times, the last value wins.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter()
+            .rev()
+            .find(|e| e.0 == key)
+            .map(|e| &e.1[..])
+    }
+
+    /// Look up and parse the value of a key
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key) {
+            Some(v) => v.parse().map(Some).or_else(|_| {
+                malformed(format!("[{}] {}: invalid value '{}'", self.name, key, v))
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A parsed configuration file
+pub struct Config {
+    pub sections: Vec<Section>,
+}
+
+#[inline]
+fn malformed<T>(msg: String) -> Result<T> {
+    Err(RedshiftError::MalformedConfig(msg))
+}
+
+impl Config {
+    pub fn empty() -> Config {
+        Config { sections: vec![] }
+    }
+
+    /// Load the configuration from `path`, or from the default
+    /// location if no path is given.
+    ///
+    /// A missing file at the default location is not an error, an
+    /// explicitly requested file must exist.
+    pub fn load(path: Option<&str>) -> Result<Config> {
+        match path {
+            Some(p) => Config::from_file(Path::new(p)),
+            None => match default_path() {
+                Some(ref p) if p.is_file() => Config::from_file(p),
+                _ => Ok(Config::empty()),
+            }
+        }
+    }
+
+    /// Load the file, what it includes, and the files of its `.d`
+    /// directory
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let mut config = Config::empty();
+        config.read_file(path, 0)?;
+        let mut dir = path.as_os_str().to_owned();
+        dir.push(".d");
+        let mut files: Vec<PathBuf> = match fs::read_dir(&dir) {
+            Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().map_or(false, |e| e == "conf") && p.is_file())
+                .collect(),
+            Err(_) => vec![],
+        };
+        files.sort();
+        for file in files {
+            config.read_file(&file, 0)?;
+        }
+        Ok(config)
+    }
+
+    fn read_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .or_else(|e| malformed(format!("{}: {}", path.display(), e)))?;
+        self.read(&contents, Some(path), depth)
+    }
+
+    /// Merge the sections of `s`, read from `path` if it is a file,
+    /// into the configuration
+    fn read(&mut self, s: &str, path: Option<&Path>, depth: usize) -> Result<()> {
+        let error = |n: usize, msg: String| -> Result<()> {
+            match path {
+                Some(p) => malformed(format!("{}: line {}: {}", p.display(), n + 1, msg)),
+                None => malformed(format!("line {}: {}", n + 1, msg)),
+            }
+        };
+        let mut current = None;
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') {
+                if !line.ends_with(']') {
+                    return error(n, "unterminated section header".to_owned());
+                }
+                let name = line[1..line.len()-1].trim();
+                // Repeated sections are merged into the first one
+                current = match self.sections.iter().position(|s| s.name == name) {
+                    Some(i) => Some(i),
+                    None => {
+                        self.sections.push(Section::new(name));
+                        Some(self.sections.len() - 1)
+                    }
+                };
+                continue;
+            }
+
+            let eq = match line.find('=') {
+                Some(i) => i,
+                None => return error(n, "expected key=value".to_owned()),
+            };
+            let key = line[..eq].trim().to_owned();
+            let value = line[eq+1..].trim().to_owned();
+
+            if key == INCLUDE {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return error(n, "includes nested too deeply".to_owned());
+                }
+                let dir = path.and_then(Path::parent);
+                self.read_file(&dir.map_or_else(|| PathBuf::from(&value), |d| d.join(&value)), depth + 1)?;
+                continue;
+            }
+
+            match current {
+                Some(i) => self.sections[i].entries.push((key, value)),
+                None => return error(n, format!("'{}' outside of a section", key)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Find a section by name
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Names of all profiles defined in the file
+    pub fn profiles(&self) -> Vec<&str> {
+        self.sections.iter()
+            .filter(|s| s.name.starts_with(PROFILE_PREFIX))
+            .map(|s| &s.name[PROFILE_PREFIX.len()..])
+            .collect()
+    }
+
+    /// The `[output:NAME]` sections, by output name
+    pub fn outputs(&self) -> Vec<(&str, &Section)> {
+        self.sections.iter()
+            .filter(|s| s.name.starts_with(OUTPUT_PREFIX))
+            .map(|s| (&s.name[OUTPUT_PREFIX.len()..], s))
+            .collect()
+    }
+
+    /// Find the section of the named profile
+    pub fn profile(&self, name: &str) -> Result<&Section> {
+        self.section(&format!("{}{}", PROFILE_PREFIX, name))
+            .ok_or_else(|| RedshiftError::ProfileNotFound(name.to_owned()))
+    }
+}
+
+impl FromStr for Config {
+    type Err = RedshiftError;
+
+    /// Parse a configuration, with includes relative to the working
+    /// directory
+    fn from_str(s: &str) -> Result<Config> {
+        let mut config = Config::empty();
+        config.read(s, None, 0)?;
+        Ok(config)
+    }
+}
+
+/// The contents of a configuration file with `key=value` in the
+/// section, in place of the last line of the key, after the last line
+/// of the section, or in a new section at the end
+///
+/// Comments, blank lines and the order of the rest stay as they are.
+pub fn set_value(contents: &str, section: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<String> = contents.lines().map(ToOwned::to_owned).collect();
+    let entry = format!("{}={}", key, value);
+    let mut current: Option<&str> = None;
+    // The last line of the key and the last entry of the section
+    let (mut found, mut last) = (None, None);
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            current = Some(line[1..line.len()-1].trim());
+            if current == Some(section) && last.is_none() {
+                last = Some(i);
+            }
+        } else if current == Some(section) && line.contains('=') && !line.starts_with(';') && !line.starts_with('#') {
+            last = Some(i);
+            if line.split('=').next().map(str::trim) == Some(key) {
+                found = Some(i);
+            }
+        }
+    }
+    match (found, last) {
+        (Some(i), _) => lines[i] = entry,
+        (None, Some(i)) => lines.insert(i + 1, entry),
+        (None, None) => {
+            if lines.last().map_or(false, |l| !l.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", section));
+            lines.push(entry);
+        }
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// The default configuration file location
+///
+/// `$XDG_CONFIG_HOME/redshift-rs/redshift.conf`, falling back to
+/// `~/.config/redshift-rs/redshift.conf`.
+pub fn default_path() -> Option<PathBuf> {
+    paths::config_dir().map(|d| d.join("redshift.conf"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, Section};
+
+    const SAMPLE: &'static str = "
+; Global settings
+[redshift]
+temp-day=5700
+temp-night = 3500
+profile=reading
+
+[profile:reading]
+temp-night=3000
+brightness-night=0.7
+
+# A second profile
+[profile:movie]
+temp-day=6500
+";
+
+    #[test]
+    fn parse_sections() {
+        let config: Config = SAMPLE.parse().unwrap();
+        let r = config.section("redshift").unwrap();
+        assert_eq!(r.get("temp-day"), Some("5700"));
+        assert_eq!(r.parse::<i32>("temp-night").unwrap(), Some(3500));
+        assert_eq!(r.get("brightness-day"), None);
+        assert_eq!(config.profiles(), vec!["reading", "movie"]);
+        assert_eq!(config.profile("reading").unwrap().get("brightness-night"), Some("0.7"));
+        assert!(config.profile("work").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("temp-day=5000".parse::<Config>().is_err());
+        assert!("[redshift\ntemp-day=5000".parse::<Config>().is_err());
+        assert!("[redshift]\ntemp-day".parse::<Config>().is_err());
+
+        let config: Config = "[redshift]\ntemp-day=warm".parse().unwrap();
+        assert!(config.section("redshift").unwrap().parse::<i32>("temp-day").is_err());
+    }
+
+    #[test]
+    fn includes() {
+        use std::env;
+        use std::fs;
+
+        let dir = env::temp_dir().join(format!("redshift-rs-config-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("redshift.conf.d")).unwrap();
+        fs::write(dir.join("base.conf"), "[redshift]\ntemp-day=5700\ntemp-night=3500\n").unwrap();
+        fs::write(dir.join("redshift.conf"), "include=base.conf\n[redshift]\ntemp-night=3000\n").unwrap();
+        fs::write(dir.join("redshift.conf.d/20-b.conf"), "[redshift]\ntemp-day=6000\n").unwrap();
+        fs::write(dir.join("redshift.conf.d/10-a.conf"), "[output:HDMI-1]\ntemp-day=5000\n").unwrap();
+        fs::write(dir.join("loop.conf"), "include=loop.conf\n").unwrap();
+
+        let config = Config::from_file(&dir.join("redshift.conf"));
+        let looping = Config::from_file(&dir.join("loop.conf"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        let r = config.section("redshift").unwrap();
+        assert_eq!(r.get("temp-day"), Some("6000"));
+        assert_eq!(r.get("temp-night"), Some("3000"));
+        assert_eq!(config.outputs().len(), 1);
+        assert!(looping.is_err());
+    }
+
+    #[test]
+    fn environment() {
+        let vars = vec![("REDSHIFT_RS_TEMP_DAY", "5500"), ("REDSHIFT_RS_METHOD", "randr"),
+                        ("REDSHIFT_RS_LOCATION", " 55.7:12.6"), ("REDSHIFT_RS_", "x"), ("HOME", "/root")];
+        let section = Section::from_vars(vars.into_iter().map(|(k, v)| (k.to_owned(), v.to_owned())));
+        assert_eq!(section.get("temp-day"), Some("5500"));
+        assert_eq!(section.get("adjustment-method"), Some("randr"));
+        assert_eq!(section.get("location"), Some("55.7:12.6"));
+        assert_eq!(section.entries.len(), 3);
+    }
+
+    #[test]
+    fn set_values() {
+        use super::set_value;
+
+        let contents = "; Mine\n[redshift]\ntemp-night=3500\n\n[profile:movie]\ntemp-day=6500\n";
+        assert_eq!(set_value(contents, "redshift", "temp-night", "3200"),
+                   "; Mine\n[redshift]\ntemp-night=3200\n\n[profile:movie]\ntemp-day=6500\n");
+        assert_eq!(set_value(contents, "redshift", "brightness-night", "0.8"),
+                   "; Mine\n[redshift]\ntemp-night=3500\nbrightness-night=0.8\n\n[profile:movie]\ntemp-day=6500\n");
+        assert_eq!(set_value("[manual]\nlat=55\n", "redshift", "temp-day", "5000"),
+                   "[manual]\nlat=55\n\n[redshift]\ntemp-day=5000\n");
+        assert_eq!(set_value("", "redshift", "temp-day", "5000"), "[redshift]\ntemp-day=5000\n");
+    }
+}
@@ -0,0 +1,196 @@
+//! Other programs adjusting the colors
+//!
+//! Desktops come with a night mode of their own, such as GNOME's Night
+//! Light and KDE's Night Color. Running one alongside the daemon makes
+//! both set the ramps in turn, and the display flickers between their
+//! settings. At startup the daemon looks for them and warns, or with
+//! `conflicts=disable` in the config file turns them off until it
+//! exits:
+//!
+//! ```text
+//! [redshift]
+//! conflicts=disable
+//! ```
+//!
+//! GNOME keeps the setting in gsettings, which is read and changed with
+//! the `gsettings` tool. The change is made for the user rather than
+//! for the session, and is undone when the daemon exits; after a crash
+//! Night Light stays off until enabled again in the settings.
+//!
+//! KWin instead lets programs inhibit Night Color over D-Bus, for as
+//! long as they stay connected. Its previous state comes back by
+//! itself when the daemon exits, also after a crash. Night Color is
+//! only looked for when the `dbus` feature is compiled in.
+
+use std::process::Command;
+use std::str::FromStr;
+
+use super::{Result, RedshiftError};
+
+const GNOME_SCHEMA: &'static str = "org.gnome.settings-daemon.plugins.color";
+const GNOME_KEY: &'static str = "night-light-enabled";
+
+/// What to do about another night mode that is enabled
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Policy {
+    Warn,
+    Disable,
+}
+
+impl FromStr for Policy {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<Policy> {
+        match s {
+            "warn" => Ok(Policy::Warn),
+            "disable" => Ok(Policy::Disable),
+            _ => Err(RedshiftError::MalformedConfig(
+                format!("conflicts: {} (expected warn or disable)", s))),
+        }
+    }
+}
+
+/// The night modes turned off at startup, turned on again when dropped
+pub struct Disabled {
+    gnome: bool,
+    #[cfg(feature = "dbus")]
+    kde: Option<kde::Inhibition>,
+}
+
+impl Drop for Disabled {
+    fn drop(&mut self) {
+        if self.gnome {
+            match gsettings(&["set", GNOME_SCHEMA, GNOME_KEY, "true"]) {
+                Some(_) => info!("Enabled GNOME Night Light again"),
+                None => warn!("Could not enable GNOME Night Light again"),
+            }
+        }
+    }
+}
+
+/// Run `gsettings`, returning its output if it succeeded
+fn gsettings(args: &[&str]) -> Option<String> {
+    Command::new("gsettings").args(args).output().ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_owned())
+}
+
+fn gnome_night_light() -> bool {
+    gsettings(&["get", GNOME_SCHEMA, GNOME_KEY]).map_or(false, |v| v == "true")
+}
+
+/// The names of the other night modes that are enabled
+pub fn enabled() -> Vec<&'static str> {
+    let mut names = vec![];
+    if gnome_night_light() {
+        names.push("GNOME Night Light");
+    }
+    #[cfg(feature = "dbus")]
+    {
+        if kde::enabled() {
+            names.push("KDE Night Color");
+        }
+    }
+    names
+}
+
+/// Look for other night modes, and warn about or disable them
+pub fn check(policy: Policy) -> Disabled {
+    let mut disabled = Disabled {
+        gnome: false,
+        #[cfg(feature = "dbus")]
+        kde: None,
+    };
+    if gnome_night_light() {
+        match policy {
+            Policy::Warn => warn!("GNOME Night Light is enabled and will fight over the gamma \
+                                   ramps; turn it off, or set conflicts=disable to turn it off \
+                                   while redshift-rs runs"),
+            Policy::Disable => match gsettings(&["set", GNOME_SCHEMA, GNOME_KEY, "false"]) {
+                Some(_) => {
+                    info!("Disabled GNOME Night Light until exit");
+                    disabled.gnome = true;
+                }
+                None => warn!("GNOME Night Light is enabled and could not be disabled"),
+            },
+        }
+    }
+    #[cfg(feature = "dbus")]
+    {
+        disabled.kde = kde::check(policy);
+    }
+    disabled
+}
+
+#[cfg(feature = "dbus")]
+mod kde {
+    use std::time::Duration;
+
+    use dbus::blocking::Connection;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+
+    use super::Policy;
+
+    const KWIN: &'static str = "org.kde.KWin";
+    /// Object path and interface of Night Color, from Plasma 6 and
+    /// before
+    const INTERFACES: [(&'static str, &'static str); 2] = [
+        ("/org/kde/KWin/NightLight", "org.kde.KWin.NightLight"),
+        ("/ColorCorrect", "org.kde.kwin.ColorCorrect"),
+    ];
+    const TIMEOUT_MS: u64 = 1000;
+
+    /// Night Color inhibited, until dropped or disconnected
+    pub struct Inhibition {
+        conn: Connection,
+        path: &'static str,
+        interface: &'static str,
+        cookie: u32,
+    }
+
+    impl Drop for Inhibition {
+        fn drop(&mut self) {
+            let proxy = self.conn.with_proxy(KWIN, self.path, Duration::from_millis(TIMEOUT_MS));
+            match proxy.method_call::<(), _, _, _>(self.interface, "uninhibit", (self.cookie,)) {
+                Ok(()) => info!("Stopped inhibiting KDE Night Color"),
+                Err(e) => warn!("Could not stop inhibiting KDE Night Color: {}", e),
+            }
+        }
+    }
+
+    /// The object path and interface of Night Color, if it is enabled
+    fn find(conn: &Connection) -> Option<(&'static str, &'static str)> {
+        INTERFACES.iter().cloned().find(|&(path, interface)| {
+            let proxy = conn.with_proxy(KWIN, path, Duration::from_millis(TIMEOUT_MS));
+            proxy.get::<bool>(interface, "enabled").unwrap_or(false)
+        })
+    }
+
+    pub fn enabled() -> bool {
+        Connection::new_session().ok().and_then(|conn| find(&conn)).is_some()
+    }
+
+    pub fn check(policy: Policy) -> Option<Inhibition> {
+        let conn = Connection::new_session().ok()?;
+        let (path, interface) = find(&conn)?;
+        if policy == Policy::Warn {
+            warn!("KDE Night Color is enabled and will fight over the gamma ramps; turn it \
+                   off, or set conflicts=disable to inhibit it while redshift-rs runs");
+            return None;
+        }
+        let cookie = {
+            let proxy = conn.with_proxy(KWIN, path, Duration::from_millis(TIMEOUT_MS));
+            proxy.method_call::<(u32,), _, _, _>(interface, "inhibit", ())
+        };
+        match cookie {
+            Ok((cookie,)) => {
+                info!("Inhibiting KDE Night Color until exit");
+                Some(Inhibition { conn: conn, path: path, interface: interface, cookie: cookie })
+            }
+            Err(e) => {
+                warn!("KDE Night Color is enabled and could not be inhibited: {}", e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! D-Bus control interface
+//!
+//! Owns the name `org.redshift_rs` on the session bus and exports the
+//! object `/org/redshift_rs` with an interface of the same name:
+//!
+//! ```text
+//! Enabled             b     read/write  false while toggled off
+//! NightVision         b     read/write  true while showing red only
+//! CurrentTemperature  i     read        Kelvin
+//! Period              s     read        "day", "night", "transition" or "none"
+//! Location            (dd)  read        latitude and longitude
+//!
+//! SetTemperature(i)   hold a temperature, 0 resumes the schedule
+//! HoldTemperature(iu) hold a temperature for the given number of
+//!                     seconds, then resume the schedule
+//! Toggle()            toggle between enabled and disabled
+//! Snooze(u)           disable for the given number of seconds, up to
+//!                     a week
+//! ```
+//!
+//! Changes of the properties are announced with the standard
+//! `org.freedesktop.DBus.Properties.PropertiesChanged` signal.
+//!
+//! The thread serving the bus sleeps until a message arrives or the
+//! status changes, which wakes it up through a pipe.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::thread;
+use std::time::Duration;
+
+use chan;
+use dbus::{Message, MessageType, MethodErr};
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::channel::{BusType, Channel};
+use libc;
+
+use daemon::{Command, Control, MAX_SNOOZE_S};
+use events::Event;
+use status::Status;
+use super::super::{check_temperature, Result, RedshiftError};
+
+const NAME: &'static str = "org.redshift_rs";
+const PATH: &'static str = "/org/redshift_rs";
+const INTERFACE: &'static str = "org.redshift_rs";
+const PROPERTIES: &'static str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE: &'static str = "org.freedesktop.DBus.Introspectable";
+
+const INTROSPECTION: &'static str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.redshift_rs">
+    <property name="Enabled" type="b" access="readwrite"/>
+    <property name="NightVision" type="b" access="readwrite"/>
+    <property name="CurrentTemperature" type="i" access="read"/>
+    <property name="Period" type="s" access="read"/>
+    <property name="Location" type="(dd)" access="read"/>
+    <method name="SetTemperature">
+      <arg name="temperature" type="i" direction="in"/>
+    </method>
+    <method name="HoldTemperature">
+      <arg name="temperature" type="i" direction="in"/>
+      <arg name="seconds" type="u" direction="in"/>
+    </method>
+    <method name="Toggle"/>
+    <method name="Snooze">
+      <arg name="seconds" type="u" direction="in"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface" type="s"/>
+      <arg name="changed_properties" type="a{sv}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>"#;
+
+type Properties = HashMap<String, Variant<Box<RefArg>>>;
+
+/// Connect to the session bus and serve the interface in a new thread
+pub fn start(control: Control) -> Result<()> {
+    let mut channel = Channel::get_private(BusType::Session).map_err(io_error)?;
+    channel.set_watch_enabled(true);
+    let conn = Connection::from(channel);
+    conn.request_name(NAME, false, false, true).map_err(io_error)?;
+    info!("Serving D-Bus control interface as {}", NAME);
+
+    let (wake_rx, wake_tx) = pipe().map_err(|e| RedshiftError::io("a pipe", e))?;
+    let (tx, rx) = chan::async();
+    let updates = control.subscribe();
+    thread::spawn(move || forward(updates, tx, wake_tx));
+    thread::spawn(move || serve(conn, control, rx, wake_rx));
+    Ok(())
+}
+
+/// A pipe, as its reading and its writing end
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/// Pass the status reports on, waking up the thread serving the bus
+/// for each
+///
+/// The pipe is closed when the daemon stops reporting, which ends that
+/// thread too.
+fn forward(updates: chan::Receiver<Event>, tx: chan::Sender<Status>, mut wake: File) {
+    for event in updates.iter() {
+        if let Event::StatusChanged(s) = event {
+            tx.send(s);
+            if wake.write_all(b"!").is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn io_error(e: ::dbus::Error) -> RedshiftError {
+    RedshiftError::Control(format!("D-Bus: {}", e))
+}
+
+fn serve(conn: Connection, control: Control, updates: chan::Receiver<Status>, mut wake: File) {
+    let mut status = match updates.recv() {
+        Some(s) => s,
+        None => return,
+    };
+    loop {
+        // Only what is there already, the waiting is done below
+        if conn.channel().read_write(Some(Duration::from_millis(0))).is_err() {
+            warn!("Lost connection to the D-Bus session bus");
+            return;
+        }
+        let mut busy = false;
+        while let Some(msg) = conn.channel().pop_message() {
+            if msg.msg_type() == MessageType::MethodCall {
+                let reply = handle(&msg, &control, &status).unwrap_or_else(|e| e.to_message(&msg));
+                let _ = conn.channel().send(reply);
+            }
+            busy = true;
+        }
+
+        // Announce what has changed since the last round
+        let mut latest = None;
+        loop {
+            chan_select! {
+                default => break,
+                updates.recv() -> s => match s {
+                    Some(s) => latest = Some(s),
+                    None => return,
+                },
+            }
+        }
+        if let Some(s) = latest {
+            let changed = changed_properties(&status, &s);
+            if !changed.is_empty() {
+                let signal = Message::new_signal(PATH, PROPERTIES, "PropertiesChanged")
+                    .expect("valid signal")
+                    .append3(INTERFACE, changed, Vec::<String>::new());
+                let _ = conn.channel().send(signal);
+                busy = true;
+            }
+            status = s;
+        }
+
+        // Flushing may read further messages, which are handled before
+        // going to sleep
+        if busy {
+            conn.channel().flush();
+            continue;
+        }
+        let mut fds = [
+            libc::pollfd { fd: conn.channel().watch().fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: wake.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                warn!("Stopped serving the D-Bus control interface: {}", e);
+                return;
+            }
+        }
+        if fds[1].revents & libc::POLLIN != 0 {
+            let _ = wake.read(&mut [0; 64]);
+        }
+    }
+}
+
+fn handle(msg: &Message, control: &Control, status: &Status) -> ::std::result::Result<Message, MethodErr> {
+    let interface = msg.interface();
+    let member = msg.member();
+    let interface = interface.as_ref().map_or("", |i| &**i);
+    let member = member.as_ref().map_or("", |m| &**m);
+
+    match (interface, member) {
+        (INTERFACE, "Toggle") => {
+            control.send(Command::SetEnabled(None));
+            Ok(msg.method_return())
+        }
+        (INTERFACE, "Snooze") => {
+            let seconds: u32 = msg.read1()?;
+            if seconds as u64 > MAX_SNOOZE_S {
+                return Err(MethodErr::invalid_arg(&format!("{}s is longer than a week", seconds)));
+            }
+            control.send(Command::Snooze(Duration::from_secs(seconds as u64)));
+            Ok(msg.method_return())
+        }
+        (INTERFACE, "SetTemperature") => {
+            let temp: i32 = msg.read1()?;
+            if temp == 0 {
+                control.send(Command::SetTemperature(None));
+            } else {
+                check_temperature(temp).map_err(|e| MethodErr::invalid_arg(&e.to_string()))?;
+                control.send(Command::SetTemperature(Some(temp)));
+            }
+            Ok(msg.method_return())
+        }
+        (INTERFACE, "HoldTemperature") => {
+            let (temp, seconds): (i32, u32) = msg.read2()?;
+            check_temperature(temp).map_err(|e| MethodErr::invalid_arg(&e.to_string()))?;
+            control.send(Command::HoldTemperature(temp, Duration::from_secs(seconds as u64)));
+            Ok(msg.method_return())
+        }
+        (PROPERTIES, "Get") => {
+            let (iface, name): (&str, &str) = msg.read2()?;
+            check_interface(iface)?;
+            properties(status).remove(name)
+                .map(|v| msg.method_return().append1(v))
+                .ok_or_else(|| MethodErr::no_property(name))
+        }
+        (PROPERTIES, "GetAll") => {
+            let iface: &str = msg.read1()?;
+            check_interface(iface)?;
+            Ok(msg.method_return().append1(properties(status)))
+        }
+        (PROPERTIES, "Set") => {
+            let (iface, name, value): (&str, &str, Variant<bool>) = msg.read3()
+                .map_err(|_| MethodErr::invalid_arg("value"))?;
+            check_interface(iface)?;
+            match name {
+                "Enabled" => {
+                    control.send(Command::SetEnabled(Some(value.0)));
+                    Ok(msg.method_return())
+                }
+                "NightVision" => {
+                    control.send(Command::SetNightVision(Some(value.0)));
+                    Ok(msg.method_return())
+                }
+                "CurrentTemperature" | "Period" | "Location" => Err(MethodErr::ro_property(name)),
+                _ => Err(MethodErr::no_property(name)),
+            }
+        }
+        (INTROSPECTABLE, "Introspect") => Ok(msg.method_return().append1(INTROSPECTION)),
+        _ => Err(MethodErr::no_method(member)),
+    }
+}
+
+fn check_interface(iface: &str) -> ::std::result::Result<(), MethodErr> {
+    if iface == INTERFACE { Ok(()) } else { Err(MethodErr::no_interface(iface)) }
+}
+
+fn properties(status: &Status) -> Properties {
+    let mut props = Properties::new();
+    props.insert("Enabled".to_owned(), Variant(Box::new(status.enabled)));
+    props.insert("NightVision".to_owned(), Variant(Box::new(status.night_vision)));
+    props.insert("CurrentTemperature".to_owned(), Variant(Box::new(status.setting.temp)));
+    props.insert("Period".to_owned(), Variant(Box::new(status.period.name().to_owned())));
+    props.insert("Location".to_owned(),
+                 Variant(Box::new((status.location.lat, status.location.lon))));
+    props
+}
+
+/// The properties whose values differ between two status reports
+fn changed_properties(old: &Status, new: &Status) -> Properties {
+    let mut props = properties(new);
+    if old.enabled == new.enabled {
+        props.remove("Enabled");
+    }
+    if old.night_vision == new.night_vision {
+        props.remove("NightVision");
+    }
+    if old.setting.temp == new.setting.temp {
+        props.remove("CurrentTemperature");
+    }
+    if old.period.name() == new.period.name() {
+        props.remove("Period");
+    }
+    if old.location == new.location {
+        props.remove("Location");
+    }
+    props
+}
@@ -0,0 +1,150 @@
+//! Prometheus metrics
+//!
+//! In a build with the `metrics` feature, a `[metrics]` section in the
+//! config file makes the daemon serve its metrics over HTTP, in the
+//! text format of Prometheus:
+//!
+//! ```text
+//! [metrics]
+//! listen=127.0.0.1:9101
+//! ```
+//!
+//! Any path is answered with the metrics, so `/metrics` works as well:
+//!
+//! ```text
+//! redshift_temperature_kelvin       gauge, the current temperature
+//! redshift_brightness               gauge
+//! redshift_solar_elevation_degrees  gauge
+//! redshift_enabled                  gauge, 1 or 0
+//! redshift_adjustments_total        counter, settings applied
+//! redshift_backend_errors_total     counter, settings that failed
+//! redshift_update_seconds           summary, time taken by an update
+//! ```
+
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use daemon::Control;
+use events::Event;
+use super::super::{Result, RedshiftError};
+
+#[derive(Debug, Default)]
+struct Metrics {
+    temperature: i32,
+    brightness: f64,
+    elevation: f64,
+    enabled: bool,
+    adjustments: u64,
+    backend_errors: u64,
+    update_seconds: f64,
+    updates: u64,
+}
+
+lazy_static! {
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+/// Count an update of the daemon, which took the given time
+pub fn record_update(took: Duration) {
+    let mut metrics = METRICS.lock().unwrap();
+    metrics.update_seconds += took.as_secs() as f64 + took.subsec_nanos() as f64 * 1e-9;
+    metrics.updates += 1;
+}
+
+impl Metrics {
+    fn update(&mut self, event: &Event) {
+        match *event {
+            Event::StatusChanged(ref status) => {
+                self.temperature = status.setting.temp;
+                self.brightness = status.setting.brightness;
+                self.elevation = status.elevation;
+                self.enabled = status.enabled;
+            }
+            Event::TemperatureApplied(_) => self.adjustments += 1,
+            Event::BackendError(_) => self.backend_errors += 1,
+            _ => {}
+        }
+    }
+
+    fn to_text(&self) -> String {
+        let mut s = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = write!(s, "# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value);
+        };
+        metric("redshift_temperature_kelvin", "gauge", "Color temperature applied",
+               self.temperature.to_string());
+        metric("redshift_brightness", "gauge", "Brightness applied", self.brightness.to_string());
+        metric("redshift_solar_elevation_degrees", "gauge", "Elevation of the sun",
+               self.elevation.to_string());
+        metric("redshift_enabled", "gauge", "Whether the adjustments are enabled",
+               (self.enabled as i32).to_string());
+        metric("redshift_adjustments_total", "counter", "Settings applied to the display",
+               self.adjustments.to_string());
+        metric("redshift_backend_errors_total", "counter", "Settings the gamma method failed to apply",
+               self.backend_errors.to_string());
+        let _ = write!(s, "# HELP redshift_update_seconds Time taken by an update\n\
+                           # TYPE redshift_update_seconds summary\n\
+                           redshift_update_seconds_sum {}\nredshift_update_seconds_count {}\n",
+                       self.update_seconds, self.updates);
+        s
+    }
+}
+
+fn serve(mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+    // Read up to the end of the headers, nothing in them matters
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    });
+    let mut line = String::new();
+    while reader.read_line(&mut line).map(|n| n > 0).unwrap_or(false) && line.trim() != "" {
+        line.clear();
+    }
+    let body = METRICS.lock().unwrap().to_text();
+    let _ = write!(stream, "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                            Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   body.len(), body);
+}
+
+/// Collect the metrics and serve them in new threads, one for each
+/// connection, so that a slow scraper doesn't hold up the others
+pub fn start(control: &Control, listen: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .map_err(|e| RedshiftError::io(format_args!("metrics endpoint {}", listen), e))?;
+    info!("Serving metrics on http://{}/metrics", listen);
+
+    let updates = control.subscribe();
+    thread::spawn(move || {
+        for event in updates.iter() {
+            METRICS.lock().unwrap().update(&event);
+        }
+    });
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|s| s.ok()) {
+            thread::spawn(move || serve(stream));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn text_format() {
+        let mut metrics = Metrics::default();
+        metrics.update(&Event::TemperatureApplied(::transition::ColorSetting::neutral()));
+        metrics.update(&Event::BackendError("gone".to_owned()));
+        metrics.update(&Event::TemperatureApplied(::transition::ColorSetting::neutral()));
+        let text = metrics.to_text();
+        assert!(text.contains("\nredshift_adjustments_total 2\n"));
+        assert!(text.contains("# TYPE redshift_backend_errors_total counter\nredshift_backend_errors_total 1\n"));
+        assert!(text.ends_with("redshift_update_seconds_count 0\n"));
+    }
+}
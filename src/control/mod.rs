@@ -0,0 +1,150 @@
+//! Control interfaces
+//!
+//! Ways for other programs, such as desktop applets and scripts, to
+//! control or follow a daemon running in continual mode. Each interface runs in
+//! its own thread and talks to the daemon through a `daemon::Control`
+//! handle.
+//!
+//! An interface that fails to start is reported, but the daemon keeps
+//! running without it.
+
+#[cfg(feature = "dbus-control")]
+mod dbus;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mqtt;
+mod socket;
+mod status_file;
+
+pub use self::mqtt::Options as MqttOptions;
+
+use std::path::Path;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use config::Config;
+use daemon::Control;
+use gamma;
+use instance;
+use preset::Presets;
+use super::{check_temperature, Result, RedshiftError};
+
+/// The running control interfaces, shut down when dropped
+pub struct Interfaces {
+    _socket: Option<socket::Listener>,
+    _status_file: Option<status_file::StatusFile>,
+}
+
+/// Start all the available control interfaces, and MQTT publishing
+/// and the metrics endpoint if configured
+///
+/// The control socket and the status file are kept in `dir`, the
+/// directory of the instance.
+pub fn start(control: &Control, dir: &Path, mqtt: Option<MqttOptions>, metrics: Option<String>) -> Interfaces {
+    #[cfg(feature = "dbus-control")]
+    {
+        if let Err(e) = dbus::start(control.clone()) {
+            warn!("Could not start D-Bus control interface: {}", e);
+        }
+    }
+
+    if let Some(options) = mqtt {
+        mqtt::start(control, options);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        if let Some(listen) = metrics {
+            if let Err(e) = metrics::start(control, &listen) {
+                warn!("Could not serve metrics: {}", e);
+            }
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = metrics;
+
+    let socket = socket::start(control.clone(), dir)
+        .map_err(|e| warn!("Could not start control socket: {}", e))
+        .ok();
+    let status_file = status_file::start(control, dir)
+        .map_err(|e| warn!("Could not keep a status file: {}", e))
+        .ok();
+    Interfaces {
+        _socket: socket,
+        _status_file: status_file,
+    }
+}
+
+/// Count an update of the daemon, which took the given time, in the
+/// metrics
+#[cfg(feature = "metrics")]
+pub fn record_update(took: Duration) {
+    metrics::record_update(took);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub fn record_update(_: Duration) {}
+
+/// Ask a running daemon, if any, to recompute its adjustments
+pub fn request_update(dir: &Path) {
+    if let Err(e) = socket::request(dir, "update") {
+        debug!("No daemon to update: {}", e);
+    }
+}
+
+/// Send the request of the `ctl` subcommand to a running daemon
+///
+/// The daemon is that of the display given, in the config file or on
+/// the command line, or else of the display of the session.
+pub fn ctl(matches: &ArgMatches, sub: &ArgMatches) -> Result<i32> {
+    let config = Config::load(matches.value_of("config"))?;
+    let setting = |name: &str| matches.value_of(name)
+        .or_else(|| config.section("redshift").and_then(|s| s.get(name)))
+        .map(ToOwned::to_owned);
+    let options = gamma::Options {
+        display: setting("display"),
+        wayland_display: setting("wayland-display"),
+        ..Default::default()
+    };
+    let request = match sub.subcommand() {
+        ("status", Some(m)) => format!("status {}", m.value_of("format").unwrap_or("text")),
+        ("set-temp", Some(m)) => match m.value_of("temperature").unwrap() {
+            "reset" => "set-temp reset".to_owned(),
+            temp => {
+                // Presets are resolved here, so that the ones defined
+                // in the config file can be used
+                let mut presets = Presets::builtin();
+                if let Some(section) = config.section("presets") {
+                    presets.extend(section)?;
+                }
+                let temp = check_temperature(presets.resolve(temp)?)?;
+                match m.value_of("until") {
+                    Some(until) => format!("set-temp {} until {}", temp, until),
+                    None => format!("set-temp {}", temp),
+                }
+            }
+        },
+        ("profile", Some(m)) => format!("profile {}", m.value_of("name").unwrap()),
+        ("snooze", Some(m)) => format!("snooze {}", m.value_of("duration").unwrap()),
+        ("night-vision", Some(m)) => match m.value_of("state") {
+            Some(state) => format!("night-vision {}", state),
+            None => "night-vision".to_owned(),
+        },
+        ("grayscale", Some(m)) => match m.value_of("state") {
+            Some(state) => format!("grayscale {}", state),
+            None => "grayscale".to_owned(),
+        },
+        (command, _) => command.to_owned(),
+    };
+
+    let reply = socket::request(&instance::runtime_dir(&options), &request)?;
+    let reply = reply.trim_end();
+    if reply.starts_with(socket::ERROR_PREFIX) {
+        Err(RedshiftError::Control(reply[socket::ERROR_PREFIX.len()..].to_owned()))
+    } else {
+        if reply != "ok" {
+            println!("{}", reply);
+        }
+        Ok(0)
+    }
+}
@@ -0,0 +1,383 @@
+//! MQTT state publishing
+//!
+//! With an `[mqtt]` section in the config file, the daemon connects to
+//! an MQTT broker, so that home automation can follow it and tell it
+//! what to do:
+//!
+//! ```text
+//! [mqtt]
+//! broker=homeassistant.local:1883
+//! topic=redshift-rs          ; prefix of the topics, the default
+//! client-id=redshift-rs      ; the default
+//! username=...
+//! password=...
+//! ```
+//!
+//! The state is published, retained, on every change:
+//!
+//! ```text
+//! redshift-rs/available     "online", or "offline" once disconnected
+//! redshift-rs/period        "day", "night", "transition" or "none"
+//! redshift-rs/temperature   Kelvin
+//! redshift-rs/brightness    e.g. "0.80"
+//! redshift-rs/enabled       "on" or "off"
+//! ```
+//!
+//! Messages on `redshift-rs/command` are requests in the language of
+//! the control socket, such as `disable` or `set-temp 4000`. Replies
+//! are only logged.
+//!
+//! The client speaks just enough of MQTT 3.1.1 for this, without TLS
+//! and at QoS 0, and connects again when the connection is lost. It
+//! pings the broker when neither side had anything to send for half
+//! the keep-alive interval.
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chan;
+use config::Section;
+use daemon::Control;
+use events::Event;
+use status::Status;
+use super::socket;
+use super::super::{Result, RedshiftError};
+
+/// Keep-alive interval announced to the broker, in seconds
+const KEEP_ALIVE_S: u16 = 60;
+
+/// How long to wait before connecting again
+const RECONNECT_S: u64 = 30;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const SUBSCRIBE: u8 = 0x82;
+const PINGREQ: u8 = 0xc0;
+const RETAIN: u8 = 0x01;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Host and port of the broker
+    pub broker: String,
+    /// Prefix of the topics
+    pub topic: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Options {
+    pub fn from_config(section: &Section) -> Result<Options> {
+        let broker = section.get("broker").ok_or_else(|| RedshiftError::MalformedConfig(
+            "[mqtt] broker: missing".to_owned()))?;
+        let broker = if broker.contains(':') { broker.to_owned() } else { format!("{}:1883", broker) };
+        Ok(Options {
+            broker: broker,
+            topic: section.get("topic").unwrap_or("redshift-rs").trim_end_matches('/').to_owned(),
+            client_id: section.get("client-id").unwrap_or("redshift-rs").to_owned(),
+            username: section.get("username").map(ToOwned::to_owned),
+            password: section.get("password").map(ToOwned::to_owned),
+        })
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic, name)
+    }
+}
+
+fn io_error(options: &Options, e: io::Error) -> RedshiftError {
+    RedshiftError::io(format_args!("MQTT broker {}", options.broker), e)
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A packet with the given type and flags and the rest of its contents
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut p = vec![header];
+    // The remaining length, seven bits at a time
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        p.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    p.extend_from_slice(body);
+    p
+}
+
+/// A length-prefixed string
+fn push_str(out: &mut Vec<u8>, s: &str) {
+    out.push((s.len() >> 8) as u8);
+    out.push(s.len() as u8);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn connect_packet(options: &Options) -> Vec<u8> {
+    let will = options.topic("available");
+    // Clean session, with a retained will
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    let mut body = vec![];
+    push_str(&mut body, "MQTT");
+    body.push(4);
+    body.push(flags);
+    body.push((KEEP_ALIVE_S >> 8) as u8);
+    body.push(KEEP_ALIVE_S as u8);
+    push_str(&mut body, &options.client_id);
+    push_str(&mut body, &will);
+    push_str(&mut body, "offline");
+    if let Some(ref username) = options.username {
+        push_str(&mut body, username);
+    }
+    if let Some(ref password) = options.password {
+        push_str(&mut body, password);
+    }
+    packet(CONNECT, &body)
+}
+
+fn publish_packet(topic: &str, payload: &str) -> Vec<u8> {
+    let mut body = vec![];
+    push_str(&mut body, topic);
+    body.extend_from_slice(payload.as_bytes());
+    packet(PUBLISH | RETAIN, &body)
+}
+
+fn subscribe_packet(topic: &str) -> Vec<u8> {
+    let mut body = vec![0, 1];
+    push_str(&mut body, topic);
+    body.push(0);
+    packet(SUBSCRIBE, &body)
+}
+
+/// Take the first complete packet off the buffer, as its header byte
+/// and the rest of its contents
+fn next_packet(buf: &mut Vec<u8>) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut len = 0;
+    let mut shift = 0;
+    let mut i = 1;
+    loop {
+        let byte = match buf.get(i) {
+            Some(&b) => b,
+            None => return Ok(None),
+        };
+        len |= ((byte & 0x7f) as usize) << shift;
+        i += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 21 {
+            return Err(protocol_error("malformed remaining length"));
+        }
+    }
+    if buf.len() < i + len {
+        return Ok(None);
+    }
+    let header = buf[0];
+    let body = buf[i..i + len].to_vec();
+    buf.drain(..i + len);
+    Ok(Some((header, body)))
+}
+
+/// The topic and payload of a published message
+fn parse_publish(header: u8, body: &[u8]) -> Option<(String, String)> {
+    if body.len() < 2 {
+        return None;
+    }
+    let len = ((body[0] as usize) << 8) | body[1] as usize;
+    let topic = body.get(2..2 + len)?;
+    // Messages above QoS 0 carry a packet id after the topic
+    let start = 2 + len + if header & 0x06 != 0 { 2 } else { 0 };
+    let payload = body.get(start..)?;
+    Some((String::from_utf8_lossy(topic).into_owned(),
+          String::from_utf8_lossy(payload).into_owned()))
+}
+
+/// The retained values of the status, by topic name
+fn values(status: &Status) -> Vec<(&'static str, String)> {
+    vec![
+        ("period", status.period.name().to_owned()),
+        ("temperature", status.setting.temp.to_string()),
+        ("brightness", format!("{:.2}", status.setting.brightness)),
+        ("enabled", if status.enabled { "on" } else { "off" }.to_owned()),
+    ]
+}
+
+/// A packet read, as its header byte and the rest of its contents, or
+/// `None` when nothing arrived for the read timeout
+type Received = io::Result<Option<(u8, Vec<u8>)>>;
+
+/// Read packets off the connection and pass them on, with `None` when
+/// nothing arrived for the read timeout, until the connection is shut
+/// down
+fn read_packets(mut stream: TcpStream, tx: chan::Sender<Received>) {
+    let mut buf = vec![];
+    loop {
+        let mut chunk = [0; 1024];
+        match stream.read(&mut chunk) {
+            Ok(0) => return tx.send(Err(io::Error::new(io::ErrorKind::UnexpectedEof, "disconnected"))),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                e.kind() == io::ErrorKind::TimedOut => tx.send(Ok(None)),
+            Err(e) => return tx.send(Err(e)),
+        }
+        loop {
+            match next_packet(&mut buf) {
+                Ok(Some(p)) => tx.send(Ok(Some(p))),
+                Ok(None) => break,
+                Err(e) => return tx.send(Err(e)),
+            }
+        }
+    }
+}
+
+/// Connect to the broker and publish the status in a new thread
+pub fn start(control: &Control, options: Options) {
+    let updates = control.subscribe();
+    let control = control.clone();
+    thread::spawn(move || {
+        loop {
+            match session(&options, &control, &updates) {
+                Ok(()) => return,
+                Err(e) => warn!("MQTT: {}, connecting again in {}s", e, RECONNECT_S),
+            }
+            thread::sleep(Duration::from_secs(RECONNECT_S));
+        }
+    });
+}
+
+/// Serve one connection, until the daemon exits or the connection is
+/// lost
+fn session(options: &Options, control: &Control, updates: &chan::Receiver<Event>) -> Result<()> {
+    let err = |e| io_error(options, e);
+    let mut stream = TcpStream::connect(&options.broker[..]).map_err(err)?;
+    // Quiet for this long, the broker is pinged
+    stream.set_read_timeout(Some(Duration::from_secs(KEEP_ALIVE_S as u64 / 2))).map_err(err)?;
+    let reader = stream.try_clone().map_err(err)?;
+    let (tx, incoming) = chan::async();
+    thread::spawn(move || read_packets(reader, tx));
+    let result = serve(options, control, updates, &mut stream, &incoming);
+    // Which ends the reader too
+    let _ = stream.shutdown(Shutdown::Both);
+    result
+}
+
+fn serve(options: &Options, control: &Control, updates: &chan::Receiver<Event>, stream: &mut TcpStream,
+         incoming: &chan::Receiver<Received>) -> Result<()> {
+    let err = |e| io_error(options, e);
+    stream.write_all(&connect_packet(options)).map_err(err)?;
+
+    let mut connected = false;
+    let mut published: Vec<(&'static str, String)> = vec![];
+    let mut last_sent = Instant::now();
+    let command_topic = options.topic("command");
+    loop {
+        let mut received = None;
+        let mut latest = None;
+        chan_select! {
+            incoming.recv() -> packet => received = match packet {
+                Some(packet) => packet.map_err(err)?,
+                None => return Err(err(io::Error::new(io::ErrorKind::UnexpectedEof, "disconnected"))),
+            },
+            updates.recv() -> event => match event {
+                Some(Event::StatusChanged(s)) => latest = Some(s),
+                Some(_) => {}
+                None => return Ok(()),
+            },
+        }
+        if let Some((header, body)) = received {
+            match header & 0xf0 {
+                CONNACK => {
+                    if body.get(1) != Some(&0) {
+                        return Err(RedshiftError::Control(
+                            format!("MQTT broker {} refused the connection ({:?})",
+                                    options.broker, body.get(1))));
+                    }
+                    info!("Publishing to MQTT broker {} under {}", options.broker, options.topic);
+                    connected = true;
+                    let mut out = subscribe_packet(&command_topic);
+                    out.extend(publish_packet(&options.topic("available"), "online"));
+                    stream.write_all(&out).map_err(err)?;
+                    published.clear();
+                }
+                PUBLISH => if let Some((topic, payload)) = parse_publish(header, &body) {
+                    if topic == command_topic {
+                        debug!("MQTT request: {}", payload.trim());
+                        match socket::handle(payload.trim(), control) {
+                            Ok(reply) => debug!("MQTT reply: {}", reply),
+                            Err(e) => warn!("MQTT request {}: {}", payload.trim(), e),
+                        }
+                    }
+                },
+                // Subscription and ping acknowledgements
+                _ => {}
+            }
+        }
+        if !connected {
+            continue;
+        }
+
+        if published.is_empty() && latest.is_none() {
+            latest = control.status();
+        }
+        let mut out = vec![];
+        if let Some(status) = latest {
+            for (name, value) in values(&status) {
+                if !published.contains(&(name, value.clone())) {
+                    out.extend(publish_packet(&options.topic(name), &value));
+                }
+            }
+            published = values(&status);
+        }
+        if out.is_empty() && last_sent.elapsed() >= Duration::from_secs(KEEP_ALIVE_S as u64 / 2) {
+            out = packet(PINGREQ, &[]);
+        }
+        if !out.is_empty() {
+            stream.write_all(&out).map_err(err)?;
+            last_sent = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packets() {
+        assert_eq!(publish_packet("a/b", "42"), [0x31, 7, 0, 3, b'a', b'/', b'b', b'4', b'2']);
+        // Lengths beyond 127 take more bytes
+        let long = packet(PUBLISH, &[0; 200]);
+        assert_eq!(long[..3], [PUBLISH, 0xc8, 0x01]);
+
+        let mut buf = long.clone();
+        buf.extend(packet(PINGREQ, &[]));
+        buf.push(PUBLISH);
+        assert_eq!(next_packet(&mut buf).unwrap(), Some((PUBLISH, vec![0; 200])));
+        assert_eq!(next_packet(&mut buf).unwrap(), Some((PINGREQ, vec![])));
+        assert_eq!(next_packet(&mut buf).unwrap(), None);
+        assert_eq!(buf, [PUBLISH]);
+
+        let publish = publish_packet("redshift-rs/command", "disable");
+        let mut buf = publish.clone();
+        let (header, body) = next_packet(&mut buf).unwrap().unwrap();
+        assert_eq!(parse_publish(header, &body),
+                   Some(("redshift-rs/command".to_owned(), "disable".to_owned())));
+    }
+}
@@ -0,0 +1,237 @@
+//! Control socket
+//!
+//! A Unix socket at `$XDG_RUNTIME_DIR/redshift-rs/DISPLAY/control.sock`,
+//! e.g. `redshift-rs/:0/control.sock`, for controlling the daemon of a
+//! display on systems without D-Bus. A client connects,
+//! writes a single request line and reads the reply until the daemon
+//! closes the connection:
+//!
+//! ```text
+//! status [text|json]       the current status, as JSON by default
+//! toggle                   toggle between enabled and disabled
+//! enable | disable
+//! snooze DURATION          disable, and enable again after e.g. 20m, at
+//!                          most a week
+//! set-temp TEMP|reset      hold a temperature, in Kelvin or mireds, or
+//!                          resume the schedule
+//! set-temp TEMP until HH:MM
+//!                          hold a temperature until the next time the
+//!                          clock shows HH:MM, then resume the schedule
+//! night-vision [on|off]    show red only, or toggle it without argument
+//! grayscale [on|off]       turn the colors gray, or toggle it without
+//!                          argument
+//! reload                   read the configuration file again
+//! update                   recompute the adjustments, e.g. after `--adjust`
+//! profile NAME             reload, switching to the named profile
+//! ```
+//!
+//! Other requests are answered with `ok`, or with `error: ` followed
+//! by a message. This makes the socket easy to use from scripts, for
+//! example with `echo toggle | socat - UNIX-CONNECT:...`.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::result;
+use std::thread;
+use std::time::Duration;
+
+use daemon::{Command, Control, MAX_SNOOZE_S};
+use paths;
+use preset;
+use status::Format;
+use time;
+use super::super::{check_temperature, parse_duration, parse_time_of_day, Result, RedshiftError};
+
+pub const ERROR_PREFIX: &'static str = "error: ";
+
+/// Location of the socket in the directory of the instance
+pub fn path(dir: &Path) -> PathBuf {
+    dir.join("control.sock")
+}
+
+fn io_error(path: &PathBuf, e: io::Error) -> RedshiftError {
+    RedshiftError::io(path.display(), e)
+}
+
+/// The bound socket, removed again when dropped
+pub struct Listener {
+    path: PathBuf,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Bind the socket and answer requests in a new thread
+///
+/// A socket left behind by an instance that did not exit cleanly is
+/// replaced, but not one that another instance is still serving.
+pub fn start(control: Control, dir: &Path) -> Result<Listener> {
+    let path = path(dir);
+    if let Some(dir) = path.parent() {
+        paths::ensure_runtime_dir(dir).map_err(|e| io_error(&path, e))?;
+    }
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io_error(&path, io::Error::new(io::ErrorKind::AddrInUse,
+                                                      "in use by another instance")));
+        }
+        fs::remove_file(&path).map_err(|e| io_error(&path, e))?;
+    }
+    let listener = UnixListener::bind(&path).map_err(|e| io_error(&path, e))?;
+    info!("Listening for control requests on {}", path.display());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => serve(stream, &control),
+                Err(e) => warn!("Control socket: {}", e),
+            }
+        }
+    });
+    Ok(Listener { path: path })
+}
+
+fn serve(stream: UnixStream, control: &Control) {
+    // Don't let a client that never finishes its request block others
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+        debug!("Control socket: {}", e);
+        return;
+    }
+    debug!("Control request: {}", line.trim());
+    let reply = handle(line.trim(), control)
+        .unwrap_or_else(|e| format!("{}{}", ERROR_PREFIX, e));
+    let _ = writeln!(&stream, "{}", reply);
+}
+
+/// Answer a request, also those received over MQTT
+pub fn handle(request: &str, control: &Control) -> result::Result<String, String> {
+    let mut words = request.split_whitespace();
+    let command = words.next().unwrap_or("");
+    let arg = words.next();
+    // Only a held temperature takes more, when to resume the schedule
+    let until = match (command, words.next(), words.next()) {
+        (_, None, _) => None,
+        ("set-temp", Some("until"), Some(time)) => Some(time),
+        _ => return Err(format!("too many arguments: {}", request)),
+    };
+    if words.next().is_some() {
+        return Err(format!("too many arguments: {}", request));
+    }
+
+    let ok = Ok("ok".to_owned());
+    match (command, arg) {
+        ("status", format) => {
+            let format = format.unwrap_or("json").parse::<Format>().map_err(|e| e.to_string())?;
+            control.status()
+                .map(|s| s.format(format))
+                .ok_or_else(|| "daemon is not running".to_owned())
+        }
+        ("toggle", None) => {
+            control.send(Command::SetEnabled(None));
+            ok
+        }
+        ("enable", None) | ("disable", None) => {
+            control.send(Command::SetEnabled(Some(command == "enable")));
+            ok
+        }
+        ("snooze", Some(duration)) => {
+            let secs = parse_duration(duration).map_err(|e| e.to_string())?;
+            if secs > MAX_SNOOZE_S as f64 {
+                return Err(format!("snooze: {} is longer than a week", duration));
+            }
+            control.send(Command::Snooze(Duration::from_millis((secs * 1000.0) as u64)));
+            ok
+        }
+        ("set-temp", Some("reset")) if until.is_none() => {
+            control.send(Command::SetTemperature(None));
+            ok
+        }
+        ("set-temp", Some(temp)) => {
+            let temp = preset::parse_temperature(temp)
+                .ok_or_else(|| format!("invalid temperature: {}", temp))
+                .and_then(|t| check_temperature(t).map_err(|e| e.to_string()))?;
+            match until {
+                Some(until) => {
+                    let until = parse_time_of_day(until).map_err(|e| e.to_string())?;
+                    let secs = secs_until(until, &time::now());
+                    control.send(Command::HoldTemperature(temp, Duration::from_secs(secs)));
+                }
+                None => control.send(Command::SetTemperature(Some(temp))),
+            }
+            ok
+        }
+        ("night-vision", on) => {
+            let on = match on {
+                None => None,
+                Some("on") => Some(true),
+                Some("off") => Some(false),
+                Some(other) => return Err(format!("night-vision: {} (expected on or off)", other)),
+            };
+            control.send(Command::SetNightVision(on));
+            ok
+        }
+        ("grayscale", on) => {
+            let on = match on {
+                None => None,
+                Some("on") => Some(true),
+                Some("off") => Some(false),
+                Some(other) => return Err(format!("grayscale: {} (expected on or off)", other)),
+            };
+            control.send(Command::SetGrayscale(on));
+            ok
+        }
+        ("update", None) => {
+            control.send(Command::Update);
+            ok
+        }
+        ("reload", None) => control.reload(None).and(ok),
+        ("profile", Some(name)) => control.reload(Some(name.to_owned())).and(ok),
+        _ => Err(format!("unknown request: {}", request)),
+    }
+}
+
+/// Seconds from the local time `now` until the clock next shows the
+/// given hour and minute
+fn secs_until(time: (u32, u32), now: &time::Tm) -> u64 {
+    const DAY_S: i64 = 24 * 3600;
+    let target = time.0 as i64 * 3600 + time.1 as i64 * 60;
+    let current = now.tm_hour as i64 * 3600 + now.tm_min as i64 * 60 + now.tm_sec as i64;
+    match target - current {
+        secs if secs > 0 => secs as u64,
+        secs => (secs + DAY_S) as u64,
+    }
+}
+
+/// Send a request to a running daemon and return its reply
+pub fn request(dir: &Path, request: &str) -> Result<String> {
+    let path = path(dir);
+    let mut stream = UnixStream::connect(&path)
+        .map_err(|e| RedshiftError::io(format!("cannot connect to {} (is redshift-rs running?)", path.display()), e))?;
+    writeln!(stream, "{}", request).map_err(|e| io_error(&path, e))?;
+    let _ = stream.shutdown(Shutdown::Write);
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).map_err(|e| io_error(&path, e))?;
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn time_until() {
+        let now = time::Tm { tm_hour: 22, tm_min: 15, tm_sec: 30, ..time::empty_tm() };
+        assert_eq!(secs_until((23, 30), &now), 75 * 60 - 30);
+        assert_eq!(secs_until((7, 0), &now), (8 * 60 + 45) * 60 - 30);
+        assert_eq!(secs_until((22, 15), &now), 24 * 3600 - 30);
+    }
+}
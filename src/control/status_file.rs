@@ -0,0 +1,70 @@
+//! Status file
+//!
+//! The current status is kept in `$XDG_RUNTIME_DIR/redshift-rs/DISPLAY/status`,
+//! e.g. `redshift-rs/:0/status`, for status bars and scripts that would
+//! rather read a file than talk to the daemon:
+//!
+//! ```text
+//! period=night
+//! temperature=3500
+//! brightness=1.00
+//! enabled=1
+//! ```
+//!
+//! The file is replaced atomically on every change, so readers never
+//! see a partly written file, and removed when the daemon exits.
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use daemon::Control;
+use events::Event;
+use paths;
+use status::Status;
+use super::super::{Result, RedshiftError};
+
+/// Location of the status file in the directory of the instance
+pub fn path(dir: &Path) -> PathBuf {
+    dir.join("status")
+}
+
+/// The status file, removed again when dropped
+pub struct StatusFile {
+    path: PathBuf,
+}
+
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Keep the status file up to date in a new thread
+pub fn start(control: &Control, dir: &Path) -> Result<StatusFile> {
+    let path = path(dir);
+    paths::ensure_runtime_dir(dir)
+        .map_err(|e| RedshiftError::io(dir.display(), e))?;
+
+    let updates = control.subscribe();
+    let target = path.clone();
+    thread::spawn(move || {
+        for event in updates.iter() {
+            let status = match event {
+                Event::StatusChanged(status) => status,
+                _ => continue,
+            };
+            if let Err(e) = write(&target, &status) {
+                warn!("Could not write status file {}: {}", target.display(), e);
+            }
+        }
+    });
+    Ok(StatusFile { path: path })
+}
+
+fn write(path: &Path, status: &Status) -> ::std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    File::create(&tmp)?.write_all(status.to_key_value().as_bytes())?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,454 @@
+//! Configuring and running the adjustments from code
+//!
+//! `RedshiftBuilder` collects what the binary takes from its command
+//! line and configuration file, and builds a `Redshift` that applies
+//! the schedule through a gamma method:
+//!
+//! ```no_run
+//! extern crate redshift_rs;
+//!
+//! use redshift_rs::RedshiftBuilder;
+//! use redshift_rs::location::Location;
+//!
+//! # fn main() {
+//! let mut redshift = RedshiftBuilder::new()
+//!     .location(Location::new(55.7, 12.6))
+//!     .temperatures(6000, 3000)
+//!     .method("randr")
+//!     .build()
+//!     .unwrap();
+//! redshift.update().unwrap();
+//! # }
+//! ```
+//!
+//! `Redshift::run()` keeps the display adjusted until told to stop, and
+//! `Redshift::subscribe()` reports what it does along the way.
+
+use chan;
+use clock::{Clock, SystemClock};
+use events::{Event, Observers};
+use gamma::{self, GammaMethod};
+use location::Location;
+use solar;
+use status::Status;
+use transition::{ColorSetting, Period, TransitionScheme};
+use super::{check_temperature, Result, RedshiftError};
+use super::{DEFAULT_BRIGHTNESS, DEFAULT_DAY_TEMP, DEFAULT_GAMMA, DEFAULT_NIGHT_TEMP};
+use super::{DEFAULT_CONTRAST, MAX_CONTRAST, MAX_GAMMA, MIN_CONTRAST, MIN_GAMMA};
+use super::IDENTITY_MATRIX;
+
+/// Longest time `run()` sleeps in one go, in seconds
+const MAX_SLEEP_S: f64 = 30.0 * 60.0;
+
+/// Settings for a `Redshift`, with the defaults of the binary
+pub struct RedshiftBuilder {
+    location: Location,
+    temperatures: (i32, i32),
+    brightness: (f64, f64),
+    invert: (bool, bool),
+    contrast: (f64, f64),
+    gamma: [f64; 3],
+    matrix: [[f64; 3]; 3],
+    elevations: (f64, f64),
+    method: Option<String>,
+    instance: Option<Box<GammaMethod>>,
+    options: gamma::Options,
+    clock: Box<Clock + Send>,
+}
+
+impl RedshiftBuilder {
+    pub fn new() -> RedshiftBuilder {
+        let scheme = TransitionScheme::new();
+        RedshiftBuilder {
+            location: Location::new(0.0, 0.0),
+            temperatures: (DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP),
+            brightness: (DEFAULT_BRIGHTNESS, DEFAULT_BRIGHTNESS),
+            invert: (false, false),
+            contrast: (DEFAULT_CONTRAST, DEFAULT_CONTRAST),
+            gamma: [DEFAULT_GAMMA; 3],
+            matrix: IDENTITY_MATRIX,
+            elevations: (scheme.high, scheme.low),
+            method: None,
+            instance: None,
+            options: gamma::Options::default(),
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn location(mut self, location: Location) -> RedshiftBuilder {
+        self.location = location;
+        self
+    }
+
+    /// Color temperatures at day and at night, in Kelvin
+    pub fn temperatures(mut self, day: i32, night: i32) -> RedshiftBuilder {
+        self.temperatures = (day, night);
+        self
+    }
+
+    /// Brightness at day and at night, between 0.1 and 1.0
+    pub fn brightness(mut self, day: f64, night: f64) -> RedshiftBuilder {
+        self.brightness = (day, night);
+        self
+    }
+
+    /// Contrast at day and at night, 1.0 leaving it as it is
+    pub fn contrast(mut self, day: f64, night: f64) -> RedshiftBuilder {
+        self.contrast = (day, night);
+        self
+    }
+
+    /// Color matrix applied at all times, of which ramps only apply
+    /// the diagonal
+    pub fn matrix(mut self, matrix: [[f64; 3]; 3]) -> RedshiftBuilder {
+        self.matrix = matrix;
+        self
+    }
+
+    /// Whether to invert the colors at day and at night
+    pub fn invert(mut self, day: bool, night: bool) -> RedshiftBuilder {
+        self.invert = (day, night);
+        self
+    }
+
+    /// Gamma correction of the red, green and blue channels
+    pub fn gamma(mut self, red: f64, green: f64, blue: f64) -> RedshiftBuilder {
+        self.gamma = [red, green, blue];
+        self
+    }
+
+    /// Solar elevations in degrees above which it is day and below
+    /// which it is night, transitioning in between
+    pub fn elevations(mut self, day: f64, night: f64) -> RedshiftBuilder {
+        self.elevations = (day, night);
+        self
+    }
+
+    /// The gamma method to use, instead of picking one
+    pub fn method(mut self, name: &str) -> RedshiftBuilder {
+        self.method = Some(name.to_owned());
+        self
+    }
+
+    /// A gamma method to start, instead of one from the registry,
+    /// such as a `RecordingMethod` in tests
+    pub fn gamma_method(mut self, method: Box<GammaMethod>) -> RedshiftBuilder {
+        self.instance = Some(method);
+        self
+    }
+
+    /// Options for connecting to the display and for the method
+    pub fn gamma_options(mut self, options: gamma::Options) -> RedshiftBuilder {
+        self.options = options;
+        self
+    }
+
+    /// The clock `update()` and `run()` follow, the system time by
+    /// default
+    pub fn clock(mut self, clock: Box<Clock + Send>) -> RedshiftBuilder {
+        self.clock = clock;
+        self
+    }
+
+    fn check(&self) -> Result<()> {
+        let invalid = |what: String| Err(RedshiftError::MalformedArgument(what));
+        let (day, night) = self.temperatures;
+        check_temperature(day)?;
+        check_temperature(night)?;
+        let (day, night) = self.brightness;
+        if !(0.1..=1.0).contains(&day) || !(0.1..=1.0).contains(&night) {
+            return invalid(format!("brightness {}/{} (expected 0.1 to 1.0)", day, night));
+        }
+        let (day, night) = self.contrast;
+        let contrasts = MIN_CONTRAST..=MAX_CONTRAST;
+        if !contrasts.contains(&day) || !contrasts.contains(&night) {
+            return invalid(format!("contrast {}/{} (expected {} to {})",
+                                   day, night, MIN_CONTRAST, MAX_CONTRAST));
+        }
+        if self.gamma.iter().any(|g| !(MIN_GAMMA..=MAX_GAMMA).contains(g)) {
+            return invalid(format!("gamma {:?} (expected {} to {})", self.gamma, MIN_GAMMA, MAX_GAMMA));
+        }
+        if self.matrix.iter().flat_map(|row| row.iter()).any(|v| !v.is_finite()) {
+            return invalid(format!("matrix {:?}", self.matrix));
+        }
+        if self.elevations.0 < self.elevations.1 {
+            return invalid(format!("elevations {}/{} (day below night)",
+                                   self.elevations.0, self.elevations.1));
+        }
+        if let Some(problem) = self.location.problem() {
+            return invalid(format!("location: {}", problem));
+        }
+        Ok(())
+    }
+
+    /// Check the settings and start the gamma method
+    pub fn build(self) -> Result<Redshift> {
+        self.check()?;
+        let mut method = match self.instance {
+            Some(method) => method,
+            None => gamma::init_gamma_method(self.method.as_ref().map(|s| &s[..]), &self.options)?,
+        };
+        method.start()?;
+
+        let mut scheme = TransitionScheme::new();
+        scheme.high = self.elevations.0;
+        scheme.low = self.elevations.1;
+        scheme.day = ColorSetting {
+            temp: self.temperatures.0,
+            gamma: self.gamma,
+            brightness: self.brightness.0,
+            invert: self.invert.0,
+            contrast: self.contrast.0,
+            matrix: self.matrix,
+            filter: IDENTITY_MATRIX,
+            calibration: None,
+        };
+        scheme.night = ColorSetting {
+            temp: self.temperatures.1,
+            gamma: self.gamma,
+            brightness: self.brightness.1,
+            invert: self.invert.1,
+            contrast: self.contrast.1,
+            matrix: self.matrix,
+            filter: IDENTITY_MATRIX,
+            calibration: None,
+        };
+        Ok(Redshift {
+            method: gamma::RestoreGuard::new(method),
+            scheme: scheme,
+            location: self.location,
+            status: Status {
+                period: Period::None,
+                elevation: 0.0,
+                setting: ColorSetting::new(),
+                location: self.location,
+                outputs: vec![],
+                enabled: true,
+                night_vision: false,
+            },
+            observers: Observers::new(),
+            clock: self.clock,
+        })
+    }
+}
+
+impl Default for RedshiftBuilder {
+    fn default() -> RedshiftBuilder {
+        RedshiftBuilder::new()
+    }
+}
+
+/// A started gamma method together with the schedule it follows
+///
+/// The ramps are restored when it is dropped.
+pub struct Redshift {
+    method: gamma::RestoreGuard,
+    scheme: TransitionScheme,
+    location: Location,
+    status: Status,
+    observers: Observers,
+    clock: Box<Clock + Send>,
+}
+
+impl Redshift {
+    pub fn set_location(&mut self, location: Location) {
+        self.location = location;
+        self.status.location = location;
+        self.observers.emit(Event::LocationUpdated(location));
+    }
+
+    /// Receive the events of every update from now on
+    pub fn subscribe(&mut self) -> chan::Receiver<Event> {
+        self.observers.subscribe()
+    }
+
+    /// Apply a setting, reporting the outcome to the observers
+    fn apply(&mut self, setting: &ColorSetting) -> Result<()> {
+        if let Err(e) = self.method.set_temperature(setting) {
+            self.observers.emit(Event::BackendError(e.to_string()));
+            return Err(e);
+        }
+        self.observers.emit(Event::TemperatureApplied(setting.clone()));
+        Ok(())
+    }
+
+    /// Apply the scheduled setting for the current time
+    pub fn update(&mut self) -> Result<&Status> {
+        let now = self.clock.now();
+        self.update_at(now)
+    }
+
+    /// Apply the scheduled setting for a time in seconds since the
+    /// epoch
+    pub fn update_at(&mut self, t: f64) -> Result<&Status> {
+        let elevation = solar::elevation(t, &self.location);
+        let setting = self.scheme.interpolate_color_settings(elevation);
+        let period = self.scheme.get_period(elevation);
+        if period.name() != self.status.period.name() {
+            self.observers.emit(Event::PeriodChanged(period));
+        }
+        let changed = setting != self.status.setting || period != self.status.period;
+        if setting != self.status.setting {
+            self.apply(&setting)?;
+        }
+        self.status.period = period;
+        self.status.elevation = elevation;
+        self.status.setting = setting;
+        self.status.outputs = self.method.outputs();
+        if changed {
+            self.observers.emit(Event::StatusChanged(self.status.clone()));
+        }
+        Ok(&self.status)
+    }
+
+    /// Apply a setting right away, regardless of the schedule
+    ///
+    /// The next update applies the schedule again.
+    pub fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+        check_temperature(setting.temp)?;
+        if !(0.1..=1.0).contains(&setting.brightness) {
+            return Err(RedshiftError::MalformedArgument(
+                format!("brightness {} (expected 0.1 to 1.0)", setting.brightness)));
+        }
+        self.apply(setting)?;
+        self.status.setting = setting.clone();
+        self.observers.emit(Event::StatusChanged(self.status.clone()));
+        Ok(())
+    }
+
+    /// The status as of the last update
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Keep the display adjusted until `stop` receives a message or
+    /// is closed, then restore the ramps
+    pub fn run(mut self, stop: chan::Receiver<()>) -> Result<()> {
+        loop {
+            let now = self.clock.now();
+            self.update_at(now)?;
+            let secs = self.scheme.next_period_change(&self.location, now, MAX_SLEEP_S);
+            let timer = chan::after_ms((secs * 1000.0).max(5000.0) as u32);
+            chan_select! {
+                stop.recv() => break,
+                timer.recv() => {},
+            }
+        }
+        self.method.restore()
+    }
+
+    /// Restore the ramps found when the method was started
+    ///
+    /// The next update adjusts the display again.
+    pub fn restore(&mut self) -> Result<()> {
+        GammaMethod::restore(&*self.method)?;
+        self.status.setting = ColorSetting::new();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builder_checks() {
+        assert!(RedshiftBuilder::new().check().is_ok());
+        assert!(RedshiftBuilder::new().temperatures(500, 3500).check().is_err());
+        assert!(RedshiftBuilder::new().brightness(1.0, 0.0).check().is_err());
+        assert!(RedshiftBuilder::new().gamma(1.0, 20.0, 1.0).check().is_err());
+        assert!(RedshiftBuilder::new().elevations(-6.0, 3.0).check().is_err());
+
+        let mut redshift = RedshiftBuilder::new()
+            .location(Location::new(55.7, 12.6))
+            .method("dummy")
+            .build()
+            .unwrap();
+        // Noon in Copenhagen, at the summer solstice of 2020
+        let status = redshift.update_at(1592650800.0).unwrap();
+        assert_eq!(status.period, Period::Day);
+        assert_eq!(status.setting.temp, DEFAULT_DAY_TEMP);
+    }
+
+    #[test]
+    fn events() {
+        let mut redshift = RedshiftBuilder::new()
+            .location(Location::new(55.7, 12.6))
+            .method("dummy")
+            .build()
+            .unwrap();
+        let events = redshift.subscribe();
+        redshift.update_at(1592650800.0).unwrap();
+        // Nothing new to report a minute later
+        redshift.update_at(1592650860.0).unwrap();
+        redshift.set_location(Location::new(-33.9, 151.2));
+
+        let events: Vec<Event> = (0..4).filter_map(|_| events.recv()).collect();
+        match &events[..] {
+            [Event::PeriodChanged(Period::Day),
+             Event::TemperatureApplied(setting),
+             Event::StatusChanged(_),
+             Event::LocationUpdated(_)] => assert_eq!(setting.temp, DEFAULT_DAY_TEMP),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn dawn() {
+        use clock::TestClock;
+
+        let clock = TestClock::new(0.0);
+        let mut redshift = RedshiftBuilder::new()
+            .location(Location::new(55.7, 12.6))
+            .method("dummy")
+            .clock(Box::new(clock.clone()))
+            .build()
+            .unwrap();
+        // Midnight UTC in Copenhagen, the night before the summer
+        // solstice of 2020, until it is day
+        clock.set(1592701200.0);
+        let mut periods = vec![];
+        let mut temp = 0;
+        for _ in 0..(12 * 6) {
+            let status = redshift.update().unwrap().clone();
+            if periods.last() != Some(&status.period.name()) {
+                periods.push(status.period.name());
+            }
+            assert!(status.setting.temp >= temp);
+            temp = status.setting.temp;
+            clock.advance(10.0 * 60.0);
+        }
+        assert_eq!(periods, ["night", "transition", "day"]);
+        assert_eq!(temp, DEFAULT_DAY_TEMP);
+    }
+
+    #[test]
+    fn recorded_ramps() {
+        use gamma::recording::{Call, RecordingMethod};
+
+        let method = RecordingMethod::new(1024);
+        let recording = method.recording();
+        let mut redshift = RedshiftBuilder::new()
+            .location(Location::new(55.7, 12.6))
+            .gamma_method(Box::new(method))
+            .build()
+            .unwrap();
+        // Midnight UTC, and noon
+        redshift.update_at(1592701200.0).unwrap();
+        let (r, g, b) = recording.ramps().unwrap();
+        assert_eq!(r.len(), 1024);
+        assert!(r[1023] > g[1023] && g[1023] > b[1023]);
+        redshift.update_at(1592739600.0).unwrap();
+        redshift.update_at(1592739660.0).unwrap();
+        redshift.restore().unwrap();
+
+        let calls = recording.calls();
+        let temps: Vec<_> = calls.iter().filter_map(|c| match *c {
+            Call::SetTemperature(ref setting, _) => Some(setting.temp),
+            Call::Restore => None,
+        }).collect();
+        assert_eq!(temps, [DEFAULT_NIGHT_TEMP, DEFAULT_DAY_TEMP]);
+        assert_eq!(calls.last(), Some(&Call::Restore));
+        assert_eq!(recording.ramps(), None);
+    }
+}
@@ -0,0 +1,1678 @@
+//! Continual mode
+//!
+//! The default functionality of Redshift is to run continually
+//! adjusting the temperature as the day progresses. It is interrupted
+//! by signals INT and TERM that both cause it to fade back to
+//! neutral, restore the saved gamma ramps and terminate. A second
+//! signal skips the fade. With `--no-transition` there is no fade
+//! when starting or exiting.
+//!
+//! SIGUSR1 toggles between the scheduled temperature (enabled) and
+//! neutral (disabled), fading between the two either way, over the
+//! `fade-duration` (2 seconds by default). A snooze disables the
+//! adjustments for a while and fades back in by itself afterwards.
+//! The real-time signals hold temperatures as bound in the `[signals]`
+//! section, see `signals`.
+//!
+//! Night vision mode shows nothing but dim red, whatever the schedule
+//! and whether enabled or not, until it is switched off again. It
+//! switches right away rather than fading, to spare dark-adapted eyes
+//! a bright fade.
+//!
+//! Besides signals, the daemon is driven by `Command`s sent through a
+//! `Control` handle, which is how the control interfaces talk to it.
+//!
+//! The main loop waits on a single channel of events together with a
+//! timer for the next scheduled update, see `Timer`. Each blocking
+//! source (signals, the control interfaces, output changes reported by
+//! the gamma method, jumps of the system clock, changes of the time
+//! zone, the screen powering off and on, the ambient light sensor, the
+//! power supply, the idle time, the session lock) runs in a thread of
+//! its own that forwards to the channel, so the loop reacts to any of
+//! them right away.
+//!
+//! The daemon publishes `events::Event`s as it goes, through the same
+//! channel for every observer: the status file, the status stream and
+//! the D-Bus interface each subscribe to it.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::ptr;
+use std::result;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chan;
+use chan_signal::{self, Signal};
+use libc;
+use time;
+
+use ambient::{self, Ambient};
+use appearance;
+use battery;
+use clock::{self, SystemClock};
+use colorramp;
+use conflicts;
+use control;
+use dpms;
+use events::{self, Observers};
+use fullscreen;
+use hooks;
+use i18n;
+use gamma;
+use idle;
+use inhibit;
+use lock;
+use signals;
+use instance;
+use location;
+use offset;
+use outputs;
+use places;
+use policy::{self, Policy};
+use solar;
+use state::State;
+use systemd;
+use timezone;
+use status::Status;
+use transition::{self, ColorSetting, Period, TransitionScheme};
+use super::{apply_grayscale, apply_offset, reload_args, start_gamma_method, systemtime_get_time};
+use super::{warn_filter, warn_filters};
+use super::{transition_scheme, Args, Result, IDENTITY_MATRIX, NEUTRAL_TEMP};
+
+/// Longest time to sleep in one go, in seconds
+const MAX_SLEEP_S: f64 = 30.0 * 60.0;
+
+/// Longest snooze, in seconds: a week
+pub const MAX_SNOOZE_S: u64 = 7 * 86400;
+
+/// How long the solar table of `low-power` is used before it is
+/// computed again, in seconds. It reaches further, for looking ahead.
+const SOLAR_TABLE_S: f64 = 86400.0;
+
+/// How often the system clock is checked for jumps where it can't be
+/// waited for, in milliseconds
+#[cfg(not(target_os = "linux"))]
+const CLOCK_CHECK_MS: u64 = 10000;
+
+/// Smallest difference between the clocks counted as a jump, in seconds
+const CLOCK_JUMP_S: f64 = 5.0;
+
+/// Smallest move of the location faded across rather than followed
+/// right away, in km
+const LOCATION_JUMP_KM: f64 = 50.0;
+
+/// A request to the running daemon
+pub enum Command {
+    /// Enable or disable the adjustments, or toggle them if `None`
+    SetEnabled(Option<bool>),
+
+    /// Disable the adjustments, and enable them again after the given
+    /// time, at most `MAX_SNOOZE_S`
+    Snooze(Duration),
+
+    /// Hold a temperature instead of following the schedule, or
+    /// resume the schedule if `None`
+    SetTemperature(Option<i32>),
+
+    /// Hold a temperature for the given time, and resume the schedule
+    /// afterwards
+    HoldTemperature(i32, Duration),
+
+    /// Switch night vision mode on or off, or toggle it if `None`
+    SetNightVision(Option<bool>),
+    /// Turn the colors gray, toggling without a value
+    SetGrayscale(Option<bool>),
+
+    /// Reply with the current status
+    GetStatus(chan::Sender<Status>),
+
+    /// Send the current status, and then every event
+    Subscribe(chan::Sender<events::Event>),
+
+    /// Recompute the adjustments right away, e.g. after the offset
+    /// was changed
+    Update,
+
+    /// Fade out, restore the ramps and exit
+    Quit(&'static str),
+
+    /// Read the configuration file again, switching to the given
+    /// profile if any, and reply whether that succeeded
+    Reload(Option<String>, chan::Sender<result::Result<(), String>>),
+}
+
+/// Everything the main loop waits for, besides its timer
+enum Event {
+    Signal(Signal),
+    /// A real-time signal, by offset from SIGRTMIN
+    RealTimeSignal(i32),
+    Command(Command),
+    /// The gamma method saw outputs change
+    OutputsChanged,
+    /// A fullscreen window became active, or went away
+    Fullscreen(bool),
+    /// The screen powered off, or back on
+    ScreenOff(bool),
+    /// The system clock jumped by the given number of seconds
+    ClockJump(f64),
+    /// The system was set to the given time zone
+    TimezoneChanged(String),
+    /// The command of the exec location provider printed another
+    /// location
+    ExecLocation(location::Location),
+    /// A location provider asked in the background found the location
+    LocationFound(location::Location),
+    /// The ambient light sensor read the given number of lux
+    AmbientLight(f64),
+    /// The power supply changed
+    Power(battery::Power),
+    /// There was no input for the idle timeout, or there was again
+    Idle(bool),
+    /// The session was locked, or unlocked
+    Locked(bool),
+    /// The desktop switched between a dark and a light appearance
+    Appearance(appearance::Scheme),
+    /// The screensaver was inhibited, or the last inhibitor released
+    Inhibited(bool),
+}
+
+/// Handle for sending commands to the daemon from other threads
+#[derive(Clone)]
+pub struct Control {
+    tx: chan::Sender<Event>,
+}
+
+impl Control {
+    pub fn send(&self, command: Command) {
+        self.tx.send(Event::Command(command));
+    }
+
+    /// Ask for the current status
+    ///
+    /// Returns `None` if the daemon is no longer running.
+    pub fn status(&self) -> Option<Status> {
+        let (tx, rx) = chan::sync(1);
+        self.send(Command::GetStatus(tx));
+        rx.recv()
+    }
+
+    /// Reload the configuration, optionally switching profile
+    pub fn reload(&self, profile: Option<String>) -> result::Result<(), String> {
+        let (tx, rx) = chan::sync(1);
+        self.send(Command::Reload(profile, tx));
+        rx.recv().unwrap_or_else(|| Err("daemon is not running".to_owned()))
+    }
+
+    /// Receive the current status, and then every event
+    pub fn subscribe(&self) -> chan::Receiver<events::Event> {
+        let (tx, rx) = chan::async();
+        self.send(Command::Subscribe(tx));
+        rx
+    }
+}
+
+/// Run continually until interrupted
+pub fn run(args: Args, scheme: TransitionScheme) -> Result<()> {
+    // Signals must be blocked before any other threads are spawned,
+    // so that they are only delivered to the signal threads
+    let rt_signals = signals::notify();
+    let signals = chan_signal::notify(&[Signal::INT, Signal::TERM, Signal::USR1]);
+
+    // Take the display before the gamma method saves its ramps, which
+    // a replaced instance could still be fading out. A dry run leaves
+    // the display alone, so it can run alongside.
+    let lock = if args.dry_run {
+        None
+    } else {
+        Some(instance::acquire(&instance::displays(&args.gamma_options), args.replace)?)
+    };
+
+    // Turned on again after the ramps are restored
+    let _conflicts = if args.dry_run { None } else { Some(conflicts::check(args.conflicts)) };
+
+    let mut daemon = Daemon::new(args, scheme)?;
+
+    // Every source of events gets a thread that forwards them to the
+    // main loop, which waits on all of them at once
+    let (tx, events) = chan::async();
+    let signal_tx = tx.clone();
+    thread::spawn(move || {
+        for sig in signals.iter() {
+            signal_tx.send(Event::Signal(sig));
+        }
+    });
+    let rt_signal_tx = tx.clone();
+    thread::spawn(move || {
+        for offset in rt_signals.iter() {
+            rt_signal_tx.send(Event::RealTimeSignal(offset));
+        }
+    });
+    if let Some(changes) = daemon.gamma_state.watch() {
+        let outputs_tx = tx.clone();
+        thread::spawn(move || {
+            for _ in changes.iter() {
+                outputs_tx.send(Event::OutputsChanged);
+            }
+        });
+    }
+    if daemon.args.simulate_day.is_none() {
+        let clock_tx = tx.clone();
+        thread::spawn(move || watch_clock(clock_tx));
+    }
+    let timezone_tx = tx.clone();
+    let zones = timezone::watch();
+    thread::spawn(move || {
+        for zone in zones.iter() {
+            timezone_tx.send(Event::TimezoneChanged(zone));
+        }
+    });
+    if let Some(interval) = daemon.args.location_interval {
+        let location_tx = tx.clone();
+        let location = daemon.args.location;
+        thread::spawn(move || watch_exec_location(location_tx, location, interval));
+    }
+    if let Some(provider) = location::pending() {
+        let location_tx = tx.clone();
+        thread::spawn(move || match location::locate_eventually(provider) {
+            Ok(location) => location_tx.send(Event::LocationFound(location)),
+            Err(e) => info!("Keeping the estimated location: {}", e),
+        });
+    }
+    if daemon.args.pause_fullscreen {
+        if let Some(changes) = fullscreen::watch(daemon.args.gamma_options.main_display()) {
+            let fullscreen_tx = tx.clone();
+            thread::spawn(move || {
+                for fullscreen in changes.iter() {
+                    fullscreen_tx.send(Event::Fullscreen(fullscreen));
+                }
+            });
+        }
+    }
+    if !daemon.args.dry_run {
+        if let Some(changes) = dpms::watch(daemon.args.gamma_options.main_display()) {
+            let dpms_tx = tx.clone();
+            thread::spawn(move || {
+                for off in changes.iter() {
+                    dpms_tx.send(Event::ScreenOff(off));
+                }
+            });
+        }
+    }
+    if daemon.ambient.is_some() {
+        if let Some(levels) = ambient::watch() {
+            let ambient_tx = tx.clone();
+            thread::spawn(move || {
+                for lux in levels.iter() {
+                    ambient_tx.send(Event::AmbientLight(lux));
+                }
+            });
+        }
+    }
+    if daemon.args.battery.is_some() {
+        if let Some(changes) = battery::watch() {
+            let power_tx = tx.clone();
+            thread::spawn(move || {
+                for power in changes.iter() {
+                    power_tx.send(Event::Power(power));
+                }
+            });
+        }
+    }
+    if daemon.args.appearance.is_some() {
+        if let Some(changes) = appearance::watch() {
+            let appearance_tx = tx.clone();
+            thread::spawn(move || {
+                for scheme in changes.iter() {
+                    appearance_tx.send(Event::Appearance(scheme));
+                }
+            });
+        }
+    }
+    if daemon.args.inhibit.is_some() {
+        if let Some(changes) = inhibit::watch() {
+            let inhibit_tx = tx.clone();
+            thread::spawn(move || {
+                for inhibited in changes.iter() {
+                    inhibit_tx.send(Event::Inhibited(inhibited));
+                }
+            });
+        }
+    }
+    if daemon.args.pause_locked {
+        if let Some(changes) = lock::watch() {
+            let lock_tx = tx.clone();
+            thread::spawn(move || {
+                for locked in changes.iter() {
+                    lock_tx.send(Event::Locked(locked));
+                }
+            });
+        }
+    }
+    if let Some(ref options) = daemon.args.idle {
+        if let Some(changes) = idle::watch(&daemon.args.gamma_options, options.timeout) {
+            let idle_tx = tx.clone();
+            thread::spawn(move || {
+                for idle in changes.iter() {
+                    idle_tx.send(Event::Idle(idle));
+                }
+            });
+        }
+    }
+    // Keep the control interfaces up until the ramps are restored
+    let control = Control { tx: tx };
+    if let Some(ref lock) = lock {
+        lock.serve(control.clone())?;
+    }
+    let _interfaces = control::start(&control, &daemon.dir, daemon.args.mqtt.clone(),
+                                     daemon.args.metrics.clone());
+    if daemon.args.status_stream {
+        let updates = control.subscribe();
+        thread::spawn(move || {
+            // Fades repeat the same line for a while
+            let mut last = String::new();
+            for event in updates.iter() {
+                let line = match event {
+                    events::Event::StatusChanged(status) => status.to_waybar(),
+                    _ => continue,
+                };
+                if line != last {
+                    println!("{}", line);
+                    last = line;
+                }
+            }
+        });
+    }
+
+    if !daemon.args.dry_run {
+        hooks::start(control.subscribe());
+    }
+    if daemon.args.classic_output {
+        let updates = control.subscribe();
+        thread::spawn(move || {
+            // Only the lines that changed, as C Redshift does
+            let mut last: Vec<String> = vec![];
+            for event in updates.iter() {
+                let lines = match event {
+                    events::Event::StatusChanged(status) => status.to_classic(),
+                    _ => continue,
+                };
+                for line in lines.iter().filter(|l| !last.contains(l)) {
+                    println!("{}", line);
+                }
+                last = lines;
+            }
+        });
+    }
+
+    daemon.run(events)
+}
+
+struct Daemon {
+    args: Args,
+    scheme: TransitionScheme,
+
+    /// Restores the saved ramps when dropped, also if the loop is
+    /// left with an error
+    gamma_state: gamma::RestoreGuard,
+    clock: ScaledClock,
+
+    enabled: bool,
+    /// When to enable again after a snooze
+    snooze: Option<Instant>,
+    /// Paused because of a fullscreen window
+    paused: bool,
+    /// Paused while the session is locked
+    locked: bool,
+    /// Paused while the screensaver is inhibited
+    inhibited: bool,
+    /// Not updating while the screen is powered off
+    screen_off: bool,
+    exiting: bool,
+    /// Red only, overriding the schedule
+    night_vision: bool,
+    /// Gray by luminance, from `Args::grayscale` until toggled
+    grayscale: bool,
+    /// The light level dimming the display, if followed
+    ambient: Option<Ambient>,
+    /// The program deciding the setting, if any
+    policy: Option<Policy>,
+    /// Dimmed for lack of input
+    idle: bool,
+    /// The setting before the location jumped, and since when it is
+    /// faded away from
+    location_jump: Option<(ColorSetting, Instant)>,
+    /// When the ramps were last checked for changes by others
+    verified: Instant,
+    /// The smoothed temperature, the target it follows, and when it
+    /// was last updated, with `smoothing`
+    smoothed: Option<(f64, i32, Instant)>,
+    /// The solar elevations of the day with `low-power`
+    solar_table: Option<solar::Table>,
+
+    /// Temperature held through the control interface
+    manual: Option<i32>,
+    /// When to resume the schedule after holding `manual` for a while
+    manual_until: Option<Instant>,
+    /// Settings of the outputs that differ from the others, as last
+    /// applied
+    output_settings: Vec<gamma::OutputSetting>,
+    /// Profile switched to through the control interface
+    profile: Option<String>,
+    /// Profiles switched to automatically, applied over `profile`, by
+    /// what switched to them
+    automatic: Vec<(&'static str, String)>,
+    temp_offset: i32,
+    /// The directory of the instance in the runtime directory, with
+    /// the offset and the control socket
+    dir: PathBuf,
+    /// The directory of the state of the instance, see `state`
+    state_dir: Option<PathBuf>,
+
+    status: Status,
+    observers: Observers,
+
+    /// Interval and time of the last ping of the systemd watchdog
+    watchdog: Option<(Duration, Instant)>,
+}
+
+impl Daemon {
+    fn new(args: Args, mut scheme: TransitionScheme) -> Result<Daemon> {
+        let gamma_state = gamma::RestoreGuard::new(start_gamma_method(&args)?);
+        warn_filters(&*gamma_state, &args);
+        if args.simulate_day.is_some() || !args.transition {
+            // Skip the initial fade, which would also take up simulated
+            // hours when simulating
+            scheme.short_trans_delta = 0;
+            scheme.adjustment_alpha = 0.0;
+        }
+
+        // Carry on as the last run left off, except when only
+        // simulating
+        let state_dir = instance::state_dir(&args.gamma_options);
+        let saved = if args.dry_run { State::default() } else { State::load(state_dir.as_deref()) };
+        if !saved.enabled {
+            info!("Starting disabled, as when last run");
+            scheme.short_trans_delta = 0;
+            scheme.adjustment_alpha = 1.0;
+        }
+        let dir = instance::runtime_dir(&args.gamma_options);
+        let limits = args.temp_limits;
+        let manual = saved.manual.map(|t| t.max(limits.0).min(limits.1));
+        if let Some(t) = manual {
+            info!("Holding color temperature at {}K, as when last run", t);
+        }
+        if saved.night_vision {
+            info!("Starting in night vision mode, as when last run");
+        }
+
+        let status = Status {
+            period: Period::None,
+            elevation: 0.0,
+            setting: ColorSetting::new(),
+            location: args.location,
+            outputs: gamma_state.outputs(),
+            enabled: saved.enabled,
+            night_vision: saved.night_vision,
+        };
+        Ok(Daemon {
+            clock: ScaledClock::new(Box::new(SystemClock), args.simulate_day),
+            ambient: args.ambient.clone().map(Ambient::new),
+            policy: match args.policy {
+                Some(ref options) if !args.dry_run => Some(Policy::start(options)?),
+                _ => None,
+            },
+            idle: false,
+            grayscale: args.grayscale,
+            args: args,
+            scheme: scheme,
+            gamma_state: gamma_state,
+            enabled: saved.enabled,
+            snooze: None,
+            paused: false,
+            locked: false,
+            inhibited: false,
+            screen_off: false,
+            exiting: false,
+            night_vision: saved.night_vision,
+            location_jump: None,
+            verified: Instant::now(),
+            smoothed: None,
+            solar_table: None,
+            manual: manual,
+            manual_until: None,
+            output_settings: vec![],
+            profile: None,
+            automatic: vec![],
+            temp_offset: 0,
+            dir: dir,
+            state_dir: state_dir,
+            status: status,
+            observers: Observers::new(),
+            watchdog: systemd::watchdog_interval().map(|i| (i, Instant::now())),
+        })
+    }
+
+    fn run(mut self, events: chan::Receiver<Event>) -> Result<()> {
+        self.monitors_changed();
+        self.place_changed();
+        let mut sleep = self.timed_update()?;
+        systemd::notify(&format!("READY=1\nSTATUS={}", self.status_line()));
+        let timer = Timer::new();
+        let timeouts = &timer.fired;
+        while let Some(ms) = sleep {
+            self.ping_watchdog();
+            // Wake up in time for the next watchdog ping, and for the
+            // end of a snooze
+            let ms = match self.watchdog {
+                Some((interval, _)) => ms.min(duration_ms(interval)),
+                None => ms,
+            };
+            let ms = match self.snooze {
+                Some(until) => ms.min(duration_ms(until.saturating_duration_since(Instant::now()))),
+                None => ms,
+            };
+            let ms = match self.manual_until {
+                Some(until) => ms.min(duration_ms(until.saturating_duration_since(Instant::now()))),
+                None => ms,
+            };
+            let deadline = Instant::now() + Duration::from_millis(ms);
+            timer.set(deadline);
+            loop {
+                chan_select! {
+                    events.recv() -> event => {
+                        // Update right away to get going with a fade,
+                        // or to report the change
+                        if let Some(quit) = self.event(event) {
+                            sleep = if quit { None } else { self.timed_update()? };
+                            break;
+                        }
+                    },
+                    timeouts.recv() -> reached => {
+                        // Skip a deadline that was replaced just as it
+                        // was reached
+                        if reached == Some(deadline) {
+                            sleep = self.timed_update()?;
+                            break;
+                        }
+                    },
+                }
+            }
+        }
+        systemd::notify("STOPPING=1");
+        self.gamma_state.restore()
+    }
+
+    /// Handle an event of the main loop, returning whether to quit, or
+    /// `None` when it changes nothing and there is nothing to update,
+    /// like a request for the status
+    fn event(&mut self, event: Option<Event>) -> Option<bool> {
+        let quit = match event {
+            Some(Event::Command(Command::GetStatus(reply))) => {
+                reply.send(self.status.clone());
+                return None;
+            }
+            Some(Event::Command(Command::Subscribe(tx))) => {
+                self.command(Command::Subscribe(tx));
+                return None;
+            }
+            Some(Event::Signal(signal)) => self.signal(signal),
+            Some(Event::RealTimeSignal(offset)) => self.real_time_signal(offset),
+            Some(Event::Command(command)) => self.command(command),
+            Some(Event::OutputsChanged) => { self.outputs_changed(); false }
+            Some(Event::Fullscreen(fullscreen)) => { self.set_paused(fullscreen); false }
+            Some(Event::ScreenOff(off)) => { self.set_screen_off(off); false }
+            Some(Event::ClockJump(jump)) => {
+                info!("System clock jumped by {:+.0}s", jump);
+                false
+            }
+            Some(Event::TimezoneChanged(zone)) => { self.timezone_changed(&zone); false }
+            Some(Event::ExecLocation(location)) => { self.exec_location_changed(location); false }
+            Some(Event::LocationFound(location)) => { self.location_found(location); false }
+            Some(Event::AmbientLight(lux)) => {
+                if let Some(ref mut ambient) = self.ambient {
+                    ambient.set_reading(lux);
+                }
+                false
+            }
+            Some(Event::Power(power)) => { self.power_changed(power); false }
+            Some(Event::Idle(idle)) => { self.set_idle(idle); false }
+            Some(Event::Locked(locked)) => { self.set_locked(locked); false }
+            Some(Event::Appearance(scheme)) => { self.appearance_changed(scheme); false }
+            Some(Event::Inhibited(inhibited)) => { self.set_inhibited(inhibited); false }
+            None => false,
+        };
+        Some(quit)
+    }
+
+    /// Adjust outputs that were added or reconfigured
+    fn outputs_changed(&mut self) {
+        if let Err(e) = self.gamma_state.refresh() {
+            warn!("Could not update outputs: {}", e);
+            return;
+        }
+        let outputs = self.gamma_state.outputs();
+        if outputs != self.status.outputs {
+            info!("Outputs: {}", outputs.join(", "));
+            self.status.outputs = outputs;
+            self.monitors_changed();
+        }
+        // Make the next update apply the setting again
+        self.status.setting = ColorSetting::new();
+    }
+
+    fn set_screen_off(&mut self, off: bool) {
+        self.screen_off = off;
+        if off {
+            info!("Screen powered off, not updating");
+        } else {
+            info!("Screen powered on");
+            // The driver may have reset the ramps meanwhile
+            self.status.setting = ColorSetting::new();
+            self.gamma_state.invalidate();
+        }
+    }
+
+    fn set_idle(&mut self, idle: bool) {
+        if idle != self.idle {
+            info!("{}", if idle { "Idle, dimming the screen" } else { "Input again, no longer dimming" });
+            self.idle = idle;
+        }
+    }
+
+    /// Tell the systemd watchdog that the loop is still running
+    fn ping_watchdog(&mut self) {
+        if let Some((interval, ref mut last)) = self.watchdog {
+            if last.elapsed() >= interval {
+                systemd::notify("WATCHDOG=1");
+                *last = Instant::now();
+            }
+        }
+    }
+
+    /// Short description of the current state, for the service status
+    fn status_line(&self) -> String {
+        if let Some(until) = self.snooze {
+            let left = until.saturating_duration_since(Instant::now()).as_secs();
+            format!("Snoozed, enabled again in {}:{:02}", left / 60, left % 60)
+        } else if !self.enabled {
+            "Disabled".to_owned()
+        } else if self.paused {
+            "Paused for a fullscreen window".to_owned()
+        } else if self.locked {
+            "Paused while locked".to_owned()
+        } else if self.inhibited {
+            "Paused while the screensaver is inhibited".to_owned()
+        } else {
+            format!("{}, {}K", self.status.period, self.status.setting.temp)
+        }
+    }
+
+    /// Handle a signal, returns true to quit right away
+    fn signal(&mut self, signal: Signal) -> bool {
+        if signal == Signal::USR1 {
+            let enabled = !self.enabled;
+            self.set_enabled(enabled);
+            false
+        } else {
+            self.quit(&format!("SIG{:?}", signal))
+        }
+    }
+
+    /// Act on a real-time signal as bound in the `[signals]` section
+    fn real_time_signal(&mut self, offset: i32) -> bool {
+        let action = self.args.signals.iter().find(|b| b.offset == offset).map(|b| b.action);
+        info!("Received SIGRTMIN+{}", offset);
+        match action {
+            Some(signals::Action::Temperature(t)) => self.command(Command::SetTemperature(Some(t))),
+            Some(signals::Action::Reset) => self.command(Command::SetTemperature(None)),
+            None => {
+                warn!("SIGRTMIN+{} is not bound in [signals], ignoring it", offset);
+                false
+            }
+        }
+    }
+
+    /// Start exiting, returns true to quit right away
+    fn quit(&mut self, reason: &str) -> bool {
+        if self.exiting {
+            info!("Received {} while exiting, quitting immediately", reason);
+            return true;
+        }
+        self.exiting = true;
+        if !self.args.transition {
+            info!("Received {}, restoring", reason);
+            return true;
+        }
+        // Fade back to neutral before restoring
+        info!("Received {}, fading out", reason);
+        self.scheme.short_trans_delta = 1;
+        self.scheme.short_trans_len = self.args.fade_duration as u16;
+        self.scheme.adjustment_alpha = self.scheme.adjustment_alpha.max(0.1);
+        false
+    }
+
+    /// Handle a command, returns true to quit right away
+    fn command(&mut self, command: Command) -> bool {
+        match command {
+            Command::Quit(reason) => return self.quit(reason),
+            Command::Update => {}
+            Command::SetEnabled(enabled) => {
+                let enabled = enabled.unwrap_or(!self.enabled);
+                self.set_enabled(enabled);
+            }
+            Command::Snooze(duration) => {
+                if self.exiting {
+                    return false;
+                }
+                self.set_enabled(false);
+                let duration = duration.min(Duration::from_secs(MAX_SNOOZE_S));
+                info!("Snoozing for {}s", duration.as_secs());
+                self.snooze = Some(Instant::now() + duration);
+                self.save_state();
+            }
+            Command::SetTemperature(temp) => {
+                let limits = self.args.temp_limits;
+                self.manual = temp.map(|t| t.max(limits.0).min(limits.1));
+                self.manual_until = None;
+                match self.manual {
+                    Some(t) => info!("Holding color temperature at {}K", t),
+                    None => info!("Resuming scheduled color temperature"),
+                }
+                self.save_state();
+            }
+            Command::HoldTemperature(temp, duration) => {
+                let limits = self.args.temp_limits;
+                let t = temp.max(limits.0).min(limits.1);
+                self.manual = Some(t);
+                self.manual_until = Some(Instant::now() + duration);
+                info!("Holding color temperature at {}K until {}", t,
+                      format_time(systemtime_get_time() + secs(duration)));
+                self.save_state();
+            }
+            Command::SetNightVision(on) => {
+                let on = on.unwrap_or(!self.night_vision);
+                if on != self.night_vision && !self.exiting {
+                    info!("Night vision: {}", if on { "On" } else { "Off" });
+                    self.night_vision = on;
+                    self.save_state();
+                }
+            }
+            Command::SetGrayscale(on) => {
+                let on = on.unwrap_or(!self.grayscale);
+                if on != self.grayscale {
+                    info!("Grayscale: {}", if on { "On" } else { "Off" });
+                    if on {
+                        warn_filter(&*self.gamma_state, "grayscale");
+                    }
+                    self.grayscale = on;
+                }
+            }
+            Command::GetStatus(reply) => reply.send(self.status.clone()),
+            Command::Subscribe(tx) => {
+                tx.send(events::Event::StatusChanged(self.status.clone()));
+                self.observers.add(tx);
+            }
+            Command::Reload(profile, reply) => {
+                let result = self.reload(profile).map_err(|e| {
+                    warn!("Reloading failed: {}", e);
+                    e.to_string()
+                });
+                reply.send(result);
+            }
+        }
+        false
+    }
+
+    /// Replace the settings with freshly loaded ones
+    ///
+    /// An ongoing fade carries on with the new settings. The gamma
+    /// method stays the same, changing it requires a restart.
+    fn reload(&mut self, profile: Option<String>) -> Result<()> {
+        let profile = profile.or_else(|| self.profile.clone());
+        let automatic: Vec<String> = self.automatic.iter().map(|a| a.1.clone()).collect();
+        let args = reload_args(profile.as_ref().map(|p| &p[..]), &automatic)?;
+        if args.method != self.args.method || args.gamma_options != self.args.gamma_options {
+            warn!("Changes of the adjustment method take effect after a restart");
+        }
+
+        let mut scheme = transition_scheme(&args);
+        scheme.short_trans_delta = self.scheme.short_trans_delta;
+        scheme.short_trans_len = self.scheme.short_trans_len;
+        scheme.adjustment_alpha = self.scheme.adjustment_alpha;
+
+        match profile {
+            Some(ref p) => info!("Reloaded settings with profile {}", p),
+            None => info!("Reloaded settings"),
+        }
+        info!("{}", i18n::message("Temperatures: %dK at day, %dK at night\n",
+                                  &[&args.temperatures.0, &args.temperatures.1]));
+        if args.location != self.status.location {
+            let distance = self.status.location.distance(&args.location);
+            info!("Location changed from {} to {} ({:.0} km)", self.status.location, args.location, distance);
+            // Rather than jumping to the schedule of the new location,
+            // e.g. after a flight or a change of the VPN
+            if distance >= LOCATION_JUMP_KM && self.status.setting.temp > 0 {
+                self.location_jump = Some((self.status.setting.clone(), Instant::now()));
+            }
+            self.status.location = args.location;
+            self.observers.emit(events::Event::LocationUpdated(args.location));
+        }
+        match (&mut self.ambient, args.ambient.clone()) {
+            (&mut Some(ref mut ambient), Some(options)) => ambient.set_options(options),
+            (&mut None, None) => {}
+            _ => warn!("Following the ambient light or not takes effect after a restart"),
+        }
+        if args.policy != self.args.policy {
+            warn!("Changes of the policy program take effect after a restart");
+        }
+        if args.idle.as_ref().map(|i| i.timeout) != self.args.idle.as_ref().map(|i| i.timeout) {
+            warn!("Changes of the idle timeout take effect after a restart");
+        }
+        if args.whitepoints != self.args.whitepoints {
+            colorramp::set_whitepoints(args.whitepoints.clone()
+                                       .unwrap_or_else(colorramp::Whitepoints::builtin));
+            // Make the next update apply the setting again
+            self.status.setting = ColorSetting::new();
+        }
+        if args.grayscale != self.args.grayscale {
+            self.grayscale = args.grayscale;
+        }
+        self.scheme = scheme;
+        self.profile = profile;
+        self.args = Args {
+            method: self.args.method.take(),
+            gamma_options: self.args.gamma_options.clone(),
+            ..args
+        };
+        self.place_changed();
+        Ok(())
+    }
+
+    /// Follow the system to another time zone
+    ///
+    /// Times are shown in the new zone from now on, and the settings
+    /// are reloaded so that a location estimated from the time zone
+    /// moves along with it.
+    fn timezone_changed(&mut self, zone: &str) {
+        info!("Time zone changed to {}", zone);
+        time::tzset();
+        if let Err(e) = self.reload(None) {
+            warn!("Could not reload the settings: {}", e);
+        }
+    }
+
+    /// Reload the settings for the location the command of the exec
+    /// provider printed, which runs it again
+    fn exec_location_changed(&mut self, location: location::Location) {
+        debug!("The location command printed {}", location);
+        if let Err(e) = self.reload(None) {
+            warn!("Could not reload the settings: {}", e);
+        }
+    }
+
+    /// Take the location a provider found in the background, which the
+    /// settings pick up from now on
+    fn location_found(&mut self, location: location::Location) {
+        debug!("The location provider found {}", location);
+        if let Err(e) = self.reload(None) {
+            warn!("Could not reload the settings: {}", e);
+        }
+    }
+
+    /// Switch to the profile of the `[battery]` section, or back
+    fn power_changed(&mut self, power: battery::Power) {
+        let profile = match self.args.battery {
+            Some(ref options) if options.applies(&power) => Some(options.profile.clone()),
+            _ => None,
+        };
+        self.set_automatic_profile("battery", profile);
+    }
+
+    /// Switch to the profile of the `[appearance]` section for the
+    /// appearance of the desktop, or back
+    fn appearance_changed(&mut self, scheme: appearance::Scheme) {
+        debug!("Appearance of the desktop: {:?}", scheme);
+        let profile = self.args.appearance.as_ref()
+            .and_then(|a| a.profile(scheme))
+            .map(ToOwned::to_owned);
+        self.set_automatic_profile("appearance", profile);
+    }
+
+    /// Switch to the profile of the `[monitors]` section for the
+    /// outputs in use, or back
+    fn monitors_changed(&mut self) {
+        let profile = self.args.monitors.as_ref()
+            .and_then(|m| m.profile(&self.status.outputs))
+            .map(ToOwned::to_owned);
+        self.set_automatic_profile("monitors", profile);
+    }
+
+    /// Switch to the profile of the `[place:NAME]` section around the
+    /// location, or back
+    fn place_changed(&mut self) {
+        let profile = places::find(&self.args.places, &self.status.location)
+            .map(|p| p.profile.clone());
+        self.set_automatic_profile("place", profile);
+    }
+
+    /// Switch to the profile of the `[weekdays]` section for the day
+    /// at `now`
+    fn weekday_changed(&mut self, now: f64) {
+        let profile = self.args.weekdays.as_ref()
+            .and_then(|w| w.profile(now))
+            .map(ToOwned::to_owned);
+        self.set_automatic_profile("weekday", profile);
+    }
+
+    /// Apply a profile over the selected one on behalf of `source`, or
+    /// stop applying the one it switched to
+    fn set_automatic_profile(&mut self, source: &'static str, profile: Option<String>) {
+        let current = self.automatic.iter().position(|a| a.0 == source);
+        if current.map(|i| &self.automatic[i].1) == profile.as_ref() {
+            return;
+        }
+        let previous = self.automatic.clone();
+        if let Some(i) = current {
+            let (_, old) = self.automatic.remove(i);
+            info!("Leaving profile {} ({})", old, source);
+        }
+        if let Some(p) = profile {
+            info!("Switching to profile {} ({})", p, source);
+            self.automatic.push((source, p));
+        }
+        if let Err(e) = self.reload(None) {
+            warn!("Could not switch profiles: {}", e);
+            self.automatic = previous;
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if self.exiting {
+            return;
+        }
+        // Any explicit choice ends a snooze
+        if self.snooze.take().is_some() && enabled == self.enabled {
+            self.save_state();
+        }
+        if enabled == self.enabled {
+            return;
+        }
+        let was_active = self.active();
+        self.enabled = enabled;
+        info!("Status: {}", if enabled { "Enabled" } else { "Disabled" });
+        if self.active() != was_active {
+            self.fade(enabled);
+        }
+        self.save_state();
+    }
+
+    /// Remember the choices made at runtime for the next run
+    fn save_state(&self) {
+        if self.args.dry_run {
+            return;
+        }
+        // A snooze is over by the next run, so don't leave the next
+        // run disabled
+        let state = State {
+            enabled: self.enabled || self.snooze.is_some(),
+            // Likewise for a temperature held for a while
+            manual: if self.manual_until.is_some() { None } else { self.manual },
+            night_vision: self.night_vision,
+        };
+        if let Err(e) = state.store(self.state_dir.as_deref()) {
+            warn!("Could not save state: {}", e);
+        }
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        if self.exiting || paused == self.paused {
+            return;
+        }
+        let was_active = self.active();
+        self.paused = paused;
+        if paused {
+            info!("Fullscreen window active, pausing");
+        } else {
+            info!("Fullscreen window gone, resuming");
+        }
+        if self.active() != was_active {
+            self.fade(!paused);
+        }
+    }
+
+    fn set_locked(&mut self, locked: bool) {
+        if self.exiting || locked == self.locked {
+            return;
+        }
+        let was_active = self.active();
+        self.locked = locked;
+        if locked {
+            info!("Session locked, pausing");
+        } else {
+            info!("Session unlocked, resuming");
+        }
+        if self.active() != was_active {
+            self.fade(!locked);
+        }
+    }
+
+    /// Pause while the screensaver is inhibited, or switch to the
+    /// profile of the `[inhibit]` section
+    fn set_inhibited(&mut self, inhibited: bool) {
+        if let Some(profile) = self.args.inhibit.as_ref().and_then(|i| i.profile.clone()) {
+            self.set_automatic_profile("inhibit", if inhibited { Some(profile) } else { None });
+            return;
+        }
+        if self.exiting || inhibited == self.inhibited {
+            return;
+        }
+        let was_active = self.active();
+        self.inhibited = inhibited;
+        if inhibited {
+            info!("Screensaver inhibited, pausing");
+        } else {
+            info!("Screensaver no longer inhibited, resuming");
+        }
+        if self.active() != was_active {
+            self.fade(!inhibited);
+        }
+    }
+
+    /// Whether the schedule is followed, rather than staying neutral
+    fn active(&self) -> bool {
+        self.enabled && !self.paused && !self.locked && !self.inhibited
+    }
+
+    /// Fade back to the schedule, or to neutral
+    ///
+    /// A fade that is under way turns around from where it is.
+    fn fade(&mut self, to_schedule: bool) {
+        self.scheme.short_trans_delta = if to_schedule { -1 } else { 1 };
+        self.scheme.short_trans_len = self.args.fade_duration as u16;
+        if !self.args.transition {
+            self.scheme.short_trans_delta = 0;
+            self.scheme.adjustment_alpha = if to_schedule { 0.0 } else { 1.0 };
+        }
+    }
+
+    /// `update`, counted in the metrics
+    fn timed_update(&mut self) -> Result<Option<u64>> {
+        let started = Instant::now();
+        let sleep = self.update();
+        control::record_update(started.elapsed());
+        sleep
+    }
+
+    /// Apply the offset, the held temperature, the ambient light, idle
+    /// dimming, grayscale, fades and night vision to a setting of the
+    /// schedule
+    fn adjust(&self, mut color_setting: ColorSetting) -> ColorSetting {
+        match self.manual {
+            Some(t) => color_setting.temp = t,
+            None => apply_offset(&mut color_setting, self.temp_offset, self.args.temp_limits),
+        }
+
+        // Dim, and maybe warm, in a dark room
+        if let Some(ref ambient) = self.ambient {
+            color_setting.brightness = ambient.dim(color_setting.brightness);
+            if self.manual.is_none() {
+                apply_offset(&mut color_setting, ambient.temperature_offset(), self.args.temp_limits);
+            }
+        }
+        match self.args.idle {
+            Some(ref idle) if self.idle => color_setting.brightness *= idle.brightness,
+            _ => {}
+        }
+        if self.grayscale {
+            apply_grayscale(&mut color_setting);
+        }
+
+        // Blend towards neutral while fading, or when disabled
+        let alpha = self.scheme.adjustment_alpha;
+        if alpha > 0.0 {
+            color_setting.temp = self.scheme.interpolation.mix(color_setting.temp, NEUTRAL_TEMP, alpha);
+            color_setting.brightness = alpha * 1.0 + (1.0-alpha) * color_setting.brightness;
+            color_setting.contrast = alpha * 1.0 + (1.0-alpha) * color_setting.contrast;
+            color_setting.matrix = transition::blend_matrices(&color_setting.matrix,
+                                                              &IDENTITY_MATRIX, alpha);
+            color_setting.filter = transition::blend_matrices(&color_setting.filter,
+                                                              &IDENTITY_MATRIX, alpha);
+            if alpha >= 0.5 {
+                color_setting.invert = false;
+            }
+        }
+
+        // Exiting restores the colors, without a detour through the
+        // schedule
+        if self.night_vision && !self.exiting {
+            color_setting = ColorSetting::night_vision(self.args.night_vision_brightness);
+        }
+        color_setting
+    }
+
+    /// Adjust the display for the current time
+    ///
+    /// Returns the number of milliseconds until the next update, or
+    /// `None` when done.
+    fn update(&mut self) -> Result<Option<u64>> {
+        if self.snooze.map_or(false, |until| Instant::now() >= until) {
+            info!("Snooze over");
+            self.set_enabled(true);
+        }
+        if self.manual_until.map_or(false, |until| Instant::now() >= until) {
+            info!("Hold over");
+            self.command(Command::SetTemperature(None));
+        }
+        // Nothing to see while the screen is off, wait for it to come
+        // back on. Exiting still restores the ramps.
+        if self.screen_off && !self.exiting {
+            return Ok(Some((MAX_SLEEP_S * 1000.0) as u64));
+        }
+        let now = self.clock.now();
+        self.weekday_changed(now);
+
+        // Compute elevation
+        let elev = self.elevation(now);
+        debug!("Solar elevation: {}", elev);
+
+        let period = self.scheme.get_period(elev);
+        if period != self.status.period {
+            info!("Period: {}", period);
+        }
+        if period.name() != self.status.period.name() {
+            self.observers.emit(events::Event::PeriodChanged(period));
+        }
+
+        // Pick up adjustments made with --adjust
+        let new_offset = offset::load(&self.dir);
+        if new_offset != self.temp_offset {
+            info!("Temperature offset: {:+}K", new_offset);
+            self.temp_offset = new_offset;
+        }
+        if let Some(ref mut ambient) = self.ambient {
+            ambient.advance();
+        }
+
+        /* Ongoing short transition? */
+        if self.scheme.short_transition() {
+            self.scheme.adjust_transition_alpha();
+        }
+
+        // Interpolate between 6500K and calculated temperature
+        let mut scheduled = self.scheme.interpolate_color_settings(elev);
+        self.ask_policy(now, elev, period, &mut scheduled);
+        let color_setting = self.adjust(scheduled);
+        let output_settings = outputs::settings(&self.args.outputs, |temperatures| {
+            self.adjust(self.scheme.interpolate_with_temperatures(elev, temperatures))
+        });
+
+        // Fade across a jump of the location
+        let color_setting = match self.location_jump.take() {
+            Some((from, since)) => {
+                // As long as the fade when toggling
+                let alpha = secs(since.elapsed()) / self.args.fade_duration;
+                if alpha < 1.0 {
+                    self.location_jump = Some((from.clone(), since));
+                    from.blend(&color_setting, alpha, self.scheme.interpolation)
+                } else {
+                    color_setting
+                }
+            }
+            None => color_setting,
+        };
+
+        // Smooth out the jitter of the ambient light or the location
+        let color_setting = self.smooth(color_setting);
+
+        // Hold back steps of a transition too small to see
+        let threshold = self.args.update_threshold;
+        let steady = match period {
+            Period::Transition(_) => self.scheme.short_transition() || self.location_jump.is_some(),
+            _ => true,
+        };
+        let (color_setting, output_settings) = if !steady && threshold > 0 &&
+            small_step(&self.status.setting, &color_setting, threshold) &&
+            small_output_steps(&self.output_settings, &output_settings, threshold) {
+            (self.status.setting.clone(), self.output_settings.clone())
+        } else {
+            (color_setting, output_settings)
+        };
+
+        let overridden = self.ramps_overridden();
+        let prev = &self.status.setting;
+        if color_setting.temp != prev.temp {
+            info!("Color temperature: {}K", color_setting.temp);
+        }
+        if color_setting.brightness != prev.brightness {
+            info!("Brightness: {:.2}", color_setting.brightness);
+        }
+        let reapply = self.gamma_state.reapply_interval_ms();
+        let changed = color_setting != *prev || period != self.status.period ||
+            self.enabled != self.status.enabled || self.night_vision != self.status.night_vision;
+        if color_setting != *prev || output_settings != self.output_settings || overridden {
+            if self.args.dry_run {
+                println!("{}  {}  {}K  brightness {:.2}",
+                         format_time(now), period, color_setting.temp,
+                         color_setting.brightness);
+            }
+            if let Err(e) = self.gamma_state.set_output_temperatures(&color_setting, &output_settings) {
+                self.observers.emit(events::Event::BackendError(e.to_string()));
+                return Err(e);
+            }
+            self.observers.emit(events::Event::TemperatureApplied(color_setting.clone()));
+        } else if reapply.is_some() && !self.args.dry_run {
+            // Next step of a dither, nothing worth reporting
+            if let Err(e) = self.gamma_state.set_output_temperatures(&color_setting, &output_settings) {
+                self.observers.emit(events::Event::BackendError(e.to_string()));
+                return Err(e);
+            }
+        }
+
+        self.status.period = period;
+        self.status.elevation = elev;
+        self.status.setting = color_setting;
+        self.output_settings = output_settings;
+        self.status.enabled = self.enabled;
+        self.status.night_vision = self.night_vision && !self.exiting;
+        if changed {
+            self.observers.emit(events::Event::StatusChanged(self.status.clone()));
+            // Not every step of a fade is worth a report
+            if !self.scheme.short_transition() {
+                systemd::notify(&format!("STATUS={}", self.status_line()));
+            }
+        }
+
+        if self.exiting && !self.scheme.short_transition() {
+            return Ok(None);
+        }
+        if self.clock.finished() {
+            return Ok(None);
+        }
+
+        // Sleep for the fade interval during fades, also across a jump
+        // of the location (0.1 second by default), and 1 second while
+        // following a change of the ambient light or while smoothing. During transitions
+        // sleep for the update interval (5 seconds by default). At day
+        // and night nothing changes until the next transition, unless
+        // an event wakes the loop up earlier.
+        let ms = if self.scheme.short_transition() || self.location_jump.is_some() {
+            (self.scheme.fade_interval * 1000.0) as u64
+        } else if !self.ambient.as_ref().map_or(true, Ambient::settled) || !self.smoothing_settled() {
+            1000
+        } else if let Period::Transition(_) = period {
+            (self.args.update_interval * 1000.0) as u64
+        } else {
+            let wait = match self.solar_table {
+                Some(ref table) => self.scheme.next_period_change_by(|t| table.elevation(t), now, MAX_SLEEP_S),
+                None => self.scheme.next_period_change(&self.args.location, now, MAX_SLEEP_S),
+            };
+            ((wait * 1000.0) as u64).max(100)
+        };
+        let ms = self.clock.scale_sleep(ms);
+        let ms = reapply.map_or(ms, |interval| ms.min(interval));
+        Ok(Some(self.args.fight_back.map_or(ms, |secs| ms.min((secs * 1000.0) as u64))))
+    }
+
+    /// Let the program of the `[policy]` section decide the setting,
+    /// and stop following it once it fails
+    fn ask_policy(&mut self, now: f64, elev: f64, period: Period, setting: &mut ColorSetting) {
+        let result = match self.policy {
+            Some(ref mut policy) => {
+                let query = policy::Query {
+                    time: now,
+                    elevation: elev,
+                    location: self.args.location,
+                    period: period,
+                    setting: &setting.clone(),
+                    lux: self.ambient.as_ref().and_then(Ambient::level),
+                };
+                policy.decide(&query, setting)
+            }
+            None => return,
+        };
+        if let Err(e) = result {
+            warn!("Not following the policy program any more: {}", e);
+            self.policy = None;
+        }
+    }
+
+    /// The solar elevation at the time, looked up in the table of the
+    /// day with `low-power`
+    fn elevation(&mut self, now: f64) -> f64 {
+        if !self.args.low_power {
+            self.solar_table = None;
+            return solar::elevation(now, &self.args.location);
+        }
+        let location = self.args.location;
+        if let Some(ref table) = self.solar_table {
+            // Computed again once a day, and for another location
+            if table.covers(&location, now) && !table.covers(&location, now - SOLAR_TABLE_S) {
+                return table.elevation(now);
+            }
+        }
+        debug!("Computing the solar elevations of the next day");
+        let table = solar::Table::new(&location, now, SOLAR_TABLE_S + MAX_SLEEP_S);
+        let elev = table.elevation(now);
+        self.solar_table = Some(table);
+        elev
+    }
+
+    /// Follow the target temperature with the time constant of
+    /// `smoothing`, if any
+    ///
+    /// Fades, held temperatures and night vision are followed right
+    /// away, they don't jitter.
+    fn smooth(&mut self, mut setting: ColorSetting) -> ColorSetting {
+        let tau = match self.args.smoothing {
+            Some(tau) if !self.scheme.short_transition() && self.location_jump.is_none() &&
+                self.manual.is_none() && !self.night_vision => tau,
+            _ => {
+                self.smoothed = None;
+                return setting;
+            }
+        };
+        let now = Instant::now();
+        let target = setting.temp as f64;
+        let temp = match self.smoothed {
+            Some((temp, _, since)) => target + (temp - target) * (-secs(now - since) / tau).exp(),
+            None => target,
+        };
+        self.smoothed = Some((temp, setting.temp, now));
+        setting.temp = temp.round() as i32;
+        setting
+    }
+
+    /// Whether the smoothed temperature has caught up with its target
+    fn smoothing_settled(&self) -> bool {
+        self.smoothed.map_or(true, |(temp, target, _)| (temp - target as f64).abs() < 1.0)
+    }
+
+    /// Whether another program changed the ramps, checked every so
+    /// often with `fight-back`
+    ///
+    /// Some games and drivers reset the ramps without telling anyone,
+    /// and the setting is applied again when they do. Methods that
+    /// can't read the ramps back get it again blindly at every check,
+    /// those that can only when theirs were changed, also with several
+    /// displays.
+    fn ramps_overridden(&mut self) -> bool {
+        let interval = match self.args.fight_back {
+            Some(secs) if !self.args.dry_run && !self.exiting => secs,
+            _ => return false,
+        };
+        if secs(self.verified.elapsed()) < interval {
+            return false;
+        }
+        self.verified = Instant::now();
+        match self.gamma_state.verify() {
+            Ok(Some(true)) => false,
+            Ok(Some(false)) => {
+                info!("The gamma ramps were changed by another program, applying them again");
+                true
+            }
+            Ok(None) => {
+                debug!("The gamma ramps can't be read back, applying them again");
+                true
+            }
+            Err(e) => {
+                debug!("Could not check the gamma ramps: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Report jumps of the system clock
+///
+/// The timer of the main loop runs on the monotonic clock, which
+/// doesn't advance during suspend and isn't affected when the system
+/// clock is set, e.g. by NTP after booting. Either would leave the
+/// schedule wrong until the timer runs out, which can take a long time
+/// at day and night. Comparing the two clocks finds such jumps.
+///
+/// On Linux the thread sleeps on a timer of the system clock that the
+/// kernel cancels when the clock is set, and on resume, so that the
+/// clocks are only compared then.
+#[cfg(target_os = "linux")]
+fn watch_clock(tx: chan::Sender<Event>) {
+    let fd = unsafe { libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_CLOEXEC) };
+    if fd < 0 {
+        warn!("Not watching the system clock: {}", io::Error::last_os_error());
+        return;
+    }
+    let mut timer = unsafe { File::from_raw_fd(fd) };
+    let mut wall = systemtime_get_time();
+    let mut monotonic = Instant::now();
+    loop {
+        // Due a year ahead, it is only there to be cancelled
+        let due = libc::itimerspec {
+            it_interval: libc::timespec { tv_sec: 0, tv_nsec: 0 },
+            it_value: libc::timespec { tv_sec: systemtime_get_time() as libc::time_t + 365 * 86400, tv_nsec: 0 },
+        };
+        let flags = libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET;
+        if unsafe { libc::timerfd_settime(fd, flags, &due, ptr::null_mut()) } != 0 {
+            warn!("Not watching the system clock: {}", io::Error::last_os_error());
+            return;
+        }
+        match timer.read(&mut [0; 8]) {
+            Err(ref e) if e.raw_os_error() == Some(libc::ECANCELED) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                warn!("Not watching the system clock: {}", e);
+                return;
+            }
+            Ok(_) => continue,
+        }
+        let (new_wall, new_monotonic) = (systemtime_get_time(), Instant::now());
+        let elapsed = duration_ms(new_monotonic - monotonic) as f64 / 1000.0;
+        let jump = (new_wall - wall) - elapsed;
+        if jump.abs() >= CLOCK_JUMP_S {
+            tx.send(Event::ClockJump(jump));
+        }
+        wall = new_wall;
+        monotonic = new_monotonic;
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_clock(tx: chan::Sender<Event>) {
+    let mut wall = systemtime_get_time();
+    let mut monotonic = Instant::now();
+    loop {
+        thread::sleep(Duration::from_millis(CLOCK_CHECK_MS));
+        let (new_wall, new_monotonic) = (systemtime_get_time(), Instant::now());
+        let elapsed = duration_ms(new_monotonic - monotonic) as f64 / 1000.0;
+        let jump = (new_wall - wall) - elapsed;
+        if jump.abs() >= CLOCK_JUMP_S {
+            tx.send(Event::ClockJump(jump));
+        }
+        wall = new_wall;
+        monotonic = new_monotonic;
+    }
+}
+
+/// Whether `to` differs from `from` by less than `threshold` Kelvin in
+/// temperature, and in nothing else
+fn small_step(from: &ColorSetting, to: &ColorSetting, threshold: i32) -> bool {
+    (to.temp - from.temp).abs() < threshold && *from == ColorSetting { temp: from.temp, ..to.clone() }
+}
+
+/// Like `small_step`, for the settings of each output
+fn small_output_steps(from: &[gamma::OutputSetting], to: &[gamma::OutputSetting], threshold: i32) -> bool {
+    from.len() == to.len() && from.iter().zip(to).all(|(from, to)| {
+        from.output == to.output && match (from.setting.as_ref(), to.setting.as_ref()) {
+            (Some(from), Some(to)) => small_step(from, to, threshold),
+            (from, to) => from == to,
+        }
+    })
+}
+
+/// Report the location printed by the command of the exec provider
+/// when it differs from the last one, running it every `interval`
+/// seconds
+fn watch_exec_location(tx: chan::Sender<Event>, mut last: location::Location, interval: f64) {
+    loop {
+        thread::sleep(Duration::from_millis((interval * 1000.0) as u64));
+        match location::determine(Some("exec")) {
+            Ok(l) if l != last => {
+                tx.send(Event::ExecLocation(l));
+                last = l;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+/// The timer of the main loop, a single thread that is set to a new
+/// deadline on every pass
+///
+/// A `chan::after_ms` per pass would leave a thread behind whenever an
+/// event wakes the loop early, each sleeping until its time is up, up
+/// to `MAX_SLEEP_S`.
+struct Timer {
+    deadline: Arc<(Mutex<TimerState>, Condvar)>,
+    /// Receives each deadline as it is reached
+    fired: chan::Receiver<Instant>,
+}
+
+struct TimerState {
+    deadline: Option<Instant>,
+    dropped: bool,
+}
+
+impl Timer {
+    fn new() -> Timer {
+        let deadline = Arc::new((Mutex::new(TimerState { deadline: None, dropped: false }),
+                                 Condvar::new()));
+        let (tx, fired) = chan::async();
+        let shared = deadline.clone();
+        thread::spawn(move || {
+            let (ref state, ref changed) = *shared;
+            let mut state = state.lock().unwrap();
+            while !state.dropped {
+                let now = Instant::now();
+                state = match state.deadline {
+                    Some(deadline) if deadline <= now => {
+                        state.deadline = None;
+                        tx.send(deadline);
+                        state
+                    }
+                    Some(deadline) => changed.wait_timeout(state, deadline - now).unwrap().0,
+                    None => changed.wait(state).unwrap(),
+                };
+            }
+        });
+        Timer { deadline: deadline, fired: fired }
+    }
+
+    /// Fire at the given time, instead of any deadline set before
+    fn set(&self, deadline: Instant) {
+        let (ref state, ref changed) = *self.deadline;
+        state.lock().unwrap().deadline = Some(deadline);
+        changed.notify_one();
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        let (ref state, ref changed) = *self.deadline;
+        state.lock().unwrap().dropped = true;
+        changed.notify_one();
+    }
+}
+
+/// Source of the current time for continual mode
+///
+/// Normally this is the time of the underlying clock, the system time
+/// when running. When simulating a day, time passes faster so that a
+/// full day elapses during the requested duration, after which the
+/// clock is finished.
+struct ScaledClock {
+    source: Box<clock::Clock>,
+    real_start: f64,
+    factor: f64,
+}
+
+impl ScaledClock {
+    fn new(source: Box<clock::Clock>, simulate_day: Option<f64>) -> ScaledClock {
+        ScaledClock {
+            real_start: source.now(),
+            source: source,
+            factor: simulate_day.map_or(1.0, |d| 86400.0 / d),
+        }
+    }
+
+    fn now(&self) -> f64 {
+        let real = self.source.now();
+        self.real_start + (real - self.real_start) * self.factor
+    }
+
+    fn finished(&self) -> bool {
+        self.factor != 1.0 && self.now() - self.real_start >= 86400.0
+    }
+
+    /// Scale a sleep interval to the speed of the clock
+    ///
+    /// Sleeping is capped to 10ms when simulating, to keep the
+    /// simulated updates reasonably fine-grained.
+    fn scale_sleep(&self, ms: u64) -> u64 {
+        if self.factor == 1.0 {
+            ms
+        } else {
+            ((ms as f64 / self.factor) as u64).max(10)
+        }
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+fn duration_ms(d: Duration) -> u64 {
+    d.as_secs() * 1000 + d.subsec_nanos() as u64 / 1_000_000
+}
+
+/// Format a time given in seconds since the epoch as local time
+pub fn format_time(t: f64) -> String {
+    let tm = time::at(time::Timespec::new(t as i64, 0));
+    time::strftime("%Y-%m-%d %H:%M:%S", &tm).unwrap_or_else(|_| format!("{}", t))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clock::TestClock;
+
+    #[test]
+    fn simulated_day() {
+        let source = TestClock::new(1592650800.0);
+        let clock = ScaledClock::new(Box::new(source.clone()), Some(120.0));
+        assert_eq!(clock.scale_sleep(5000), 10);
+        assert_eq!(clock.scale_sleep(1_800_000), 2500);
+
+        source.advance(60.0);
+        assert_eq!(clock.now(), 1592650800.0 + 43200.0);
+        assert!(!clock.finished());
+        source.advance(60.0);
+        assert!(clock.finished());
+
+        let clock = ScaledClock::new(Box::new(source.clone()), None);
+        source.advance(86400.0);
+        assert_eq!(clock.now(), 1592650800.0 + 120.0 + 86400.0);
+        assert!(!clock.finished());
+    }
+
+    #[test]
+    fn small_steps() {
+        let from = ColorSetting { temp: 4000, ..ColorSetting::neutral() };
+        assert!(small_step(&from, &ColorSetting { temp: 4020, ..from.clone() }, 25));
+        assert!(!small_step(&from, &ColorSetting { temp: 4025, ..from.clone() }, 25));
+        assert!(!small_step(&from, &ColorSetting { brightness: 0.9, ..from.clone() }, 25));
+        assert!(!small_step(&ColorSetting::new(), &from, 25));
+    }
+}
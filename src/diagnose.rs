@@ -0,0 +1,169 @@
+//! Self-check
+//!
+//! `redshift-rs --diagnose` tries every gamma method and location
+//! provider compiled in, lists the outputs each method finds, and
+//! looks for other night modes, printing a report to paste into bug
+//! reports:
+//!
+//! ```text
+//! $ redshift-rs --diagnose
+//! redshift-rs 0.1.1 (randr, hyprland)
+//! Session: x11, DISPLAY=:0, WAYLAND_DISPLAY unset, desktop GNOME
+//! Configuration: /home/user/.config/redshift-rs/redshift.conf (ok)
+//!
+//! Gamma methods:
+//!   dummy      ok, no outputs
+//!   hyprland   failed: hyprland: No such file or directory (os error 2)
+//!   randr      ok
+//!     eDP-1    DEL DELL U2415, CRTC 63, 1024 entries
+//! Location providers:
+//!   manual     55.70 N, 12.60 E
+//!   timezone   55.68 N, 12.57 E
+//! Other night modes: GNOME Night Light
+//!
+//! Summary: 2 of 3 gamma methods and 2 of 2 location providers work, 1 conflicting night mode
+//! ```
+//!
+//! Methods are connected to as with `-m`, `-d` and `--wayland-display`
+//! but not started, so the ramps are left alone. A broken configuration
+//! file is reported rather than stopping the check.
+
+use std::env;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+
+use config;
+use conflicts;
+use gamma;
+use location;
+use super::{Args, Result};
+
+const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+/// Cargo features and whether each was compiled in
+const FEATURES: &'static [(&'static str, bool)] = &[
+    ("randr", cfg!(feature = "randr")),
+    ("hyprland", cfg!(feature = "hyprland")),
+    ("geoclue2", cfg!(feature = "geoclue2")),
+    ("portal", cfg!(feature = "portal")),
+    ("dbus", cfg!(feature = "dbus")),
+    ("dbus-control", cfg!(feature = "dbus-control")),
+    ("metrics", cfg!(feature = "metrics")),
+];
+
+/// Width of the name column
+const NAME_WIDTH: usize = 10;
+
+pub fn run(matches: &ArgMatches) -> Result<i32> {
+    let features: Vec<&str> = FEATURES.iter().filter(|f| f.1).map(|f| f.0).collect();
+    println!("redshift-rs {} ({})", VERSION,
+             if features.is_empty() { "no features".to_owned() } else { features.join(", ") });
+    println!("Session: {}, {}, {}, desktop {}",
+             env::var("XDG_SESSION_TYPE").unwrap_or_else(|_| "unknown".to_owned()),
+             variable("DISPLAY"), variable("WAYLAND_DISPLAY"),
+             env::var("XDG_CURRENT_DESKTOP").unwrap_or_else(|_| "unknown".to_owned()));
+
+    let path = matches.value_of("config").map(PathBuf::from).or_else(config::default_path);
+    let loaded = Args::from_matches(matches, None, &[]);
+    match path {
+        None => println!("Configuration: no default location"),
+        Some(ref p) if !p.is_file() => println!("Configuration: {} (not found)", p.display()),
+        Some(ref p) => match loaded {
+            Ok(_) => println!("Configuration: {} (ok)", p.display()),
+            Err(ref e) => println!("Configuration: {} ({})", p.display(), e),
+        },
+    }
+    let options = match loaded {
+        Ok(args) => args.gamma_options,
+        Err(_) => gamma::Options {
+            display: matches.value_of("display").map(ToOwned::to_owned),
+            wayland_display: matches.value_of("wayland-display").map(ToOwned::to_owned),
+            ..gamma::Options::default()
+        },
+    };
+
+    println!("\nGamma methods:");
+    let methods = gamma::methods();
+    let mut working = 0;
+    for name in &methods {
+        match gamma::init_gamma_method(Some(name), &options) {
+            Err(e) => println!("  {:w$} failed: {}", name, e, w = NAME_WIDTH),
+            Ok(ref method) if !method.is_available() =>
+                println!("  {:w$} connected, but can't adjust this display", name, w = NAME_WIDTH),
+            Ok(method) => {
+                working += 1;
+                let outputs = method.output_info();
+                println!("  {:w$} ok{}", name, if outputs.is_empty() { ", no outputs" } else { "" },
+                         w = NAME_WIDTH);
+                for output in outputs {
+                    println!("    {:w$} {}", output.name, describe(&output), w = NAME_WIDTH);
+                }
+            }
+        }
+    }
+    for name in gamma::disabled_methods() {
+        println!("  {:w$} not compiled in", name, w = NAME_WIDTH);
+    }
+
+    println!("Location providers:");
+    let providers = location::providers();
+    let mut located = 0;
+    for name in &providers {
+        match location::determine(Some(name)) {
+            Ok(l) => {
+                located += 1;
+                println!("  {:w$} {}", name, l, w = NAME_WIDTH);
+            }
+            Err(e) => println!("  {:w$} failed: {}", name, e, w = NAME_WIDTH),
+        }
+    }
+    for name in location::disabled_providers() {
+        println!("  {:w$} not compiled in", name, w = NAME_WIDTH);
+    }
+
+    let conflicting = conflicts::enabled();
+    println!("Other night modes: {}",
+             if conflicting.is_empty() { "none".to_owned() } else { conflicting.join(", ") });
+
+    println!("\nSummary: {} of {} gamma methods and {} of {} location providers work, \
+              {} conflicting night mode{}",
+             working, methods.len(), located, providers.len(),
+             conflicting.len(), if conflicting.len() == 1 { "" } else { "s" });
+    Ok(if working > 0 { 0 } else { 1 })
+}
+
+/// `NAME=value`, or `NAME unset`
+fn variable(name: &str) -> String {
+    match env::var(name) {
+        Ok(value) => format!("{}={}", name, value),
+        Err(_) => format!("{} unset", name),
+    }
+}
+
+/// The monitor, CRTC and ramp size of an output, those that are known
+fn describe(output: &gamma::OutputInfo) -> String {
+    let mut parts = vec![];
+    parts.extend(output.model.clone());
+    parts.extend(output.crtc.map(|c| format!("CRTC {}", c)));
+    parts.extend(output.ramp_size.map(|n| format!("{} entries", n)));
+    if !output.adjusted {
+        parts.push("left out by the method options".to_owned());
+    }
+    if parts.is_empty() { "no details".to_owned() } else { parts.join(", ") }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn output_details() {
+        let mut output = gamma::OutputInfo::named("DP-1".to_owned());
+        assert_eq!(describe(&output), "no details");
+        output.crtc = Some(63);
+        output.ramp_size = Some(1024);
+        output.adjusted = false;
+        assert_eq!(describe(&output), "CRTC 63, 1024 entries, left out by the method options");
+    }
+}
@@ -0,0 +1,108 @@
+//! Display power state
+//!
+//! Adjusting a display that is powered off is wasted work, and some
+//! drivers reset the gamma ramps while it is off anyway. The daemon
+//! therefore stops updating while the screen is off, and applies the
+//! current setting again as soon as it powers back on.
+//!
+//! On X the state is read from the DPMS extension. It has no events of
+//! its own, but the server activates the screensaver when powering the
+//! screen off and resets it when powering it on, so the state is read
+//! again on the notifications of MIT-SCREEN-SAVER. When the screensaver
+//! was already active, the screen is instead looked at once more when
+//! the first DPMS timeout is due. Other displays count as always on.
+
+use chan;
+
+/// Watch for the screen powering off and on again
+///
+/// The channel receives `true` when the screen goes off (standby,
+/// suspend or off) and `false` when it comes back on. `None` if the
+/// power state can't be followed.
+#[cfg(feature = "randr")]
+pub fn watch(display: Option<&str>) -> Option<chan::Receiver<bool>> {
+    x11::watch(display)
+}
+
+#[cfg(not(feature = "randr"))]
+pub fn watch(_: Option<&str>) -> Option<chan::Receiver<bool>> {
+    None
+}
+
+#[cfg(feature = "randr")]
+mod x11 {
+    use std::thread;
+    use std::time::Duration;
+
+    use chan;
+    use xcb;
+    use xcb::{dpms, screensaver};
+
+    fn screen_off(conn: &xcb::Connection) -> Option<bool> {
+        dpms::info(conn).get_reply().ok()
+            .map(|r| r.state() && r.power_level() != dpms::DPMS_MODE_ON as u16)
+    }
+
+    /// How long until DPMS is due to power the screen off, while the
+    /// screensaver is active and so won't be activated again for it
+    fn until_due(conn: &xcb::Connection, root: xcb::Window) -> Option<Duration> {
+        let saver = screensaver::query_info(conn, root).get_reply().ok()?;
+        if saver.state() as u32 != screensaver::STATE_ON {
+            return None;
+        }
+        let timeouts = dpms::get_timeouts(conn).get_reply().ok()?;
+        let first = [timeouts.standby_timeout(), timeouts.suspend_timeout(), timeouts.off_timeout()]
+            .iter().cloned().filter(|&t| t > 0).min()?;
+        let (due, idle) = (first as u64 * 1000, saver.ms_since_user_input() as u64);
+        if due > idle { Some(Duration::from_millis(due - idle)) } else { None }
+    }
+
+    pub fn watch(display: Option<&str>) -> Option<chan::Receiver<bool>> {
+        let (conn, _) = match xcb::Connection::connect(display) {
+            Ok(c) => c,
+            Err(_) => return None,
+        };
+        let capable = dpms::capable(&conn).get_reply().map(|r| r.capable()).unwrap_or(false);
+        if !capable {
+            debug!("DPMS not supported, not following the power state of the screen");
+            return None;
+        }
+        if conn.get_extension_data(screensaver::id()).map_or(true, |e| !e.present()) {
+            debug!("MIT-SCREEN-SAVER not supported, not following the power state of the screen");
+            return None;
+        }
+        drop(conn);
+
+        let display = display.map(ToOwned::to_owned);
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            // The connection is not Send, so the thread makes its own
+            let (conn, screen_num) = match xcb::Connection::connect(display.as_ref().map(|s| &s[..])) {
+                Ok(c) => c,
+                Err(_) => return,
+            };
+            let root = conn.get_setup().roots().nth(screen_num as usize).unwrap().root();
+            screensaver::select_input(&conn, root, screensaver::EVENT_NOTIFY_MASK);
+            conn.flush();
+            let mut off = false;
+            loop {
+                match screen_off(&conn) {
+                    Some(now) if now != off => {
+                        off = now;
+                        tx.send(off);
+                    }
+                    Some(_) => {}
+                    None => return,
+                }
+                // Notifications that arrive while sleeping are read next
+                let due = if off { None } else { until_due(&conn, root) };
+                if let Some(wait) = due {
+                    thread::sleep(wait);
+                } else if conn.wait_for_event().is_none() {
+                    return;
+                }
+            }
+        });
+        Some(rx)
+    }
+}
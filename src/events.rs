@@ -0,0 +1,113 @@
+//! Notifications of state changes
+//!
+//! Rather than polling the status, observers subscribe to a channel of
+//! `Event`s. Every subscriber receives every event, in the order they
+//! happened:
+//!
+//! ```no_run
+//! extern crate redshift_rs;
+//!
+//! use redshift_rs::RedshiftBuilder;
+//! use redshift_rs::events::Event;
+//!
+//! # fn main() {
+//! let mut redshift = RedshiftBuilder::new().build().unwrap();
+//! let events = redshift.subscribe();
+//! redshift.update().unwrap();
+//! // The first update enters a period and applies its setting
+//! for event in events.iter().take(2) {
+//!     if let Event::TemperatureApplied(setting) = event {
+//!         println!("{}K", setting.temp);
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Both `Redshift` and the daemon of the binary publish their events
+//! this way; the status file and the D-Bus interface of the daemon are
+//! observers like any other.
+
+use chan;
+use location::Location;
+use status::Status;
+use transition::{ColorSetting, Period};
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// It became day, night or a transition between the two
+    ///
+    /// The progress of a transition is reported by `StatusChanged`,
+    /// not by this event.
+    PeriodChanged(Period),
+
+    /// A setting was applied to the display
+    TemperatureApplied(ColorSetting),
+
+    /// The gamma method failed to apply a setting
+    BackendError(String),
+
+    /// The schedule follows a new location
+    LocationUpdated(Location),
+
+    /// Anything in the status changed, with the new status
+    StatusChanged(Status),
+}
+
+/// The subscribers of a source of events
+pub struct Observers {
+    senders: Vec<chan::Sender<Event>>,
+}
+
+impl Observers {
+    pub fn new() -> Observers {
+        Observers { senders: vec![] }
+    }
+
+    /// Receive every event from now on
+    pub fn subscribe(&mut self) -> chan::Receiver<Event> {
+        let (tx, rx) = chan::async();
+        self.add(tx);
+        rx
+    }
+
+    /// Send every event from now on to an existing channel
+    pub fn add(&mut self, tx: chan::Sender<Event>) {
+        self.senders.push(tx);
+    }
+
+    pub fn emit(&self, event: Event) {
+        for tx in &self.senders {
+            tx.send(event.clone());
+        }
+    }
+}
+
+impl Default for Observers {
+    fn default() -> Observers {
+        Observers::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_subscriber() {
+        let mut observers = Observers::new();
+        observers.emit(Event::BackendError("unseen".to_owned()));
+        let (first, second) = (observers.subscribe(), observers.subscribe());
+        observers.emit(Event::PeriodChanged(Period::Night));
+        observers.emit(Event::LocationUpdated(Location::new(55.7, 12.6)));
+        for rx in &[first, second] {
+            match rx.recv() {
+                Some(Event::PeriodChanged(Period::Night)) => {}
+                e => panic!("unexpected {:?}", e),
+            }
+            match rx.recv() {
+                Some(Event::LocationUpdated(l)) => assert_eq!(l, Location::new(55.7, 12.6)),
+                e => panic!("unexpected {:?}", e),
+            }
+        }
+    }
+}
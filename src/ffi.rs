@@ -0,0 +1,259 @@
+//! C interface
+//!
+//! A small C ABI over the gamma methods and the day/night schedule,
+//! for desktop components not written in Rust. The crate is built as
+//! a cdylib too, with the functions declared in `include/redshift_rs.h`.
+//!
+//! `redshift_init` starts the named gamma method, or picks one when
+//! `method` is NULL, and returns NULL on failure. The other functions
+//! return 0 on success, `ERROR` on failure and `PANICKED` when redshift-rs
+//! panicked, which doesn't unwind into C. `redshift_update` applies
+//! the scheduled temperature for the current time at the location,
+//! calling the period callback whenever the period changed since the
+//! last update; periods are numbered as `PERIOD_*` below.
+//! `redshift_shutdown` restores the ramps and frees the handle.
+//!
+//! The header has to be kept in step with this module by hand.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_double, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use controller::{Redshift, RedshiftBuilder};
+use location::Location;
+use transition::{ColorSetting, Period};
+
+pub const PERIOD_NONE: c_int = 0;
+pub const PERIOD_DAY: c_int = 1;
+pub const PERIOD_NIGHT: c_int = 2;
+pub const PERIOD_TRANSITION: c_int = 3;
+
+pub const ERROR: c_int = -1;
+pub const PANICKED: c_int = -2;
+
+/// Callback for period changes, with the user data given along
+pub type PeriodCallback = extern "C" fn(c_int, *mut c_void);
+
+/// The handle passed to C as `redshift *`
+pub struct Handle {
+    redshift: Redshift,
+    period: Period,
+    callback: Option<(PeriodCallback, *mut c_void)>,
+}
+
+fn period_number(period: Period) -> c_int {
+    match period {
+        Period::None => PERIOD_NONE,
+        Period::Day => PERIOD_DAY,
+        Period::Night => PERIOD_NIGHT,
+        Period::Transition(_) => PERIOD_TRANSITION,
+    }
+}
+
+fn status<E: ::std::fmt::Display>(result: Result<(), E>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("{}", e);
+            ERROR
+        }
+    }
+}
+
+/// Run the body of a function, returning `panicked` if it panics
+///
+/// Unwinding out of an `extern "C"` function aborts the process, or
+/// worse with older compilers.
+fn guard<T, F: FnOnce() -> T>(panicked: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        error!("Panicked in a function of the C interface");
+        panicked
+    })
+}
+
+/// Start a gamma method
+///
+/// # Safety
+///
+/// `method` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_init(method: *const c_char) -> *mut Handle {
+    guard(ptr::null_mut(), || init(method))
+}
+
+unsafe fn init(method: *const c_char) -> *mut Handle {
+    let name = if method.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(method).to_str() {
+            Ok(s) => Some(s),
+            Err(_) => return ptr::null_mut(),
+        }
+    };
+    let mut builder = RedshiftBuilder::new();
+    if let Some(name) = name {
+        builder = builder.method(name);
+    }
+    match builder.build() {
+        Ok(redshift) => Box::into_raw(Box::new(Handle {
+            redshift: redshift,
+            period: Period::None,
+            callback: None,
+        })),
+        Err(e) => {
+            error!("{}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Set the location the schedule is computed for
+///
+/// # Safety
+///
+/// Like for all functions taking a handle, `handle` must be NULL or
+/// returned by `redshift_init` and not shut down yet.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_set_location(handle: *mut Handle, lat: c_double, lon: c_double) -> c_int {
+    guard(PANICKED, || match handle.as_mut() {
+        Some(h) if lat.abs() <= 90.0 && lon.abs() <= 180.0 => {
+            h.redshift.set_location(Location::new(lat, lon));
+            0
+        }
+        _ => ERROR,
+    })
+}
+
+/// Apply a temperature right away, regardless of the schedule
+///
+/// Fails for a temperature out of range, and for a brightness that
+/// is not between 0.1 and 1.0.
+///
+/// # Safety
+///
+/// See `redshift_set_location`.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_set_temperature(handle: *mut Handle, temp: c_int,
+                                                  brightness: c_double) -> c_int {
+    guard(PANICKED, || match handle.as_mut() {
+        Some(h) => status(h.redshift.set_temperature(&ColorSetting {
+            temp: temp,
+            brightness: brightness,
+            ..ColorSetting::neutral()
+        })),
+        None => ERROR,
+    })
+}
+
+/// Apply the scheduled temperature for the current time
+///
+/// # Safety
+///
+/// See `redshift_set_location`. The callback is called from this
+/// function, on the same thread.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_update(handle: *mut Handle) -> c_int {
+    guard(PANICKED, || update(handle))
+}
+
+unsafe fn update(handle: *mut Handle) -> c_int {
+    let h = match handle.as_mut() {
+        Some(h) => h,
+        None => return ERROR,
+    };
+    let period = match h.redshift.update() {
+        Ok(status) => status.period,
+        Err(e) => {
+            error!("{}", e);
+            return ERROR;
+        }
+    };
+    if period_number(period) != period_number(h.period) {
+        if let Some((callback, user_data)) = h.callback {
+            callback(period_number(period), user_data);
+        }
+    }
+    h.period = period;
+    0
+}
+
+/// Set or, with a NULL callback, clear the period callback
+///
+/// # Safety
+///
+/// See `redshift_set_location`. `user_data` is passed to the callback
+/// as it is and must stay valid while the callback is set.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_set_period_callback(handle: *mut Handle,
+                                                      callback: Option<PeriodCallback>,
+                                                      user_data: *mut c_void) {
+    guard((), || if let Some(h) = handle.as_mut() {
+        h.callback = callback.map(|c| (c, user_data));
+    })
+}
+
+/// Restore the ramps found when the method was started
+///
+/// # Safety
+///
+/// See `redshift_set_location`.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_restore(handle: *mut Handle) -> c_int {
+    guard(PANICKED, || match handle.as_mut() {
+        Some(h) => status(h.redshift.restore()),
+        None => ERROR,
+    })
+}
+
+/// Restore the ramps and free the handle
+///
+/// # Safety
+///
+/// See `redshift_set_location`. The handle must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn redshift_shutdown(handle: *mut Handle) {
+    guard((), || if !handle.is_null() {
+        // Restores the ramps
+        drop(Box::from_raw(handle));
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+    use super::super::NEUTRAL_TEMP;
+
+    extern "C" fn count(period: c_int, user_data: *mut c_void) {
+        assert!(period != PERIOD_NONE);
+        unsafe { *(user_data as *mut i32) += 1 };
+    }
+
+    #[test]
+    fn dummy_handle() {
+        let name = CString::new("dummy").unwrap();
+        let mut calls = 0i32;
+        unsafe {
+            let h = redshift_init(name.as_ptr());
+            assert!(!h.is_null());
+            assert_eq!(redshift_set_location(h, 55.7, 12.6), 0);
+            assert_eq!(redshift_set_location(h, 91.0, 0.0), ERROR);
+            redshift_set_period_callback(h, Some(count), &mut calls as *mut i32 as *mut c_void);
+            assert_eq!(redshift_update(h), 0);
+            assert_eq!(redshift_update(h), 0);
+            assert_eq!(redshift_set_temperature(h, NEUTRAL_TEMP, 1.0), 0);
+            assert_eq!(redshift_set_temperature(h, NEUTRAL_TEMP, 0.0), ERROR);
+            assert_eq!(redshift_set_temperature(h, NEUTRAL_TEMP, ::std::f64::NAN), ERROR);
+            redshift_shutdown(h);
+            assert_eq!(redshift_restore(ptr::null_mut()), ERROR);
+        }
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn panics() {
+        assert_eq!(guard(0, || 1), 1);
+        assert_eq!(guard(PANICKED, || -> c_int { panic!("in Rust") }), PANICKED);
+    }
+}
@@ -0,0 +1,128 @@
+//! Fullscreen detection
+//!
+//! With `pause-fullscreen=1` in the config file, or `--pause-fullscreen`,
+//! the adjustments are paused while the active window is fullscreen,
+//! so that videos and games are shown with their real colors.
+//!
+//! On X this follows the EWMH properties of the window manager:
+//! `_NET_ACTIVE_WINDOW` on the root window, and `_NET_WM_STATE` of the
+//! active window containing `_NET_WM_STATE_FULLSCREEN`. Window
+//! managers that don't set them are not supported, and neither are
+//! Wayland compositors yet.
+
+use chan;
+
+/// Watch for the active window entering and leaving fullscreen
+///
+/// The channel receives `true` when a fullscreen window becomes
+/// active and `false` when it goes away. `None` if fullscreen windows
+/// can't be detected.
+#[cfg(feature = "randr")]
+pub fn watch(display: Option<&str>) -> Option<chan::Receiver<bool>> {
+    x11::watch(display)
+}
+
+#[cfg(not(feature = "randr"))]
+pub fn watch(_: Option<&str>) -> Option<chan::Receiver<bool>> {
+    warn!("Fullscreen windows can't be detected, support for X is not compiled in");
+    None
+}
+
+#[cfg(feature = "randr")]
+mod x11 {
+    use std::thread;
+
+    use chan;
+    use xcb;
+
+    struct Atoms {
+        active_window: xcb::Atom,
+        wm_state: xcb::Atom,
+        fullscreen: xcb::Atom,
+    }
+
+    fn intern(conn: &xcb::Connection, name: &str) -> Option<xcb::Atom> {
+        xcb::intern_atom(conn, false, name).get_reply().ok().map(|r| r.atom())
+    }
+
+    fn active_window(conn: &xcb::Connection, root: xcb::Window, atoms: &Atoms) -> Option<xcb::Window> {
+        xcb::get_property(conn, false, root, atoms.active_window, xcb::ATOM_WINDOW, 0, 1)
+            .get_reply().ok()
+            .and_then(|r| r.value::<u32>().first().cloned())
+            .filter(|&w| w != 0)
+    }
+
+    fn is_fullscreen(conn: &xcb::Connection, window: xcb::Window, atoms: &Atoms) -> bool {
+        xcb::get_property(conn, false, window, atoms.wm_state, xcb::ATOM_ATOM, 0, 32)
+            .get_reply()
+            .map(|r| r.value::<u32>().contains(&atoms.fullscreen))
+            .unwrap_or(false)
+    }
+
+    fn watch_properties(conn: &xcb::Connection, window: xcb::Window) {
+        xcb::change_window_attributes(conn, window, &[(xcb::CW_EVENT_MASK,
+                                                       xcb::EVENT_MASK_PROPERTY_CHANGE)]);
+    }
+
+    pub fn watch(display: Option<&str>) -> Option<chan::Receiver<bool>> {
+        let display = display.map(ToOwned::to_owned);
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let (conn, screen_num) = match xcb::Connection::connect(display.as_ref().map(|s| &s[..])) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Not watching for fullscreen windows: {:?}", e);
+                    return;
+                }
+            };
+            let root = conn.get_setup().roots().nth(screen_num as usize).unwrap().root();
+            let atoms = match (intern(&conn, "_NET_ACTIVE_WINDOW"),
+                               intern(&conn, "_NET_WM_STATE"),
+                               intern(&conn, "_NET_WM_STATE_FULLSCREEN")) {
+                (Some(a), Some(s), Some(f)) => Atoms { active_window: a, wm_state: s, fullscreen: f },
+                _ => {
+                    warn!("Not watching for fullscreen windows: no EWMH support");
+                    return;
+                }
+            };
+            watch_properties(&conn, root);
+
+            let mut active = None;
+            let mut fullscreen = false;
+            loop {
+                // Follow the state of the active window, whichever
+                // window that currently is
+                let window = active_window(&conn, root, &atoms);
+                if window != active {
+                    if let Some(w) = window {
+                        watch_properties(&conn, w);
+                    }
+                    active = window;
+                }
+                conn.flush();
+
+                let now = active.map_or(false, |w| is_fullscreen(&conn, w, &atoms));
+                if now != fullscreen {
+                    fullscreen = now;
+                    tx.send(fullscreen);
+                }
+
+                // Wait for a relevant property change
+                loop {
+                    let event = match conn.wait_for_event() {
+                        Some(e) => e,
+                        None => return,
+                    };
+                    if event.response_type() & !0x80 != xcb::PROPERTY_NOTIFY {
+                        continue;
+                    }
+                    let event: &xcb::PropertyNotifyEvent = xcb::cast_event(&event);
+                    if event.atom() == atoms.active_window || event.atom() == atoms.wm_state {
+                        break;
+                    }
+                }
+            }
+        });
+        Some(rx)
+    }
+}
@@ -0,0 +1,273 @@
+//! Several X displays at once
+//!
+//! Given a list of displays, as in `-d :0,:1` for the seats of a
+//! multi-seat machine, one instance of the method is started for each
+//! display and all of them get the same settings. A display that fails
+//! doesn't hold up the others: its errors are logged, and only when
+//! every display fails is it an error, unless the failure policy is
+//! strict.
+//!
+//! The outputs of each display are named after it, as in `:1/HDMI-1`.
+//! An `[output:NAME]` section with a name like that applies to the
+//! output of one display, one with a plain name to the outputs of that
+//! name on all of them.
+
+use std::thread;
+
+use chan;
+use transition::ColorSetting;
+use super::{FailurePolicy, GammaMethod, OutputInfo, OutputSetting};
+use super::super::Result;
+
+pub struct Displays {
+    /// The method of each display, by name of the display
+    methods: Vec<(String, Box<GammaMethod>)>,
+    policy: FailurePolicy,
+}
+
+impl Displays {
+    pub fn new(methods: Vec<(String, Box<GammaMethod>)>, policy: FailurePolicy) -> Displays {
+        Displays { methods: methods, policy: policy }
+    }
+
+    /// Call `f` on the method of every display, logging the errors
+    ///
+    /// Fails with the first error as the policy says.
+    fn each<F>(&mut self, mut f: F) -> Result<()>
+        where F: FnMut(&str, &mut GammaMethod) -> Result<()>
+    {
+        let results: Vec<_> = self.methods.iter_mut()
+            .map(|&mut (ref display, ref mut method)| {
+                f(display, &mut **method).map_err(|e| { warn!("Display {}: {}", display, e); e })
+            })
+            .collect();
+        self.policy.outcome(results)
+    }
+}
+
+/// The settings of the outputs of one display, out of those for all
+/// of them
+fn display_outputs(display: &str, outputs: &[OutputSetting]) -> Vec<OutputSetting> {
+    let prefix = format!("{}/", display);
+    outputs.iter()
+        .filter(|o| o.output.starts_with(&prefix) || !o.output.contains('/'))
+        .map(|o| OutputSetting {
+            output: o.output.trim_start_matches(&prefix[..]).to_owned(),
+            setting: o.setting.clone(),
+        })
+        .collect()
+}
+
+impl GammaMethod for Displays {
+    fn name(&self) -> &'static str {
+        self.methods[0].1.name()
+    }
+
+    fn is_available(&self) -> bool {
+        self.methods.iter().any(|m| m.1.is_available())
+    }
+
+    /// Displays that fail to start are left out from then on
+    fn start(&mut self) -> Result<()> {
+        let mut first = None;
+        self.methods.retain_mut(|&mut (ref display, ref mut method)| match method.start() {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Leaving out display {}: {}", display, e);
+                first = first.take().or(Some(e));
+                false
+            }
+        });
+        match first {
+            Some(e) if self.methods.is_empty() || self.policy == FailurePolicy::Strict => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+        self.each(|_, method| method.set_temperature(setting))
+    }
+
+    fn set_output_temperatures(&mut self, setting: &ColorSetting,
+                               outputs: &[OutputSetting]) -> Result<()> {
+        self.each(|display, method| {
+            method.set_output_temperatures(setting, &display_outputs(display, outputs))
+        })
+    }
+
+    /// Restores every display, failing with the first error
+    fn restore(&self) -> Result<()> {
+        let mut result = Ok(());
+        for m in self.methods.iter() {
+            let restored = m.1.restore();
+            if result.is_ok() {
+                result = restored;
+            }
+        }
+        result
+    }
+
+    fn recovered(&self) -> bool {
+        self.methods.iter().any(|m| m.1.recovered())
+    }
+
+    fn mixes_channels(&self) -> bool {
+        self.methods.iter().all(|m| m.1.mixes_channels())
+    }
+
+    fn reapply_interval_ms(&self) -> Option<u64> {
+        self.methods.iter().filter_map(|m| m.1.reapply_interval_ms()).min()
+    }
+
+    /// Every display is checked on its own. Those whose method can't
+    /// read back its ramps are invalidated, so that they alone get the
+    /// next setting in full, while the others only where their ramps
+    /// were changed. Unknown when some can't tell and none changed.
+    fn verify(&mut self) -> Result<Option<bool>> {
+        let mut changed = false;
+        let mut blind = false;
+        self.each(|_, method| {
+            match method.verify()? {
+                Some(same) => changed |= !same,
+                None => {
+                    method.invalidate();
+                    blind = true;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(if changed { Some(false) } else if blind { None } else { Some(true) })
+    }
+
+    fn invalidate(&self) {
+        for m in self.methods.iter() {
+            m.1.invalidate();
+        }
+    }
+
+    fn outputs(&self) -> Vec<String> {
+        self.methods.iter()
+            .flat_map(|m| {
+                let display = &m.0;
+                m.1.outputs().into_iter().map(move |o| format!("{}/{}", display, o))
+            })
+            .collect()
+    }
+
+    fn output_info(&self) -> Vec<OutputInfo> {
+        self.methods.iter()
+            .flat_map(|m| {
+                let display = &m.0;
+                m.1.output_info().into_iter()
+                    .map(move |o| OutputInfo { name: format!("{}/{}", display, o.name), ..o })
+            })
+            .collect()
+    }
+
+    /// Changes of any of the displays
+    fn watch(&mut self) -> Option<chan::Receiver<()>> {
+        let (tx, rx) = chan::async();
+        let mut watching = false;
+        for &mut (_, ref mut method) in self.methods.iter_mut() {
+            if let Some(changes) = method.watch() {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for _ in changes.iter() {
+                        tx.send(());
+                    }
+                });
+                watching = true;
+            }
+        }
+        if watching { Some(rx) } else { None }
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        self.each(|_, method| method.refresh())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use gamma::recording::{Call, RecordingMethod};
+    use RedshiftError;
+
+    struct Broken;
+    impl GammaMethod for Broken {
+        fn name(&self) -> &'static str { "broken" }
+        fn start(&mut self) -> Result<()> { Ok(()) }
+        fn set_temperature(&mut self, _: &ColorSetting) -> Result<()> {
+            Err(RedshiftError::GammaMethodNotFound("broken".to_owned()))
+        }
+        fn restore(&self) -> Result<()> { Ok(()) }
+    }
+
+    /// Ramps that read back as given, `None` where they can't, and
+    /// whether it was invalidated
+    struct Checked(Option<bool>, Rc<Cell<bool>>);
+    impl GammaMethod for Checked {
+        fn name(&self) -> &'static str { "checked" }
+        fn start(&mut self) -> Result<()> { Ok(()) }
+        fn set_temperature(&mut self, _: &ColorSetting) -> Result<()> { Ok(()) }
+        fn restore(&self) -> Result<()> { Ok(()) }
+        fn verify(&mut self) -> Result<Option<bool>> { Ok(self.0) }
+        fn invalidate(&self) { self.1.set(true) }
+    }
+
+    #[test]
+    fn one_failing_display() {
+        let method = RecordingMethod::new(16);
+        let recording = method.recording();
+        let mut displays = Displays::new(vec![(":0".to_owned(), Box::new(Broken) as Box<GammaMethod>),
+                                              (":1".to_owned(), Box::new(method))],
+                                         FailurePolicy::BestEffort);
+        displays.start().unwrap();
+        assert!(displays.set_temperature(&ColorSetting::neutral()).is_ok());
+        match recording.calls()[0] {
+            Call::SetTemperature(ref s, _) => assert_eq!(*s, ColorSetting::neutral()),
+            ref call => panic!("{:?}", call),
+        }
+
+        let mut broken = Displays::new(vec![(":0".to_owned(), Box::new(Broken) as Box<GammaMethod>)],
+                                       FailurePolicy::BestEffort);
+        assert!(broken.set_temperature(&ColorSetting::neutral()).is_err());
+
+        let mut strict = Displays::new(vec![(":0".to_owned(), Box::new(Broken) as Box<GammaMethod>),
+                                            (":1".to_owned(), Box::new(RecordingMethod::new(16)))],
+                                       FailurePolicy::Strict);
+        assert!(strict.set_temperature(&ColorSetting::neutral()).is_err());
+    }
+
+    #[test]
+    fn verify_each_display() {
+        let verify = |same: [Option<bool>; 2]| {
+            let invalidated = [Rc::new(Cell::new(false)), Rc::new(Cell::new(false))];
+            let mut displays = Displays::new(
+                vec![(":0".to_owned(), Box::new(Checked(same[0], invalidated[0].clone())) as Box<GammaMethod>),
+                     (":1".to_owned(), Box::new(Checked(same[1], invalidated[1].clone())))],
+                FailurePolicy::BestEffort);
+            (displays.verify().unwrap(), [invalidated[0].get(), invalidated[1].get()])
+        };
+        assert_eq!(verify([Some(true), Some(true)]), (Some(true), [false, false]));
+        assert_eq!(verify([Some(true), Some(false)]), (Some(false), [false, false]));
+        // Only the one that can't read back its ramps gets them again
+        // in full
+        assert_eq!(verify([Some(true), None]), (None, [false, true]));
+        assert_eq!(verify([Some(false), None]), (Some(false), [false, true]));
+    }
+
+    #[test]
+    fn outputs_by_display() {
+        let outputs = vec![
+            OutputSetting { output: ":1/HDMI-1".to_owned(), setting: None },
+            OutputSetting { output: ":0/DP-1".to_owned(), setting: None },
+            OutputSetting { output: "eDP-1".to_owned(), setting: None },
+        ];
+        let names: Vec<String> = display_outputs(":1", &outputs).into_iter().map(|o| o.output).collect();
+        assert_eq!(names, vec!["HDMI-1", "eDP-1"]);
+    }
+}
@@ -0,0 +1,61 @@
+//! Monitor names from EDID
+//!
+//! Output names like "DP-2" say which connector a monitor is on, not
+//! which monitor it is. The EDID block a monitor reports holds its
+//! manufacturer and, in one of four descriptors, usually its model
+//! name, which is what `--list-outputs` shows next to the output.
+
+/// Length of the base block, which is all that is read
+const BLOCK_LEN: usize = 128;
+
+/// Offsets of the four 18 byte descriptors in the base block
+const DESCRIPTORS: [usize; 4] = [54, 72, 90, 108];
+
+/// Tag of the descriptor with the model name
+const TAG_NAME: u8 = 0xfc;
+
+/// The manufacturer and model of a monitor, e.g. "DEL DELL U2415"
+///
+/// `None` if the data is not an EDID block. Without a name descriptor
+/// only the manufacturer is given.
+pub fn model(edid: &[u8]) -> Option<String> {
+    if edid.len() < BLOCK_LEN || edid[..8] != [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00] {
+        return None;
+    }
+    // Three letters of five bits each, 1 being 'A'
+    let id = (edid[8] as u16) << 8 | edid[9] as u16;
+    let manufacturer: String = [10, 5, 0].iter()
+        .map(|shift| (b'A' - 1 + ((id >> shift) & 0x1f) as u8) as char)
+        .collect();
+    let name = DESCRIPTORS.iter()
+        .map(|&offset| &edid[offset..offset + 18])
+        .find(|d| d[..3] == [0, 0, 0] && d[3] == TAG_NAME)
+        .map(|d| String::from_utf8_lossy(&d[5..]).split('\n').next().unwrap_or("").trim().to_owned())
+        .filter(|name| !name.is_empty());
+    Some(match name {
+        Some(name) => format!("{} {}", manufacturer, name),
+        None => manufacturer,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn model_names() {
+        let mut edid = vec![0; 128];
+        edid[..8].copy_from_slice(&[0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+        // "DEL"
+        edid[8] = 0x10;
+        edid[9] = 0xac;
+        assert_eq!(model(&edid).unwrap(), "DEL");
+
+        edid[72 + 3] = TAG_NAME;
+        edid[72 + 5..72 + 18].copy_from_slice(b"DELL U2415\n  ");
+        assert_eq!(model(&edid).unwrap(), "DEL DELL U2415");
+
+        assert_eq!(model(&edid[..100]), None);
+        assert_eq!(model(&[0; 128]), None);
+    }
+}
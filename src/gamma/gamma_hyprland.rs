@@ -0,0 +1,257 @@
+//! Hyprland color transform
+//!
+//! Hyprland doesn't offer wlr-gamma-control. It takes a color
+//! transformation matrix (CTM) for each output instead, through its
+//! hyprland-ctm-control-v1 protocol, which is what hyprsunset uses. A
+//! matrix applies the temperature, the brightness and all of
+//! `ColorSetting::matrix` and `ColorSetting::filter`, including the
+//! entries that mix channels, such as grayscale, but not the gamma, inversion or contrast, which bend the channels
+//! rather than scale them.
+//!
+//! The protocol takes a handful of requests, spoken with the minimal
+//! client of the `wayland` module. Only one client may hold the
+//! matrices at a time, so this fails while hyprsunset is running.
+//! Outputs plugged in later are picked up at the next update.
+//!
+//! The outputs are bound right away, binding the manager waits for
+//! start(), so that listing the outputs leaves hyprsunset alone.
+
+use std::error::Error;
+use std::io;
+
+use colorramp;
+use transition::{self, ColorSetting};
+use wayland::{Arg, Connection, Event, REGISTRY_ID, REGISTRY_GLOBAL, REGISTRY_GLOBAL_REMOVE};
+
+use super::{GammaMethod, Options, OutputSetting};
+use super::Result;
+use super::super::RedshiftError;
+
+const MANAGER_INTERFACE: &'static str = "hyprland_ctm_control_manager_v1";
+const OUTPUT_INTERFACE: &'static str = "wl_output";
+
+/// Highest versions bound, those of the `blocked` event and of output
+/// names respectively
+const MANAGER_VERSION: u32 = 2;
+const OUTPUT_VERSION: u32 = 4;
+
+// Opcodes of the requests and events used, by interface
+const OUTPUT_NAME: u16 = 4;
+const MANAGER_SET_CTM_FOR_OUTPUT: u16 = 0;
+const MANAGER_COMMIT: u16 = 1;
+const MANAGER_BLOCKED: u16 = 0;
+
+const IDENTITY: [[f64; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+fn error<E: Into<Box<Error + Send + Sync>>>(e: E) -> RedshiftError {
+    let e: Box<Error + Send + Sync> = e.into();
+    RedshiftError::Gamma("hyprland".to_owned(), e)
+}
+
+struct Output {
+    id: u32,
+    /// Name of its global in the registry
+    global: u32,
+    /// E.g. "DP-1", once the compositor sent it
+    name: String,
+}
+
+pub struct HyprlandState {
+    conn: Connection,
+    /// Name and version of the global of the CTM manager, if offered
+    manager_global: Option<(u32, u32)>,
+    /// Id of the bound manager, 0 before start()
+    manager: u32,
+    outputs: Vec<Output>,
+    /// Globals of outputs not bound yet, with their version
+    new_outputs: Vec<(u32, u32)>,
+    blocked: bool,
+    /// Whether leaving out parts of a setting was reported
+    warned: bool,
+}
+
+impl HyprlandState {
+    fn init(display: Option<&str>) -> Result<HyprlandState> {
+        let conn = Connection::connect(display).map_err(error)?;
+        let mut state = HyprlandState {
+            conn: conn,
+            manager_global: None,
+            manager: 0,
+            outputs: vec![],
+            new_outputs: vec![],
+            blocked: false,
+            warned: false,
+        };
+        state.sync()?;
+        Ok(state)
+    }
+
+    /// Handle the events sent since the last call, binding outputs
+    /// that appeared
+    fn sync(&mut self) -> Result<()> {
+        loop {
+            for event in self.conn.roundtrip().map_err(error)? {
+                self.dispatch(event).map_err(error)?;
+            }
+            if self.blocked {
+                return Err(error("another program, such as hyprsunset, holds the color transform"));
+            }
+            if self.new_outputs.is_empty() {
+                return Ok(());
+            }
+            let new_outputs: Vec<_> = self.new_outputs.drain(..).collect();
+            for (global, version) in new_outputs {
+                let id = self.conn.bind(global, OUTPUT_INTERFACE, version.min(OUTPUT_VERSION)).map_err(error)?;
+                // Outputs too old to send their name go by their global
+                let name = format!("output-{}", global);
+                self.outputs.push(Output { id: id, global: global, name: name });
+            }
+        }
+    }
+
+    fn dispatch(&mut self, mut event: Event) -> io::Result<()> {
+        match (event.object, event.opcode) {
+            (REGISTRY_ID, REGISTRY_GLOBAL) => {
+                let (global, interface, version) = (event.uint()?, event.string()?, event.uint()?);
+                if interface == MANAGER_INTERFACE {
+                    self.manager_global = Some((global, version));
+                } else if interface == OUTPUT_INTERFACE {
+                    self.new_outputs.push((global, version));
+                }
+            }
+            (REGISTRY_ID, REGISTRY_GLOBAL_REMOVE) => {
+                let global = event.uint()?;
+                self.outputs.retain(|o| o.global != global);
+                self.new_outputs.retain(|o| o.0 != global);
+            }
+            (object, MANAGER_BLOCKED) if object == self.manager => self.blocked = true,
+            (object, OUTPUT_NAME) => {
+                if let Some(output) = self.outputs.iter_mut().find(|o| o.id == object) {
+                    output.name = event.string()?;
+                    debug!("Output {}", output.name);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Set the matrices of all outputs, by name of the output
+    fn set_ctms<F: Fn(&str) -> [[f64; 3]; 3]>(&self, ctm: F) -> Result<()> {
+        for output in &self.outputs {
+            let m = ctm(&output.name);
+            let mut args = vec![Arg::Uint(output.id)];
+            args.extend(m.iter().flat_map(|row| row.iter()).map(|v| Arg::Fixed(*v)));
+            self.conn.send(self.manager, MANAGER_SET_CTM_FOR_OUTPUT, &args).map_err(error)?;
+        }
+        self.conn.send(self.manager, MANAGER_COMMIT, &[]).map_err(error)
+    }
+
+    fn warn_unsupported(&mut self, setting: &ColorSetting) {
+        if !self.warned && (setting.gamma != [1.0; 3] || setting.invert || setting.contrast != 1.0) {
+            warn!("Hyprland's color transform can't apply gamma, inversion or contrast, leaving them out");
+            self.warned = true;
+        }
+    }
+}
+
+/// The matrix scaling the channels by the whitepoint and the
+/// brightness, after `setting.filter` and before `setting.matrix`
+fn ctm(setting: &ColorSetting) -> [[f64; 3]; 3] {
+    let white = colorramp::white_point(setting.temp);
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = setting.matrix[i][j] * white[j] * setting.brightness;
+        }
+    }
+    transition::multiply_matrices(&m, &setting.filter)
+}
+
+impl GammaMethod for HyprlandState {
+    fn name(&self) -> &'static str {
+        "hyprland"
+    }
+
+    /// Only Hyprland offers the protocol
+    fn is_available(&self) -> bool {
+        self.manager_global.is_some()
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let (global, version) = self.manager_global
+            .ok_or_else(|| error("the compositor has no color transform control, it is not Hyprland"))?;
+        self.manager = self.conn.bind(global, MANAGER_INTERFACE, version.min(MANAGER_VERSION)).map_err(error)?;
+        self.sync()?;
+        info!("Found {} outputs", self.outputs.len());
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+        self.set_output_temperatures(setting, &[])
+    }
+
+    /// Outputs left alone get the identity, which is what they start
+    /// out with
+    fn set_output_temperatures(&mut self, setting: &ColorSetting,
+                               outputs: &[OutputSetting]) -> Result<()> {
+        self.warn_unsupported(setting);
+        self.set_ctms(|name| match outputs.iter().find(|o| o.output == name) {
+            Some(&OutputSetting { setting: Some(ref s), .. }) => ctm(s),
+            Some(&OutputSetting { setting: None, .. }) => IDENTITY,
+            None => ctm(setting),
+        })?;
+        self.sync()
+    }
+
+    fn restore(&self) -> Result<()> {
+        if self.manager == 0 {
+            return Ok(());
+        }
+        self.set_ctms(|_| IDENTITY)?;
+        self.conn.roundtrip().map(|_| ()).map_err(error)
+    }
+
+    fn mixes_channels(&self) -> bool {
+        true
+    }
+
+    fn outputs(&self) -> Vec<String> {
+        self.outputs.iter().map(|o| o.name.clone()).collect()
+    }
+}
+
+/// The init function
+pub fn init(options: &Options) -> Result<Box<GammaMethod>> {
+    HyprlandState::init(options.wayland_display.as_ref().map(|s| &s[..]))
+        .map(|h| Box::new(h) as Box<GammaMethod>)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use GRAYSCALE_MATRIX;
+
+    #[test]
+    fn matrices() {
+        assert_eq!(ctm(&ColorSetting::neutral()), IDENTITY);
+        let m = ctm(&ColorSetting { temp: 3500, brightness: 0.5, ..ColorSetting::neutral() });
+        assert_eq!(m[0][0], 0.5);
+        assert!(m[2][2] < m[1][1] && m[1][1] < 0.5);
+        assert_eq!(m[0][1], 0.0);
+    }
+
+    #[test]
+    fn grayscale() {
+        let gray = ColorSetting { temp: 3500, filter: GRAYSCALE_MATRIX, ..ColorSetting::neutral() };
+        let m = ctm(&gray);
+        // Every channel takes the luminance, then the whitepoint tints
+        // the gray
+        let white = colorramp::white_point(3500);
+        for i in 0..3 {
+            for j in 0..3 {
+                assert!((m[i][j] - white[i] * GRAYSCALE_MATRIX[i][j]).abs() < 1e-9);
+            }
+        }
+    }
+}
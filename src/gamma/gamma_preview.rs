@@ -0,0 +1,206 @@
+//! Previews of the ramps
+//!
+//! `-m preview` touches no display. On every update it writes two PNG
+//! images instead, to look at what a configuration does, or to check
+//! it in CI:
+//!
+//! - `curves.png`: the red, green and blue ramps, plotted over the
+//!   identity
+//! - `image.png`: bands of gray, red, green, blue and of the hues, as
+//!   they are above and as the ramps turn them below
+//!
+//! The images are written to `$XDG_RUNTIME_DIR/redshift-rs/preview`
+//! unless the `[preview]` section says otherwise, and replaced at
+//! once so that a viewer never reads half an image:
+//!
+//! ```text
+//! [preview]
+//! dir=/tmp/redshift-preview
+//! size=1024
+//! ```
+//!
+//! `size` is the number of entries of the ramps, 256 by default.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use colorramp;
+use paths;
+use transition::ColorSetting;
+use super::{GammaMethod, Options, Ramps};
+use super::png;
+use super::Result;
+use super::super::RedshiftError;
+
+/// Width of both images, one pixel per input level
+const WIDTH: u32 = 256;
+/// Height of a band of the test image
+const BAND: u32 = 32;
+const BANDS: u32 = 5;
+/// Gap between the original and the adjusted bands
+const GAP: u32 = 4;
+
+const BACKGROUND: [u8; 3] = [24, 24, 24];
+const GRID: [u8; 3] = [56, 56, 56];
+
+fn error(e: io::Error) -> RedshiftError {
+    RedshiftError::Gamma("preview".to_owned(), Box::new(e))
+}
+
+pub struct PreviewState {
+    dir: PathBuf,
+    size: usize,
+}
+
+impl PreviewState {
+    fn write(&self, name: &str, png: &[u8]) -> Result<()> {
+        let path = self.dir.join(name);
+        let tmp = self.dir.join(format!(".{}.tmp", name));
+        fs::write(&tmp, png).and_then(|_| fs::rename(&tmp, &path)).map_err(error)
+    }
+}
+
+/// The ramps plotted over a grid and the identity
+fn curves(ramps: &Ramps) -> Vec<[u8; 3]> {
+    let side = WIDTH as usize;
+    let mut pixels = vec![BACKGROUND; side * side];
+    for i in 0..side {
+        for j in (0..side).step_by(side / 4) {
+            pixels[i * side + j] = GRID;
+            pixels[j * side + i] = GRID;
+        }
+        pixels[(side - 1 - i) * side + i] = GRID;
+    }
+    for (channel, ramp) in [&ramps.0, &ramps.1, &ramps.2].iter().enumerate() {
+        let y = |x: usize| side - 1 - ramp[x * (ramp.len() - 1) / (side - 1)] as usize * (side - 1) / 65535;
+        for x in 0..side {
+            // Joined to the previous point, where the curve is steep
+            let (from, to) = if x == 0 { (y(0), y(0)) } else { (y(x - 1), y(x)) };
+            for row in from.min(to)..from.max(to) + 1 {
+                let pixel = &mut pixels[row * side + x];
+                if *pixel == BACKGROUND || *pixel == GRID {
+                    *pixel = [0; 3];
+                }
+                pixel[channel] = 255;
+            }
+        }
+    }
+    pixels
+}
+
+/// A color of full saturation, by hue from 0 to 1
+fn hue(h: f64) -> [u8; 3] {
+    let channel = |offset: f64| {
+        let d = ((h * 6.0 + offset) % 6.0 - 3.0).abs();
+        ((d - 1.0).clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+    [channel(0.0), channel(4.0), channel(2.0)]
+}
+
+/// The test image, as it is above and through the ramps below
+fn image(ramps: &Ramps) -> Vec<[u8; 3]> {
+    let apply = |c: [u8; 3]| -> [u8; 3] {
+        let level = |ramp: &Vec<u16>, v: u8| (ramp[v as usize * (ramp.len() - 1) / 255] >> 8) as u8;
+        [level(&ramps.0, c[0]), level(&ramps.1, c[1]), level(&ramps.2, c[2])]
+    };
+    let band = |b: u32, x: u32| -> [u8; 3] {
+        let v = x as u8;
+        match b {
+            0 => [v, v, v],
+            1 => [v, 0, 0],
+            2 => [0, v, 0],
+            3 => [0, 0, v],
+            _ => hue(x as f64 / WIDTH as f64),
+        }
+    };
+    let mut pixels = vec![];
+    for adjusted in &[false, true] {
+        for b in 0..BANDS {
+            for _ in 0..BAND {
+                pixels.extend((0..WIDTH).map(|x| if *adjusted { apply(band(b, x)) } else { band(b, x) }));
+            }
+        }
+        if !adjusted {
+            pixels.extend((0..WIDTH * GAP).map(|_| BACKGROUND));
+        }
+    }
+    pixels
+}
+
+impl GammaMethod for PreviewState {
+    fn name(&self) -> &'static str {
+        "preview"
+    }
+
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "dir" => {
+                self.dir = PathBuf::from(value);
+                Ok(())
+            }
+            "size" => {
+                self.size = value.trim().parse().ok().filter(|n| (2..=65536).contains(n))
+                    .ok_or_else(|| RedshiftError::MalformedConfig(
+                        format!("[preview] size: invalid ramp size '{}' (2 to 65536)", value)))?;
+                Ok(())
+            }
+            _ => Err(RedshiftError::MalformedConfig(format!("[preview] {}: unknown option", key))),
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        paths::ensure_runtime_dir(&self.dir).map_err(error)?;
+        info!("Writing previews to {}", self.dir.display());
+        Ok(())
+    }
+
+    fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+        let ramps = colorramp::ramps(setting, self.size);
+        self.write("curves.png", &png::encode_rgb(WIDTH, WIDTH, &curves(&ramps)))?;
+        self.write("image.png", &png::encode_rgb(WIDTH, 2 * BANDS * BAND + GAP, &image(&ramps)))
+    }
+
+    /// The previews stay as they are
+    fn restore(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn outputs(&self) -> Vec<String> {
+        vec![self.dir.display().to_string()]
+    }
+}
+
+/// The init function
+pub fn init(_: &Options) -> Result<Box<GammaMethod>> {
+    Ok(Box::new(PreviewState {
+        dir: paths::runtime_dir().join("preview"),
+        size: 256,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn neutral_image() {
+        let ramps = colorramp::ramps(&ColorSetting::neutral(), 256);
+        let pixels = image(&ramps);
+        assert_eq!(pixels.len() as u32, WIDTH * (2 * BANDS * BAND + GAP));
+        // Neutral ramps leave the bands as they are
+        let adjusted = ((BANDS * BAND + GAP) * WIDTH) as usize;
+        assert_eq!(&pixels[..adjusted - (GAP * WIDTH) as usize], &pixels[adjusted..]);
+        assert_eq!(hue(0.0), [255, 0, 0]);
+        assert_eq!(hue(1.0 / 3.0), [0, 255, 0]);
+    }
+
+    #[test]
+    fn warm_curves() {
+        let ramps = colorramp::ramps(&ColorSetting { temp: 3000, ..ColorSetting::neutral() }, 1024);
+        let pixels = curves(&ramps);
+        // The red ramp ends at the top, the blue one well below it
+        assert_eq!(pixels[255][0], 255);
+        assert_eq!(pixels[255][2], 0);
+    }
+}
@@ -3,14 +3,24 @@ use xcb::randr;
 use transition;
 use colorramp;
 
-use super::GammaMethod;
+use super::{FailurePolicy, GammaMethod, Options, OutputInfo, OutputSetting};
 use super::Result;
+use super::edid;
+use super::super::RedshiftError;
+use super::saved;
+use std::cell::RefCell;
 use std::error::Error;
 use std::fmt;
+use std::thread;
+
+use chan;
 
 const RANDR_MAJOR_VERSION: u32 = 1;
 const RANDR_MINOR_VERSION: u32 = 3;
 
+/// How often a dithered setting steps to its next phase
+const DITHER_INTERVAL_MS: u64 = 100;
+
 /// Wrapper for XCB and RandR errors
 pub enum RandrError<T> {
     Generic(xcb::Error<T>),
@@ -19,18 +29,24 @@ pub enum RandrError<T> {
 }
 
 impl<T: 'static> RandrError<T> {
-    fn generic(e: xcb::Error<T>) -> Box<Error> {
-        Box::new(RandrError::Generic(e)) as Box<Error>
+    fn generic(e: xcb::Error<T>) -> RedshiftError {
+        RandrError::Generic(e).into()
     }
 }
 
 impl RandrError<()> {
-    fn conn(e: xcb::ConnError) -> Box<Error> {
-        Box::new(RandrError::Conn::<()>(e)) as Box<Error>
+    fn conn(e: xcb::ConnError) -> RedshiftError {
+        RandrError::Conn::<()>(e).into()
+    }
+
+    fn unsupported_version(major: u32, minor: u32) -> RedshiftError {
+        RandrError::UnsupportedVersion::<()>(major, minor).into()
     }
+}
 
-    fn unsupported_version(major: u32, minor: u32) -> Box<Error> {
-        Box::new(RandrError::UnsupportedVersion::<()>(major, minor)) as Box<Error>
+impl<T: 'static> From<RandrError<T>> for RedshiftError {
+    fn from(e: RandrError<T>) -> RedshiftError {
+        RedshiftError::Gamma("randr".to_owned(), Box::new(e))
     }
 }
 
@@ -69,28 +85,45 @@ struct Crtc {
     /// The ramp size.
     ramp_size: u32,
 
+    /// Names of the outputs it drives, e.g. "HDMI-1"
+    outputs: Vec<String>,
+
     /// The initial gamma ramp values - used for restore
     saved_ramps: (Vec<u16>, Vec<u16>, Vec<u16>),
 
-    /// A scratchpad for color computation - it saves the cost of
-    /// allocating three new arrays whenever set_temperature() is
-    /// called.
-    scratch: (Vec<u16>, Vec<u16>, Vec<u16>),
+    /// The setting applied last with the generation of the whitepoints
+    /// it was applied with, if the ramps are still those of it
+    applied: RefCell<Option<(transition::ColorSetting, usize)>>,
 }
 
 /// Wrapping struct for RandR state
 pub struct RandrState {
+    display: Option<String>,
     conn: xcb::Connection,
     screen_num: i32,
     window_dummy: u32,
-    crtcs: Vec<Crtc>
+    crtcs: Vec<Crtc>,
+    /// Ramps by size, computed once for all the CRTCs of a size
+    cache: colorramp::Cache,
+    /// Ids of the CRTCs to adjust, all of them if empty
+    crtc_filter: Vec<u32>,
+    /// Bit depth of the panels to dither the ramps for, if any
+    dither_bits: Option<u32>,
+    dither_phase: usize,
+    /// Whether a failing CRTC fails the update
+    policy: FailurePolicy,
+
+    /// Name of the original ramps on disk
+    saved_name: String,
+    recovered: bool,
 }
 
 impl RandrState {
 
-    fn init() -> Result<RandrState> {
-        let (conn, screen_num) = xcb::Connection::connect(None)
+    fn init(display: Option<&str>, policy: FailurePolicy) -> Result<RandrState> {
+        let (conn, screen_num) = xcb::Connection::connect(display)
             .map_err(RandrError::conn)?;
+        info!("Connected to X display {}", display.unwrap_or("$DISPLAY"));
 
         query_version(&conn)?;
 
@@ -105,42 +138,83 @@ impl RandrState {
             window_dummy
         };
 
+        let name = display.map(ToOwned::to_owned)
+            .or_else(|| ::std::env::var("DISPLAY").ok())
+            .unwrap_or_default();
         Ok(RandrState {
+            display: display.map(ToOwned::to_owned),
             conn: conn,
             screen_num: screen_num,
             window_dummy: window_dummy,
-            crtcs: vec![]
+            crtcs: vec![],
+            cache: colorramp::Cache::new(4),
+            crtc_filter: vec![],
+            dither_bits: None,
+            dither_phase: 0,
+            policy: policy,
+            saved_name: format!("randr{}", name),
+            recovered: false,
         })
     }
 
     // Set the temperature for the indicated CRTC
-    fn set_crtc_temperatures(&mut self, setting: &transition::ColorSetting) -> Result<()> {
-        for crtc in self.crtcs.iter_mut() {
-
-            let (ref mut r, ref mut g, ref mut b) = crtc.scratch;
-
-            let u16_max1 = u16::max_value() as f64 + 1.0;
-            let ramp_size = crtc.ramp_size as f64;
-            for i in 0 .. r.len() {
-                let v = ((i as f64 / ramp_size) * u16_max1) as u16;
-                r[i] = v;
-                g[i] = v;
-                b[i] = v;
+    //
+    // The requests for all the CRTCs are sent before waiting for any
+    // reply, so that the monitors change together rather than one
+    // round-trip after the other.
+    fn set_crtc_temperatures(&mut self, setting: &transition::ColorSetting,
+                             outputs: &[OutputSetting]) -> Result<()> {
+        self.dither_phase = (self.dither_phase + 1) % colorramp::DITHER_PHASES;
+        let mut requests = Vec::with_capacity(self.crtcs.len());
+        for crtc in self.crtcs.iter() {
+            let setting = match outputs.iter().find(|o| crtc.is_named(&o.output)) {
+                Some(&OutputSetting { setting: Some(ref s), .. }) => s,
+                Some(&OutputSetting { setting: None, .. }) => continue,
+                None => setting,
+            };
+            // A dithered setting changes the ramps at every step
+            let applied = (setting.clone(), colorramp::whitepoints_generation());
+            if self.dither_bits.is_none() && crtc.applied.borrow().as_ref() == Some(&applied) {
+                continue;
             }
-
-            /* Create new gamma ramps */
-            colorramp::fill(&mut r[..], &mut g[..], &mut b[..],
-                            setting, crtc.ramp_size as usize);
-
-            randr::set_crtc_gamma_checked(&self.conn,
-                                          crtc.id,
-                                          &r[..],
-                                          &g[..],
-                                          &b[..])
-                .request_check()
-                .map_err(RandrError::generic)?;
+            let mut ramps = self.cache.ramps(setting, crtc.ramp_size as usize);
+            let dithered;
+            if let Some(bits) = self.dither_bits {
+                let mut copy = ramps.clone();
+                colorramp::dither(&mut copy.0, bits, self.dither_phase);
+                colorramp::dither(&mut copy.1, bits, self.dither_phase);
+                colorramp::dither(&mut copy.2, bits, self.dither_phase);
+                dithered = copy;
+                ramps = &dithered;
+            }
+            let (ref r, ref g, ref b) = *ramps;
+            let cookie = randr::set_crtc_gamma_checked(&self.conn,
+                                                       crtc.id,
+                                                       &r[..],
+                                                       &g[..],
+                                                       &b[..]);
+            requests.push((crtc, applied, cookie));
         }
-        Ok(())
+        self.conn.flush();
+        let results: Vec<_> = requests.into_iter()
+            .map(|(crtc, applied, cookie)| match cookie.request_check() {
+                Ok(()) => { *crtc.applied.borrow_mut() = Some(applied); Ok(()) }
+                Err(e) => {
+                    let e = RandrError::generic(e);
+                    warn!("CRTC {}: {}", crtc.id, e);
+                    Err(e)
+                }
+            })
+            .collect();
+        self.policy.outcome(results)
+    }
+}
+
+impl Crtc {
+    /// Whether `name` is one of its outputs, or the CRTC itself given
+    /// as "crtc-ID"
+    fn is_named(&self, name: &str) -> bool {
+        self.outputs.iter().any(|o| o == name) || name == format!("crtc-{}", self.id)
     }
 }
 
@@ -159,58 +233,321 @@ fn query_version(conn: &xcb::Connection) -> Result<()> {
 }
 
 impl GammaMethod for RandrState {
+    fn name(&self) -> &'static str {
+        "randr"
+    }
+
+    /// RandR is of no use if no CRTC has gamma ramps, as under
+    /// Xwayland
+    fn is_available(&self) -> bool {
+        let resources = match randr::get_screen_resources(&self.conn, self.window_dummy).get_reply() {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+        resources.crtcs().iter().any(|crtc| {
+            randr::get_crtc_gamma_size(&self.conn, *crtc).get_reply()
+                .map(|r| r.size() > 0)
+                .unwrap_or(false)
+        })
+    }
+
+    /// Supports `crtc`, a comma-separated list of the CRTC ids to
+    /// adjust, and `dither`, the bit depth of the panels (e.g. 8) to
+    /// dither the ramps for, or 0 not to
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "crtc" => {
+                self.crtc_filter = value.split(',')
+                    .map(|id| id.trim().parse().map_err(|_| RedshiftError::MalformedConfig(
+                        format!("[randr] crtc: invalid CRTC id '{}'", id))))
+                    .collect::<Result<_>>()?;
+                Ok(())
+            }
+            "dither" => {
+                let bits = value.trim().parse::<u32>().ok().filter(|b| *b < 16)
+                    .ok_or_else(|| RedshiftError::MalformedConfig(
+                        format!("[randr] dither: invalid bit depth '{}' (0 to 15)", value)))?;
+                self.dither_bits = if bits == 0 { None } else { Some(bits) };
+                Ok(())
+            }
+            _ => Err(RedshiftError::MalformedConfig(format!("[randr] {}: unknown option", key))),
+        }
+    }
+
 
     //
     // Restore saved gamma ramps
     //
     fn restore(&self) -> Result<()> {
-        for crtc in self.crtcs.iter() {
-            randr::set_crtc_gamma_checked(&self.conn,
-                                          crtc.id,
-                                          &crtc.saved_ramps.0[..],
-                                          &crtc.saved_ramps.1[..],
-                                          &crtc.saved_ramps.2[..])
-                .request_check()
-                .map_err(RandrError::generic)?;
+        let cookies: Vec<_> = self.crtcs.iter()
+            .map(|crtc| {
+                *crtc.applied.borrow_mut() = None;
+                randr::set_crtc_gamma_checked(&self.conn,
+                                              crtc.id,
+                                              &crtc.saved_ramps.0[..],
+                                              &crtc.saved_ramps.1[..],
+                                              &crtc.saved_ramps.2[..])
+            })
+            .collect();
+        self.conn.flush();
+        for cookie in cookies {
+            cookie.request_check().map_err(RandrError::generic)?;
         }
+        saved::remove(&self.saved_name);
         Ok(())
     }
 
     fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
-        self.set_crtc_temperatures(setting)
+        self.set_crtc_temperatures(setting, &[])
+    }
+
+    fn set_output_temperatures(&mut self, setting: &transition::ColorSetting,
+                               outputs: &[OutputSetting]) -> Result<()> {
+        self.set_crtc_temperatures(setting, outputs)
+    }
+
+    fn reapply_interval_ms(&self) -> Option<u64> {
+        self.dither_bits.map(|_| DITHER_INTERVAL_MS)
+    }
+
+    /// Reads back the ramps of the CRTCs, comparing them to those of
+    /// the setting applied last. Dithered ramps are applied again
+    /// every step anyway.
+    fn verify(&mut self) -> Result<Option<bool>> {
+        if self.dither_bits.is_some() {
+            return Ok(Some(true));
+        }
+        let conn = &self.conn;
+        let cookies: Vec<_> = self.crtcs.iter()
+            .filter(|crtc| crtc.applied.borrow().is_some())
+            .map(|crtc| (crtc, randr::get_crtc_gamma(conn, crtc.id)))
+            .collect();
+        let mut same = true;
+        for (crtc, cookie) in cookies {
+            let reply = cookie.get_reply().map_err(RandrError::generic)?;
+            let applied = crtc.applied.borrow().clone();
+            let changed = match applied {
+                Some((ref setting, generation)) if generation == colorramp::whitepoints_generation() => {
+                    let ramps = self.cache.ramps(setting, crtc.ramp_size as usize);
+                    reply.red() != &ramps.0[..] || reply.green() != &ramps.1[..] || reply.blue() != &ramps.2[..]
+                }
+                _ => false,
+            };
+            if changed {
+                debug!("CRTC {}: gamma ramps changed", crtc.id);
+                *crtc.applied.borrow_mut() = None;
+                same = false;
+            }
+        }
+        Ok(Some(same))
+    }
+
+    fn invalidate(&self) {
+        for crtc in self.crtcs.iter() {
+            *crtc.applied.borrow_mut() = None;
+        }
+    }
+
+    /// The names of the outputs, and of the CRTCs driving none as
+    /// "crtc-ID"
+    fn outputs(&self) -> Vec<String> {
+        self.crtcs.iter()
+            .flat_map(|crtc| if crtc.outputs.is_empty() {
+                vec![format!("crtc-{}", crtc.id)]
+            } else {
+                crtc.outputs.clone()
+            })
+            .collect()
+    }
+
+    /// Every CRTC of the screen with its outputs, also those the
+    /// `crtc` option leaves out
+    fn output_info(&self) -> Vec<OutputInfo> {
+        self.query_output_info().unwrap_or_else(|e| {
+            warn!("Could not list the outputs: {}", e);
+            vec![]
+        })
     }
 
     /// Find initial information on all the CRTCs
     fn start(&mut self) -> Result<()> {
+        self.crtcs = self.query_crtcs(&[])?;
+        info!("Found {} CRTCs", self.crtcs.len());
+
+        // The ramps found are not the originals if an earlier instance
+        // was killed before restoring them
+        let originals = saved::load(&self.saved_name);
+        for crtc in self.crtcs.iter_mut() {
+            if let Some(ramps) = originals.get(&crtc.id).filter(|r| r.0.len() == crtc.ramp_size as usize) {
+                crtc.saved_ramps = ramps.clone();
+                self.recovered = true;
+            }
+        }
+        if self.recovered {
+            info!("Using the original gamma ramps saved by an earlier run");
+        }
+        self.save_ramps();
+        Ok(())
+    }
+
+    fn recovered(&self) -> bool {
+        self.recovered
+    }
+
+    /// Receive RandR notifications on a second connection, so that
+    /// waiting for them doesn't get in the way of the requests made
+    /// on the main one
+    fn watch(&mut self) -> Option<chan::Receiver<()>> {
+        let display = self.display.clone();
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let (conn, screen_num) = match xcb::Connection::connect(display.as_ref().map(|s| &s[..])) {
+                Ok(c) => c,
+                Err(e) => {
+                    warn!("Not watching for output changes: {:?}", e);
+                    return;
+                }
+            };
+            let root = conn.get_setup().roots().nth(screen_num as usize).unwrap().root();
+            randr::select_input(&conn, root, (randr::NOTIFY_MASK_SCREEN_CHANGE |
+                                              randr::NOTIFY_MASK_CRTC_CHANGE |
+                                              randr::NOTIFY_MASK_OUTPUT_CHANGE) as u16);
+            conn.flush();
+            while conn.wait_for_event().is_some() {
+                tx.send(());
+            }
+        });
+        Some(rx)
+    }
+
+    /// Look for CRTCs that were added or removed
+    fn refresh(&mut self) -> Result<()> {
+        let old = ::std::mem::replace(&mut self.crtcs, vec![]);
+        self.crtcs = self.query_crtcs(&old)?;
+        debug!("Outputs changed, now {} CRTCs", self.crtcs.len());
+        self.save_ramps();
+        Ok(())
+    }
+}
+
+impl RandrState {
+    /// Keep the original ramps on disk, in case we don't get to
+    /// restore them
+    fn save_ramps(&self) {
+        if let Err(e) = saved::store(&self.saved_name,
+                                     self.crtcs.iter().map(|c| (c.id, &c.saved_ramps))) {
+            warn!("Could not save the original gamma ramps: {}", e);
+        }
+    }
+
+    /// Query the CRTCs of the screen with their gamma ramps
+    ///
+    /// CRTCs that are in `known` keep the ramps saved when they were
+    /// first seen, as their current ramps are our own adjustments.
+    fn query_crtcs(&self, known: &[Crtc]) -> Result<Vec<Crtc>> {
         /* Get list of CRTCs for the screen */
         let screen_resources = randr::get_screen_resources(&self.conn,
                                                            self.window_dummy)
             .get_reply()
             .map_err(RandrError::generic)?;
-        self.crtcs = Vec::with_capacity(screen_resources.num_crtcs() as usize);
+        let mut crtcs = Vec::with_capacity(screen_resources.num_crtcs() as usize);
 
         /* Save size and gamma ramps of all CRTCs */
-        for crtc in screen_resources.crtcs() {
+        let crtc_ids = screen_resources.crtcs().iter()
+            .filter(|id| self.crtc_filter.is_empty() || self.crtc_filter.contains(id));
+        for crtc in crtc_ids {
             let gamma = randr::get_crtc_gamma(&self.conn, *crtc)
                 .get_reply()
                 .map_err(RandrError::generic)?;
 
+            let outputs = self.output_names(*crtc, screen_resources.config_timestamp())?;
+            debug!("CRTC {}: id {}, gamma ramp size {}, outputs {}",
+                   crtcs.len(), crtc, gamma.size(), outputs.join(", "));
+
             let red = gamma.red().to_vec();
             let green = gamma.green().to_vec();
             let blue = gamma.blue().to_vec();
 
-            self.crtcs.push(Crtc {
+            let saved_ramps = match known.iter().find(|c| c.id == *crtc && c.ramp_size == gamma.size() as u32) {
+                Some(c) => c.saved_ramps.clone(),
+                None => (red, green, blue),
+            };
+            crtcs.push(Crtc {
                 id: *crtc,
                 ramp_size: gamma.size() as u32,
-                saved_ramps: (red.clone(), green.clone(), blue.clone()),
-                scratch: (red, green, blue),
+                outputs: outputs,
+                saved_ramps: saved_ramps,
+                applied: RefCell::new(None),
             });
         }
-        Ok(())
+        Ok(crtcs)
+    }
+
+    fn query_output_info(&self) -> Result<Vec<OutputInfo>> {
+        let resources = randr::get_screen_resources(&self.conn, self.window_dummy)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        let timestamp = resources.config_timestamp();
+        let edid_atom = xcb::intern_atom(&self.conn, true, "EDID").get_reply()
+            .map(|r| r.atom())
+            .unwrap_or(xcb::ATOM_NONE);
+        let mut infos = vec![];
+        for &crtc in resources.crtcs() {
+            let ramp_size = randr::get_crtc_gamma_size(&self.conn, crtc)
+                .get_reply()
+                .map_err(RandrError::generic)?
+                .size() as usize;
+            let info = randr::get_crtc_info(&self.conn, crtc, timestamp)
+                .get_reply()
+                .map_err(RandrError::generic)?;
+            let entry = |name: String, model: Option<String>| OutputInfo {
+                name: name,
+                model: model,
+                ramp_size: Some(ramp_size),
+                crtc: Some(crtc),
+                adjusted: self.crtc_filter.is_empty() || self.crtc_filter.contains(&crtc),
+            };
+            if info.outputs().is_empty() {
+                infos.push(entry(format!("crtc-{}", crtc), None));
+            }
+            for &output in info.outputs() {
+                let name = randr::get_output_info(&self.conn, output, timestamp)
+                    .get_reply()
+                    .map(|o| String::from_utf8_lossy(o.name()).into_owned())
+                    .map_err(RandrError::generic)?;
+                // The base block, in 32 bit units
+                let model = if edid_atom == xcb::ATOM_NONE {
+                    None
+                } else {
+                    randr::get_output_property(&self.conn, output, edid_atom, xcb::ATOM_ANY,
+                                               0, 32, false, false)
+                        .get_reply().ok()
+                        .and_then(|r| edid::model(r.data()))
+                };
+                infos.push(entry(name, model));
+            }
+        }
+        Ok(infos)
+    }
+
+    /// Names of the outputs driven by a CRTC
+    fn output_names(&self, crtc: u32, timestamp: xcb::Timestamp) -> Result<Vec<String>> {
+        let info = randr::get_crtc_info(&self.conn, crtc, timestamp)
+            .get_reply()
+            .map_err(RandrError::generic)?;
+        info.outputs().iter()
+            .map(|output| {
+                randr::get_output_info(&self.conn, *output, timestamp)
+                    .get_reply()
+                    .map(|o| String::from_utf8_lossy(o.name()).into_owned())
+                    .map_err(RandrError::generic)
+            })
+            .collect()
     }
 }
 
 /// The init function
-pub fn init() -> Result<Box<GammaMethod>> {
-    RandrState::init().map(|r| Box::new(r) as Box<GammaMethod>)
+pub fn init(options: &Options) -> Result<Box<GammaMethod>> {
+    RandrState::init(options.display.as_ref().map(|s| &s[..]), options.failure_policy)
+        .map(|r| Box::new(r) as Box<GammaMethod>)
 }
@@ -1,35 +1,271 @@
+mod displays;
+pub mod edid;
+#[cfg(feature = "hyprland")]
+mod gamma_hyprland;
+mod gamma_preview;
 #[cfg(feature = "randr")]
 mod gamma_randr;
+#[cfg(feature = "randr")]
+mod saved;
+mod png;
+pub mod recording;
 
+use chan;
+use i18n;
 use transition;
 use super::{Result, RedshiftError};
 
 use std::collections::HashMap;
-use std::error::Error;
+use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub use colorramp::Ramps;
+
+/// The setting of an output that differs from the others
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSetting {
+    /// Name of the output, as listed by `outputs()`
+    pub output: String,
+    /// The setting to apply, or `None` to leave the output alone
+    pub setting: Option<transition::ColorSetting>,
+}
+
+/// What a gamma method knows about an output, for listing them
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputInfo {
+    /// Name of the output, as listed by `outputs()`
+    pub name: String,
+    /// Manufacturer and model of the monitor, from its EDID
+    pub model: Option<String>,
+    /// Number of entries of its gamma ramps
+    pub ramp_size: Option<usize>,
+    /// The CRTC driving it, on X
+    pub crtc: Option<u32>,
+    /// Whether the options of the method let it be adjusted
+    pub adjusted: bool,
+}
+
+impl OutputInfo {
+    /// An output known by name only
+    pub fn named(name: String) -> OutputInfo {
+        OutputInfo { name: name, model: None, ramp_size: None, crtc: None, adjusted: true }
+    }
+}
+
+/// Initialisation function of a gamma method
+pub type GammaInit = fn(&Options) -> Result<Box<GammaMethod>>;
+
+/// A gamma method in the registry
+#[derive(Clone, Copy)]
+pub struct Registration {
+    /// The name selecting the method with `-m`
+    pub name: &'static str,
+
+    /// Methods are tried from the highest priority down when none is
+    /// requested. Those with a negative priority are only used when
+    /// requested.
+    pub priority: i32,
+
+    /// Cheap check whether the method could work at all, done before
+    /// trying to initialise it when none is requested
+    pub probe: fn(&Options) -> bool,
+
+    pub init: GammaInit,
+}
+
+/// Options for connecting to the display server, given to the
+/// initialisation function of the gamma method
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Options {
+    /// The X display to connect to, e.g. ":1". Defaults to `$DISPLAY`.
+    ///
+    /// Several displays are separated by commas, e.g. ":0,:1", and
+    /// each gets an instance of the method, see `displays`.
+    pub display: Option<String>,
 
-type GammaInit = fn() -> Result<Box<GammaMethod>>;
+    /// The Wayland socket to connect to, e.g. "wayland-1". Defaults
+    /// to `$WAYLAND_DISPLAY`.
+    pub wayland_display: Option<String>,
+
+    /// Options of each method by method name, passed to its
+    /// `set_option()` before it is started
+    pub method_options: HashMap<String, Vec<(String, String)>>,
+
+    /// What to do when some of the outputs or displays fail
+    pub failure_policy: FailurePolicy,
+}
+
+/// What to do when setting the ramps of some of the outputs fails
+///
+/// With `BestEffort` the failing outputs are logged and left out, and
+/// only when all of them fail is it an error. With `Strict` any
+/// failure is an error, on which the daemon restores the ramps and
+/// exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    Strict,
+    #[default]
+    BestEffort,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<FailurePolicy> {
+        match s {
+            "strict" => Ok(FailurePolicy::Strict),
+            "best-effort" => Ok(FailurePolicy::BestEffort),
+            _ => Err(RedshiftError::MalformedConfig(
+                format!("output-failures: {} (expected strict or best-effort)", s))),
+        }
+    }
+}
+
+impl FailurePolicy {
+    /// Fold the results of the outputs, which have been logged, into
+    /// that of the whole
+    pub fn outcome<I: IntoIterator<Item = Result<()>>>(self, results: I) -> Result<()> {
+        let mut first = None;
+        let mut ok = false;
+        for result in results {
+            match result {
+                Ok(()) => ok = true,
+                Err(e) => first = first.or(Some(e)),
+            }
+        }
+        match first {
+            Some(e) if self == FailurePolicy::Strict || !ok => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Options {
+    /// The X displays given, none for `$DISPLAY`
+    pub fn displays(&self) -> Vec<&str> {
+        self.display.as_ref()
+            .map(|d| d.split(',').map(str::trim).filter(|d| !d.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The first of the X displays, for what follows a single one
+    pub fn main_display(&self) -> Option<&str> {
+        self.displays().into_iter().next()
+    }
+}
 
 lazy_static! {
-    static ref SUPPORTED_GAMMA_METHODS: HashMap<&'static str, GammaInit> = {
-        let mut m: HashMap<&'static str, GammaInit> = HashMap::with_capacity(4);
-        add_randr_method(&mut m);
-        m.insert("dummy", init_dummy);
-        m
+    static ref REGISTRY: Mutex<Vec<Registration>> = {
+        let mut methods = Vec::with_capacity(4);
+        add_randr_method(&mut methods);
+        add_hyprland_method(&mut methods);
+        methods.push(Registration {
+            name: "dummy",
+            priority: -1,
+            probe: |_| true,
+            init: init_dummy,
+        });
+        methods.push(Registration {
+            name: "preview",
+            priority: -1,
+            probe: |_| true,
+            init: gamma_preview::init,
+        });
+        Mutex::new(methods)
     };
 }
 
 #[cfg(feature = "randr")]
-fn add_randr_method<'a>(m: &mut HashMap<&'a str, GammaInit>) {
-    m.insert("randr", gamma_randr::init);
+fn add_randr_method(methods: &mut Vec<Registration>) {
+    methods.push(Registration {
+        name: "randr",
+        priority: 100,
+        probe: |options| options.display.is_some() || ::std::env::var_os("DISPLAY").is_some(),
+        init: gamma_randr::init,
+    });
 }
 
 #[cfg(not(feature = "randr"))]
-fn add_randr_method<'a>(_: &mut HashMap<&'a str, GammaInit>) {}
+fn add_randr_method(_: &mut Vec<Registration>) {}
+
+/// Below RandR, which Xwayland offers too but without gamma ramps
+#[cfg(feature = "hyprland")]
+fn add_hyprland_method(methods: &mut Vec<Registration>) {
+    methods.push(Registration {
+        name: "hyprland",
+        priority: 50,
+        probe: |_| ::std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some(),
+        init: gamma_hyprland::init,
+    });
+}
+
+#[cfg(not(feature = "hyprland"))]
+fn add_hyprland_method(_: &mut Vec<Registration>) {}
+
+/// Built-in gamma methods, with the cargo feature that compiles each
+/// of them in
+const FEATURES: &'static [(&'static str, &'static str)] = &[
+    ("randr", "randr"),
+    ("hyprland", "hyprland"),
+];
+
+/// Built-in gamma methods left out at compile time, sorted by name
+pub fn disabled_methods() -> Vec<&'static str> {
+    let mut names: Vec<_> = FEATURES.iter()
+        .map(|&(name, _)| name)
+        .filter(|name| !is_method_available(name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// The cargo feature a built-in method was left out with, if it was
+fn missing_feature(name: &str) -> Option<&'static str> {
+    FEATURES.iter()
+        .find(|entry| entry.0 == name)
+        .filter(|_| !is_method_available(name))
+        .map(|&(_, feature)| feature)
+}
+
+/// Add a gamma method, or replace the one with the same name
+///
+/// Methods registered before the command line is parsed can be
+/// selected with `-m` like the built-in ones.
+pub fn register(method: Registration) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|m| m.name != method.name);
+    registry.push(method);
+}
+
+fn registered(name: &str) -> Option<Registration> {
+    REGISTRY.lock().unwrap().iter().find(|m| m.name == name).cloned()
+}
 
 /// Any gamma method provider should implement this trait
 ///
 pub trait GammaMethod {
 
+    /// Name of the method, as given to `-m`
+    fn name(&self) -> &'static str;
+
+    /// Whether the method can adjust the display it connected to
+    ///
+    /// A method that connected but can't do anything useful, such as
+    /// RandR on an X server without gamma ramps, returns false so that
+    /// another one is picked when none was requested.
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Set an option of the method, before start() is called
+    ///
+    /// Methods without options reject every key.
+    fn set_option(&mut self, key: &str, value: &str) -> Result<()> {
+        let _ = value;
+        Err(RedshiftError::MalformedConfig(format!("[{}] {}: unknown option", self.name(), key)))
+    }
+
     /// Initialization method
     ///
     /// Called before set_temperature()
@@ -42,64 +278,285 @@ pub trait GammaMethod {
     /// repeatedly. In oneshot mode, this method is invoked once.
     fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()>;
 
+    /// Use the given color setting, except on the outputs in `outputs`
+    ///
+    /// Methods that can't tell their outputs apart apply `setting` to
+    /// all of them.
+    fn set_output_temperatures(&mut self, setting: &transition::ColorSetting,
+                               outputs: &[OutputSetting]) -> Result<()> {
+        let _ = outputs;
+        self.set_temperature(setting)
+    }
     /// The restore method is called when Redshift exits from
     /// running in continual mode.
     fn restore(&self) -> Result<()>;
+
+    /// Whether start() found the original ramps left behind by an
+    /// instance that did not exit cleanly, which restore() applies
+    /// instead of the ones found on the display
+    fn recovered(&self) -> bool {
+        false
+    }
+
+    /// How often the method wants the setting applied again, in
+    /// milliseconds, also when it did not change
+    ///
+    /// A method that dithers the ramps over time steps the dither this
+    /// way; others leave the ramps alone between changes.
+    fn reapply_interval_ms(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether the ramps on the display are still the ones applied
+    /// last, or another program changed them meanwhile
+    ///
+    /// Methods that can't read back what they applied report `None`,
+    /// and have to apply the next setting in full, as they can't tell
+    /// whether it is needed.
+    fn verify(&mut self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// Apply the next setting in full, also where it did not change,
+    /// after something else may have reset the ramps
+    fn invalidate(&self) {}
+
+    /// Whether the method applies `ColorSetting::filter`, which mixes
+    /// the channels
+    ///
+    /// Methods that apply gamma ramps can't; see `colorramp`.
+    fn mixes_channels(&self) -> bool {
+        false
+    }
+
+    /// Names of the outputs being adjusted, for status reports
+    ///
+    /// Only meaningful after start() has been called.
+    fn outputs(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// All the outputs the method can see, also those its options
+    /// leave out
+    ///
+    /// May be called before start(), which is not needed to list them
+    /// on X. By default the outputs of `outputs()`.
+    fn output_info(&self) -> Vec<OutputInfo> {
+        self.outputs().into_iter().map(OutputInfo::named).collect()
+    }
+
+    /// Watch for outputs being added, removed or reconfigured
+    ///
+    /// Methods that can detect this return a channel that receives a
+    /// message on every change. Called once, after start().
+    ///
+    /// Anything else that loses the ramps can be reported the same
+    /// way, so that they are applied again. A method driving DRM
+    /// directly would report switching back to its VT here, since the
+    /// console resets the LUTs.
+    fn watch(&mut self) -> Option<chan::Receiver<()>> {
+        None
+    }
+
+    /// Pick up changed outputs, after a message from watch()
+    ///
+    /// The current color setting is applied again afterwards.
+    fn refresh(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
-fn init_dummy() -> Result<Box<GammaMethod>> {
+fn init_dummy(_: &Options) -> Result<Box<GammaMethod>> {
     Ok(Box::new(DummyMethod) as Box<GammaMethod>)
 }
 
 pub fn is_method_available(method_name: &str) -> bool {
-    SUPPORTED_GAMMA_METHODS.contains_key(method_name)
+    registered(method_name).is_some()
+}
+
+/// Names of all the registered gamma methods, sorted by name
+pub fn methods() -> Vec<&'static str> {
+    let mut names: Vec<_> = REGISTRY.lock().unwrap().iter().map(|m| m.name).collect();
+    names.sort();
+    names
+}
+
+/// The message naming the method in use, as C Redshift words it when
+/// translated
+fn using_method(name: &str) -> String {
+    i18n::translated("Using method `%s'.\n", &[&name]).unwrap_or_else(|| format!("Using method {}", name))
 }
 
 /// Initialise the gamma adjustment method
 ///
-/// If a specific method is requsted (ie method_name is `Some(..)`)
-/// then it is assumed that the method exists and we can call its
-/// initialisation function. If a requested method does not exist,
-/// this function panics.
+/// If a specific method is requested (ie method_name is `Some(..)`)
+/// then that method is initialised, or an error returned if no such
+/// method is registered.
 ///
-/// If `method_name` is `None` then all available methods (except for
-/// the dummy) are tried in turn until one successfully starts - and
+/// If `method_name` is `None` then the registered methods are tried
+/// by priority, skipping those whose probe fails and those with a
+/// negative priority, until one initialises and is available - and
 /// then that method is used.
-pub fn init_gamma_method(method_name: Option<&str>) -> Result<Box<GammaMethod>> {
-    match method_name {
+///
+/// Requesting a built-in method that was left out at compile time
+/// fails with `FeatureDisabled`.
+///
+/// The options for the method in `options.method_options` are set
+/// before it is returned.
+///
+/// With several X displays in `options.display` the method is
+/// initialised for each of them, leaving out those where that fails.
+pub fn init_gamma_method(method_name: Option<&str>, options: &Options) -> Result<Box<GammaMethod>> {
+    let displays = options.displays();
+    if displays.len() > 1 {
+        let mut methods = vec![];
+        let mut first = None;
+        for display in displays {
+            let options = Options { display: Some(display.to_owned()), ..options.clone() };
+            match init_gamma_method(method_name, &options) {
+                Ok(method) => methods.push((display.to_owned(), method)),
+                Err(e) => {
+                    warn!("Leaving out display {}: {}", display, e);
+                    first = first.or(Some(e));
+                }
+            }
+        }
+        return match first {
+            Some(e) if methods.is_empty() || options.failure_policy == FailurePolicy::Strict => Err(e),
+            _ => Ok(Box::new(displays::Displays::new(methods, options.failure_policy))),
+        };
+    }
+    let mut method = match method_name {
         Some(m) => {
-            SUPPORTED_GAMMA_METHODS[m]()
+            let registration = registered(m).ok_or_else(|| match missing_feature(m) {
+                Some(feature) => RedshiftError::FeatureDisabled(format!("gamma method {}", m), feature),
+                None => RedshiftError::GammaMethodNotFound(m.to_owned()),
+            })?;
+            info!("{}", using_method(m));
+            (registration.init)(options)?
         }
         None => {
-            /// Loop over each method and try their init function
-            /// (skipping the dummy)
-            SUPPORTED_GAMMA_METHODS.iter()
-                .filter_map(|(name, method_init)| {
-                    if &name[..] == "dummy" { None }
-                    else {
-                        method_init()
-                            .map(|s| { println!("Using method {}", name); s })
-                            .ok()
+            let mut candidates: Vec<Registration> = REGISTRY.lock().unwrap().iter()
+                .filter(|m| m.priority >= 0)
+                .cloned()
+                .collect();
+            candidates.sort_by(|a, b| b.priority.cmp(&a.priority));
+            candidates.into_iter()
+                .filter(|m| (m.probe)(options))
+                .filter_map(|m| {
+                    match (m.init)(options) {
+                        Ok(s) => Some((m.name, s)),
+                        Err(e) => { debug!("Method {} failed: {}", m.name, e); None }
                     }
                 })
-                .take(1)
-                .next()
-                .ok_or_else(|| Box::new(RedshiftError::GammaMethodNotFound("None".to_owned())) as Box<Error>)
+                .find(|&(_, ref s)| s.is_available())
+                .map(|(name, s)| { info!("{}", using_method(name)); s })
+                .ok_or_else(|| RedshiftError::GammaMethodNotFound("None".to_owned()))?
+        }
+    };
+    if let Some(entries) = options.method_options.get(method.name()) {
+        for &(ref key, ref value) in entries {
+            method.set_option(key, value)?;
         }
     }
+    Ok(method)
 }
 
 pub struct DummyMethod;
 impl GammaMethod for DummyMethod {
+    fn name(&self) -> &'static str { "dummy" }
+
     fn restore(&self) -> Result<()> { Ok(()) }
 
     fn set_temperature(&mut self, setting: &transition::ColorSetting) -> Result<()> {
-        println!("Temperature: {}", setting.temp);
+        info!("Temperature: {}", setting.temp);
         Ok(())
     }
 
     fn start(&mut self) -> Result<()> {
-        println!("WARNING: Using dummy gamma method! Display will not affected by this gamma method.");
+        warn!("Using dummy gamma method! Display will not be affected by this gamma method.");
         Ok(())
     }
 }
+
+/// Used in dry-run mode, never touches the display
+pub struct DryRunMethod;
+impl GammaMethod for DryRunMethod {
+    fn name(&self) -> &'static str { "dry-run" }
+
+    fn restore(&self) -> Result<()> { Ok(()) }
+
+    fn set_temperature(&mut self, _: &transition::ColorSetting) -> Result<()> {
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        info!("Dry run, the display will not be adjusted");
+        Ok(())
+    }
+}
+
+/// Restores the gamma ramps of a started gamma method when dropped
+///
+/// This guarantees that the display is restored when continual mode
+/// is left, also because of an error or a panic.
+pub struct RestoreGuard {
+    method: Box<GammaMethod>,
+    restored: bool,
+}
+
+impl RestoreGuard {
+    pub fn new(method: Box<GammaMethod>) -> RestoreGuard {
+        RestoreGuard {
+            method: method,
+            restored: false,
+        }
+    }
+
+    /// Restore the ramps now, reporting any error
+    pub fn restore(mut self) -> Result<()> {
+        self.restored = true;
+        self.method.restore()
+    }
+}
+
+impl Deref for RestoreGuard {
+    type Target = GammaMethod + 'static;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.method
+    }
+}
+
+impl DerefMut for RestoreGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.method
+    }
+}
+
+impl Drop for RestoreGuard {
+    fn drop(&mut self) {
+        if !self.restored {
+            if let Err(e) = self.method.restore() {
+                error!("Failed to restore gamma ramps: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn disabled_methods_fail() {
+        assert_eq!(disabled_methods().is_empty(), cfg!(all(feature = "randr", feature = "hyprland")));
+        for name in disabled_methods() {
+            assert!(!methods().contains(&name));
+            match init_gamma_method(Some(name), &Options::default()) {
+                Err(RedshiftError::FeatureDisabled(_, feature)) => assert_eq!(feature, name),
+                _ => panic!("{} started", name),
+            }
+        }
+    }
+}
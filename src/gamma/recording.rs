@@ -0,0 +1,112 @@
+//! A gamma method for tests, which records what it is asked to do
+//!
+//! `RecordingMethod` computes the ramps of every setting like a real
+//! method would, and keeps them together with the setting instead of
+//! applying them to a display. A `Recording` handle, taken before the
+//! method is handed over, reads back the calls:
+//!
+//! ```
+//! extern crate redshift_rs;
+//!
+//! use redshift_rs::RedshiftBuilder;
+//! use redshift_rs::gamma::recording::{Call, RecordingMethod};
+//!
+//! # fn main() {
+//! let method = RecordingMethod::new(256);
+//! let recording = method.recording();
+//! let mut redshift = RedshiftBuilder::new()
+//!     .gamma_method(Box::new(method))
+//!     .build()
+//!     .unwrap();
+//! redshift.update().unwrap();
+//! drop(redshift);
+//!
+//! let calls = recording.calls();
+//! assert_eq!(calls.len(), 2);
+//! assert_eq!(calls[1], Call::Restore);
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use colorramp;
+use transition::ColorSetting;
+use super::{GammaMethod, Ramps};
+use super::super::Result;
+
+/// A call made on a `RecordingMethod`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    /// A setting was applied, resulting in the given ramps
+    SetTemperature(ColorSetting, Ramps),
+    Restore,
+}
+
+pub struct RecordingMethod {
+    ramp_size: usize,
+    cache: colorramp::Cache,
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+/// Read access to the calls of a `RecordingMethod`, also after it was
+/// dropped
+#[derive(Clone)]
+pub struct Recording {
+    calls: Arc<Mutex<Vec<Call>>>,
+}
+
+impl RecordingMethod {
+    /// A method with ramps of the given size, as reported by the
+    /// display for each output
+    pub fn new(ramp_size: usize) -> RecordingMethod {
+        RecordingMethod {
+            ramp_size: ramp_size,
+            cache: colorramp::Cache::new(1),
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    pub fn recording(&self) -> Recording {
+        Recording { calls: self.calls.clone() }
+    }
+}
+
+impl Recording {
+    /// Every call so far, in order
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The ramps applied last, unless restored since
+    pub fn ramps(&self) -> Option<Ramps> {
+        match self.calls.lock().unwrap().last() {
+            Some(&Call::SetTemperature(_, ref ramps)) => Some(ramps.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn clear(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+}
+
+impl GammaMethod for RecordingMethod {
+    fn name(&self) -> &'static str { "recording" }
+
+    fn start(&mut self) -> Result<()> { Ok(()) }
+
+    fn set_temperature(&mut self, setting: &ColorSetting) -> Result<()> {
+        let ramps = self.cache.ramps(setting, self.ramp_size).clone();
+        self.calls.lock().unwrap().push(Call::SetTemperature(setting.clone(), ramps));
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<()> {
+        self.calls.lock().unwrap().push(Call::Restore);
+        Ok(())
+    }
+
+    fn outputs(&self) -> Vec<String> {
+        vec!["recording".to_owned()]
+    }
+}
@@ -0,0 +1,118 @@
+//! Original gamma ramps kept on disk
+//!
+//! A method saves the ramps it found at startup here, and removes
+//! them again once it has restored them. If the daemon is killed
+//! before that, the file is left behind, and the next instance (or
+//! `-x`) restores these ramps rather than taking the shifted ones it
+//! finds as the originals.
+//!
+//! The files are in the runtime directory, which does not survive a
+//! reboot, so stale ramps are never applied to a fresh display. Each
+//! CRTC takes four lines:
+//!
+//! ```text
+//! crtc 63 1024
+//! 0 64 128 ...
+//! 0 64 128 ...
+//! 0 64 128 ...
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use paths;
+use super::Ramps;
+
+fn path(name: &str) -> PathBuf {
+    let name: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    paths::runtime_dir().join(format!("ramps-{}", name))
+}
+
+/// The ramps saved under `name`, by CRTC id
+pub fn load(name: &str) -> HashMap<u32, Ramps> {
+    let mut contents = String::new();
+    match File::open(path(name)).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => parse(&contents).unwrap_or_else(|| {
+            warn!("Ignoring malformed saved ramps in {}", path(name).display());
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Save the ramps of every CRTC under `name`
+pub fn store<'a, I>(name: &str, crtcs: I) -> io::Result<()>
+    where I: IntoIterator<Item = (u32, &'a Ramps)>
+{
+    let path = path(name);
+    if let Some(dir) = path.parent() {
+        paths::ensure_runtime_dir(dir)?;
+    }
+    // Written in one go, so that a crash leaves either the old file
+    // or the new one
+    let tmp = path.with_extension("tmp");
+    File::create(&tmp)?.write_all(contents(crtcs).as_bytes())?;
+    fs::rename(&tmp, &path)
+}
+
+/// Forget the ramps saved under `name`, after restoring them
+pub fn remove(name: &str) {
+    let _ = fs::remove_file(path(name));
+}
+
+fn contents<'a, I>(crtcs: I) -> String
+    where I: IntoIterator<Item = (u32, &'a Ramps)>
+{
+    let mut s = String::new();
+    for (id, ramps) in crtcs {
+        let _ = writeln!(s, "crtc {} {}", id, ramps.0.len());
+        for ramp in &[&ramps.0, &ramps.1, &ramps.2] {
+            let values: Vec<String> = ramp.iter().map(|v| v.to_string()).collect();
+            s.push_str(&values.join(" "));
+            s.push('\n');
+        }
+    }
+    s
+}
+
+fn parse(s: &str) -> Option<HashMap<u32, Ramps>> {
+    let mut crtcs = HashMap::new();
+    let mut lines = s.lines();
+    while let Some(header) = lines.next() {
+        let mut words = header.split_whitespace();
+        let (id, size) = match (words.next(), words.next(), words.next()) {
+            (Some("crtc"), Some(id), Some(size)) => (id.parse().ok()?, size.parse::<usize>().ok()?),
+            _ => return None,
+        };
+        let mut ramp = || -> Option<Vec<u16>> {
+            let values = lines.next()?.split_whitespace()
+                .map(|v| v.parse().ok())
+                .collect::<Option<Vec<u16>>>()?;
+            if values.len() == size { Some(values) } else { None }
+        };
+        let ramps = (ramp()?, ramp()?, ramp()?);
+        crtcs.insert(id, ramps);
+    }
+    Some(crtcs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let ramps = (vec![0, 32768], vec![0, 30000], vec![0, 20000]);
+        let s = contents(vec![(63, &ramps)]);
+        assert_eq!(s, "crtc 63 2\n0 32768\n0 30000\n0 20000\n");
+        assert_eq!(parse(&s).unwrap()[&63], ramps);
+        assert!(parse("crtc 63 3\n0 1\n0 1\n0 1\n").is_none());
+        assert!(parse("crtc 63\n").is_none());
+        assert!(parse("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,122 @@
+//! Hook scripts
+//!
+//! Executables in the hook directories of redshift-rs, C Redshift and
+//! gammastep are run whenever the period changes, with the arguments
+//! C Redshift passes, so that hooks written for it keep working:
+//!
+//! ```text
+//! ~/.config/redshift-rs/hooks/notify period-changed daytime transition
+//! ```
+//!
+//! The periods are named `none`, `daytime`, `night` and `transition`.
+//! The first period after startup changes from `none`. Hooks run one
+//! after another, in the order of their names, in a thread of their
+//! own so that a slow hook doesn't hold up the adjustments.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+
+use chan;
+use events::Event;
+use paths;
+use transition::Period;
+
+/// Programs whose hook directories are used, in `$XDG_CONFIG_HOME`
+const PROGRAMS: [&'static str; 3] = ["redshift-rs", "redshift", "gammastep"];
+
+/// The name of a period in the arguments of a hook
+fn period_name(period: Period) -> &'static str {
+    match period {
+        Period::None => "none",
+        Period::Day => "daytime",
+        Period::Night => "night",
+        Period::Transition(_) => "transition",
+    }
+}
+
+/// The hooks in the given directories, sorted by name
+fn find(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut hooks = vec![];
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut found: Vec<PathBuf> = entries.filter_map(|e| e.ok())
+            .filter(|e| e.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false))
+            .map(|e| e.path())
+            .collect();
+        found.sort();
+        hooks.extend(found);
+    }
+    hooks
+}
+
+fn run(hooks: &[PathBuf], old: Period, new: Period) {
+    for hook in hooks {
+        debug!("Running hook {}", hook.display());
+        let status = Command::new(hook)
+            .args(["period-changed", period_name(old), period_name(new)])
+            .status();
+        match status {
+            Ok(s) if !s.success() => warn!("Hook {} failed: {}", hook.display(), s),
+            Ok(_) => {}
+            Err(e) => warn!("Could not run hook {}: {}", hook.display(), e),
+        }
+    }
+}
+
+/// Run the hooks on every period change received
+///
+/// The directories are searched again on every change, so that hooks
+/// can be added and removed while the daemon runs.
+pub fn start(events: chan::Receiver<Event>) {
+    let dirs: Vec<PathBuf> = PROGRAMS.iter()
+        .filter_map(|p| paths::config_dir_of(p))
+        .map(|d| d.join("hooks"))
+        .collect();
+    thread::spawn(move || {
+        let mut old = Period::None;
+        for event in events.iter() {
+            if let Event::PeriodChanged(new) = event {
+                run(&find(&dirs), old, new);
+                old = new;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::fs::File;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn arguments() {
+        let dir = env::temp_dir().join(format!("redshift-rs-hooks-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out");
+        let hook = dir.join("record");
+        {
+            let mut f = File::create(&hook).unwrap();
+            writeln!(f, "#!/bin/sh\necho \"$@\" >> {}", out.display()).unwrap();
+        }
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let hooks = find(&[dir.clone(), dir.join("missing")]);
+        assert_eq!(hooks, [hook]);
+        run(&hooks, Period::None, Period::Day);
+        run(&hooks, Period::Day, Period::Transition(0.5));
+        let mut contents = String::new();
+        File::open(&out).unwrap().read_to_string(&mut contents).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(contents, "period-changed none daytime\nperiod-changed daytime transition\n");
+    }
+}
@@ -0,0 +1,242 @@
+//! Translated messages
+//!
+//! Rather than keeping translations of its own, redshift-rs uses the
+//! gettext catalogs of C Redshift, `redshift.mo`, which the redshift
+//! package of most distributions installs under
+//! `/usr/share/locale/LANG/LC_MESSAGES`. The language is taken from
+//! `$LANGUAGE`, `$LC_ALL`, `$LC_MESSAGES` and `$LANG`, as gettext does.
+//!
+//! Messages are looked up by the message of C Redshift, which for
+//! formatted ones has its printf conversions, as in
+//! `"Color temperature: %uK\n"`; `printf` fills those in.
+//!
+//! Translated are the messages C Redshift has too: the status output,
+//! the description in the help, the temperatures and the location
+//! logged at startup, the method in use, the headings of the lists of
+//! methods and providers, and the errors about temperatures, gamma
+//! values and unknown methods. The other messages, such as the help of
+//! the options, which C Redshift words differently, stay in English
+//! for lack of translations to reuse, as does machine-readable output.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+/// Text domain of C Redshift
+const DOMAIN: &'static str = "redshift";
+
+const MAGIC: u32 = 0x950412de;
+
+/// The messages of a compiled gettext catalog, by original message
+#[derive(Debug, Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Read a `.mo` file, `None` if it is not one
+    ///
+    /// Of plural forms only the first is kept.
+    pub fn parse(data: &[u8]) -> Option<Catalog> {
+        let word = |offset: usize, big_endian: bool| -> Option<u32> {
+            let b = data.get(offset..offset + 4)?;
+            let b = [b[0], b[1], b[2], b[3]];
+            Some(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+        };
+        let big_endian = match word(0, false)? {
+            MAGIC => false,
+            m if m.swap_bytes() == MAGIC => true,
+            _ => return None,
+        };
+        let string = |table: usize, i: usize| -> Option<&str> {
+            let len = word(table + i * 8, big_endian)? as usize;
+            let offset = word(table + i * 8 + 4, big_endian)? as usize;
+            let bytes = data.get(offset..offset + len)?;
+            ::std::str::from_utf8(bytes).ok()?.split('\0').next()
+        };
+        let count = word(8, big_endian)? as usize;
+        let originals = word(12, big_endian)? as usize;
+        let translations = word(16, big_endian)? as usize;
+        let mut messages = HashMap::new();
+        for i in 0..count {
+            let (original, translation) = (string(originals, i)?, string(translations, i)?);
+            // The empty message is the header
+            if !original.is_empty() && !translation.is_empty() {
+                messages.insert(original.to_owned(), translation.to_owned());
+            }
+        }
+        Some(Catalog { messages: messages })
+    }
+
+    /// The catalog of the first language of the user that has one
+    pub fn load() -> Option<Catalog> {
+        for language in languages() {
+            for dir in locale_dirs() {
+                let path = dir.join(&language).join("LC_MESSAGES").join(format!("{}.mo", DOMAIN));
+                let mut data = vec![];
+                if File::open(&path).and_then(|mut f| f.read_to_end(&mut data)).is_err() {
+                    continue;
+                }
+                match Catalog::parse(&data) {
+                    Some(catalog) => {
+                        debug!("Using translations from {}", path.display());
+                        return Some(catalog);
+                    }
+                    None => warn!("Ignoring {}, which is not a message catalog", path.display()),
+                }
+            }
+        }
+        None
+    }
+
+    pub fn get(&self, msgid: &str) -> Option<&str> {
+        self.messages.get(msgid).map(|s| &s[..])
+    }
+}
+
+/// The languages to look for, most preferred first, each followed by
+/// the language without its territory, e.g. "pt_BR" then "pt"
+fn languages() -> Vec<String> {
+    let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+    let locale = var("LC_ALL").or_else(|| var("LC_MESSAGES")).or_else(|| var("LANG"));
+    // $LANGUAGE is ignored in the C locale, like gettext does
+    let preferred = match locale {
+        None => return vec![],
+        Some(ref l) if l == "C" || l == "POSIX" => return vec![],
+        Some(l) => var("LANGUAGE").unwrap_or(l),
+    };
+    let mut languages = vec![];
+    for entry in preferred.split(':').filter(|l| !l.is_empty()) {
+        // Without the encoding and modifier, as in "de_DE.UTF-8@euro"
+        let language = entry.split(&['.', '@'][..]).next().unwrap_or(entry);
+        for candidate in &[language, language.split('_').next().unwrap_or(language)] {
+            if !languages.iter().any(|l| l == candidate) {
+                languages.push(candidate.to_string());
+            }
+        }
+    }
+    languages
+}
+
+/// Directories with a subdirectory of catalogs per language
+fn locale_dirs() -> Vec<PathBuf> {
+    let data_dirs = env::var("XDG_DATA_DIRS").ok().filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_owned());
+    data_dirs.split(':')
+        .filter(|d| !d.is_empty())
+        .map(|d| PathBuf::from(d).join("locale"))
+        .collect()
+}
+
+lazy_static! {
+    static ref CATALOG: Option<Catalog> = Catalog::load();
+}
+
+/// The translation of a message of C Redshift, if there is one
+pub fn translate(msgid: &str) -> Option<&'static str> {
+    CATALOG.as_ref().and_then(|c| c.get(msgid))
+}
+
+/// A message of C Redshift translated, or as it is
+pub fn tr(msgid: &'static str) -> &'static str {
+    translate(msgid).unwrap_or(msgid)
+}
+
+/// A message of C Redshift translated, or as it is, with its
+/// conversions filled in and without the line break it ends with
+pub fn message(msgid: &'static str, args: &[&fmt::Display]) -> String {
+    printf(tr(msgid), args).trim_end_matches('\n').to_owned()
+}
+
+/// Like `message`, but `None` without a translation, for messages
+/// worded differently in English than in C Redshift
+pub fn translated(msgid: &str, args: &[&fmt::Display]) -> Option<String> {
+    translate(msgid).map(|t| printf(t, args).trim_end_matches('\n').to_owned())
+}
+
+/// Fill in the printf conversions of a message with `args`
+///
+/// The arguments are formatted already, each conversion only says
+/// where one goes. Conversions numbered as in `%2$s`, which
+/// translations use to reorder them, are supported.
+pub fn printf(template: &str, args: &[&fmt::Display]) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next = 0;
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            out.push('%');
+            continue;
+        }
+        let mut spec = String::new();
+        while let Some(&c) = chars.peek() {
+            chars.next();
+            if c.is_ascii_alphabetic() && !"hlLqjzt".contains(c) {
+                break;
+            }
+            spec.push(c);
+        }
+        let index = match spec.find('$').and_then(|i| spec[..i].parse::<usize>().ok()) {
+            Some(n) => n.saturating_sub(1),
+            None => { next += 1; next - 1 }
+        };
+        if let Some(arg) = args.get(index) {
+            let _ = write!(out, "{}", arg);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A little-endian catalog of the messages
+    fn catalog(messages: &[(&str, &str)]) -> Vec<u8> {
+        let n = messages.len();
+        let mut header = vec![MAGIC, 0, n as u32, 28, 28 + 8 * n as u32, 0, 0];
+        let mut strings = vec![];
+        let start = 28 + 16 * n;
+        let mut tables: (Vec<u32>, Vec<u32>) = (vec![], vec![]);
+        for &(original, translation) in messages {
+            tables.0.extend(&[original.len() as u32, (start + strings.len()) as u32]);
+            strings.extend(original.bytes().chain(Some(0)));
+            tables.1.extend(&[translation.len() as u32, (start + strings.len()) as u32]);
+            strings.extend(translation.bytes().chain(Some(0)));
+        }
+        header.extend(tables.0);
+        header.extend(tables.1);
+        let mut data: Vec<u8> = header.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect();
+        data.extend(strings);
+        data
+    }
+
+    #[test]
+    fn catalogs() {
+        let data = catalog(&[("", "Content-Type: text/plain; charset=UTF-8\n"),
+                             ("Night", "Nacht"),
+                             ("Color temperature: %uK\n", "Farbtemperatur: %uK\n")]);
+        let catalog = Catalog::parse(&data).unwrap();
+        assert_eq!(catalog.get("Night"), Some("Nacht"));
+        assert_eq!(catalog.get(""), None);
+        assert_eq!(catalog.get("Daytime"), None);
+        assert!(Catalog::parse(&data[4..]).is_none());
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(printf("Color temperature: %uK\n", &[&6500]), "Color temperature: 6500K\n");
+        assert_eq!(printf("Period: %s (%.2f%% day)\n", &[&"Transition", &"25.00"]),
+                   "Period: Transition (25.00% day)\n");
+        assert_eq!(printf("%2$s, %1$s", &[&"a", &"b"]), "b, a");
+        assert_eq!(printf("%lu missing %s", &[&1]), "1 missing ");
+    }
+}
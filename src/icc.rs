@@ -0,0 +1,183 @@
+//! Calibration curves of ICC profiles
+//!
+//! A display calibrated with a colorimeter gets an ICC profile with a
+//! `vcgt` tag, the video card gamma table, which is meant to be loaded
+//! into the gamma ramps of its output. Redshift writes the ramps too,
+//! so the calibration would be lost. With the profile given in the
+//! output's section, the curves are applied to the ramps of every
+//! setting instead:
+//!
+//! ```text
+//! [output:DP-1]
+//! icc=/usr/share/color/icc/dell-u2720q.icc
+//! ```
+//!
+//! The calibration comes last, after the temperature and brightness,
+//! as it maps the colors meant to be shown to what the display needs
+//! to show them. Both forms of the tag are read, tables of 8 or 16-bit
+//! entries and the gamma formula.
+
+use std::fs;
+use std::path::Path;
+
+use super::{Result, RedshiftError};
+
+const VCGT: &[u8; 4] = b"vcgt";
+/// Size of the header, which the tag table follows
+const HEADER: usize = 128;
+const TABLE: u32 = 0;
+const FORMULA: u32 = 1;
+/// Entries of the curves of a formula
+const FORMULA_ENTRIES: usize = 1024;
+
+/// The red, green and blue curves of a `vcgt` tag, as 16-bit values
+/// over the range of the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    curves: [Vec<u16>; 3],
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    data.get(at..at + 2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some((u16_at(data, at)? as u32) << 16 | u16_at(data, at + 2)? as u32)
+}
+
+/// A signed 15.16 fixed-point number
+fn fixed_at(data: &[u8], at: usize) -> Option<f64> {
+    u32_at(data, at).map(|n| n as i32 as f64 / 65536.0)
+}
+
+impl Calibration {
+    /// Curves of the red, green and blue channels, of two entries or
+    /// more each
+    pub fn from_curves(curves: [Vec<u16>; 3]) -> Calibration {
+        assert!(curves.iter().all(|c| c.len() >= 2));
+        Calibration { curves: curves }
+    }
+
+    pub fn load(path: &Path) -> Result<Calibration> {
+        let data = fs::read(path).map_err(|e| RedshiftError::io(path.display(), e))?;
+        Calibration::parse(&data).map_err(|msg| RedshiftError::MalformedConfig(
+            format!("{}: {}", path.display(), msg)))
+    }
+
+    /// Read the `vcgt` tag of a profile
+    pub fn parse(data: &[u8]) -> ::std::result::Result<Calibration, String> {
+        if data.get(36..40) != Some(b"acsp") {
+            return Err("not an ICC profile".to_owned());
+        }
+        let truncated = || "truncated profile".to_owned();
+        let count = u32_at(data, HEADER).ok_or_else(truncated)? as usize;
+        let tag = (0..count)
+            .map(|i| HEADER + 4 + 12 * i)
+            .find(|&entry| data.get(entry..entry + 4) == Some(&VCGT[..]))
+            .ok_or_else(|| "no calibration (vcgt tag) in the profile".to_owned())?;
+        let offset = u32_at(data, tag + 4).ok_or_else(truncated)? as usize;
+        let size = u32_at(data, tag + 8).ok_or_else(truncated)? as usize;
+        let vcgt = data.get(offset..offset.saturating_add(size)).ok_or_else(truncated)?;
+        if vcgt.get(0..4) != Some(&VCGT[..]) {
+            return Err("malformed vcgt tag".to_owned());
+        }
+
+        let curves = match u32_at(vcgt, 8).ok_or_else(truncated)? {
+            TABLE => {
+                let channels = u16_at(vcgt, 12).ok_or_else(truncated)? as usize;
+                let entries = u16_at(vcgt, 14).ok_or_else(truncated)? as usize;
+                let entry_size = u16_at(vcgt, 16).ok_or_else(truncated)? as usize;
+                if entries < 2 || (entry_size != 1 && entry_size != 2) || (channels != 1 && channels != 3) {
+                    return Err(format!("unsupported vcgt table ({} channels of {} {}-byte entries)",
+                                       channels, entries, entry_size));
+                }
+                let value = |i: usize| -> Option<u16> {
+                    let at = 18 + i * entry_size;
+                    if entry_size == 1 { vcgt.get(at).map(|&b| b as u16 * 257) } else { u16_at(vcgt, at) }
+                };
+                // A single curve is the same for all channels
+                let curve = |c: usize| -> Option<Vec<u16>> {
+                    (0..entries).map(|i| value((c % channels) * entries + i)).collect()
+                };
+                [curve(0).ok_or_else(truncated)?, curve(1).ok_or_else(truncated)?,
+                 curve(2).ok_or_else(truncated)?]
+            }
+            FORMULA => {
+                let curve = |c: usize| -> Option<Vec<u16>> {
+                    let at = 12 + 12 * c;
+                    let (gamma, min, max) = (fixed_at(vcgt, at)?, fixed_at(vcgt, at + 4)?, fixed_at(vcgt, at + 8)?);
+                    Some((0..FORMULA_ENTRIES).map(|i| {
+                        let x = i as f64 / (FORMULA_ENTRIES - 1) as f64;
+                        ((min + (max - min) * x.powf(gamma)).clamp(0.0, 1.0) * 65535.0).round() as u16
+                    }).collect())
+                };
+                [curve(0).ok_or_else(truncated)?, curve(1).ok_or_else(truncated)?,
+                 curve(2).ok_or_else(truncated)?]
+            }
+            kind => return Err(format!("unsupported vcgt type {}", kind)),
+        };
+        Ok(Calibration { curves: curves })
+    }
+
+    /// Pass the values of the ramp of a channel through its curve,
+    /// interpolating between the entries
+    pub fn apply(&self, ramp: &mut [u16], channel: usize) {
+        let curve = &self.curves[channel];
+        let last = (curve.len() - 1) as f64;
+        for v in ramp.iter_mut() {
+            let x = *v as f64 / 65535.0 * last;
+            let i = (x.floor() as usize).min(curve.len() - 2);
+            let (a, b) = (curve[i] as f64, curve[i + 1] as f64);
+            *v = (a + (b - a) * (x - i as f64)).round() as u16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A profile with nothing but a `vcgt` tag of the given contents
+    fn profile(vcgt: &[u8]) -> Vec<u8> {
+        let mut data = vec![0; HEADER];
+        data[36..40].copy_from_slice(b"acsp");
+        let offset = HEADER as u32 + 4 + 12;
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(VCGT);
+        data.extend_from_slice(&offset.to_be_bytes());
+        data.extend_from_slice(&(vcgt.len() as u32).to_be_bytes());
+        data.extend_from_slice(vcgt);
+        data
+    }
+
+    #[test]
+    fn table() {
+        // Three 8-bit curves of two entries, the red one halved
+        let mut vcgt = b"vcgt\0\0\0\0".to_vec();
+        vcgt.extend_from_slice(&[0, 0, 0, 0, 0, 3, 0, 2, 0, 1]);
+        vcgt.extend_from_slice(&[0, 128, 0, 255, 0, 255]);
+        let calibration = Calibration::parse(&profile(&vcgt)).unwrap();
+        let mut ramp = vec![0, 32768, 65535];
+        calibration.apply(&mut ramp, 0);
+        assert_eq!(ramp, [0, 16448, 32896]);
+        let mut ramp = vec![0, 32768, 65535];
+        calibration.apply(&mut ramp, 2);
+        assert_eq!(ramp, [0, 32768, 65535]);
+    }
+
+    #[test]
+    fn formula() {
+        // Gamma 1.0 from 0.0 to 1.0 in all channels, the identity
+        let mut vcgt = b"vcgt\0\0\0\0\0\0\0\x01".to_vec();
+        for _ in 0..3 {
+            vcgt.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0]);
+        }
+        let calibration = Calibration::parse(&profile(&vcgt)).unwrap();
+        let mut ramp = vec![0, 1000, 40000, 65535];
+        calibration.apply(&mut ramp, 1);
+        assert_eq!(ramp, [0, 1000, 40000, 65535]);
+
+        assert!(Calibration::parse(b"not a profile").is_err());
+        assert!(Calibration::parse(&profile(b"vcgt\0\0\0\0\0\0\0\x07")).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+//! Idle dimming
+//!
+//! With an `[idle]` section in the config file, the daemon dims the
+//! display further once there has been no keyboard or mouse input for
+//! a while, and brightens it again at the next input:
+//!
+//! ```text
+//! [idle]
+//! timeout=10m       ; without input, 5 minutes by default
+//! brightness=0.5    ; factor of the brightness while idle
+//! ```
+//!
+//! The factor scales the brightness of the schedule, after the
+//! ambient light, so it dims the same way at day and at night.
+//!
+//! On Wayland the compositor tells when the seat went idle, through
+//! the ext-idle-notify-v1 protocol, and on X alarms of the SYNC
+//! extension on its IDLETIME counter tell when the time since the last
+//! input reaches the timeout, and when input resets it. Wayland is
+//! used when there is a Wayland display, as XWayland only sees the
+//! input to X clients.
+
+use std::env;
+
+use chan;
+
+use config::Section;
+use gamma;
+use super::{parse_interval, Result, RedshiftError};
+
+/// Time without input without a `timeout` setting, in seconds
+const DEFAULT_TIMEOUT_S: f64 = 300.0;
+
+const TIMEOUT_RANGE_S: (f64, f64) = (10.0, 86400.0);
+
+/// Factor of the brightness without a `brightness` setting
+const DEFAULT_BRIGHTNESS: f64 = 0.5;
+
+/// Lowest factor of the brightness, keeping the screen readable
+const MIN_BRIGHTNESS: f64 = 0.1;
+
+/// Settings of the `[idle]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Time without input until the display is dimmed, in seconds
+    pub timeout: f64,
+    /// Factor of the brightness while idle
+    pub brightness: f64,
+}
+
+impl Options {
+    pub fn from_config(section: &Section) -> Result<Options> {
+        let brightness = match section.get("brightness") {
+            Some(b) => match b.trim().parse::<f64>() {
+                Ok(b) if (MIN_BRIGHTNESS..=1.0).contains(&b) => b,
+                _ => return Err(RedshiftError::MalformedConfig(
+                    format!("[{}] brightness: {} ({} to 1.0)", section.name, b, MIN_BRIGHTNESS))),
+            },
+            None => DEFAULT_BRIGHTNESS,
+        };
+        Ok(Options {
+            timeout: section.get("timeout")
+                .map_or(Ok(DEFAULT_TIMEOUT_S), |t| parse_interval("timeout", t, TIMEOUT_RANGE_S))?,
+            brightness: brightness,
+        })
+    }
+}
+
+/// Follow whether the user is idle
+///
+/// The channel receives `true` once there was no input for `timeout`
+/// seconds, and `false` at the next input. `None` if the idle time
+/// can't be followed.
+pub fn watch(options: &gamma::Options, timeout: f64) -> Option<chan::Receiver<bool>> {
+    let timeout_ms = (timeout * 1000.0) as u32;
+    if options.wayland_display.is_some() || env::var_os("WAYLAND_DISPLAY").is_some() {
+        return wayland::watch(options.wayland_display.as_ref().map(|s| &s[..]), timeout_ms);
+    }
+    x11_watch(options.main_display(), timeout_ms)
+}
+
+#[cfg(feature = "randr")]
+fn x11_watch(display: Option<&str>, timeout_ms: u32) -> Option<chan::Receiver<bool>> {
+    x11::watch(display, timeout_ms)
+}
+
+#[cfg(not(feature = "randr"))]
+fn x11_watch(_: Option<&str>, _: u32) -> Option<chan::Receiver<bool>> {
+    debug!("Built without X support, not following the idle time");
+    None
+}
+
+mod wayland {
+    use std::io;
+    use std::thread;
+
+    use chan;
+    use redshift_rs::wayland::{Arg, Connection, REGISTRY_ID, REGISTRY_GLOBAL};
+
+    const NOTIFIER_INTERFACE: &'static str = "ext_idle_notifier_v1";
+    const SEAT_INTERFACE: &'static str = "wl_seat";
+
+    // Opcodes of the requests and events used, by interface
+    const NOTIFIER_GET_IDLE_NOTIFICATION: u16 = 1;
+    const NOTIFICATION_IDLED: u16 = 0;
+    const NOTIFICATION_RESUMED: u16 = 1;
+
+    /// Ask the compositor for a notification after `timeout_ms`
+    /// without input on the first seat, returning the connection and
+    /// the id of the notification
+    fn subscribe(display: Option<&str>, timeout_ms: u32) -> io::Result<Option<(Connection, u32)>> {
+        let conn = Connection::connect(display)?;
+        let (mut notifier, mut seat) = (None, None);
+        for mut event in conn.roundtrip()? {
+            if event.object == REGISTRY_ID && event.opcode == REGISTRY_GLOBAL {
+                let (global, interface) = (event.uint()?, event.string()?);
+                if interface == NOTIFIER_INTERFACE {
+                    notifier = Some(global);
+                } else if interface == SEAT_INTERFACE {
+                    seat = seat.or(Some(global));
+                }
+            }
+        }
+        let (notifier, seat) = match (notifier, seat) {
+            (Some(n), Some(s)) => (n, s),
+            _ => return Ok(None),
+        };
+        let notifier = conn.bind(notifier, NOTIFIER_INTERFACE, 1)?;
+        let seat = conn.bind(seat, SEAT_INTERFACE, 1)?;
+        let notification = conn.new_id();
+        conn.send(notifier, NOTIFIER_GET_IDLE_NOTIFICATION,
+                  &[Arg::Uint(notification), Arg::Uint(timeout_ms), Arg::Uint(seat)])?;
+        conn.roundtrip()?;
+        // From now on nothing happens until the next event, which must
+        // not be given up on halfway through
+        conn.set_read_timeout(None)?;
+        Ok(Some((conn, notification)))
+    }
+
+    pub fn watch(display: Option<&str>, timeout_ms: u32) -> Option<chan::Receiver<bool>> {
+        let (conn, notification) = match subscribe(display, timeout_ms) {
+            Ok(Some(subscribed)) => subscribed,
+            Ok(None) => {
+                warn!("The compositor doesn't support ext-idle-notify, not following the idle time");
+                return None;
+            }
+            Err(e) => {
+                warn!("Not following the idle time: {}", e);
+                return None;
+            }
+        };
+        let (tx, rx) = chan::async();
+        thread::spawn(move || loop {
+            match conn.read_event() {
+                Ok(ref event) if event.object == notification => match event.opcode {
+                    NOTIFICATION_IDLED => tx.send(true),
+                    NOTIFICATION_RESUMED => tx.send(false),
+                    _ => {}
+                },
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("No longer following the idle time: {}", e);
+                    return;
+                }
+            }
+        });
+        Some(rx)
+    }
+}
+
+#[cfg(feature = "randr")]
+mod x11 {
+    use std::thread;
+
+    use chan;
+    use xcb;
+    use xcb::ffi::sync::{xcb_sync_create_alarm, xcb_sync_create_alarm_value_list_t, xcb_sync_int64_t};
+    use xcb::sync;
+
+    /// The system counter of the time since the last input, in
+    /// milliseconds
+    const IDLETIME: &'static str = "IDLETIME";
+
+    fn idle_counter(conn: &xcb::Connection) -> Option<sync::Counter> {
+        sync::initialize(conn, 3, 1).get_reply().ok()?;
+        let counters = sync::list_system_counters(conn).get_reply().ok()?;
+        let counter = counters.counters().find(|c| c.name() == IDLETIME).map(|c| c.counter());
+        counter
+    }
+
+    fn millis(value: sync::Int64) -> i64 {
+        ((value.hi() as i64) << 32) | value.lo() as i64
+    }
+
+    /// An alarm on the idle time crossing `ms`, upwards or downwards by
+    /// the test
+    fn alarm(conn: &xcb::Connection, counter: sync::Counter, ms: u32, test: sync::Testtype) -> sync::Alarm {
+        let alarm = conn.generate_id();
+        let values = xcb_sync_create_alarm_value_list_t {
+            counter: counter,
+            valueType: sync::VALUETYPE_ABSOLUTE,
+            value: xcb_sync_int64_t { hi: 0, lo: ms },
+            testType: test,
+            // Transitions keep the alarm armed without a delta
+            delta: xcb_sync_int64_t { hi: 0, lo: 0 },
+            events: 1,
+        };
+        let mask = sync::CA_COUNTER | sync::CA_VALUE_TYPE | sync::CA_VALUE | sync::CA_TEST_TYPE |
+                   sync::CA_DELTA | sync::CA_EVENTS;
+        // With every attribute given, the list of values is laid out as
+        // the structure
+        unsafe {
+            xcb_sync_create_alarm(conn.get_raw_conn(), alarm, mask, &values);
+        }
+        alarm
+    }
+
+    pub fn watch(display: Option<&str>, timeout_ms: u32) -> Option<chan::Receiver<bool>> {
+        let (conn, _) = match xcb::Connection::connect(display) {
+            Ok(c) => c,
+            Err(_) => return None,
+        };
+        if idle_counter(&conn).is_none() {
+            warn!("The SYNC extension has no IDLETIME counter, not following the idle time");
+            return None;
+        }
+        drop(conn);
+
+        let display = display.map(ToOwned::to_owned);
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            // The connection is not Send, so the thread makes its own
+            let (conn, _) = match xcb::Connection::connect(display.as_ref().map(|s| &s[..])) {
+                Ok(c) => c,
+                Err(_) => return,
+            };
+            let counter = match idle_counter(&conn) {
+                Some(c) => c,
+                None => return,
+            };
+            let notify = match conn.get_extension_data(sync::id()) {
+                Some(ext) => ext.first_event() + sync::ALARM_NOTIFY,
+                None => return,
+            };
+            // One alarm for reaching the timeout, and one for the input
+            // that resets the idle time after it
+            let alarms = [alarm(&conn, counter, timeout_ms, sync::TESTTYPE_POSITIVE_TRANSITION),
+                          alarm(&conn, counter, timeout_ms - 1, sync::TESTTYPE_NEGATIVE_TRANSITION)];
+            conn.flush();
+            let mut idle = false;
+            if let Ok(reply) = sync::query_counter(&conn, counter).get_reply() {
+                if millis(reply.counter_value()) >= timeout_ms as i64 {
+                    idle = true;
+                    tx.send(idle);
+                }
+            }
+            while let Some(event) = conn.wait_for_event() {
+                if event.response_type() & !0x80 != notify {
+                    continue;
+                }
+                let event: &sync::AlarmNotifyEvent = xcb::cast_event(&event);
+                if !alarms.contains(&event.alarm()) {
+                    continue;
+                }
+                let now = millis(event.counter_value()) >= timeout_ms as i64;
+                if now != idle {
+                    idle = now;
+                    tx.send(idle);
+                }
+            }
+        });
+        Some(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::Config;
+
+    #[test]
+    fn options() {
+        let config = "[idle]\ntimeout=10m\n".parse::<Config>().unwrap();
+        let options = Options::from_config(config.section("idle").unwrap()).unwrap();
+        assert_eq!(options, Options { timeout: 600.0, brightness: DEFAULT_BRIGHTNESS });
+
+        let config = "[idle]\nbrightness=0.05\n".parse::<Config>().unwrap();
+        assert!(Options::from_config(config.section("idle").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//! Screensaver inhibitors
+//!
+//! Presentation tools and video players keep the screen on by
+//! inhibiting the screensaver, through `org.freedesktop.ScreenSaver`
+//! or the Inhibit portal. With an `[inhibit]` section in the config
+//! file the daemon follows them too: the adjustments are paused while
+//! an inhibitor is held, or with `profile` a profile is switched to
+//! instead, until the last inhibitor is released:
+//!
+//! ```text
+//! [inhibit]
+//! profile=presentation    ; a [profile:presentation] section
+//! ```
+//!
+//! Both kinds of inhibitors end up with the session manager: GNOME's
+//! `org.gnome.SessionManager`, and `org.freedesktop.PowerManagement.Inhibit`
+//! of KDE, Xfce and others. It is asked again whenever it signals that
+//! an inhibitor was added or removed. Asking it takes the `dbus`
+//! feature.
+
+use chan;
+
+use config::{Config, Section};
+use super::{Result, RedshiftError};
+
+/// Settings of the `[inhibit]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Profile to switch to while inhibited, rather than pausing
+    pub profile: Option<String>,
+}
+
+impl Options {
+    pub fn from_config(section: &Section, config: &Config) -> Result<Options> {
+        for e in &section.entries {
+            if e.0 != "profile" {
+                return Err(RedshiftError::MalformedConfig(
+                    format!("[{}] {}: unknown option", section.name, e.0)));
+            }
+        }
+        let profile = section.get("profile").map(|p| p.trim().to_owned());
+        if let Some(ref profile) = profile {
+            config.profile(profile)?;
+        }
+        Ok(Options { profile: profile })
+    }
+}
+
+/// Follow whether the screensaver is inhibited
+///
+/// The channel receives `true` when an inhibitor is taken and `false`
+/// when the last one is released. `None` if inhibitors can't be
+/// followed.
+#[cfg(feature = "dbus")]
+pub fn watch() -> Option<chan::Receiver<bool>> {
+    session::watch()
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn watch() -> Option<chan::Receiver<bool>> {
+    warn!("Inhibitors can't be followed, support for D-Bus is not compiled in");
+    None
+}
+
+#[cfg(feature = "dbus")]
+mod session {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use std::thread;
+
+    use chan;
+    use dbus::Message;
+    use dbus::blocking::Connection;
+    use dbus::message::MatchRule;
+
+    const GNOME: &'static str = "org.gnome.SessionManager";
+    const GNOME_PATH: &'static str = "/org/gnome/SessionManager";
+    /// The flag of inhibitors of the idle state, which blank the screen
+    const GNOME_INHIBIT_IDLE: u32 = 8;
+
+    const POWER_MANAGEMENT: &'static str = "org.freedesktop.PowerManagement";
+    const POWER_MANAGEMENT_PATH: &'static str = "/org/freedesktop/PowerManagement/Inhibit";
+    const POWER_MANAGEMENT_INHIBIT: &'static str = "org.freedesktop.PowerManagement.Inhibit";
+
+    /// The signals after which the inhibitors are asked for again
+    const SIGNALS: [(&'static str, &'static str); 3] = [
+        (GNOME, "InhibitorAdded"),
+        (GNOME, "InhibitorRemoved"),
+        (POWER_MANAGEMENT_INHIBIT, "HasInhibitChanged"),
+    ];
+
+    const TIMEOUT_MS: u64 = 1000;
+    /// Longest wait for a signal in one go, in milliseconds
+    const WAIT_MS: u64 = 3_600_000;
+
+    /// Whether any inhibitor is held, by whichever session manager
+    /// answers
+    fn inhibited(conn: &Connection) -> Result<bool, ::dbus::Error> {
+        let timeout = Duration::from_millis(TIMEOUT_MS);
+        let gnome = conn.with_proxy(GNOME, GNOME_PATH, timeout)
+            .method_call(GNOME, "IsInhibited", (GNOME_INHIBIT_IDLE,));
+        match gnome {
+            Ok((inhibited,)) => Ok(inhibited),
+            Err(_) => conn.with_proxy(POWER_MANAGEMENT, POWER_MANAGEMENT_PATH, timeout)
+                .method_call(POWER_MANAGEMENT_INHIBIT, "HasInhibit", ())
+                .map(|(inhibited,): (bool,)| inhibited),
+        }
+    }
+
+    fn follow(conn: &Connection, tx: chan::Sender<bool>) -> Result<(), ::dbus::Error> {
+        let changed = Arc::new(AtomicBool::new(true));
+        for &(interface, member) in &SIGNALS {
+            let flag = changed.clone();
+            let rule = MatchRule::new_signal(interface, member);
+            conn.add_match(rule, move |_: (), _: &Connection, _: &Message| {
+                flag.store(true, Ordering::SeqCst);
+                true
+            })?;
+        }
+        let mut last = None;
+        loop {
+            if changed.swap(false, Ordering::SeqCst) {
+                let inhibited = inhibited(conn)?;
+                if Some(inhibited) != last {
+                    last = Some(inhibited);
+                    tx.send(inhibited);
+                }
+            }
+            conn.process(Duration::from_millis(WAIT_MS))?;
+        }
+    }
+
+    pub fn watch() -> Option<chan::Receiver<bool>> {
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let result = Connection::new_session().and_then(|conn| follow(&conn, tx));
+            if let Err(e) = result {
+                warn!("Not following the inhibitors: {}", e);
+            }
+        });
+        Some(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn options() {
+        let config = "[inhibit]\n[profile:presentation]\n".parse::<Config>().unwrap();
+        let options = Options::from_config(config.section("inhibit").unwrap(), &config).unwrap();
+        assert_eq!(options.profile, None);
+
+        let config = "[inhibit]\nprofile=presentation\n[profile:presentation]\n".parse::<Config>().unwrap();
+        let options = Options::from_config(config.section("inhibit").unwrap(), &config).unwrap();
+        assert_eq!(options.profile, Some("presentation".to_owned()));
+
+        let config = "[inhibit]\nprofile=missing\n".parse::<Config>().unwrap();
+        assert!(Options::from_config(config.section("inhibit").unwrap(), &config).is_err());
+        let config = "[inhibit]\npause=1\n".parse::<Config>().unwrap();
+        assert!(Options::from_config(config.section("inhibit").unwrap(), &config).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+//! Single-instance detection
+//!
+//! Two daemons adjusting the same display would fight over the gamma
+//! ramps, and each would restore the other's ramps when exiting. A
+//! daemon therefore holds a lock for each display it adjusts, and
+//! refuses to start while another instance holds any of them. The
+//! names of the displays are normalised first, so that `:0`, `:0.0`
+//! and `unix:0` are the same display.
+//!
+//! On Linux a lock is a Unix socket in the abstract namespace named
+//! after the user and the display, which goes away with the process,
+//! so a crashed instance never leaves a stale lock behind. Elsewhere it
+//! is a file in the runtime directory locked with flock(2), which is
+//! released with the process just the same, with a socket next to it.
+//!
+//! A new instance started with `--replace` connects to the socket to
+//! ask the running one to quit, and waits until it has. The abstract
+//! namespace is shared by all users of the host, without permissions,
+//! so the request is only obeyed when it comes from the same user.
+//!
+//! The files an instance keeps in the runtime directory, such as its
+//! control socket, are in a directory named after its first display,
+//! see `runtime_dir`, and so is its state, see `state_dir`.
+
+use std::env;
+#[cfg(not(target_os = "linux"))]
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(not(target_os = "linux"))]
+use std::os::unix::io::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use daemon::{Command, Control};
+use gamma;
+use paths;
+use super::{Result, RedshiftError};
+
+const REPLACE_REQUEST: &'static str = "replace";
+
+/// How long to wait for a replaced instance to fade out and exit
+const REPLACE_TIMEOUT_MS: u64 = 10000;
+
+/// The lock of one display
+struct Held {
+    listener: UnixListener,
+    /// The locked file, and the socket next to it
+    #[cfg(not(target_os = "linux"))]
+    files: (File, PathBuf),
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Drop for Held {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.files.1);
+    }
+}
+
+/// The locks of the displays, held until the process exits
+pub struct Lock {
+    held: Vec<Held>,
+}
+
+/// An X display without the screen, and without the host when it is
+/// local, e.g. ":0" for "unix:0.0" or "/tmp/.X11-unix/X0"
+fn x_display(display: &str) -> String {
+    if display.starts_with('/') {
+        // The socket of a local display
+        if let Some(n) = display.rsplit('/').next().and_then(|socket| socket.strip_prefix('X')) {
+            return format!(":{}", n);
+        }
+    }
+    let (host, number) = match display.rfind(':') {
+        Some(i) => (&display[..i], &display[i + 1..]),
+        None => return display.to_owned(),
+    };
+    let host = if host == "unix" { "" } else { host };
+    let number = number.split('.').next().unwrap_or(number);
+    format!("{}:{}", host, number)
+}
+
+/// A Wayland socket by its name in the runtime directory, where it is
+/// in there
+fn wayland_display(display: &str) -> String {
+    let path = Path::new(display);
+    match (path.parent(), env::var_os("XDG_RUNTIME_DIR")) {
+        (Some(dir), Some(runtime)) if path.is_absolute() && dir == Path::new(&runtime) =>
+            path.file_name().map_or(display.to_owned(), |n| n.to_string_lossy().into_owned()),
+        _ => display.to_owned(),
+    }
+}
+
+/// The normalised names of the displays the gamma method connects to
+///
+/// These are the X displays given, or `$DISPLAY`, and the Wayland
+/// socket, or "console" when there is neither, e.g. when adjusting
+/// through DRM.
+pub fn displays(options: &gamma::Options) -> Vec<String> {
+    let mut x: Vec<String> = options.displays().iter().map(|d| x_display(d)).collect();
+    if x.is_empty() {
+        x.extend(env::var("DISPLAY").ok().filter(|d| !d.is_empty()).map(|d| x_display(&d)));
+    }
+    let wayland = options.wayland_display.clone().or_else(|| env::var("WAYLAND_DISPLAY").ok());
+    let mut displays = vec![];
+    for display in x.into_iter().chain(wayland.filter(|w| !w.is_empty()).map(|w| wayland_display(&w))) {
+        if !displays.contains(&display) {
+            displays.push(display);
+        }
+    }
+    if displays.is_empty() {
+        displays.push("console".to_owned());
+    }
+    displays
+}
+
+/// The name of the directories of an instance on these displays,
+/// after the first display
+fn dir_name(options: &gamma::Options) -> String {
+    displays(options).swap_remove(0).replace('/', "_")
+}
+
+/// The directory of the files an instance on these displays keeps in
+/// the runtime directory, named after the first display
+pub fn runtime_dir(options: &gamma::Options) -> PathBuf {
+    paths::runtime_dir().join(dir_name(options))
+}
+
+/// The directory of the state an instance on these displays keeps
+/// across restarts, named after the first display, `None` without a
+/// home directory
+pub fn state_dir(options: &gamma::Options) -> Option<PathBuf> {
+    paths::state_dir().map(|d| d.join(dir_name(options)))
+}
+
+fn io_error(display: &str, e: io::Error) -> RedshiftError {
+    RedshiftError::io(format_args!("instance lock for {}", display), e)
+}
+
+#[cfg(target_os = "linux")]
+fn address(display: &str) -> io::Result<SocketAddr> {
+    let uid = unsafe { libc::getuid() };
+    SocketAddr::from_abstract_name(format!("redshift-rs/{}/{}", uid, display).as_bytes())
+}
+
+/// The user on the other end of a connection
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED,
+                         &mut cred as *mut libc::ucred as *mut libc::c_void, &mut len)
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Whether a connection comes from the user running this instance
+fn same_user(stream: &UnixStream) -> bool {
+    match peer_uid(stream) {
+        Ok(uid) => uid == unsafe { libc::getuid() },
+        Err(e) => {
+            warn!("Could not tell who is asking to replace this instance: {}", e);
+            false
+        }
+    }
+}
+
+/// Take the lock of a display, `None` if another instance holds it
+#[cfg(target_os = "linux")]
+fn lock(display: &str) -> io::Result<Option<Held>> {
+    match UnixListener::bind_addr(&address(display)?) {
+        Ok(listener) => Ok(Some(Held { listener: listener })),
+        Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock_path(display: &str) -> PathBuf {
+    paths::runtime_dir().join(format!("{}.lock", display.replace('/', "_")))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock(display: &str) -> io::Result<Option<Held>> {
+    let path = lock_path(display);
+    if let Some(dir) = path.parent() {
+        paths::ensure_runtime_dir(dir)?;
+    }
+    let file = OpenOptions::new().write(true).create(true).open(&path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let e = io::Error::last_os_error();
+        return if e.kind() == io::ErrorKind::WouldBlock { Ok(None) } else { Err(e) };
+    }
+    // Left behind by an instance that held the lock before
+    let socket = path.with_extension("sock");
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket)?;
+    Ok(Some(Held { listener: listener, files: (file, socket) }))
+}
+
+/// Connect to the instance holding the lock of a display
+#[cfg(target_os = "linux")]
+fn connect(display: &str) -> io::Result<UnixStream> {
+    UnixStream::connect_addr(&address(display)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect(display: &str) -> io::Result<UnixStream> {
+    UnixStream::connect(lock_path(display).with_extension("sock"))
+}
+
+/// Take the locks of the displays
+///
+/// Fails if another instance holds any of them, unless `replace` is
+/// given, in which case the instances holding them are asked to quit
+/// first.
+pub fn acquire(displays: &[String], replace: bool) -> Result<Lock> {
+    let mut held = vec![];
+    let mut taken = vec![];
+    for display in displays {
+        match lock(display).map_err(|e| io_error(display, e))? {
+            Some(h) => held.push(h),
+            None => taken.push(display),
+        }
+    }
+    if !taken.is_empty() && !replace {
+        let taken: Vec<&str> = taken.iter().map(|d| &d[..]).collect();
+        return Err(RedshiftError::AlreadyRunning(taken.join(", ")));
+    }
+
+    for display in &taken {
+        info!("Replacing the instance running on {}", display);
+        if let Ok(mut stream) = connect(display) {
+            writeln!(stream, "{}", REPLACE_REQUEST).map_err(|e| io_error(display, e))?;
+        }
+    }
+    let start = Instant::now();
+    while let Some(&display) = taken.last() {
+        if let Some(h) = lock(display).map_err(|e| io_error(display, e))? {
+            held.push(h);
+            taken.pop();
+            continue;
+        }
+        if start.elapsed() >= Duration::from_millis(REPLACE_TIMEOUT_MS) {
+            return Err(io_error(display, io::Error::new(io::ErrorKind::TimedOut,
+                                                        "the running instance did not quit")));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(Lock { held: held })
+}
+
+impl Lock {
+    /// Answer requests of instances of the same user that want to
+    /// replace this one
+    pub fn serve(&self, control: Control) -> Result<()> {
+        for held in &self.held {
+            let listener = held.listener.try_clone().map_err(|e| io_error("socket", e))?;
+            let control = control.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().filter_map(|s| s.ok()) {
+                    if !same_user(&stream) {
+                        warn!("Ignoring a replace request of another user");
+                        continue;
+                    }
+                    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                    let mut line = String::new();
+                    if BufReader::new(&stream).read_line(&mut line).is_ok() &&
+                        line.trim() == REPLACE_REQUEST
+                    {
+                        control.send(Command::Quit("replace request"));
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalised_displays() {
+        assert_eq!(x_display(":0"), ":0");
+        assert_eq!(x_display(":0.1"), ":0");
+        assert_eq!(x_display("unix:1.0"), ":1");
+        assert_eq!(x_display("/tmp/.X11-unix/X2"), ":2");
+        assert_eq!(x_display("remote:10.0"), "remote:10");
+
+        let options = gamma::Options {
+            display: Some(":0.0, unix:0,:1".to_owned()),
+            wayland_display: Some("wayland-1".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(displays(&options), [":0", ":1", "wayland-1"]);
+    }
+
+    #[test]
+    fn replace_requests_of_the_user() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert!(same_user(&a));
+    }
+}
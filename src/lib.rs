@@ -0,0 +1,213 @@
+//!
+//! # Redshift in Rust
+//!
+//! The machinery behind the `redshift-rs` binary, for programs that
+//! want to adjust the color temperature themselves (status bars,
+//! compositor helpers, GUIs) instead of running the binary:
+//!
+//! - `solar` computes the elevation of the sun at a location
+//! - `clock` tells the time the schedule is computed for
+//! - `transition` maps the elevation to a `ColorSetting` by the day
+//!   and night settings of a `TransitionScheme`
+//! - `colorramp` fills gamma ramps for a `ColorSetting`
+//! - `vision` has the filters for color vision deficiencies
+//! - `gamma` applies a `ColorSetting` to the display through one of
+//!   the supported gamma methods
+//! - `location` parses locations and lists the location providers
+//! - `status` formats the computed parameters for reports
+//! - `controller` puts these together behind `RedshiftBuilder`
+//! - `events` notifies observers of changes, such as a new period
+//! - `ffi` offers the same to C, the crate is built as a cdylib too
+//!
+//! A oneshot adjustment, as done by `redshift-rs -o`:
+//!
+//! ```no_run
+//! extern crate redshift_rs;
+//!
+//! use redshift_rs::{gamma, solar, transition};
+//! use redshift_rs::location::Location;
+//!
+//! # fn main() {
+//! let location = Location::new(55.7, 12.6);
+//! let now = std::time::SystemTime::now()
+//!     .duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as f64;
+//!
+//! let mut scheme = transition::TransitionScheme::new();
+//! scheme.day = transition::ColorSetting { temp: 5500, ..transition::ColorSetting::neutral() };
+//! scheme.night = transition::ColorSetting { temp: 3500, ..transition::ColorSetting::neutral() };
+//! let setting = scheme.interpolate_color_settings(solar::elevation(now, &location));
+//!
+//! let mut method = gamma::init_gamma_method(None, &Default::default()).unwrap();
+//! method.start().unwrap();
+//! method.set_temperature(&setting).unwrap();
+//! # }
+//! ```
+//!
+//! The daemon, its configuration file and its control interfaces are
+//! part of the binary and not of this crate.
+//!
+
+#[cfg(test)] extern crate time;
+#[macro_use] extern crate chan;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+extern crate libc;
+
+// Optional features for gamma method providers
+#[cfg(feature = "randr")] extern crate xcb;
+
+// Optional features for location providers
+#[cfg(any(feature = "geoclue2", feature = "portal"))] extern crate dbus;
+
+use std::fmt;
+use std::io;
+use std::result;
+use std::error::Error;
+
+pub mod clock;
+pub mod colorramp;
+pub mod controller;
+pub mod events;
+pub mod ffi;
+pub mod gamma;
+pub mod i18n;
+pub mod icc;
+pub mod location;
+pub mod paths;
+pub mod solar;
+pub mod status;
+pub mod transition;
+pub mod vision;
+pub mod wayland;
+
+pub use controller::{Redshift, RedshiftBuilder};
+
+pub type Result<T> = result::Result<T, RedshiftError>;
+
+/// Temperature that leaves the colors of the display unchanged
+pub const NEUTRAL_TEMP: i32 = 6500;
+pub const DEFAULT_DAY_TEMP:   i32 = 5500;
+pub const DEFAULT_NIGHT_TEMP: i32 = 3500;
+pub const DEFAULT_BRIGHTNESS: f64 = 1.0;
+pub const DEFAULT_GAMMA:      f64 = 1.0;
+pub const DEFAULT_CONTRAST:   f64 = 1.0;
+/// Brightness of the red channel in night vision mode
+pub const DEFAULT_NIGHT_VISION_BRIGHTNESS: f64 = 0.5;
+/// Color matrix that leaves the colors unchanged
+pub const IDENTITY_MATRIX: [[f64; 3]; 3] = [[1.0, 0.0, 0.0],
+                                            [0.0, 1.0, 0.0],
+                                            [0.0, 0.0, 1.0]];
+/// Color matrix turning each pixel into gray of the same luminance,
+/// by the weights of sRGB
+pub const GRAYSCALE_MATRIX: [[f64; 3]; 3] = [[0.2126, 0.7152, 0.0722],
+                                             [0.2126, 0.7152, 0.0722],
+                                             [0.2126, 0.7152, 0.0722]];
+/// Lowest supported temperature
+pub const MIN_TEMP:     i32 = 1000;
+/// Highest supported temperature
+pub const MAX_TEMP:     i32 = 25000;
+/// Lowest supported gamma
+pub const MIN_GAMMA:    f64 = 0.1;
+/// Highest supported gamma
+pub const MAX_GAMMA:    f64 = 10.0;
+/// Lowest supported contrast
+pub const MIN_CONTRAST: f64 = 0.1;
+/// Highest supported contrast
+pub const MAX_CONTRAST: f64 = 2.0;
+
+
+/// Check that a temperature is within the supported range, that of
+/// the whitepoint table of `colorramp`
+pub fn check_temperature(temp: i32) -> Result<i32> {
+    if temp < MIN_TEMP || temp > MAX_TEMP {
+        Err(RedshiftError::MalformedArgument(
+            match i18n::translated("Temperature must be between %uK and %uK.\n", &[&MIN_TEMP, &MAX_TEMP]) {
+                Some(range) => format!("{} ({}K)", range, temp),
+                None => format!("temperature {}K is outside the supported range ({}K to {}K)",
+                                temp, MIN_TEMP, MAX_TEMP),
+            }))
+    } else {
+        Ok(temp)
+    }
+}
+
+/// Everything that can go wrong, in kinds that callers can match on
+///
+/// Errors caused by another error, such as a failing system call,
+/// include its message and return it from `Error::source()`.
+#[derive(Debug)]
+pub enum RedshiftError {
+    /// A command line argument, or a value in the same syntax, is invalid
+    MalformedArgument(String),
+    /// The configuration file, or another file read, is invalid
+    MalformedConfig(String),
+    ProfileNotFound(String),
+    GammaMethodNotFound(String),
+    /// The named built-in gamma method or location provider was left
+    /// out at compile time, and needs the given cargo feature
+    FeatureDisabled(String, &'static str),
+    /// The named gamma method failed to start or adjust the display
+    Gamma(String, Box<Error>),
+    /// The named location provider failed
+    Location(String, Box<Error>),
+    /// Reading or writing the given file or socket failed
+    Io(String, io::Error),
+    /// A control request failed, or a control interface could not be
+    /// started
+    Control(String),
+    /// Another instance holds the lock of the given display
+    AlreadyRunning(String),
+}
+
+impl RedshiftError {
+    /// An I/O error while accessing `what`
+    pub fn io<D: fmt::Display>(what: D, e: io::Error) -> RedshiftError {
+        RedshiftError::Io(what.to_string(), e)
+    }
+}
+
+impl fmt::Display for RedshiftError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use RedshiftError::*;
+        match *self {
+            MalformedArgument(ref msg) =>
+                write!(f, "malformed argument: {}", msg),
+            MalformedConfig(ref msg) =>
+                write!(f, "malformed config: {}", msg),
+            ProfileNotFound(ref profile) =>
+                write!(f, "profile '{}' not found", profile),
+            GammaMethodNotFound(ref method_name) =>
+                match i18n::translated("Unknown adjustment method `%s'.\n", &[method_name]) {
+                    Some(msg) => write!(f, "{}", msg),
+                    None => write!(f, "gamma method '{}' not found", method_name),
+                },
+            FeatureDisabled(ref what, feature) =>
+                write!(f, "{} is not compiled in (build with --features {})", what, feature),
+            Gamma(ref method, ref e) =>
+                write!(f, "gamma method {}: {}", method, e),
+            Location(ref provider, ref e) =>
+                write!(f, "location provider {}: {}", provider, e),
+            Io(ref what, ref e) =>
+                write!(f, "{}: {}", what, e),
+            Control(ref msg) =>
+                write!(f, "{}", msg),
+            AlreadyRunning(ref display) =>
+                write!(f, "another instance is already adjusting {} (use --replace to take over)",
+                       display),
+        }
+    }
+}
+
+impl Error for RedshiftError {
+    fn description(&self) -> &str {
+        "redshift error"
+    }
+
+    fn source(&self) -> Option<&(Error + 'static)> {
+        match *self {
+            RedshiftError::Gamma(_, ref e) | RedshiftError::Location(_, ref e) => Some(&**e),
+            RedshiftError::Io(_, ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
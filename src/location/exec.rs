@@ -0,0 +1,77 @@
+//! Location from a command
+//!
+//! For setups no provider covers, such as a company API or a phone
+//! sharing its position, the location can come from a command, run
+//! with `sh -c`, that prints the latitude and the longitude:
+//!
+//! ```text
+//! [exec]
+//! command=curl -s https://example.com/where | jq -r '"\(.lat) \(.lon)"'
+//! interval=15m
+//! ```
+//!
+//! The two numbers are separated by spaces or a comma, and only the
+//! first line counts. A command that fails, or prints anything else,
+//! leaves the location to the next provider when none was requested.
+//! The provider is only offered once a command is set.
+
+use std::process::Command;
+use std::sync::Mutex;
+
+use super::Location;
+use super::super::{Result, RedshiftError};
+
+lazy_static! {
+    static ref COMMAND: Mutex<Option<String>> = Mutex::new(None);
+}
+
+fn error<E: Into<Box<::std::error::Error>>>(e: E) -> RedshiftError {
+    RedshiftError::Location("exec".to_owned(), e.into())
+}
+
+/// The command to run, if one is set
+pub fn command() -> Option<String> {
+    COMMAND.lock().unwrap().clone()
+}
+
+pub fn set_command(command: Option<String>) {
+    *COMMAND.lock().unwrap() = command;
+}
+
+/// Run the command and read the location it prints
+pub fn location() -> Result<Location> {
+    let command = command().ok_or_else(|| error("no command is set in the [exec] section"))?;
+    let output = Command::new("sh").arg("-c").arg(&command).output().map_err(error)?;
+    if !output.status.success() {
+        return Err(error(format!("'{}' failed ({})", command, output.status)));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse(&stdout).ok_or_else(|| error(format!("'{}' printed '{}' instead of LAT LON", command, stdout.trim())))
+}
+
+/// The location in the first line of the output
+fn parse(output: &str) -> Option<Location> {
+    let line = output.lines().next()?;
+    let numbers: Vec<f64> = line.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [lat, lon] => Some(Location::new(lat, lon)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn output() {
+        assert_eq!(parse("55.7 12.6\n"), Some(Location::new(55.7, 12.6)));
+        assert_eq!(parse("-33.9, 151.2\nmore\n"), Some(Location::new(-33.9, 151.2)));
+        assert_eq!(parse("55.7\n"), None);
+        assert_eq!(parse("here\n"), None);
+        assert_eq!(parse(""), None);
+    }
+}
@@ -1,58 +1,203 @@
-/// Geoclue2 support
+//! Geoclue2 support
+//!
+//! GeoClue hands out the location only to applications that the user
+//! allowed to have it. The request names the application by its
+//! desktop id, `redshift-rs`, which GeoClue asks an agent about: on
+//! GNOME and KDE the shell, which asks the user or follows the privacy
+//! settings. Applications can also be allowed in
+//! `/etc/geoclue/geoclue.conf`, which works without an agent:
+//!
+//! ```text
+//! [redshift-rs]
+//! allowed=true
+//! system=false
+//! users=
+//! ```
+//!
+//! At login, the daemon may start before the agent of the session is
+//! up. GeoClue refuses for the lack of an agent then, and the request
+//! is repeated whenever a client connects to the system bus, as the
+//! agent does when it comes up. `location` gives up on the agent after
+//! a little while, `location_eventually`, which the daemon asks in the
+//! background, keeps waiting. A refusal by the agent or the
+//! configuration is final.
+
 use super::Location;
-use dbus::{Connection, BusType, ConnectionItem};
+use super::super::{Result, RedshiftError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use dbus::{Message, Path};
+use dbus::blocking::{Connection, Proxy};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
+use dbus::message::MatchRule;
 
 const GEOCLUE: &'static str = "org.freedesktop.GeoClue2";
+const MANAGER_PATH: &'static str = "/org/freedesktop/GeoClue2/Manager";
+const MANAGER: &'static str = "org.freedesktop.GeoClue2.Manager";
+const CLIENT: &'static str = "org.freedesktop.GeoClue2.Client";
+const LOCATION: &'static str = "org.freedesktop.GeoClue2.Location";
 
-pub fn location() -> Location {
+/// The id GeoClue and its agents know the application by
+const DESKTOP_ID: &'static str = "redshift-rs";
 
-    let c = Connection::get_private(BusType::System).unwrap();
-    c.add_match(GEOCLUE);
+/// Accuracy level of a city, all the schedule needs
+const ACCURACY_CITY: u32 = 4;
 
-    for (i, c) in c.iter(1000).enumerate() {
-        match c {
-            ConnectionItem::MethodCall(ref msg) => {
-                println!("method: {:?}", msg);
-            }
-            ConnectionItem::Signal(ref msg) => {
-                println!("signal: {:?}", msg);
-            }
-            ConnectionItem::MethodReturn(ref msg) => {
-                println!("return: {:?}", msg);
-            }
-            _ => {}
+const TIMEOUT_MS: u64 = 1000;
+
+/// How long to wait for a location once access is granted
+const FIX_WAIT_S: u64 = 30;
+
+/// How long `location` waits for GeoClue to have an agent to ask the
+/// user
+const AGENT_WAIT_S: u64 = 15;
+
+/// Longest wait for a client to connect while there is no agent, in
+/// case the agent connected before it registered with GeoClue
+const AGENT_RETRY_S: u64 = 60;
+
+fn error(e: ::dbus::Error) -> RedshiftError {
+    RedshiftError::Location("geoclue2".to_owned(), Box::new(e))
+}
+
+fn refused(msg: String) -> RedshiftError {
+    RedshiftError::Location("geoclue2".to_owned(), msg.into())
+}
+
+/// Why GeoClue refused to start a client
+enum Refusal {
+    /// There is no agent to ask the user, yet
+    NoAgent,
+    /// The agent or the configuration said no
+    Denied(String),
+}
+
+fn refusal(e: &::dbus::Error) -> Option<Refusal> {
+    if e.name() != Some("org.freedesktop.DBus.Error.AccessDenied") {
+        return None;
+    }
+    let msg = e.message().unwrap_or("").to_owned();
+    Some(if msg.contains("no agent") { Refusal::NoAgent } else { Refusal::Denied(msg) })
+}
+
+/// The location, giving up when GeoClue has no agent for a while
+pub fn location() -> Result<Location> {
+    locate(Some(Instant::now() + Duration::from_secs(AGENT_WAIT_S)))
+}
+
+/// The location, waiting for as long as GeoClue has no agent
+pub fn location_eventually() -> Result<Location> {
+    locate(None)
+}
+
+fn locate(give_up: Option<Instant>) -> Result<Location> {
+    let c = Connection::new_system().map_err(error)?;
+    let timeout = Duration::from_millis(TIMEOUT_MS);
+    let manager = c.with_proxy(GEOCLUE, MANAGER_PATH, timeout);
+    let (client,): (Path<'static>,) = manager.method_call(MANAGER, "GetClient", ()).map_err(error)?;
+    debug!("GeoClue2 client: {:?}", client);
+
+    let proxy = c.with_proxy(GEOCLUE, client.clone(), timeout);
+    // Without a desktop id the agent can't tell who is asking, and
+    // GeoClue refuses
+    proxy.set(CLIENT, "DesktopId", DESKTOP_ID.to_owned()).map_err(error)?;
+    proxy.set(CLIENT, "RequestedAccuracyLevel", ACCURACY_CITY).map_err(error)?;
+
+    // Set up before starting, so that no signal is missed
+    let updated = Arc::new(AtomicBool::new(false));
+    let flag = updated.clone();
+    c.add_match(MatchRule::new_signal(CLIENT, "LocationUpdated").with_path(client),
+                move |_: (Path, Path), _: &Connection, _: &Message| {
+                    flag.store(true, Ordering::SeqCst);
+                    true
+                }).map_err(error)?;
+
+    start(&c, &proxy, give_up)?;
+    let result = wait_for_fix(&c, &proxy, &updated);
+    let _ = proxy.method_call::<(), _, _, _>(CLIENT, "Stop", ());
+    result
+}
+
+/// Start the client, trying again whenever a client connects to the
+/// system bus while GeoClue has no agent
+fn start(c: &Connection, proxy: &Proxy<&Connection>, give_up: Option<Instant>) -> Result<()> {
+    let connected = Arc::new(AtomicBool::new(false));
+    let mut watching = false;
+    loop {
+        let e = match proxy.method_call::<(), _, _, _>(CLIENT, "Start", ()) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let wait = match (refusal(&e), give_up) {
+            (Some(Refusal::NoAgent), Some(give_up)) if Instant::now() < give_up => give_up - Instant::now(),
+            (Some(Refusal::NoAgent), None) => Duration::from_secs(AGENT_RETRY_S),
+            (Some(Refusal::NoAgent), Some(_)) => return Err(refused(format!(
+                "no agent to ask for permission ({}); allow {} in /etc/geoclue/geoclue.conf \
+                 or run a GeoClue agent", e.message().unwrap_or(""), DESKTOP_ID))),
+            (Some(Refusal::Denied(msg)), _) => return Err(refused(format!(
+                "access to the location was denied ({}); allow {} in the location or \
+                 privacy settings of the desktop, or in /etc/geoclue/geoclue.conf",
+                msg, DESKTOP_ID))),
+            (None, _) => return Err(error(e)),
+        };
+        if !watching {
+            info!("GeoClue has no agent to ask for the location yet, waiting for one");
+            let flag = connected.clone();
+            c.add_match(MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged"),
+                        move |_: (String, String, String), _: &Connection, _: &Message| {
+                            flag.store(true, Ordering::SeqCst);
+                            true
+                        }).map_err(error)?;
+            watching = true;
+        }
+        let wait = wait.min(Duration::from_secs(AGENT_RETRY_S));
+        let until = Instant::now() + wait;
+        while !connected.swap(false, Ordering::SeqCst) && Instant::now() < until {
+            c.process(until - Instant::now()).map_err(error)?;
         }
     }
+}
 
-    Location {
-        lat: 55.7,
-        lon: 12.6,
+/// Wait for the started client to find the location
+fn wait_for_fix(c: &Connection, proxy: &Proxy<&Connection>, updated: &AtomicBool) -> Result<Location> {
+    let give_up = Instant::now() + Duration::from_secs(FIX_WAIT_S);
+    loop {
+        // Found already, or just now
+        let path: Path = proxy.get(CLIENT, "Location").map_err(error)?;
+        if &*path != "/" {
+            let location = c.with_proxy(GEOCLUE, path, Duration::from_millis(TIMEOUT_MS));
+            let lat: f64 = location.get(LOCATION, "Latitude").map_err(error)?;
+            let lon: f64 = location.get(LOCATION, "Longitude").map_err(error)?;
+            return Ok(Location::new(lat, lon));
+        }
+        while !updated.swap(false, Ordering::SeqCst) {
+            let now = Instant::now();
+            if now >= give_up {
+                return Err(refused(format!("no location found within {}s", FIX_WAIT_S)));
+            }
+            c.process(give_up - now).map_err(error)?;
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use dbus::{Connection, BusType, Message};
-    use dbus::arg::Array;
+    use std::time::Duration;
+    use dbus::Path;
+    use dbus::blocking::Connection;
     use super::GEOCLUE;
 
     #[test]
     fn use_location() {
         //let _ = super::location();
 
-        let c = Connection::get_private(BusType::System).unwrap();
-        //let m = Message::new_method_call("org.freedesktop.DBus", "/", "org.freedesktop.DBus", "ListNames").unwrap();
-        let m = Message::new_method_call("org.freedesktop.GeoClue2", "/org/freedesktop/GeoClue2/Manager", "org.freedesktop.GeoClue2.Manager", "GetClient").unwrap();
-        let r = c.send_with_reply_and_block(m, 2000).unwrap();
-
-        println!("reply: {:?} from sender {:?}", r, r.sender());
-
-        // ListNames returns one argument, which is an array of strings.
+        let c = Connection::new_system().unwrap();
+        let manager = c.with_proxy(GEOCLUE, "/org/freedesktop/GeoClue2/Manager",
+                                   Duration::from_millis(2000));
+        let (client,): (Path,) = manager.method_call("org.freedesktop.GeoClue2.Manager",
+                                                     "GetClient", ()).unwrap();
 
-        // let arr: Array<&str, _>  = r.get1().unwrap();
-        // println!("List of names:");
-        // for name in arr {
-        //     println!("  {}", name);
-        // }
+        println!("client: {:?}", client);
     }
 }
@@ -3,16 +3,24 @@
 /// Module for different location providers. Can be manual or provided
 /// by some service.
 
+mod exec;
 #[cfg(feature = "geoclue2")]
 mod geoclue2;
+#[cfg(feature = "portal")]
+mod portal;
+mod timezone;
 
+use std::fmt;
+#[cfg(any(feature = "geoclue2", feature = "portal"))]
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Mutex;
 use super::{Result, RedshiftError};
-use std::error::Error;
 
 /**
  * Latitude and longitude location
  */
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Location {
     pub lat: f64,
     pub lon: f64
@@ -26,20 +34,47 @@ impl Location {
         }
     }
 
-    pub fn print(&self) {
-        // TODO(tj): Print N/E/S/W for lat/lon
-        println!("Location {:2}, {:2}", self.lat, self.lon);
+    /// What is wrong with the coordinates, if they are not on the globe
+    pub fn problem(&self) -> Option<String> {
+        if !self.lat.is_finite() || self.lat.abs() > 90.0 {
+            Some(format!("latitude {} is outside -90 to 90", self.lat))
+        } else if !self.lon.is_finite() || self.lon.abs() > 180.0 {
+            Some(format!("longitude {} is outside -180 to 180", self.lon))
+        } else {
+            None
+        }
+    }
+
+    /// Distance to another location along the surface of the earth,
+    /// in kilometers
+    pub fn distance(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// Mean radius of the earth
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} {}, {:.2} {}",
+               self.lat.abs(), if self.lat >= 0.0 { "N" } else { "S" },
+               self.lon.abs(), if self.lon >= 0.0 { "E" } else { "W" })
     }
 }
 
 
 impl FromStr for Location {
-    type Err = Box<Error>;
+    type Err = RedshiftError;
 
     fn from_str(s: &str) -> Result<Location> {
         #[inline]
         fn m<T>(msg: String) -> Result<T> {
-            Err(Box::new(RedshiftError::MalformedArgument(msg)))
+            Err(RedshiftError::MalformedArgument(msg))
         }
 
         let mut parts = s.split(':');
@@ -52,12 +87,202 @@ impl FromStr for Location {
             .map_or(m(format!("location: {}", s)),
                     |l| l.parse::<f64>().or(m(format!("location: {} (of {})", l, s))))?;
 
-        parts.next()
-            .map_or(Ok(Location::new(lat, lon)),
-                    |trailing| m(format!("location: trailing {} (of {})", trailing, s)))
+        if let Some(trailing) = parts.next() {
+            return m(format!("location: trailing {} (of {})", trailing, s));
+        }
+        let location = Location::new(lat, lon);
+        match location.problem() {
+            Some(problem) => m(format!("location: {} (of {})", problem, s)),
+            None => Ok(location),
+        }
     }
 }
 
+/// Distance from the location of the time zone beyond which a location
+/// is suspicious, in km, more than the extent of the larger zones
+const SUSPICIOUS_KM: f64 = 2000.0;
+
+/// A hint about a likely sign mistake or mix-up in a location, judging
+/// by the location of the time zone
+///
+/// Getting the sign of the latitude wrong puts the location in the
+/// other hemisphere, with days long where they are short, and getting
+/// that of the longitude wrong moves the schedule by hours. Neither
+/// fails, so they are only noticed by the schedule being off.
+pub fn sign_hint(location: &Location, zone: &Location) -> Option<String> {
+    let distance = location.distance(zone);
+    if distance < SUSPICIOUS_KM {
+        return None;
+    }
+    let (lat, lon) = (location.lat, location.lon);
+    let lat_hint = if lat > 0.0 { "southern latitudes are negative" } else { "northern latitudes are positive" };
+    let lon_hint = if lon > 0.0 { "western longitudes are negative" } else { "eastern longitudes are positive" };
+    let mut candidates = vec![
+        (Location::new(-lat, lon), lat_hint.to_owned()),
+        (Location::new(lat, -lon), lon_hint.to_owned()),
+        (Location::new(-lat, -lon), format!("{}, and {}", lat_hint, lon_hint)),
+    ];
+    if lon.abs() <= 90.0 {
+        candidates.push((Location::new(lon, lat), "the latitude comes first".to_owned()));
+    }
+    candidates.into_iter()
+        .map(|(l, hint)| (l.distance(zone), l, hint))
+        .filter(|&(d, _, _)| d < SUSPICIOUS_KM && d < distance / 4.0)
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+        .map(|(_, l, hint)| format!("{} is far from the time zone, did you mean {}:{}? ({})",
+                                    location, l.lat, l.lon, hint))
+}
+
+/// The time zone the system is set to, e.g. "Europe/Copenhagen", if
+/// it is known
+pub fn time_zone() -> Option<String> {
+    timezone::zone()
+}
+
+/// Set the command of the `exec` provider, see `exec`
+pub fn set_exec_command(command: Option<String>) {
+    exec::set_command(command)
+}
+
+/// Warn about a location that looks mistaken for the time zone
+fn check_hemisphere(location: &Location) {
+    let zone = match timezone::zone().and_then(|z| timezone::lookup(&z)) {
+        Some(zone) => zone,
+        None => return,
+    };
+    if let Some(hint) = sign_hint(location, &zone) {
+        warn!("Location {}", hint);
+    }
+}
+
+/// Location provider that uses the default/configured coordinates
+const MANUAL: &'static str = "manual";
+
+/// A location provider in the registry
+#[derive(Clone, Copy)]
+pub struct Registration {
+    /// The name selecting the provider with `-l`
+    pub name: &'static str,
+
+    /// Providers are tried from the highest priority down when no
+    /// location is given. Those with a negative priority are only
+    /// used when requested.
+    pub priority: i32,
+
+    /// Cheap check whether the provider could work at all, done
+    /// before asking it when no location is given
+    pub probe: fn() -> bool,
+
+    pub locate: fn() -> Result<Location>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Vec<Registration>> = {
+        let mut providers = vec![Registration {
+            name: MANUAL,
+            priority: 0,
+            probe: || true,
+            locate: || Ok(default()),
+        }, Registration {
+            name: "timezone",
+            priority: 10,
+            probe: || timezone::zone().is_some(),
+            locate: timezone::location,
+        }, Registration {
+            name: "exec",
+            priority: 200,
+            probe: || exec::command().is_some(),
+            locate: exec::location,
+        }];
+        add_geoclue2_provider(&mut providers);
+        add_portal_provider(&mut providers);
+        Mutex::new(providers)
+    };
+
+    /// Locations found by providers asked in the background
+    static ref FOUND: Mutex<Vec<(&'static str, Location)>> = Mutex::new(vec![]);
+
+    /// The provider `determine_later` left to the background
+    static ref PENDING: Mutex<Option<&'static str>> = Mutex::new(None);
+}
+
+#[cfg(feature = "geoclue2")]
+fn add_geoclue2_provider(providers: &mut Vec<Registration>) {
+    providers.push(Registration {
+        name: "geoclue2",
+        priority: 100,
+        probe: || Path::new("/run/dbus/system_bus_socket").exists(),
+        locate: geoclue2::location,
+    });
+}
+
+#[cfg(not(feature = "geoclue2"))]
+fn add_geoclue2_provider(_: &mut Vec<Registration>) {}
+
+/// Above GeoClue in a Flatpak sandbox, where only the portal can
+/// hand out the location. Elsewhere only when requested.
+#[cfg(feature = "portal")]
+fn add_portal_provider(providers: &mut Vec<Registration>) {
+    providers.push(Registration {
+        name: "portal",
+        priority: 150,
+        probe: || Path::new("/.flatpak-info").exists(),
+        locate: portal::location,
+    });
+}
+
+#[cfg(not(feature = "portal"))]
+fn add_portal_provider(_: &mut Vec<Registration>) {}
+
+/// For the providers that can keep the caller waiting, for an agent
+/// or the user to allow access, the function that waits for as long
+/// as it takes
+fn patient(name: &str) -> Option<fn() -> Result<Location>> {
+    match name {
+        #[cfg(feature = "geoclue2")]
+        "geoclue2" => Some(geoclue2::location_eventually),
+        #[cfg(feature = "portal")]
+        "portal" => Some(portal::location),
+        _ => None,
+    }
+}
+
+/// Built-in location providers, with the cargo feature that compiles
+/// each of them in
+const FEATURES: &'static [(&'static str, &'static str)] = &[
+    ("geoclue2", "geoclue2"),
+    ("portal", "portal"),
+];
+
+/// Built-in location providers left out at compile time, sorted by
+/// name
+pub fn disabled_providers() -> Vec<&'static str> {
+    let mut names: Vec<_> = FEATURES.iter()
+        .map(|&(name, _)| name)
+        .filter(|name| !is_provider(name))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Add a location provider, or replace the one with the same name
+pub fn register(provider: Registration) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.retain(|p| p.name != provider.name);
+    registry.push(provider);
+}
+
+/// Names of the registered location providers
+pub fn providers() -> Vec<&'static str> {
+    let mut names: Vec<_> = REGISTRY.lock().unwrap().iter().map(|p| p.name).collect();
+    names.sort();
+    names
+}
+
+pub fn is_provider(name: &str) -> bool {
+    providers().contains(&name)
+}
+
 /// Determine the current location from the given argument.
 ///
 /// The location can either be specified as <LAT:LON> or by naming a
@@ -65,14 +290,158 @@ impl FromStr for Location {
 /// matches the input, then that provider is tried. Otherwise, the
 /// argument is attempted parsed as "LAT:LON".
 ///
-/// If the location argument is omitted, a default is chosen.
+/// If the location argument is omitted, the providers are tried by
+/// priority until one succeeds. Short of a provider that finds the
+/// actual location, it is estimated from the time zone, the last
+/// resort being the default location.
 pub fn determine(location_arg: Option<&str>) -> Result<Location> {
+    let registry = REGISTRY.lock().unwrap().clone();
     match location_arg {
         Some(loc) => {
             // Look for provider and use if matched, otherwise parse
             // as LAT:LON.
-            loc.parse::<Location>()
+            match registry.iter().find(|p| p.name == loc) {
+                Some(provider) => locate(provider),
+                None => match FEATURES.iter().find(|entry| entry.0 == loc) {
+                    Some(&(_, feature)) => Err(RedshiftError::FeatureDisabled(
+                        format!("location provider {}", loc), feature)),
+                    None => {
+                        let location = loc.parse::<Location>()?;
+                        check_hemisphere(&location);
+                        Ok(location)
+                    }
+                },
+            }
+        }
+        None => Ok(by_priority(registry, false)),
+    }
+}
+
+/// Determine the location like `determine`, without waiting for the
+/// providers that can keep the caller waiting, such as GeoClue for the
+/// user to allow access
+///
+/// Until such a provider has found the location, the location is
+/// estimated by the providers below it, and `pending` names the
+/// provider to ask with `locate_eventually` in the background. Once
+/// it has found the location, that is the location.
+pub fn determine_later(location_arg: Option<&str>) -> Result<Location> {
+    *PENDING.lock().unwrap() = None;
+    let registry = REGISTRY.lock().unwrap().clone();
+    match location_arg {
+        Some(loc) if patient(loc).is_some() => {
+            let name = match registry.iter().find(|p| p.name == loc) {
+                Some(provider) => provider.name,
+                None => return determine(location_arg),
+            };
+            if let Some(location) = found(name) {
+                return Ok(location);
+            }
+            info!("Estimating the location until {} finds it", name);
+            *PENDING.lock().unwrap() = Some(name);
+            let others = registry.into_iter().filter(|p| patient(p.name).is_none()).collect();
+            Ok(by_priority(others, false))
+        }
+        Some(_) => determine(location_arg),
+        None => Ok(by_priority(registry, true)),
+    }
+}
+
+/// The provider `determine_later` left to the background, if any
+pub fn pending() -> Option<&'static str> {
+    *PENDING.lock().unwrap()
+}
+
+/// Ask a provider that can keep the caller waiting for the location,
+/// for as long as it takes, and keep it for `determine_later`
+pub fn locate_eventually(name: &'static str) -> Result<Location> {
+    let wait = patient(name).ok_or_else(|| RedshiftError::Location(
+        name.to_owned(), "not a provider that finds the location in the background".into()))?;
+    let location = checked(name, wait()?)?;
+    let mut found = FOUND.lock().unwrap();
+    found.retain(|f| f.0 != name);
+    found.push((name, location));
+    Ok(location)
+}
+
+fn found(name: &str) -> Option<Location> {
+    FOUND.lock().unwrap().iter().find(|f| f.0 == name).map(|f| f.1)
+}
+
+/// Try the providers by priority until one succeeds, short of which
+/// the location is the default one
+///
+/// With `later`, the providers that can keep the caller waiting are
+/// passed over until they found the location in the background, the
+/// first of them being left `pending`.
+fn by_priority(registry: Vec<Registration>, later: bool) -> Location {
+    let mut candidates: Vec<_> = registry.into_iter()
+        .filter(|p| p.priority >= 0)
+        .collect();
+    candidates.sort_by(|a, b| b.priority.cmp(&a.priority));
+    for provider in candidates.into_iter().filter(|p| (p.probe)()) {
+        let result = match found(provider.name) {
+            Some(location) if later => Ok(location),
+            None if later && patient(provider.name).is_some() => {
+                PENDING.lock().unwrap().get_or_insert(provider.name);
+                continue;
+            }
+            _ => locate(&provider),
+        };
+        match result {
+            Ok(location) => {
+                if provider.name != MANUAL {
+                    info!("Using location provider {}", provider.name);
+                }
+                return location;
+            }
+            Err(e) => debug!("Location provider {} failed: {}", provider.name, e),
         }
-        None => Ok(Location::new(55.7, 12.6))
+    }
+    default()
+}
+
+/// Ask a provider for the location, rejecting coordinates off the
+/// globe
+fn locate(provider: &Registration) -> Result<Location> {
+    checked(provider.name, (provider.locate)()?)
+}
+
+fn checked(name: &str, location: Location) -> Result<Location> {
+    match location.problem() {
+        Some(problem) => Err(RedshiftError::Location(name.to_owned(), problem.into())),
+        None => Ok(location),
+    }
+}
+
+fn default() -> Location {
+    Location::new(55.7, 12.6)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coordinates() {
+        assert_eq!("-33.9:151.2".parse::<Location>().unwrap(), Location::new(-33.9, 151.2));
+        assert!("91:10".parse::<Location>().is_err());
+        assert!("45:-181".parse::<Location>().is_err());
+        assert!("NaN:10".parse::<Location>().is_err());
+    }
+
+    #[test]
+    fn sign_hints() {
+        let sydney = Location::new(-33.87, 151.21);
+        let new_york = Location::new(40.71, -74.01);
+        assert_eq!(sign_hint(&Location::new(-33.9, 151.2), &sydney), None);
+        assert!(sign_hint(&Location::new(33.9, 151.2), &sydney).unwrap()
+                .contains("did you mean -33.9:151.2? (southern latitudes are negative)"));
+        assert!(sign_hint(&Location::new(40.7, 74.0), &new_york).unwrap()
+                .contains("western longitudes are negative"));
+        assert!(sign_hint(&Location::new(-74.0, 40.7), &new_york).unwrap()
+                .contains("the latitude comes first"));
+        // Far from the zone, but not for a mistake
+        assert_eq!(sign_hint(&Location::new(51.5, -0.1), &sydney), None);
     }
 }
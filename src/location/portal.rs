@@ -0,0 +1,134 @@
+//! Location portal
+//!
+//! In a Flatpak sandbox GeoClue can't be talked to directly. The
+//! Location portal of xdg-desktop-portal hands out the location
+//! instead, after asking the user, or following the choice made
+//! before, through the desktop's own dialog. Without the sandbox the
+//! portal works too, with `-l portal`.
+//!
+//! A location session is created and started, and the first location
+//! it reports is taken, after which the session is closed again. The
+//! user turning the request down is final.
+
+use std::collections::HashMap;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use dbus::{Message, Path};
+use dbus::arg::{self, PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+
+use super::Location;
+use super::super::{Result, RedshiftError};
+
+const PORTAL: &'static str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &'static str = "/org/freedesktop/portal/desktop";
+const LOCATION: &'static str = "org.freedesktop.portal.Location";
+const REQUEST: &'static str = "org.freedesktop.portal.Request";
+const SESSION: &'static str = "org.freedesktop.portal.Session";
+
+/// Accuracy of a city, all the schedule needs
+const ACCURACY_CITY: u32 = 2;
+
+const TIMEOUT_MS: u64 = 1000;
+
+/// How long to wait for the user to answer and for a location
+const FIX_WAIT_S: u64 = 60;
+
+fn error(e: ::dbus::Error) -> RedshiftError {
+    RedshiftError::Location("portal".to_owned(), Box::new(e))
+}
+
+fn refused(msg: String) -> RedshiftError {
+    RedshiftError::Location("portal".to_owned(), msg.into())
+}
+
+fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<RefArg>> {
+    Variant(Box::new(value))
+}
+
+/// What the signals of the session brought
+#[derive(Default)]
+struct Answer {
+    /// The response to starting the session, 0 if it was allowed
+    response: Option<u32>,
+    location: Option<Location>,
+}
+
+pub fn location() -> Result<Location> {
+    let conn = Connection::new_session().map_err(error)?;
+    // The portal puts the objects of a caller under its unique name,
+    // e.g. ":1.42" becomes "1_42"
+    let sender = conn.unique_name().trim_start_matches(':').replace('.', "_");
+    let token = format!("redshift_rs_{}", process::id());
+    let request = Path::new(format!("{}/request/{}/{}", PORTAL_PATH, sender, token)).map_err(refused)?;
+
+    let answer = Arc::new(Mutex::new(Answer::default()));
+    {
+        let answer = answer.clone();
+        conn.add_match(MatchRule::new_signal(REQUEST, "Response").with_path(request.clone()),
+                       move |(response, _): (u32, PropMap), _: &Connection, _: &Message| {
+                           answer.lock().unwrap().response = Some(response);
+                           true
+                       }).map_err(error)?;
+    }
+    {
+        let answer = answer.clone();
+        conn.add_match(MatchRule::new_signal(LOCATION, "LocationUpdated"),
+                       move |(_, location): (Path<'static>, PropMap), _: &Connection, _: &Message| {
+                           let lat = arg::prop_cast::<f64>(&location, "Latitude").cloned();
+                           let lon = arg::prop_cast::<f64>(&location, "Longitude").cloned();
+                           if let (Some(lat), Some(lon)) = (lat, lon) {
+                               answer.lock().unwrap().location = Some(Location::new(lat, lon));
+                           }
+                           true
+                       }).map_err(error)?;
+    }
+
+    let portal = conn.with_proxy(PORTAL, PORTAL_PATH, Duration::from_millis(TIMEOUT_MS));
+    let mut options: PropMap = HashMap::new();
+    options.insert("session_handle_token".to_owned(), variant(token.clone()));
+    options.insert("accuracy".to_owned(), variant(ACCURACY_CITY));
+    let (session,): (Path<'static>,) = portal.method_call(LOCATION, "CreateSession", (options,))
+        .map_err(error)?;
+    debug!("Location portal session: {:?}", session);
+
+    let result = start(&conn, &session, &token, &answer);
+    let _ = conn.with_proxy(PORTAL, session, Duration::from_millis(TIMEOUT_MS))
+        .method_call::<(), _, _, _>(SESSION, "Close", ());
+    result
+}
+
+/// Start the session and wait for its first location
+fn start(conn: &Connection, session: &Path<'static>, token: &str,
+         answer: &Arc<Mutex<Answer>>) -> Result<Location> {
+    let portal = conn.with_proxy(PORTAL, PORTAL_PATH, Duration::from_millis(TIMEOUT_MS));
+    let mut options: PropMap = HashMap::new();
+    options.insert("handle_token".to_owned(), variant(token.to_owned()));
+    // No parent window to put the dialog over
+    let _: (Path<'static>,) = portal.method_call(LOCATION, "Start", (session.clone(), "", options))
+        .map_err(error)?;
+
+    let give_up = Instant::now() + Duration::from_secs(FIX_WAIT_S);
+    loop {
+        {
+            let answer = answer.lock().unwrap();
+            match answer.response {
+                Some(1) => return Err(refused("access to the location was denied".to_owned())),
+                Some(r) if r != 0 => return Err(refused(format!("the portal failed (response {})", r))),
+                _ => {}
+            }
+            if let Some(location) = answer.location {
+                return Ok(location);
+            }
+        }
+        let now = Instant::now();
+        if now >= give_up {
+            return Err(refused(format!("no location found within {}s", FIX_WAIT_S)));
+        }
+        // Until a signal comes in
+        conn.process(give_up - now).map_err(error)?;
+    }
+}
@@ -0,0 +1,114 @@
+//! Session lock
+//!
+//! With `pause-locked=1` in the config file, or `--pause-locked`, the
+//! adjustments are paused while the session is locked, as lock screens
+//! can look odd when heavily tinted. They fade back in on unlocking.
+//!
+//! The lock state is that of the session in logind, on the system
+//! bus. Lock screens report it through the `LockedHint` property of
+//! the session, which is read again whenever logind announces a change
+//! of it, and `loginctl lock-session` and `unlock-session` announce it
+//! with the `Lock` and `Unlock` signals. Builds without the `dbus`
+//! feature don't follow the lock at all.
+//!
+//! Wayland's ext-session-lock protocol is only offered to the lock
+//! screen itself, other clients can't tell whether it is in use.
+
+use chan;
+
+/// Follow the lock state of the session
+///
+/// The channel receives `true` when the session is locked and `false`
+/// when it is unlocked, possibly more than once in a row. `None` if
+/// the lock state can't be followed.
+#[cfg(feature = "dbus")]
+pub fn watch() -> Option<chan::Receiver<bool>> {
+    logind::watch()
+}
+
+#[cfg(not(feature = "dbus"))]
+pub fn watch() -> Option<chan::Receiver<bool>> {
+    warn!("The session lock can't be followed, support for D-Bus is not compiled in");
+    None
+}
+
+#[cfg(feature = "dbus")]
+mod logind {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use std::thread;
+
+    use chan;
+    use dbus::{Message, Path};
+    use dbus::blocking::Connection;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::{Properties, PropertiesPropertiesChanged};
+    use dbus::message::{MatchRule, SignalArgs};
+
+    const LOGIN1: &'static str = "org.freedesktop.login1";
+    const MANAGER_PATH: &'static str = "/org/freedesktop/login1";
+    const MANAGER: &'static str = "org.freedesktop.login1.Manager";
+    /// The session of the caller, or the graphical session of the
+    /// user when running as a service outside of it
+    const AUTO_SESSION: &'static str = "/org/freedesktop/login1/session/auto";
+    const SESSION: &'static str = "org.freedesktop.login1.Session";
+    const TIMEOUT_MS: u64 = 1000;
+    /// Longest wait for a signal in one go, in milliseconds
+    const WAIT_MS: u64 = 3_600_000;
+
+    /// The object of the session, which sends its signals under its
+    /// own path rather than that of the `auto` alias
+    fn session(conn: &Connection) -> Result<Path<'static>, ::dbus::Error> {
+        let timeout = Duration::from_millis(TIMEOUT_MS);
+        let id: String = conn.with_proxy(LOGIN1, AUTO_SESSION, timeout).get(SESSION, "Id")?;
+        let (path,): (Path<'static>,) = conn.with_proxy(LOGIN1, MANAGER_PATH, timeout)
+            .method_call(MANAGER, "GetSession", (id,))?;
+        Ok(path)
+    }
+
+    fn follow(conn: &Connection, tx: chan::Sender<bool>) -> Result<(), ::dbus::Error> {
+        let path = session(conn)?;
+        for &(member, locked) in &[("Lock", true), ("Unlock", false)] {
+            let tx = tx.clone();
+            conn.add_match(MatchRule::new_signal(SESSION, member).with_path(path.clone()),
+                           move |_: (), _: &Connection, _: &Message| {
+                               tx.send(locked);
+                               true
+                           })?;
+        }
+        let changed = Arc::new(AtomicBool::new(true));
+        let flag = changed.clone();
+        let rule = PropertiesPropertiesChanged::match_rule(None, Some(&path)).static_clone();
+        conn.add_match(rule, move |p: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            if p.interface_name == SESSION && (p.changed_properties.contains_key("LockedHint") ||
+                                               p.invalidated_properties.iter().any(|i| i == "LockedHint")) {
+                flag.store(true, Ordering::SeqCst);
+            }
+            true
+        })?;
+        let proxy = conn.with_proxy(LOGIN1, path, Duration::from_millis(TIMEOUT_MS));
+        let mut last = None;
+        loop {
+            if changed.swap(false, Ordering::SeqCst) {
+                let locked: bool = proxy.get(SESSION, "LockedHint")?;
+                if Some(locked) != last {
+                    last = Some(locked);
+                    tx.send(locked);
+                }
+            }
+            // Handles the signals, including Lock and Unlock
+            conn.process(Duration::from_millis(WAIT_MS))?;
+        }
+    }
+
+    pub fn watch() -> Option<chan::Receiver<bool>> {
+        let (tx, rx) = chan::async();
+        thread::spawn(move || {
+            let result = Connection::new_system().and_then(|conn| follow(&conn, tx));
+            if let Err(e) = result {
+                warn!("Not following the session lock: {}", e);
+            }
+        });
+        Some(rx)
+    }
+}
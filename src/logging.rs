@@ -0,0 +1,196 @@
+//! Logging
+//!
+//! All diagnostic output goes through the `log` crate macros. The
+//! logger installed here writes the records to one of several
+//! targets:
+//!
+//! - `stderr`: timestamped lines on standard error (the default),
+//!   with the level colored on a terminal
+//! - `syslog`: RFC 3164 messages sent to the local syslog socket
+//! - `journald`: structured entries using the native journal
+//!   protocol, so `journalctl --user -u redshift-rs` shows them with
+//!   the proper priority
+//!
+//! The level filter is set from the verbosity flags (`-q`, `-v`,
+//! `-vv`). Regular output, like the parameters printed in print mode,
+//! does not go through the logger.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use time;
+
+use term;
+use super::{Result, RedshiftError};
+
+const IDENTIFIER: &'static str = "redshift-rs";
+const SYSLOG_SOCKET: &'static str = "/dev/log";
+const JOURNALD_SOCKET: &'static str = "/run/systemd/journal/socket";
+
+/// syslog facility LOG_USER, shifted into place
+const LOG_USER: u8 = 1 << 3;
+
+/// Where log records are written
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Target {
+    Stderr,
+    Syslog,
+    Journald,
+}
+
+impl Target {
+    fn from_usize(n: usize) -> Target {
+        match n {
+            1 => Target::Syslog,
+            2 => Target::Journald,
+            _ => Target::Stderr,
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<Target> {
+        match s {
+            "stderr" => Ok(Target::Stderr),
+            "syslog" => Ok(Target::Syslog),
+            "journald" => Ok(Target::Journald),
+            _ => Err(RedshiftError::MalformedArgument(
+                format!("log target: {} (expected stderr, syslog or journald)", s))),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Stderr => write!(f, "stderr"),
+            Target::Syslog => write!(f, "syslog"),
+            Target::Journald => write!(f, "journald"),
+        }
+    }
+}
+
+/// Determine the level filter from the number of `-v` flags given,
+/// and whether `-q` was given.
+pub fn level_from_flags(verbose: u64, quiet: bool) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+struct Logger {
+    target: AtomicUsize,
+}
+
+static LOGGER: Logger = Logger { target: AtomicUsize::new(0) };
+
+/// Install the logger, writing to stderr at the given level
+pub fn init(level: LevelFilter) {
+    // Only fails if a logger was already installed, which is fine
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+/// Change where log records are written
+pub fn set_target(target: Target) {
+    LOGGER.target.store(target as usize, Ordering::Relaxed);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let target = Target::from_usize(self.target.load(Ordering::Relaxed));
+        let sent = match target {
+            Target::Stderr => Ok(()),
+            Target::Syslog => send_syslog(record),
+            Target::Journald => send_journald(record),
+        };
+        // Fall back to stderr if the socket is unavailable, rather
+        // than losing the message
+        if target == Target::Stderr || sent.is_err() {
+            write_stderr(record);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Whether stderr is a terminal, so that levels are colored
+fn stderr_colors() -> bool {
+    lazy_static! {
+        static ref COLORS: bool = term::colors(term::STDERR);
+    }
+    *COLORS
+}
+
+fn write_stderr(record: &Record) {
+    let now = time::strftime("%Y-%m-%d %H:%M:%S", &time::now())
+        .unwrap_or_else(|_| String::new());
+    let level = format!("{:<5}", record.level());
+    let level = if stderr_colors() { term::paint(term::level_color(level.trim_end()), &level) } else { level };
+    let _ = writeln!(io::stderr(), "{} {} {}", now, level, record.args());
+}
+
+/// syslog severity of a log level
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn send_syslog(record: &Record) -> io::Result<()> {
+    let now = time::strftime("%b %e %H:%M:%S", &time::now())
+        .unwrap_or_else(|_| String::new());
+    let msg = format!("<{}>{} {}[{}]: {}",
+                      LOG_USER | severity(record.level()),
+                      now, IDENTIFIER, process::id(), record.args());
+    UnixDatagram::unbound()?.send_to(msg.as_bytes(), SYSLOG_SOCKET).map(|_| ())
+}
+
+/// Append a field in the native journal protocol
+///
+/// Values containing newlines must be sent as a length-prefixed
+/// binary blob.
+fn journal_field(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    } else {
+        buf.push(b'=');
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+fn send_journald(record: &Record) -> io::Result<()> {
+    let mut buf = Vec::new();
+    journal_field(&mut buf, "MESSAGE", &record.args().to_string());
+    journal_field(&mut buf, "PRIORITY", &severity(record.level()).to_string());
+    journal_field(&mut buf, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    journal_field(&mut buf, "CODE_MODULE", record.module_path().unwrap_or(""));
+    UnixDatagram::unbound()?.send_to(&buf, JOURNALD_SOCKET).map(|_| ())
+}
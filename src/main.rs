@@ -4,129 +4,76 @@
 //! aka redshift-rs
 //! aka rustshift
 //!
+//! The command line program, built on the `redshift_rs` library: the
+//! configuration file, the daemon of continual mode and its control
+//! interfaces.
+//!
+
+extern crate redshift_rs;
 
 extern crate time;
 #[macro_use]
 extern crate chan;
 extern crate chan_signal;
+extern crate libc;
 #[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
 
 extern crate clap;
 
 // Optional features for gamma method providers
 #[cfg(feature = "randr")] extern crate xcb;
 
-// Optional features for location providers
-#[cfg(feature = "geoclue2")] extern crate dbus;
+// Optional features for control interfaces
+#[cfg(feature = "dbus")] extern crate dbus;
 
+use std::env;
+use std::path::Path;
 use std::thread;
-use std::fmt;
-use std::result;
-use std::error::Error;
-
-use clap::{App, AppSettings, Arg};
-
-mod transition;
-mod colorramp;
-mod location;
-mod solar;
-mod gamma;
-
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-
-const ABOUT: &'static str = "
-Set color temperature of display according to time of day.
-
-A Rust clone of the original Redshift written in C by Jon Lund Steffensen.";
-
-const USAGE: &'static str = "\
-    redshift-rs [OPTIONS]
-    redshift-rs (-h | --help)
-    redshift-rs (-V | --version)";
-
-pub type Result<T> = result::Result<T, Box<Error>>;
-
-// Constants
-const NEUTRAL_TEMP:        i32 = 6500;
-const DEFAULT_DAY_TEMP:    i32 = 5500;
-const DEFAULT_NIGHT_TEMP:  i32 = 3500;
-const MIN_TEMP:            i32 = 1000;
-const MAX_TEMP:            i32 = 25000;
-const DEFAULT_BRIGHTNESS:  f64 = 1.0;
-const DEFAULT_GAMMA:       f64 = 1.0;
-const MIN_GAMMA:           f64 = 0.1;
-const MAX_GAMMA:           f64 = 10.0;
-
-
-// Error codes returned
-// TODO(tj): Improve how this is presented
-#[derive(Debug)]
-pub enum RedshiftError {
-    MalformedArgument(String),
-    GammaMethodNotFound(String),
-}
-
-impl fmt::Display for RedshiftError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use RedshiftError::*;
-        match *self {
-            MalformedArgument(ref msg) =>
-                write!(f, "malformed argument: {}", msg),
-            GammaMethodNotFound(ref method_name) =>
-                write!(f, "gamma method '{}' not found", method_name),
-        }
-    }
-}
-
-impl Error for RedshiftError {
-    fn description(&self) -> &str {
-        "redshift error"
-    }
-}
-
-fn app<'app>() -> App<'app, 'app> {
-    let arg = |name| Arg::with_name(name).long(name);
-    App::new("redshift-rs")
-        .author("Thomas Jespersen <laumann.thomas@gmail.com>")
-        .version(VERSION)
-        .about(ABOUT)
-        .usage(USAGE)
-        .setting(AppSettings::UnifiedHelpMessage)
-        .setting(AppSettings::ColorNever)
-        .arg(arg("brightness")
-             .short("b")
-             .value_name("DAY:NIGHT")
-             .help("Screen brightness to apply (between 0.1 and 1.0)"))
-        .arg(arg("method")
-             .short("m")
-             .value_name("METHOD")
-             .help("Method to use to set color temperature"))
-        .arg(arg("location")
-             .short("l")
-             .value_name("LAT:LON")
-             .help("Your current location"))
-        .arg(arg("temperature")
-             .short("t")
-             .value_name("DAY:NIGHT")
-             .help("Set day/night color temperatures"))
-        .arg(arg("gamma")
-             .short("g")
-             .value_name("R:G:B")
-             .help("Additional gamma correction to apply"))
-        .arg(arg("no-transition").short("r").help("Disable temperature transitions"))
-        .arg(arg("print").short("p")
-             .help("Print parameters and exit")
-             .conflicts_with_all(&["oneshot", "reset", "oneshot-manual"]))
-        .arg(arg("oneshot").short("o")
-             .help("One shot mode")
-             .conflicts_with_all(&["print", "reset", "oneshot-manual"]))
-        .arg(arg("oneshot-manual").short("O")
-             .help("One shot mode (set color temperature)")
-             .value_name("TEMP")
-             .conflicts_with_all(&["print", "oneshot", "reset"]))
-        .arg(arg("reset").short("x").help("Reset (remove adjustments to screen)"))
-        .arg(arg("verbose").short("v").help("Verbose output"))
-}
+use std::time::Duration;
+
+mod ambient;
+mod appearance;
+mod battery;
+mod bench;
+mod calibrate;
+mod cli;
+mod colord;
+mod config;
+mod conflicts;
+mod control;
+mod daemon;
+mod diagnose;
+mod logging;
+mod offset;
+mod preset;
+mod service;
+mod signals;
+mod state;
+mod sweep;
+mod systemd;
+mod term;
+mod timezone;
+mod dpms;
+mod fullscreen;
+mod hooks;
+mod idle;
+mod inhibit;
+mod instance;
+mod lock;
+mod monitors;
+mod outputs;
+mod places;
+mod policy;
+mod ramps;
+mod weekdays;
+
+use redshift_rs::{clock, colorramp, events, gamma, i18n, icc, location, paths, solar, status, transition, vision};
+use redshift_rs::{check_temperature, Result, RedshiftError};
+use redshift_rs::{NEUTRAL_TEMP, MIN_TEMP, MAX_TEMP, MIN_GAMMA, MAX_GAMMA};
+use redshift_rs::{DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP, DEFAULT_BRIGHTNESS, DEFAULT_GAMMA};
+use redshift_rs::{DEFAULT_CONTRAST, MIN_CONTRAST, MAX_CONTRAST, IDENTITY_MATRIX, GRAYSCALE_MATRIX};
+use redshift_rs::DEFAULT_NIGHT_VISION_BRIGHTNESS;
 
 /// Selected run mode
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
@@ -146,105 +93,799 @@ enum Mode {
     Print,
 
     /// One shot manual mode - set color temperature
-    Manual(i32)
+    Manual(i32),
+
+    /// List the available gamma adjustment methods and exit
+    ListMethods,
+
+    /// List the available location providers and exit
+    ListProviders,
+
+    /// List the outputs of the gamma method and exit
+    ListOutputs,
+
+    /// Change the stored temperature offset and exit
+    Adjust(offset::Adjustment),
 }
 
 struct Args {
-    pub verbose: bool,
     pub brightness: (f64, f64),
-    pub gamma: (f64, f64, f64),
+    /// Brightness by solar elevation, replacing `brightness`
+    pub brightness_curve: Option<transition::Curve>,
+    /// Gamma correction of the red, green and blue channels, at day
+    /// and at night
+    pub gamma: ((f64, f64, f64), (f64, f64, f64)),
+    /// Whether the colors are inverted at day and at night
+    pub invert: (bool, bool),
+    pub contrast: (f64, f64),
+    /// Color matrix applied at day and at night
+    pub matrix: [[f64; 3]; 3],
+    /// Gray by luminance, before the other adjustments
+    pub grayscale: bool,
+    /// Filter for a color vision deficiency, after grayscale
+    pub color_vision: Option<vision::Filter>,
+    /// Whitepoints replacing the built-in table
+    pub whitepoints: Option<colorramp::Whitepoints>,
     pub location: location::Location,
     pub method: Option<String>,
     pub temperatures: (i32, i32),
+    pub temp_limits: (i32, i32),
+    /// Solar elevations above which it is day and below which it is
+    /// night, in degrees
+    pub elevations: (f64, f64),
     pub transition: bool,
     pub mode: Mode,
+    pub format: status::Format,
+    pub dry_run: bool,
+    pub simulate_day: Option<f64>,
+    pub gamma_options: gamma::Options,
+    pub replace: bool,
+    pub pause_fullscreen: bool,
+    /// Pause while the session is locked
+    pub pause_locked: bool,
+    /// What to do about another night mode that is enabled
+    pub conflicts: conflicts::Policy,
+    /// Brightness of the red channel in night vision mode
+    pub night_vision_brightness: f64,
+    pub status_stream: bool,
+    /// Keep printing in print mode
+    pub follow: bool,
+    /// Broker to publish the state to, from the `[mqtt]` section
+    pub mqtt: Option<control::MqttOptions>,
+    /// Address to serve metrics on, from the `[metrics]` section
+    pub metrics: Option<String>,
+    /// Following the ambient light sensor, from the `[ambient-light]`
+    /// section
+    pub ambient: Option<ambient::Options>,
+    /// Dimming without input, from the `[idle]` section
+    pub idle: Option<idle::Options>,
+    /// Temperatures selected by real-time signals, from the
+    /// `[signals]` section
+    pub signals: Vec<signals::Binding>,
+    /// Profile to switch to on battery, from the `[battery]` section
+    pub battery: Option<battery::Options>,
+    /// Profiles by the outputs in use, from the `[monitors]` section
+    pub monitors: Option<monitors::Options>,
+    /// Profiles by day of the week, from the `[weekdays]` section
+    pub weekdays: Option<weekdays::Options>,
+    /// Profiles by the dark or light appearance of the desktop, from
+    /// the `[appearance]` section
+    pub appearance: Option<appearance::Options>,
+    /// Pause, or switch profiles, while the screensaver is inhibited,
+    /// from the `[inhibit]` section
+    pub inhibit: Option<inhibit::Options>,
+    /// A program deciding the setting, from the `[policy]` section
+    pub policy: Option<policy::Options>,
+    /// Profiles by location, from the `[place:NAME]` sections
+    pub places: Vec<places::Place>,
+    /// Outputs with settings of their own, from the `[output:NAME]`
+    /// sections
+    pub outputs: Vec<outputs::Options>,
+    /// Print the status lines of C Redshift's verbose mode
+    pub classic_output: bool,
+    /// Seconds between updates during transitions
+    pub update_interval: f64,
+    /// Seconds between the steps of a fade
+    pub fade_interval: f64,
+    /// Seconds a fade takes when toggling
+    pub fade_duration: f64,
+    /// Smallest change of the temperature applied during transitions,
+    /// in Kelvin
+    pub update_threshold: i32,
+    /// How temperatures are interpolated in transitions and fades
+    pub interpolation: transition::Interpolation,
+    /// Seconds between checks that the ramps are still ours, if they
+    /// are checked
+    ///
+    /// Where the gamma method reads the ramps back, they are only
+    /// applied again when another program changed them. Where it
+    /// can't, as with most methods other than RandR, they are applied
+    /// again blindly at every check. With several displays each is
+    /// treated on its own.
+    pub fight_back: Option<f64>,
+    /// Time constant the temperature follows its target with, in
+    /// seconds, `None` to follow it right away
+    pub smoothing: Option<f64>,
+    /// Seconds between runs of the command of the `exec` location
+    /// provider, `None` to run it only when the settings are loaded
+    pub location_interval: Option<f64>,
+    /// Look the solar elevation up in a table computed once a day
+    pub low_power: bool,
+}
+
+/// Settings that can be given in the configuration file
+///
+/// The `[redshift]` section is applied first, then the selected
+/// profile (if any), then the profiles switched to automatically, and
+/// finally any command-line arguments.
+struct Settings {
+    brightness: (f64, f64),
+    brightness_curve: Option<transition::Curve>,
+    display: Option<String>,
+    gamma: ((f64, f64, f64), (f64, f64, f64)),
+    invert: (bool, bool),
+    contrast: (f64, f64),
+    matrix: [[f64; 3]; 3],
+    grayscale: bool,
+    color_vision: Option<vision::Filter>,
+    whitepoints: Option<String>,
+    location: Option<String>,
+    log_target: logging::Target,
+    method: Option<String>,
+    pause_fullscreen: bool,
+    pause_locked: bool,
+    brightness_only: bool,
+    conflicts: conflicts::Policy,
+    night_vision_brightness: f64,
+    temperatures: (i32, i32),
+    temp_limits: (i32, i32),
+    elevations: (f64, f64),
+    transition: bool,
+    update_interval: f64,
+    fade_interval: f64,
+    fade_duration: f64,
+    update_threshold: i32,
+    interpolation: transition::Interpolation,
+    fight_back: Option<f64>,
+    smoothing: Option<f64>,
+    wayland_display: Option<String>,
+    output_failures: gamma::FailurePolicy,
+    low_power: bool,
+    colord: bool,
+}
+
+impl Settings {
+    fn new() -> Settings {
+        Settings {
+            brightness: (DEFAULT_BRIGHTNESS, DEFAULT_BRIGHTNESS),
+            brightness_curve: None,
+            display: None,
+            gamma: ((DEFAULT_GAMMA, DEFAULT_GAMMA, DEFAULT_GAMMA),
+                    (DEFAULT_GAMMA, DEFAULT_GAMMA, DEFAULT_GAMMA)),
+            invert: (false, false),
+            contrast: (DEFAULT_CONTRAST, DEFAULT_CONTRAST),
+            matrix: IDENTITY_MATRIX,
+            grayscale: false,
+            color_vision: None,
+            whitepoints: None,
+            location: None,
+            log_target: logging::Target::Stderr,
+            method: None,
+            pause_fullscreen: false,
+            pause_locked: false,
+            brightness_only: false,
+            conflicts: conflicts::Policy::Warn,
+            night_vision_brightness: DEFAULT_NIGHT_VISION_BRIGHTNESS,
+            temperatures: (DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP),
+            temp_limits: (MIN_TEMP, MAX_TEMP),
+            elevations: (DEFAULT_ELEVATION_HIGH, solar::CIVIL_TWILIGHT_ELEV),
+            transition: true,
+            update_interval: DEFAULT_UPDATE_INTERVAL_S,
+            fade_interval: DEFAULT_FADE_INTERVAL_S,
+            fade_duration: DEFAULT_FADE_DURATION_S,
+            update_threshold: 0,
+            interpolation: transition::Interpolation::Kelvin,
+            fight_back: None,
+            smoothing: None,
+            wayland_display: None,
+            output_failures: gamma::FailurePolicy::BestEffort,
+            low_power: false,
+            colord: false,
+        }
+    }
+
+    /// Override settings with those given in a config section
+    fn apply(&mut self, section: &config::Section, presets: &preset::Presets) -> Result<()> {
+        if let Some(t) = section.get("temp-day") {
+            self.temperatures.0 = presets.resolve(t)?;
+        }
+        if let Some(t) = section.get("temp-night") {
+            self.temperatures.1 = presets.resolve(t)?;
+        }
+        if let Some(t) = section.get("temp-min") {
+            self.temp_limits.0 = check_temperature(presets.resolve(t)?)?;
+        }
+        if let Some(t) = section.get("temp-max") {
+            self.temp_limits.1 = check_temperature(presets.resolve(t)?)?;
+        }
+        if let Some(e) = section.parse("elevation-high")? {
+            self.elevations.0 = check_elevation(e)?;
+        }
+        if let Some(e) = section.parse("elevation-low")? {
+            self.elevations.1 = check_elevation(e)?;
+        }
+        // Brightness given either way replaces what was given the other
+        // way before, e.g. in the main section
+        if let Some(b) = section.get("brightness") {
+            self.brightness = parse_brightness(b)?;
+            self.brightness_curve = None;
+        }
+        if let Some(b) = section.parse("brightness-day")? {
+            self.brightness.0 = b;
+            self.brightness_curve = None;
+        }
+        if let Some(b) = section.parse("brightness-night")? {
+            self.brightness.1 = b;
+            self.brightness_curve = None;
+        }
+        if let Some(c) = section.get("brightness-curve") {
+            self.brightness_curve = Some(parse_brightness_curve(c)?);
+        }
+        if let Some(g) = section.get("gamma") {
+            let g = parse_gamma(g)?;
+            self.gamma = (g, g);
+        }
+        if let Some(g) = section.get("gamma-day") {
+            self.gamma.0 = parse_gamma(g)?;
+        }
+        if let Some(g) = section.get("gamma-night") {
+            self.gamma.1 = parse_gamma(g)?;
+        }
+        if let Some(c) = section.get("contrast") {
+            self.contrast = parse_contrast(c)?;
+        }
+        if let Some(c) = section.parse("contrast-day")? {
+            self.contrast.0 = check_contrast(c)?;
+        }
+        if let Some(c) = section.parse("contrast-night")? {
+            self.contrast.1 = check_contrast(c)?;
+        }
+        if let Some(m) = section.get("matrix") {
+            self.matrix = parse_matrix(m)?;
+        }
+        if let Some(g) = section.parse::<i32>("grayscale")? {
+            self.grayscale = g != 0;
+        }
+        if let Some(c) = section.get("color-vision") {
+            self.color_vision = match c {
+                "none" => None,
+                c => Some(c.parse()?),
+            };
+        }
+        if let Some(w) = section.get("whitepoints") {
+            self.whitepoints = Some(w.to_owned());
+        }
+        if let Some(i) = section.get("invert") {
+            self.invert = parse_invert(i)?;
+        }
+        if let Some(t) = section.get("log-target") {
+            self.log_target = t.parse()?;
+        }
+        if let Some(l) = section.get("location") {
+            self.location = Some(l.to_owned());
+        }
+        if let Some(m) = section.get("adjustment-method") {
+            self.method = Some(m.to_owned());
+        }
+        if let Some(t) = section.parse::<i32>("transition")? {
+            self.transition = t != 0;
+        }
+        if let Some(i) = section.get("update-interval") {
+            self.update_interval = parse_interval("update-interval", i, UPDATE_INTERVAL_RANGE_S)?;
+        }
+        if let Some(i) = section.get("fade-interval") {
+            self.fade_interval = parse_interval("fade-interval", i, FADE_INTERVAL_RANGE_S)?;
+        }
+        if let Some(d) = section.get("fade-duration") {
+            self.fade_duration = parse_interval("fade-duration", d, FADE_DURATION_RANGE_S)?.round();
+        }
+        if let Some(t) = section.parse::<i32>("update-threshold")? {
+            if !(0..=MAX_UPDATE_THRESHOLD).contains(&t) {
+                return Err(RedshiftError::MalformedConfig(
+                    format!("update-threshold {} is outside the supported range (0K to {}K)",
+                            t, MAX_UPDATE_THRESHOLD)));
+            }
+            self.update_threshold = t;
+        }
+        if let Some(i) = section.get("interpolation") {
+            self.interpolation = i.parse()?;
+        }
+        if let Some(i) = section.get("fight-back") {
+            self.fight_back = match i.trim() {
+                "0" => None,
+                i => Some(parse_interval("fight-back", i, FIGHT_BACK_RANGE_S)?),
+            };
+        }
+        if let Some(s) = section.get("smoothing") {
+            self.smoothing = match s.trim() {
+                "0" => None,
+                s => Some(parse_interval("smoothing", s, SMOOTHING_RANGE_S)?),
+            };
+        }
+        if let Some(p) = section.parse::<i32>("pause-fullscreen")? {
+            self.pause_fullscreen = p != 0;
+        }
+        if let Some(p) = section.parse::<i32>("pause-locked")? {
+            self.pause_locked = p != 0;
+        }
+        if let Some(b) = section.parse::<i32>("brightness-only")? {
+            self.brightness_only = b != 0;
+        }
+        if let Some(c) = section.get("conflicts") {
+            self.conflicts = c.parse()?;
+        }
+        if let Some(b) = section.parse::<f64>("night-vision-brightness")? {
+            if !(0.1..=1.0).contains(&b) {
+                return Err(RedshiftError::MalformedConfig(
+                    format!("night-vision-brightness {} is outside the supported range (0.1 to 1.0)", b)));
+            }
+            self.night_vision_brightness = b;
+        }
+        if let Some(d) = section.get("display") {
+            self.display = Some(d.to_owned());
+        }
+        if let Some(d) = section.get("wayland-display") {
+            self.wayland_display = Some(d.to_owned());
+        }
+        if let Some(f) = section.get("output-failures") {
+            self.output_failures = f.parse()?;
+        }
+        if let Some(l) = section.parse::<i32>("low-power")? {
+            self.low_power = l != 0;
+        }
+        if let Some(c) = section.parse::<i32>("colord")? {
+            self.colord = c != 0;
+        }
+        Ok(())
+    }
 }
 
 impl Args {
 
-    /// Parse the command-line arguments into a Redshift configuration
-    pub fn parse() -> Result<Args> {
-        let matches = app().get_matches();
+    /// Turn the command-line arguments into a Redshift configuration
+    ///
+    /// A `profile` given here takes precedence over both the command
+    /// line and the config file. The `automatic` profiles, such as the
+    /// one of the `[battery]` section while on battery or the one of the
+    /// `[monitors]` section for the monitors in use, are applied on
+    /// top of it in order.
+    pub fn from_matches(matches: &clap::ArgMatches, profile: Option<&str>,
+                        automatic: &[String]) -> Result<Args> {
+        let config = config::Config::load(matches.value_of("config"))?;
+        let mut presets = preset::Presets::builtin();
+        if let Some(section) = config.section("presets") {
+            presets.extend(section)?;
+        }
+
+        let mut settings = Settings::new();
+        if let Some(section) = config.section("redshift") {
+            settings.apply(section, &presets)?;
+        }
+
+        let environment = config::Section::from_env();
+
+        // A profile on the command line takes precedence over the
+        // one selected in the environment or the config file
+        let profile = profile.or_else(|| matches.value_of("profile"))
+            .or_else(|| environment.get("profile"))
+            .or_else(|| config.section("redshift").and_then(|s| s.get("profile")));
+        if let Some(name) = profile {
+            let section = config.profile(name).or_else(|e| {
+                let profiles = config.profiles();
+                if profiles.is_empty() {
+                    Err(e)
+                } else {
+                    malformed(format!("{} (available profiles: {})", e, profiles.join(", ")))
+                }
+            })?;
+            settings.apply(section, &presets)?;
+        }
+        for name in automatic {
+            settings.apply(config.profile(name)?, &presets)?;
+        }
+        // Only the command line goes over the environment
+        settings.apply(&environment, &presets)?;
+
+        // The command of the exec location provider, which has to be
+        // set before the location is determined
+        let exec = config.section("exec");
+        location::set_exec_command(exec.and_then(|s| s.get("command")).map(ToOwned::to_owned));
+        let location_interval = match exec.and_then(|s| s.get("interval")).map(str::trim) {
+            None | Some("0") => None,
+            Some(i) => Some(parse_interval("[exec] interval", i, EXEC_INTERVAL_RANGE_S)?),
+        };
+
+        if let Some(target) = matches.value_of("log-target") {
+            settings.log_target = target.parse()?;
+        }
+        logging::set_target(settings.log_target);
 
         let brightness = matches.value_of("brightness")
-            .map_or(Ok((DEFAULT_BRIGHTNESS, DEFAULT_BRIGHTNESS)),
+            .map_or(Ok(settings.brightness),
                     |input| parse_brightness(input))?;
 
+        // Pinning the limits keeps the temperature neutral whatever
+        // else would change it, such as offsets and held temperatures
+        if settings.brightness_only || matches.is_present("brightness-only") {
+            settings.temperatures = (NEUTRAL_TEMP, NEUTRAL_TEMP);
+            settings.temp_limits = (NEUTRAL_TEMP, NEUTRAL_TEMP);
+        }
+        let (min, max) = settings.temp_limits;
+        if min > max {
+            return Err(RedshiftError::MalformedConfig(
+                format!("temp-min ({}K) is greater than temp-max ({}K)", min, max)));
+        }
+        let (high, low) = settings.elevations;
+        if high <= low {
+            return Err(RedshiftError::MalformedConfig(
+                format!("elevation-high ({}) is not above elevation-low ({})", high, low)));
+        }
+
         let temperatures = matches.value_of("temperature")
-            .map_or(Ok((DEFAULT_DAY_TEMP, DEFAULT_NIGHT_TEMP)),
-                    |input| parse_temperature(input))?;
+            .map_or(Ok(settings.temperatures),
+                    |input| parse_temperature(input, &presets))?;
+        let temperatures = (clamp_temperature(temperatures.0, settings.temp_limits)?,
+                            clamp_temperature(temperatures.1, settings.temp_limits)?);
 
         let gamma = matches.value_of("gamma")
-            .map_or(Ok((DEFAULT_GAMMA, DEFAULT_GAMMA, DEFAULT_GAMMA)),
-                    |input| parse_gamma(input))?;
+            .map_or(Ok(settings.gamma),
+                    |input| parse_gamma(input).map(|g| (g, g)))?;
+        let contrast = matches.value_of("contrast")
+            .map_or(Ok(settings.contrast), parse_contrast)?;
+        let matrix = matches.value_of("matrix")
+            .map_or(Ok(settings.matrix), parse_matrix)?;
+        if (0..3).any(|i| (0..3).any(|j| i != j && matrix[i][j] != 0.0)) {
+            warn!("Only the diagonal of the color matrix can be applied with gamma ramps, \
+                   ignoring the other entries");
+        }
+        let invert = matches.value_of("invert")
+            .map_or(Ok(settings.invert), parse_invert)?;
+
+        let mut outputs = config.outputs().into_iter()
+            .map(|(name, s)| outputs::Options::from_config(name, s, &presets, temperatures,
+                                                          settings.temp_limits))
+            .collect::<Result<_>>()?;
+        if settings.colord {
+            if !cfg!(feature = "dbus") {
+                return Err(RedshiftError::FeatureDisabled("colord profiles".to_owned(), "dbus"));
+            }
+            match colord::profiles() {
+                Ok(profiles) => colord::calibrate(&mut outputs, colord::calibrations(profiles), temperatures),
+                Err(e) => warn!("Could not look up the profiles of colord: {}", e),
+            }
+            for loader in colord::loaders() {
+                warn!("{} loads the calibration of colord into the ramps too, which then gets lost \
+                       or applied twice; turn it off or set colord=0", loader);
+            }
+        }
 
         // Determine run mode
-        let mode = if matches.is_present("print") {
+        let mode = if matches.value_of("method") == Some("list") {
+            Mode::ListMethods
+        } else if matches.value_of("location") == Some("list") {
+            Mode::ListProviders
+        } else if matches.is_present("list-outputs") {
+            Mode::ListOutputs
+        } else if matches.is_present("print") {
             Mode::Print
         } else if matches.is_present("oneshot") {
             Mode::OneShot
         } else if let Some(temp) = matches.value_of("oneshot-manual") {
-            let t = temp.parse()?;
-            if t < MIN_TEMP || t > MAX_TEMP {
-                return malformed(format!("Temperature must be between {} and {} (was {})", MIN_TEMP, MAX_TEMP, t));
-            }
-            Mode::Manual(t)
+            let t = presets.resolve(temp)?;
+            Mode::Manual(clamp_temperature(t, settings.temp_limits)?)
         } else if matches.is_present("reset") {
             Mode::Reset
+        } else if let Some(delta) = matches.value_of("adjust") {
+            Mode::Adjust(offset::Adjustment::parse(delta)?)
         } else {
             Mode::Continual
         };
 
         Ok(Args {
-            verbose: matches.is_present("verbose"),
             brightness: brightness,
+            // Brightness on the command line goes over the curve
+            brightness_curve: settings.brightness_curve.filter(|_| !matches.is_present("brightness")),
             gamma: gamma,
-            location: location::determine(matches.value_of("location"))?,
+            invert: invert,
+            contrast: contrast,
+            matrix: matrix,
+            grayscale: settings.grayscale || matches.is_present("grayscale"),
+            color_vision: match matches.value_of("color-vision") {
+                Some("none") => None,
+                Some(c) => Some(c.parse()?),
+                None => settings.color_vision,
+            },
+            whitepoints: settings.whitepoints
+                .map_or(Ok(None), |w| colorramp::Whitepoints::load(Path::new(&w)).map(Some))?,
+            location: {
+                let location = matches.value_of("location")
+                    .filter(|l| *l != "list")
+                    .or(settings.location.as_ref().map(|s| &s[..]));
+                // The daemon doesn't wait for GeoClue and the like,
+                // they are asked in the background
+                match mode {
+                    Mode::Continual => location::determine_later(location)?,
+                    _ => location::determine(location)?,
+                }
+            },
             method: matches.value_of("method")
+                .filter(|m| *m != "list")
                 .map(ToOwned::to_owned)
+                .or(settings.method)
                 .map_or(Ok(None), |s| determine_gamma_method(s).map(Some))?,
             temperatures: temperatures,
-            transition: !matches.is_present("no-transition"),
+            temp_limits: settings.temp_limits,
+            elevations: settings.elevations,
+            transition: settings.transition && !matches.is_present("no-transition"),
             mode: mode,
+            format: matches.value_of("format").map_or(Ok(status::Format::Text), str::parse)?,
+            dry_run: matches.is_present("dry-run") || matches.is_present("simulate-day"),
+            simulate_day: matches.value_of("simulate-day")
+                .map_or(Ok(None), |d| parse_duration(d).map(Some))?,
+            gamma_options: gamma::Options {
+                display: matches.value_of("display").map(ToOwned::to_owned)
+                    .or(settings.display),
+                wayland_display: matches.value_of("wayland-display").map(ToOwned::to_owned)
+                    .or(settings.wayland_display),
+                // Sections named after a method, e.g. [randr]
+                method_options: gamma::methods().into_iter()
+                    .filter_map(|m| config.section(m).map(|s| (m.to_owned(), s.entries.clone())))
+                    .collect(),
+                failure_policy: settings.output_failures,
+            },
+            replace: matches.is_present("replace"),
+            pause_fullscreen: settings.pause_fullscreen || matches.is_present("pause-fullscreen"),
+            pause_locked: settings.pause_locked || matches.is_present("pause-locked"),
+            conflicts: settings.conflicts,
+            night_vision_brightness: settings.night_vision_brightness,
+            status_stream: matches.is_present("status-stream"),
+            follow: matches.is_present("follow"),
+            mqtt: config.section("mqtt")
+                .map_or(Ok(None), |s| control::MqttOptions::from_config(s).map(Some))?,
+            metrics: match config.section("metrics") {
+                Some(_) if !cfg!(feature = "metrics") =>
+                    return Err(RedshiftError::FeatureDisabled("metrics endpoint".to_owned(), "metrics")),
+                Some(s) => Some(s.get("listen").unwrap_or(DEFAULT_METRICS_ADDRESS).to_owned()),
+                None => None,
+            },
+            ambient: match config.section("ambient-light") {
+                Some(_) if !cfg!(feature = "dbus") =>
+                    return Err(RedshiftError::FeatureDisabled("ambient light sensor".to_owned(),
+                                                              "dbus")),
+                Some(s) => Some(ambient::Options::from_config(s)?),
+                None => None,
+            },
+            idle: config.section("idle").map_or(Ok(None), |s| idle::Options::from_config(s).map(Some))?,
+            signals: config.section("signals").map_or(Ok(vec![]), |s| signals::from_config(s, &presets))?,
+            battery: match config.section("battery") {
+                Some(_) if !cfg!(feature = "dbus") =>
+                    return Err(RedshiftError::FeatureDisabled("battery profile".to_owned(),
+                                                              "dbus")),
+                Some(s) => {
+                    let options = battery::Options::from_config(s)?;
+                    config.profile(&options.profile)?;
+                    Some(options)
+                }
+                None => None,
+            },
+            monitors: config.section("monitors")
+                .map_or(Ok(None), |s| monitors::Options::from_config(s, &config).map(Some))?,
+            weekdays: config.section("weekdays")
+                .map_or(Ok(None), |s| weekdays::Options::from_config(s, &config).map(Some))?,
+            appearance: match config.section("appearance") {
+                Some(_) if !cfg!(feature = "dbus") =>
+                    return Err(RedshiftError::FeatureDisabled("appearance profiles".to_owned(),
+                                                              "dbus")),
+                Some(s) => Some(appearance::Options::from_config(s, &config)?),
+                None => None,
+            },
+            inhibit: match config.section("inhibit") {
+                Some(_) if !cfg!(feature = "dbus") =>
+                    return Err(RedshiftError::FeatureDisabled("inhibitors".to_owned(), "dbus")),
+                Some(s) => Some(inhibit::Options::from_config(s, &config)?),
+                None => None,
+            },
+            policy: config.section("policy").map_or(Ok(None), |s| policy::Options::from_config(s).map(Some))?,
+            places: places::from_config(&config)?,
+            outputs: outputs,
+            classic_output: matches.is_present("verbose") && !matches.is_present("status-stream"),
+            update_interval: settings.update_interval,
+            fade_interval: settings.fade_interval,
+            fade_duration: settings.fade_duration,
+            update_threshold: settings.update_threshold,
+            interpolation: settings.interpolation,
+            fight_back: settings.fight_back,
+            smoothing: settings.smoothing,
+            location_interval: location_interval,
+            low_power: settings.low_power,
         })
     }
 }
 
+/// Time between updates during transitions without an
+/// `update-interval` setting, and the range allowed, in seconds
+const DEFAULT_UPDATE_INTERVAL_S: f64 = 5.0;
+const UPDATE_INTERVAL_RANGE_S: (f64, f64) = (0.5, 300.0);
+
+/// Time between the steps of a fade without a `fade-interval`
+/// setting, and the range allowed, in seconds
+const DEFAULT_FADE_INTERVAL_S: f64 = 0.1;
+const FADE_INTERVAL_RANGE_S: (f64, f64) = (0.02, 1.0);
+
+/// Time a fade takes when toggling without a `fade-duration` setting,
+/// and the range allowed, in whole seconds
+const DEFAULT_FADE_DURATION_S: f64 = 2.0;
+const FADE_DURATION_RANGE_S: (f64, f64) = (1.0, 60.0);
+
+/// Largest `update-threshold`, which is 0 (off) by default, in Kelvin
+const MAX_UPDATE_THRESHOLD: i32 = 500;
+
+/// Range allowed for the time between checks of the ramps with
+/// `fight-back`, which is off by default, in seconds
+const FIGHT_BACK_RANGE_S: (f64, f64) = (0.5, 3600.0);
+
+/// Range allowed for the time constant of the `smoothing` of the
+/// temperature, which is off by default, in seconds
+const SMOOTHING_RANGE_S: (f64, f64) = (1.0, 3600.0);
+
+/// Range allowed for the time between runs of the command of the
+/// `exec` location provider, which runs once by default, in seconds
+const EXEC_INTERVAL_RANGE_S: (f64, f64) = (60.0, 86400.0);
+
+/// Solar elevation above which it is day without an `elevation-high`
+/// setting, in degrees. Night begins below civil twilight by default.
+const DEFAULT_ELEVATION_HIGH: f64 = 3.0;
+
+/// Where metrics are served without a `listen` address
+const DEFAULT_METRICS_ADDRESS: &'static str = "127.0.0.1:9101";
+
 #[inline]
 fn malformed<T>(msg: String) -> Result<T> {
-    Err(Box::new(RedshiftError::MalformedArgument(msg)))
+    Err(RedshiftError::MalformedArgument(msg))
+}
+
+
+/// Check a temperature and clamp it to the configured limits
+///
+/// The limits are set with `temp-min` and `temp-max` in the config
+/// file, to guard against settings that render the screen unusable.
+fn clamp_temperature(temp: i32, limits: (i32, i32)) -> Result<i32> {
+    let t = check_temperature(temp)?.max(limits.0).min(limits.1);
+    if t != temp {
+        warn!("Temperature {}K clamped to {}K (limits are {}K to {}K)",
+              temp, t, limits.0, limits.1);
+    }
+    Ok(t)
+}
+
+/// Add a temperature offset to a computed color setting, keeping
+/// the result within the configured limits
+fn apply_offset(setting: &mut transition::ColorSetting, offset: i32, limits: (i32, i32)) {
+    setting.temp = (setting.temp + offset).max(limits.0).min(limits.1);
+}
+
+/// Turn a setting gray by luminance, before its filter
+fn apply_grayscale(setting: &mut transition::ColorSetting) {
+    setting.filter = transition::multiply_matrices(&setting.filter, &GRAYSCALE_MATRIX);
+}
+
+/// Warn that `what`, part of the filter of the settings, is left out
+/// by a method that applies ramps
+fn warn_filter(method: &gamma::GammaMethod, what: &str) {
+    if !method.mixes_channels() {
+        warn!("The {} method applies gamma ramps, which can't mix the channels, \
+               leaving out {}", method.name(), what);
+    }
+}
+
+/// Warn about the parts of the filter of `args` that the method leaves
+/// out
+fn warn_filters(method: &gamma::GammaMethod, args: &Args) {
+    if args.grayscale {
+        warn_filter(method, "grayscale");
+    }
+    if args.color_vision.is_some() {
+        warn_filter(method, "the color vision filter");
+    }
 }
 
 fn determine_gamma_method(method: String) -> Result<String> {
-    if gamma::is_method_available(&method[..]) {
+    // Methods left out at compile time fail later, with a hint
+    if gamma::is_method_available(&method[..]) || gamma::disabled_methods().contains(&&method[..]) {
         Ok(method)
     } else {
-        Err(Box::new(RedshiftError::GammaMethodNotFound(method)))
+        Err(RedshiftError::GammaMethodNotFound(method))
     }
 }
 
 
 /// Parse the temperature argument
 ///
-/// Expected as "DAY:NIGHT", where DAY and NIGHT are 32-bit
-/// integers. Any other input produces an error.
-fn parse_temperature(input: &str) -> Result<(i32, i32)> {
+/// Expected as "DAY:NIGHT", where DAY and NIGHT are temperatures in
+/// Kelvin, mireds or names of presets. Any other input produces an error.
+fn parse_temperature(input: &str, presets: &preset::Presets) -> Result<(i32, i32)> {
     let mut parts = input.split(':');
 
     let day = parts.next()
         .map_or(malformed(format!("temperature argument: {}", input)),
-                |l| l.parse().or(
-                    malformed(format!("temperature argument: {} (of {})", l, input))))?;
+                |l| presets.resolve(l))?;
 
     let night = parts.next()
         .map_or(malformed(format!("temperature argument: {}", input)),
-                |l| l.parse().or(
-                    malformed(format!("temperature argument: {} (of {})", l, input))))?;
+                |l| presets.resolve(l))?;
 
     parts.next().map_or(Ok((day, night)),
                         |_| malformed(format!("temperature argument: {}", input)))
 }
 
+/// Check that a contrast is within the supported range
+fn check_contrast(contrast: f64) -> Result<f64> {
+    if (MIN_CONTRAST..=MAX_CONTRAST).contains(&contrast) {
+        Ok(contrast)
+    } else {
+        malformed(format!("contrast {} is outside the supported range ({} to {})",
+                          contrast, MIN_CONTRAST, MAX_CONTRAST))
+    }
+}
+
+/// Check that a solar elevation is one the sun can have
+fn check_elevation(elevation: f64) -> Result<f64> {
+    if (-90.0..=90.0).contains(&elevation) {
+        Ok(elevation)
+    } else {
+        Err(RedshiftError::MalformedConfig(format!("elevation {} is not between -90 and 90 degrees", elevation)))
+    }
+}
+
+/// Parse a color matrix, nine numbers row by row, separated by spaces
+/// or commas
+fn parse_matrix(input: &str) -> Result<[[f64; 3]; 3]> {
+    let values = input.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<f64>().ok().filter(|v| v.is_finite())
+             .map_or(malformed(format!("matrix: {} (of {})", s, input)), Ok))
+        .collect::<Result<Vec<f64>>>()?;
+    if values.len() != 9 {
+        return malformed(format!("matrix: expected 9 values, got {}", values.len()));
+    }
+    let mut matrix = [[0.0; 3]; 3];
+    for (i, v) in values.into_iter().enumerate() {
+        matrix[i / 3][i % 3] = v;
+    }
+    Ok(matrix)
+}
+
+/// Parse a contrast argument, "DAY:NIGHT" or a single value for both
+fn parse_contrast(input: &str) -> Result<(f64, f64)> {
+    let parse = |s: &str| s.parse::<f64>()
+        .or(malformed(format!("contrast: {} (of {})", s, input)))
+        .and_then(check_contrast);
+    let mut parts = input.splitn(2, ':');
+    let day = parse(parts.next().unwrap_or(""))?;
+    let night = parts.next().map_or(Ok(day), parse)?;
+    Ok((day, night))
+}
+
+/// Parse when to invert the colors, "never", "night" or "always"
+fn parse_invert(input: &str) -> Result<(bool, bool)> {
+    match input {
+        "never" => Ok((false, false)),
+        "night" => Ok((false, true)),
+        "always" => Ok((true, true)),
+        _ => malformed(format!("invert: {} (expected never, night or always)", input)),
+    }
+}
+
 /// Parse brightness argument
 ///
 /// Expected format is "DAY:NIGHT" where DAY and NIGHT are floating
@@ -267,14 +908,27 @@ fn parse_brightness(input: &str) -> Result<(f64, f64)> {
 }
 
 
+/// Parse a curve of the brightness by solar elevation, as in
+/// "-18:0.5,-6:0.8,3:1.0"
+fn parse_brightness_curve(input: &str) -> Result<transition::Curve> {
+    let curve = transition::Curve::parse(input)?;
+    match curve.points().iter().find(|p| !(0.1..=1.0).contains(&p.1)) {
+        Some(p) => Err(RedshiftError::MalformedConfig(
+            format!("brightness-curve: {} at {}° is not between 0.1 and 1.0", p.1, p.0))),
+        None => Ok(curve),
+    }
+}
+
 /// A gamma string contains either one floating point value, or three
 /// separated by colons
 fn parse_gamma(input: &str) -> Result<(f64, f64, f64)> {
     macro_rules! validate_gamma {
         ($val:ident) => (
             if $val < MIN_GAMMA || $val > MAX_GAMMA {
-                return malformed(format!("Gamma value must be between {} and {}. Was {}",
-                                         MIN_GAMMA, MAX_GAMMA, $val));
+                let range = i18n::translated("Gamma value must be between %.1f and %.1f.\n",
+                                             &[&format!("{:.1}", MIN_GAMMA), &format!("{:.1}", MAX_GAMMA)])
+                    .unwrap_or_else(|| format!("Gamma value must be between {} and {}.", MIN_GAMMA, MAX_GAMMA));
+                return malformed(format!("{} Was {}", range, $val));
             }
         )
     }
@@ -306,57 +960,217 @@ fn parse_gamma(input: &str) -> Result<(f64, f64, f64)> {
     }
 }
 
+/// Parse a duration such as "90s", "2m" or "1h" into seconds
+///
+/// A plain number is taken to be seconds.
+fn parse_duration(input: &str) -> Result<f64> {
+    let input = input.trim();
+    let (num, unit) = match input.find(|c: char| c.is_alphabetic()) {
+        Some(i) => (&input[..i], &input[i..]),
+        None => (input, "s"),
+    };
+    let scale = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" | "min" => 60.0,
+        "h" => 3600.0,
+        _ => return malformed(format!("duration: {} (unknown unit '{}')", input, unit)),
+    };
+    match num.parse::<f64>() {
+        Ok(n) if n > 0.0 => Ok(n * scale),
+        _ => malformed(format!("duration: {}", input)),
+    }
+}
+
+/// Parse a time of day such as "23:30" into the hour and minute
+fn parse_time_of_day(input: &str) -> Result<(u32, u32)> {
+    let mut parts = input.trim().splitn(2, ':');
+    match (parts.next().map(str::parse::<u32>), parts.next().map(str::parse::<u32>)) {
+        (Some(Ok(h)), Some(Ok(m))) if h < 24 && m < 60 => Ok((h, m)),
+        _ => malformed(format!("time of day: {} (expected HH:MM)", input)),
+    }
+}
+
+/// Parse the duration of an interval setting within its range
+fn parse_interval(key: &str, input: &str, range: (f64, f64)) -> Result<f64> {
+    let secs = parse_duration(input)?;
+    if secs < range.0 || secs > range.1 {
+        return Err(RedshiftError::MalformedConfig(
+            format!("{} {} is outside the supported range ({}s to {}s)", key, input, range.0, range.1)));
+    }
+    Ok(secs)
+}
+
+/// Initialise the gamma method selected by the arguments
+///
+/// In dry-run mode no method is initialised, and a method that
+/// leaves the display untouched is returned instead.
+fn start_gamma_method(args: &Args) -> Result<Box<gamma::GammaMethod>> {
+    let mut gamma_state = if args.dry_run {
+        Box::new(gamma::DryRunMethod)
+    } else {
+        gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()), &args.gamma_options)?
+    };
+    gamma_state.start()?;
+    Ok(gamma_state)
+}
+
+/// Parse the command line and the configuration file again, to
+/// reload the settings of a running daemon
+fn reload_args(profile: Option<&str>, automatic: &[String]) -> Result<Args> {
+    let matches = cli::app().get_matches_from_safe(env::args_os())
+        .map_err(|e| RedshiftError::MalformedArgument(e.message))?;
+    Args::from_matches(&matches, profile, automatic)
+}
+
+/// Set up the transition scheme for the configured temperatures,
+/// brightness and gamma
+fn transition_scheme(args: &Args) -> transition::TransitionScheme {
+    let mut scheme = transition::TransitionScheme::new();
+    scheme.day.temp = args.temperatures.0;
+    scheme.night.temp = args.temperatures.1;
+    scheme.high = args.elevations.0;
+    scheme.low = args.elevations.1;
+    scheme.day.brightness = args.brightness.0;
+    scheme.night.brightness = args.brightness.1;
+    scheme.brightness_curve = args.brightness_curve.clone();
+
+    let (day, night) = args.gamma;
+    scheme.day.gamma = [day.0, day.1, day.2];
+    scheme.night.gamma = [night.0, night.1, night.2];
+    scheme.day.invert = args.invert.0;
+    scheme.night.invert = args.invert.1;
+    scheme.day.contrast = args.contrast.0;
+    scheme.night.contrast = args.contrast.1;
+    scheme.day.matrix = args.matrix;
+    scheme.night.matrix = args.matrix;
+    let filter = args.color_vision.map_or(IDENTITY_MATRIX, vision::Filter::matrix);
+    scheme.day.filter = filter;
+    scheme.night.filter = filter;
+    scheme.fade_interval = args.fade_interval;
+    scheme.interpolation = args.interpolation;
+    scheme
+}
+
 fn main() {
-    ::std::process::exit(match Args::parse().and_then(run) {
+    let matches = cli::app().get_matches();
+    logging::init(logging::level_from_flags(matches.occurrences_of("verbose"),
+                                            matches.is_present("quiet")));
+    let result = match matches.subcommand() {
+        ("completions", Some(sub)) => cli::completions(sub.value_of("shell").unwrap()),
+        ("ctl", Some(sub)) => control::ctl(&matches, sub),
+        ("ramps", Some(sub)) => ramps::print(&matches, sub),
+        ("bench", Some(sub)) => bench::run(&matches, sub),
+        ("preview", Some(sub)) => sweep::run(&matches, sub),
+        ("calibrate", Some(sub)) => calibrate::run(&matches, sub),
+        ("install-service", Some(sub)) => service::install(&matches, sub),
+        _ if matches.is_present("diagnose") => diagnose::run(&matches),
+        _ => Args::from_matches(&matches, None, &[]).and_then(run),
+    };
+    ::std::process::exit(match result {
         Ok(exit_code) => {
             exit_code
         }
         Err(e) => {
-            println!("{}", e);
+            error!("{}", e);
+            if let Some(hint) = hint(&e) {
+                error!("{}", hint);
+            }
             1
         }
     });
 }
 
+/// What to try next after an error, where that is not obvious
+fn hint(e: &RedshiftError) -> Option<&'static str> {
+    match *e {
+        RedshiftError::GammaMethodNotFound(_) =>
+            Some("Use '-m list' to see the available gamma methods"),
+        RedshiftError::Gamma(..) =>
+            Some("Choose another gamma method with -m, or use '-m dummy' to test without one"),
+        RedshiftError::Location(..) =>
+            Some("Give the location as LAT:LON with -l instead"),
+        RedshiftError::ProfileNotFound(_) =>
+            Some("Profiles are the [profile:NAME] sections of the configuration file"),
+        _ => None,
+    }
+}
+
 // (3) Running continual mode (if requested)
 fn run(args: Args) -> Result<i32> {
+    if let Some(ref whitepoints) = args.whitepoints {
+        colorramp::set_whitepoints(whitepoints.clone());
+    }
 
     let (temp_day, temp_night) = args.temperatures;
-    let (bright_day, bright_night) = args.brightness;
-
-    // Init transition scheme
-    let mut scheme = transition::TransitionScheme::new();
-    scheme.day.temp = temp_day;
-    scheme.night.temp = temp_night;
-    scheme.day.brightness = bright_day;
-    scheme.night.brightness = bright_night;
-
-    scheme.day.gamma[0] = args.gamma.0;
-    scheme.day.gamma[1] = args.gamma.1;
-    scheme.day.gamma[2] = args.gamma.2;
-
-    scheme.night.gamma[0] = args.gamma.0;
-    scheme.night.gamma[1] = args.gamma.1;
-    scheme.night.gamma[2] = args.gamma.2;
-
-    if args.verbose {
-        println!("Temperatures: {}K at day, {}K at night", temp_day, temp_night);
-        args.location.print();
+    let scheme = transition_scheme(&args);
+
+    info!("{}", i18n::message("Temperatures: %dK at day, %dK at night\n", &[&temp_day, &temp_night]));
+    info!("{}", status::location_text(&args.location));
+    for output in &args.outputs {
+        match output.temperatures {
+            _ if !output.enabled => info!("Output {}: not adjusted", output.name),
+            Some((day, night)) => info!("Output {}: {}K at day, {}K at night", output.name, day, night),
+            None => {}
+        }
     }
 
     match args.mode {
+        Mode::ListMethods => {
+            println!("{}", i18n::message("Available adjustment methods:\n", &[]));
+            for name in gamma::methods() {
+                println!("  {}", name);
+            }
+            for name in gamma::disabled_methods() {
+                println!("  {} (not compiled in)", name);
+            }
+            println!("\nSpecify a method by name with `-m METHOD`.");
+        }
+        Mode::ListProviders => {
+            println!("{}", i18n::message("Available location providers:\n", &[]));
+            for name in location::providers() {
+                println!("  {}", name);
+            }
+            for name in location::disabled_providers() {
+                println!("  {} (not compiled in)", name);
+            }
+            println!("\nSpecify a provider by name with `-l PROVIDER`, or give \
+                      a location directly with `-l LAT:LON`.");
+        }
+        Mode::ListOutputs => {
+            // Without starting the method, which would save the ramps
+            // of a running instance as the originals
+            let method = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()),
+                                                  &args.gamma_options)?;
+            outputs::list(&*method, &args.outputs);
+        }
+        Mode::Adjust(adjustment) => {
+            let dir = instance::runtime_dir(&args.gamma_options);
+            let offset = adjustment.apply(offset::load(&dir));
+            offset::store(&dir, offset)?;
+            control::request_update(&dir);
+            println!("Temperature offset: {:+}K", offset);
+        }
         Mode::Reset => {
-            let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-            gamma_state.start()?;
-            gamma_state.set_temperature(&transition::ColorSetting {
-                temp: NEUTRAL_TEMP,
-                gamma: [1.0, 1.0, 1.0],
-                brightness: 1.0
-            })?;
+            if !args.dry_run {
+                let dir = instance::runtime_dir(&args.gamma_options);
+                offset::store(&dir, 0)?;
+                control::request_update(&dir);
+            }
+            let mut gamma_state = start_gamma_method(&args)?;
+            // Put back the ramps of an instance that was killed, rather
+            // than merely neutral ones
+            if gamma_state.recovered() {
+                gamma_state.restore()?;
+            } else {
+                let neutral = transition::ColorSetting::neutral();
+                let outputs = outputs::settings(&args.outputs, |_| neutral.clone());
+                gamma_state.set_output_temperatures(&neutral, &outputs)?;
+            }
         }
+        Mode::Print if args.follow => follow(&args, &scheme),
         Mode::OneShot | Mode::Print => {
             let now = systemtime_get_time();
-            let print = args.verbose || args.mode == Mode::Print;
 
             // Compute elevation
             let elev = solar::elevation(now, &args.location);
@@ -364,141 +1178,117 @@ fn run(args: Args) -> Result<i32> {
             let period = scheme.get_period(elev);
 
             // Interpolate between 6500K and calculated temperature
-            let color_setting = scheme.interpolate_color_settings(elev);
-
-            if print {
-                println!("Solar elevation: {}", elev);
-                period.print();
-                println!("Color temperature: {}K", color_setting.temp);
-                println!("Brightness: {:.2}", color_setting.brightness);
+            let offset = offset::load(&instance::runtime_dir(&args.gamma_options));
+            let mut color_setting = scheme.interpolate_color_settings(elev);
+            apply_offset(&mut color_setting, offset, args.temp_limits);
+            if args.grayscale {
+                apply_grayscale(&mut color_setting);
             }
 
+            let mut status = status::Status {
+                period: period,
+                elevation: elev,
+                setting: color_setting,
+                location: args.location,
+                outputs: vec![],
+                enabled: true,
+                night_vision: false,
+            };
+
             if args.mode == Mode::OneShot {
-                let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-                gamma_state.start()?;
-                gamma_state.set_temperature(&color_setting)?;
+                let outputs = outputs::settings(&args.outputs, |temperatures| {
+                    let mut setting = scheme.interpolate_with_temperatures(elev, temperatures);
+                    apply_offset(&mut setting, offset, args.temp_limits);
+                    if args.grayscale {
+                        apply_grayscale(&mut setting);
+                    }
+                    setting
+                });
+                let mut gamma_state = start_gamma_method(&args)?;
+                warn_filters(&*gamma_state, &args);
+                gamma_state.set_output_temperatures(&status.setting, &outputs)?;
+                status.outputs = gamma_state.outputs();
+            }
+
+            // In one shot mode the report is only diagnostic output,
+            // unless it was explicitly requested as JSON
+            if args.mode == Mode::Print && args.format == status::Format::Text && term::colors(term::STDOUT) {
+                println!("{}", term::status(&status));
+            } else if args.mode == Mode::Print || args.format == status::Format::Json {
+                println!("{}", status.format(args.format));
+            } else {
+                info!("{}", status.to_text());
             }
         }
         Mode::Manual(temp) => {
-            if args.verbose {
-                println!("Color temperature: {}", temp);
-            }
-            let color_setting = transition::ColorSetting {
+            info!("Color temperature: {}K", temp);
+            let mut color_setting = transition::ColorSetting {
                 temp: temp,
                 gamma: scheme.day.gamma.clone(),
-                brightness: scheme.day.brightness
+                brightness: scheme.day.brightness,
+                invert: scheme.day.invert,
+                contrast: scheme.day.contrast,
+                matrix: scheme.day.matrix,
+                filter: scheme.day.filter,
+                calibration: None,
             };
+            if args.grayscale {
+                apply_grayscale(&mut color_setting);
+            }
 
-            let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-            gamma_state.start()?;
-            gamma_state.set_temperature(&color_setting)?;
+            let outputs = outputs::settings(&args.outputs, |_| color_setting.clone());
+            let mut gamma_state = start_gamma_method(&args)?;
+            warn_filters(&*gamma_state, &args);
+            gamma_state.set_output_temperatures(&color_setting, &outputs)?;
         }
         Mode::Continual => {
-            run_continual_mode(args, scheme)?;
+            daemon::run(args, scheme)?;
         }
     }
     Ok(0)
 }
 
-/// Continual mode
+/// Print a line whenever the period, the temperature or the enabled
+/// state changes, until interrupted
 ///
-/// The default functionality of Redshift is to run continually
-/// adjusting the temperature as the day progresses. It is interrupted
-/// by signals INT and TERM that both cause it to terminate.
-///
-/// TODO: Respect the transition scheme, espectially in the presence
-///       of the --no-transition flag
-fn run_continual_mode(args: Args, mut scheme: transition::TransitionScheme) -> Result<()> {
-    let mut gamma_state = gamma::init_gamma_method(args.method.as_ref().map(|s| s.as_str()))?;
-    gamma_state.start()?;
-
-    // Create signal thread
-    let sigint = chan_signal::notify(&[chan_signal::Signal::INT,
-                                       chan_signal::Signal::TERM]);
-    let (signal_tx, signal_rx) = chan::sync(0);
-    thread::spawn(move || {
-        for sig in sigint.iter() {
-            signal_tx.send(sig);
-        }
-    });
-
-    let (timer_tx, timer_rx) = chan::sync(0);
-    let (sleep_tx, sleep_rx) = chan::sync(0);
-    thread::spawn(move || {
-        for ms in sleep_rx.iter() {
-            thread::sleep(std::time::Duration::from_millis(ms));
-            timer_tx.send(());
-        }
-    });
-
-    let mut now;
-    let mut exiting = false;
-    let mut prev_color_setting = transition::ColorSetting::new();
-    let mut prev_period = transition::Period::None;
-    sleep_tx.send(0);
+/// The temperature is that of print mode, with the offset. The
+/// enabled state is the one a running daemon saves.
+fn follow(args: &Args, scheme: &transition::TransitionScheme) -> ! {
+    let colors = term::colors(term::STDOUT);
+    let dir = instance::runtime_dir(&args.gamma_options);
+    let state_dir = instance::state_dir(&args.gamma_options);
+    let mut last = None;
     loop {
-        chan_select! {
-            signal_rx.recv() -> _signal => {
-                if exiting {
-                    break // If already exiting, just exit immediately
-                }
-                exiting = true;
-                scheme.short_trans_delta = 1;
-                scheme.short_trans_len = 2;
-                scheme.adjustment_alpha = 0.1;
-            },
-            timer_rx.recv() => {
-                now = systemtime_get_time();
-
-                // Compute elevation
-                let elev = solar::elevation(now, &args.location);
-
-                let period = scheme.get_period(elev);
-                if period != prev_period {
-                    if args.verbose {
-                        period.print();
-                    }
-                    prev_period = period;
-                }
-
-                // Interpolate between 6500K and calculated temperature
-                let mut color_setting = scheme.interpolate_color_settings(elev);
-
-                /* Ongoing short transition? */
-                if scheme.short_transition() {
-                    scheme.adjust_transition_alpha();
-                    color_setting.temp = (scheme.adjustment_alpha * NEUTRAL_TEMP as f64 +
-                                          (1.0-scheme.adjustment_alpha) * color_setting.temp as f64) as i32;
-                    color_setting.brightness = scheme.adjustment_alpha * 1.0 +
-                        (1.0-scheme.adjustment_alpha) * color_setting.brightness;
-                }
-
-                if args.verbose {
-                    if color_setting.temp != prev_color_setting.temp {
-                        println!("Color temperature: {:?}K", color_setting.temp);
-                    }
-                    if color_setting.brightness != prev_color_setting.brightness {
-                        println!("Brightness: {:?}", color_setting.brightness);
-                    }
-                }
-                if color_setting != prev_color_setting {
-                    gamma_state.set_temperature(&color_setting)?;
-                }
-
-                if exiting && !scheme.short_transition() {
-                    break
-                }
-
-                // Sleep for 5 seconds or 0.1 second
-                sleep_tx.send(if scheme.short_transition() { 100 } else { 5000 });
-
-                /* Save temperature */
-                prev_color_setting = color_setting;
+        let now = systemtime_get_time();
+        let elev = solar::elevation(now, &args.location);
+        let period = scheme.get_period(elev);
+        let mut setting = scheme.interpolate_color_settings(elev);
+        apply_offset(&mut setting, offset::load(&dir), args.temp_limits);
+        let enabled = state::State::load(state_dir.as_deref()).enabled;
+
+        let current = (period.name(), setting.temp, enabled);
+        if last != Some(current) {
+            last = Some(current);
+            match args.format {
+                status::Format::Text => println!("{}  {}  {}{}K  brightness {:.2}{}",
+                                                 daemon::format_time(now), period,
+                                                 if colors { term::swatch(setting.temp) + " " } else { String::new() },
+                                                 setting.temp,
+                                                 setting.brightness,
+                                                 if enabled { "" } else { "  (disabled)" }),
+                status::Format::Json => println!("{}", status::Status {
+                    period: period,
+                    elevation: elev,
+                    setting: setting,
+                    location: args.location,
+                    outputs: vec![],
+                    enabled: enabled,
+                    night_vision: false,
+                }.to_json()),
             }
         }
+        thread::sleep(Duration::from_millis((args.update_interval * 1000.0) as u64));
     }
-    gamma_state.restore()?;
-    Ok(())
 }
 
 fn systemtime_get_time() -> f64 {
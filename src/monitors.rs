@@ -0,0 +1,70 @@
+//! Profiles by monitor layout
+//!
+//! A `[monitors]` section in the config file switches to a profile
+//! while certain outputs are in use, such as milder settings while
+//! docked to an external monitor that is calibrated already:
+//!
+//! ```text
+//! [monitors]
+//! eDP-1+DP-2=docked     ; a [profile:docked] section
+//! HDMI-1=projector
+//! ```
+//!
+//! Each key is a list of output names joined by `+`, all of which
+//! must be in use for the profile to apply. Other outputs may be in
+//! use as well. The first entry that matches wins, and with none the
+//! layout selects no profile.
+//!
+//! The profile is applied on top of the one selected otherwise, like
+//! the one of the `[battery]` section. The layout is looked at when
+//! the daemon starts and whenever the gamma method reports changed
+//! outputs, which RandR does on docking and undocking.
+
+use config::{Config, Section};
+use super::Result;
+
+/// Settings of the `[monitors]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// The outputs of each layout with its profile, in file order
+    layouts: Vec<(Vec<String>, String)>,
+}
+
+impl Options {
+    /// Read the section, checking that the profiles exist
+    pub fn from_config(section: &Section, config: &Config) -> Result<Options> {
+        let mut layouts = vec![];
+        for &(ref outputs, ref profile) in &section.entries {
+            config.profile(profile)?;
+            let outputs = outputs.split('+').map(|o| o.trim().to_owned()).collect();
+            layouts.push((outputs, profile.clone()));
+        }
+        Ok(Options { layouts: layouts })
+    }
+
+    /// The profile of the layout with the given outputs in use, if any
+    pub fn profile(&self, outputs: &[String]) -> Option<&str> {
+        self.layouts.iter()
+            .find(|layout| layout.0.iter().all(|o| outputs.contains(o)))
+            .map(|layout| &layout.1[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layouts() {
+        let config: Config = "[monitors]\neDP-1+DP-2=docked\nHDMI-1=projector\n\
+                              [profile:docked]\n[profile:projector]".parse().unwrap();
+        let options = Options::from_config(config.section("monitors").unwrap(), &config).unwrap();
+        let outputs = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(options.profile(&outputs(&["eDP-1"])), None);
+        assert_eq!(options.profile(&outputs(&["DP-2", "eDP-1", "DP-3"])), Some("docked"));
+        assert_eq!(options.profile(&outputs(&["eDP-1", "HDMI-1"])), Some("projector"));
+
+        let config: Config = "[monitors]\nHDMI-1=cinema".parse().unwrap();
+        assert!(Options::from_config(config.section("monitors").unwrap(), &config).is_err());
+    }
+}
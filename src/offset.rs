@@ -0,0 +1,98 @@
+//! Relative temperature adjustment
+//!
+//! A temperature offset, set with `--adjust +500` or `--adjust -500`,
+//! is added on top of the scheduled temperature until it is reset
+//! with `--adjust reset` or `-x`. Each display has its own offset,
+//! kept in a file in `$XDG_RUNTIME_DIR/redshift-rs/DISPLAY/`, which the
+//! instance on that display reads on every update.
+//! Changing the offset asks the daemon for an update through the
+//! control socket, so that keybindings can nudge its temperature.
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use paths;
+use super::{Result, RedshiftError};
+
+/// A requested change of the offset
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Adjustment {
+    /// Add to the current offset
+    Relative(i32),
+
+    /// Clear the offset
+    Reset,
+}
+
+impl Adjustment {
+    /// Parse "+N", "-N" or "reset"
+    pub fn parse(input: &str) -> Result<Adjustment> {
+        let malformed = || RedshiftError::MalformedArgument(
+            format!("adjustment: {} (expected +N, -N or reset)", input));
+        match input {
+            "reset" | "0" => Ok(Adjustment::Reset),
+            _ if input.starts_with('+') || input.starts_with('-') => {
+                let digits = input.trim_end_matches(&['K', 'k'][..]);
+                digits.parse().map(Adjustment::Relative).map_err(|_| malformed())
+            }
+            _ => Err(malformed()),
+        }
+    }
+
+    /// Apply the adjustment to an offset
+    pub fn apply(&self, offset: i32) -> i32 {
+        match *self {
+            Adjustment::Relative(delta) => offset + delta,
+            Adjustment::Reset => 0,
+        }
+    }
+}
+
+fn path(dir: &Path) -> PathBuf {
+    dir.join("offset")
+}
+
+/// The current offset in Kelvin in the directory of an instance, zero
+/// when none is set
+pub fn load(dir: &Path) -> i32 {
+    let mut contents = String::new();
+    match File::open(path(dir)).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_) => contents.trim().parse().unwrap_or_else(|_| {
+            warn!("Ignoring malformed temperature offset in {}", path(dir).display());
+            0
+        }),
+        Err(_) => 0,
+    }
+}
+
+/// Store a new offset, removing the file when it is zero
+pub fn store(dir: &Path, offset: i32) -> Result<()> {
+    let path = path(dir);
+    if offset == 0 {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(RedshiftError::io(path.display(), e)),
+            _ => Ok(()),
+        };
+    }
+    let write = || -> ::std::io::Result<()> {
+        paths::ensure_runtime_dir(dir)?;
+        File::create(&path)?.write_all(format!("{}\n", offset).as_bytes())
+    };
+    write().map_err(|e| RedshiftError::io(path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::Adjustment;
+
+    #[test]
+    fn parse_adjustment() {
+        assert_eq!(Adjustment::parse("+500").unwrap(), Adjustment::Relative(500));
+        assert_eq!(Adjustment::parse("-250K").unwrap(), Adjustment::Relative(-250));
+        assert_eq!(Adjustment::parse("reset").unwrap(), Adjustment::Reset);
+        assert!(Adjustment::parse("500").is_err());
+        assert!(Adjustment::parse("+warm").is_err());
+        assert_eq!(Adjustment::Relative(-500).apply(200), -300);
+    }
+}
@@ -0,0 +1,168 @@
+//! Settings of single outputs
+//!
+//! An `[output:NAME]` section in the config file leaves an output
+//! alone, such as a projector or a display used for color grading, or
+//! gives it temperatures of its own:
+//!
+//! ```text
+//! [output:HDMI-1]
+//! enabled=0
+//!
+//! [output:DP-2]
+//! temp-day=6000
+//! temp-night=4000
+//! ```
+//!
+//! The name is the one the gamma method knows the output by, as shown
+//! by `--list-outputs` and by the daemon at startup; RandR uses the
+//! output names of xrandr.
+//! Outputs with temperatures of their own follow the same schedule,
+//! offset and fades as the others. Methods that can't tell their
+//! outputs apart apply the global settings to all of them.
+//!
+//! `icc` gives the ICC profile of a calibrated display, whose
+//! calibration curves are applied on top of every setting; see
+//! `redshift_rs::icc`. With `colord=1` the profiles assigned by colord
+//! are used for the outputs without one.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use config::Section;
+use gamma::{GammaMethod, OutputInfo, OutputSetting};
+use icc::Calibration;
+use preset::Presets;
+use transition::ColorSetting;
+use super::{clamp_temperature, Result};
+
+/// Settings of an `[output:NAME]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub name: String,
+    /// Whether the output is adjusted at all
+    pub enabled: bool,
+    /// Temperatures at day and at night, if different from the others
+    pub temperatures: Option<(i32, i32)>,
+    /// Calibration curves of the display, from its ICC profile
+    pub calibration: Option<Arc<Calibration>>,
+}
+
+impl Options {
+    /// Read the section of an output, given the temperatures of the
+    /// others and the limits of all temperatures
+    pub fn from_config(name: &str, section: &Section, presets: &Presets,
+                       temperatures: (i32, i32), limits: (i32, i32)) -> Result<Options> {
+        let day = section.get("temp-day").map(|t| presets.resolve(t)).transpose()?;
+        let night = section.get("temp-night").map(|t| presets.resolve(t)).transpose()?;
+        let calibration = section.get("icc")
+            .map(|p| Calibration::load(Path::new(p)).map(Arc::new))
+            .transpose()?;
+        // A calibrated output gets settings of its own, if only to
+        // apply the calibration
+        let temperatures = match (day, night) {
+            (None, None) if calibration.is_none() => None,
+            (day, night) => Some((clamp_temperature(day.unwrap_or(temperatures.0), limits)?,
+                                  clamp_temperature(night.unwrap_or(temperatures.1), limits)?)),
+        };
+        Ok(Options {
+            name: name.to_owned(),
+            enabled: section.parse::<i32>("enabled")?.map_or(true, |e| e != 0),
+            temperatures: temperatures,
+            calibration: calibration,
+        })
+    }
+}
+
+/// The settings of the outputs that differ from the others
+///
+/// `own` computes the setting of an output from its own temperatures
+/// at day and at night.
+pub fn settings<F>(outputs: &[Options], mut own: F) -> Vec<OutputSetting>
+    where F: FnMut((i32, i32)) -> ColorSetting
+{
+    outputs.iter()
+        .filter_map(|o| {
+            let setting = if o.enabled {
+                let mut setting = own(o.temperatures?);
+                setting.calibration = o.calibration.clone();
+                Some(setting)
+            } else {
+                None
+            };
+            Some(OutputSetting { output: o.name.clone(), setting: setting })
+        })
+        .collect()
+}
+
+/// Print the outputs the method sees, with whether and how each is
+/// adjusted
+pub fn list(method: &GammaMethod, outputs: &[Options]) {
+    let infos = method.output_info();
+    if infos.is_empty() {
+        println!("Method {} lists no outputs", method.name());
+        return;
+    }
+    let width = |f: &Fn(&OutputInfo) -> usize, min: usize| infos.iter().map(f).max().unwrap_or(0).max(min);
+    let name_width = width(&|i| i.name.len(), 4);
+    let model_width = width(&|i| i.model.as_ref().map_or(1, |m| m.chars().count()), 5);
+    println!("Outputs of method {}:", method.name());
+    println!("  {:nw$}  {:mw$}   RAMP   CRTC  ADJUSTED", "NAME", "MODEL",
+             nw = name_width, mw = model_width);
+    for info in &infos {
+        println!("  {:nw$}  {:mw$}  {:>5}  {:>5}  {}",
+                 info.name,
+                 info.model.as_ref().map_or("-", |m| &m[..]),
+                 info.ramp_size.map_or("-".to_owned(), |s| s.to_string()),
+                 info.crtc.map_or("-".to_owned(), |c| c.to_string()),
+                 adjusted(info, outputs),
+                 nw = name_width, mw = model_width);
+    }
+}
+
+/// Whether an output is adjusted, and why not or how if it matters
+fn adjusted(info: &OutputInfo, outputs: &[Options]) -> String {
+    if !info.adjusted {
+        return "no, left out by the options of the method".to_owned();
+    }
+    match outputs.iter().find(|o| o.name == info.name) {
+        Some(o) if !o.enabled => format!("no, [output:{}] enabled=0", o.name),
+        Some(&Options { temperatures: Some((day, night)), ref calibration, .. }) =>
+            format!("yes, {}K at day, {}K at night{}", day, night,
+                    if calibration.is_some() { ", calibrated" } else { "" }),
+        _ => "yes".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::Config;
+
+    #[test]
+    fn output_sections() {
+        let config: Config = "[output:HDMI-1]\nenabled=0\n[output:DP-2]\ntemp-night=4000\n[output:DP-3]"
+            .parse().unwrap();
+        let presets = Presets::builtin();
+        let outputs: Vec<Options> = config.outputs().into_iter()
+            .map(|(name, s)| Options::from_config(name, s, &presets, (6500, 3500), (1000, 25000)).unwrap())
+            .collect();
+        assert!(!outputs[0].enabled);
+        assert_eq!(outputs[1].temperatures, Some((6500, 4000)));
+        assert_eq!(outputs[2].temperatures, None);
+
+        let settings = settings(&outputs, |(_, night)| ColorSetting { temp: night, ..ColorSetting::neutral() });
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings[0], OutputSetting { output: "HDMI-1".to_owned(), setting: None });
+        assert_eq!(settings[1].setting.as_ref().map(|s| s.temp), Some(4000));
+
+        let info = |name: &str| OutputInfo::named(name.to_owned());
+        assert_eq!(adjusted(&info("HDMI-1"), &outputs), "no, [output:HDMI-1] enabled=0");
+        assert_eq!(adjusted(&info("DP-2"), &outputs), "yes, 6500K at day, 4000K at night");
+        assert_eq!(adjusted(&info("eDP-1"), &outputs), "yes");
+        assert!(adjusted(&OutputInfo { adjusted: false, ..info("DP-2") }, &outputs).starts_with("no"));
+
+        let config: Config = "[output:DP-4]\nicc=/nonexistent.icc\n".parse().unwrap();
+        let (name, section) = config.outputs()[0];
+        assert!(Options::from_config(name, section, &presets, (6500, 3500), (1000, 25000)).is_err());
+    }
+}
@@ -0,0 +1,87 @@
+//! Standard file locations
+//!
+//! Directories follow the XDG base directory specification, each
+//! with a `redshift-rs` subdirectory.
+
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use libc;
+
+const APP_DIR: &'static str = "redshift-rs";
+
+fn xdg_dir(var: &str, fallback: &str, app: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|h| Path::new(&h).join(fallback)))
+        .map(|d| d.join(app))
+}
+
+/// `$XDG_CONFIG_HOME/redshift-rs`, or `~/.config/redshift-rs`
+pub fn config_dir() -> Option<PathBuf> {
+    config_dir_of(APP_DIR)
+}
+
+/// The configuration directory of another program, such as the
+/// `redshift` of C Redshift
+pub fn config_dir_of(app: &str) -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config", app)
+}
+
+/// `$XDG_STATE_HOME/redshift-rs`, or `~/.local/state/redshift-rs`
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state", APP_DIR)
+}
+
+/// `$XDG_RUNTIME_DIR/redshift-rs`, falling back to a per-user
+/// directory in /tmp when the runtime directory is not set
+///
+/// Directories in it are created with `ensure_runtime_dir`.
+pub fn runtime_dir() -> PathBuf {
+    xdg_runtime_dir().unwrap_or_else(tmp_runtime_dir).join(APP_DIR)
+}
+
+fn xdg_runtime_dir() -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+}
+
+/// The directory in /tmp standing in for the runtime directory
+fn tmp_runtime_dir() -> PathBuf {
+    let user = env::var("USER").unwrap_or_else(|_| "unknown".to_owned());
+    env::temp_dir().join(format!("{}-{}", APP_DIR, user))
+}
+
+/// Create a directory, and its parents, if it does not exist
+pub fn ensure_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+/// Create a directory in the runtime directory, and its parents, if it
+/// does not exist, accessible to the user only
+///
+/// Anyone can create the directory in /tmp that stands in for the
+/// runtime directory when that is not set, and whoever does can take
+/// over the control socket. It is only used when it is the user's own,
+/// and private to them.
+pub fn ensure_runtime_dir(dir: &Path) -> io::Result<()> {
+    if xdg_runtime_dir().is_none() {
+        let tmp = tmp_runtime_dir();
+        match DirBuilder::new().mode(0o700).create(&tmp) {
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            result => result?,
+        }
+        let meta = fs::symlink_metadata(&tmp)?;
+        if !meta.is_dir() || meta.uid() != unsafe { libc::getuid() } || meta.mode() & 0o777 != 0o700 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("{} is not a directory of the user's own with mode 0700",
+                                              tmp.display())));
+        }
+    }
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
@@ -0,0 +1,103 @@
+//! Profiles by place
+//!
+//! `[place:NAME]` sections in the config file switch to a profile
+//! while the location is near a saved place, for a laptop that moves
+//! between a dim home and a bright office:
+//!
+//! ```text
+//! [place:office]
+//! location=55.68:12.57
+//! radius=2             ; in km, 1 by default
+//! profile=bright       ; a [profile:bright] section, the name of
+//!                      ; the place by default
+//! ```
+//!
+//! The first place within its radius of the location wins. Its
+//! profile is applied on top of the one selected otherwise, like the
+//! one of the `[battery]` section. The location is that of the
+//! location provider, looked at when the daemon starts and whenever
+//! the settings are reloaded.
+
+use config::{Config, Section};
+use location::Location;
+use super::{Result, RedshiftError};
+
+const PLACE_PREFIX: &'static str = "place:";
+
+/// Radius of a place without a `radius` setting, in km
+const DEFAULT_RADIUS_KM: f64 = 1.0;
+
+/// A `[place:NAME]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub name: String,
+    pub location: Location,
+    /// In km
+    pub radius: f64,
+    pub profile: String,
+}
+
+impl Place {
+    fn from_config(name: &str, section: &Section, config: &Config) -> Result<Place> {
+        let location = section.get("location")
+            .ok_or_else(|| RedshiftError::MalformedConfig(format!("[{}] location: missing", section.name)))?
+            .parse::<Location>()
+            .map_err(|e| RedshiftError::MalformedConfig(format!("[{}] {}", section.name, e)))?;
+        let radius = section.parse::<f64>("radius")?.unwrap_or(DEFAULT_RADIUS_KM);
+        if radius.is_nan() || radius <= 0.0 {
+            return Err(RedshiftError::MalformedConfig(
+                format!("[{}] radius: {} is not a distance", section.name, radius)));
+        }
+        let profile = section.get("profile").unwrap_or(name);
+        config.profile(profile)?;
+        Ok(Place {
+            name: name.to_owned(),
+            location: location,
+            radius: radius,
+            profile: profile.to_owned(),
+        })
+    }
+
+    pub fn contains(&self, location: &Location) -> bool {
+        self.location.distance(location) <= self.radius
+    }
+}
+
+/// All the places of the config file, in file order
+pub fn from_config(config: &Config) -> Result<Vec<Place>> {
+    config.sections.iter()
+        .filter(|s| s.name.starts_with(PLACE_PREFIX))
+        .map(|s| Place::from_config(&s.name[PLACE_PREFIX.len()..], s, config))
+        .collect()
+}
+
+/// The first of the places that contains the location
+pub fn find<'a>(places: &'a [Place], location: &Location) -> Option<&'a Place> {
+    places.iter().find(|p| p.contains(location))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn places() {
+        let config: Config = "[place:home]\nlocation=55.7:12.6\n\
+                              [place:office]\nlocation=55.68:12.57\nradius=2\nprofile=bright\n\
+                              [profile:home]\n[profile:bright]".parse().unwrap();
+        let places = from_config(&config).unwrap();
+        assert_eq!(places[1].profile, "bright");
+
+        // Copenhagen to Aarhus
+        let aarhus = Location::new(56.16, 10.20);
+        let d = places[0].location.distance(&aarhus);
+        assert!(d > 150.0 && d < 160.0, "{}", d);
+
+        assert_eq!(find(&places, &Location::new(55.701, 12.601)).map(|p| &p.name[..]), Some("home"));
+        assert_eq!(find(&places, &Location::new(55.67, 12.56)).map(|p| &p.name[..]), Some("office"));
+        assert_eq!(find(&places, &aarhus), None);
+
+        let config: Config = "[place:home]\nlocation=55.7:12.6".parse().unwrap();
+        assert!(from_config(&config).is_err());
+    }
+}
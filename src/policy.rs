@@ -0,0 +1,214 @@
+//! Scheduling policy of the user's own
+//!
+//! For schedules the settings can't express, a program of the user's
+//! own can have the last word on the temperature and the brightness:
+//!
+//! ```text
+//! [policy]
+//! command=~/bin/redshift-policy
+//! ```
+//!
+//! The command is started once with `sh -c` and kept running. On every
+//! update it is given a line on its standard input with the time, the
+//! solar elevation, the location and what the schedule works out, and
+//! the ambient light in lux when that is followed:
+//!
+//! ```text
+//! time=1792040000 elevation=-4.20 latitude=55.70 longitude=12.60 period=transition temperature=4800 brightness=1.00 lux=35
+//! ```
+//!
+//! It answers with a line on its standard output with the temperature
+//! and the brightness to use instead, e.g. `temperature=4000
+//! brightness=0.8`. Either can be left out to keep the scheduled one,
+//! an empty line keeps both. The offset, held temperatures, the ambient
+//! light, fades and night vision still apply on top of the answer.
+//! Outputs with temperatures of their own follow their own schedule.
+//!
+//! A program that exits, answers anything else, or takes longer than
+//! `TIMEOUT_MS` to answer is stopped, and the schedule is followed
+//! without it until the daemon is restarted.
+//!
+//! The policy is a program of its own, no scripting engine is embedded
+//! in the daemon. Like the hooks and the `exec` location provider, it
+//! runs as the user, with the environment of the daemon and nothing
+//! sandboxed, so whoever can edit the config file can run programs as
+//! the user, as they could with those already. Its answers only choose
+//! the setting, within the limits above.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use config::Section;
+use location::Location;
+use transition::{ColorSetting, Period};
+use super::{MAX_TEMP, MIN_TEMP, Result, RedshiftError};
+
+/// How long the program may take to answer
+const TIMEOUT_MS: u64 = 1000;
+
+const MIN_BRIGHTNESS: f64 = 0.1;
+
+/// Settings of the `[policy]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// The program, run with `sh -c`
+    pub command: String,
+}
+
+impl Options {
+    pub fn from_config(section: &Section) -> Result<Options> {
+        for e in &section.entries {
+            if e.0 != "command" {
+                return Err(RedshiftError::MalformedConfig(
+                    format!("[{}] {}: unknown option", section.name, e.0)));
+            }
+        }
+        match section.get("command").map(str::trim) {
+            Some(command) if !command.is_empty() => Ok(Options { command: command.to_owned() }),
+            _ => Err(RedshiftError::MalformedConfig(
+                format!("[{}]: no command given", section.name))),
+        }
+    }
+}
+
+/// What the program is told about an update
+pub struct Query<'a> {
+    pub time: f64,
+    pub elevation: f64,
+    pub location: Location,
+    pub period: Period,
+    pub setting: &'a ColorSetting,
+    pub lux: Option<f64>,
+}
+
+impl<'a> Query<'a> {
+    fn line(&self) -> String {
+        let mut line = format!("time={:.0} elevation={:.2} latitude={:.2} longitude={:.2} \
+                                period={} temperature={} brightness={:.2}",
+                               self.time, self.elevation, self.location.lat, self.location.lon,
+                               self.period.name(), self.setting.temp, self.setting.brightness);
+        if let Some(lux) = self.lux {
+            line.push_str(&format!(" lux={:.0}", lux));
+        }
+        line
+    }
+}
+
+/// The temperature and the brightness of an answer, `None` where the
+/// scheduled one is kept
+fn parse(answer: &str) -> ::std::result::Result<(Option<i32>, Option<f64>), String> {
+    let mut temp = None;
+    let mut brightness = None;
+    for field in answer.split_whitespace() {
+        let mut kv = field.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("temperature"), Some(v)) => match v.parse::<i32>() {
+                Ok(t) if t >= MIN_TEMP && t <= MAX_TEMP => temp = Some(t),
+                _ => return Err(format!("temperature {} is not between {}K and {}K",
+                                        v, MIN_TEMP, MAX_TEMP)),
+            },
+            (Some("brightness"), Some(v)) => match v.parse::<f64>() {
+                Ok(b) if b >= MIN_BRIGHTNESS && b <= 1.0 => brightness = Some(b),
+                _ => return Err(format!("brightness {} is not between {} and 1.0",
+                                        v, MIN_BRIGHTNESS)),
+            },
+            _ => return Err(format!("'{}' is not temperature=KELVIN or brightness=VALUE", field)),
+        }
+    }
+    Ok((temp, brightness))
+}
+
+/// The running program
+pub struct Policy {
+    child: Child,
+    stdin: ChildStdin,
+    answers: mpsc::Receiver<String>,
+}
+
+impl Policy {
+    pub fn start(options: &Options) -> Result<Policy> {
+        let error = |e| RedshiftError::io(format_args!("policy command '{}'", options.command), e);
+        let mut child = Command::new("sh").arg("-c").arg(&options.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(error)?;
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        let (tx, answers) = mpsc::channel();
+        // Reading in a thread of its own, for the timeout
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) => if tx.send(line).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+        info!("Following the policy of '{}'", options.command);
+        Ok(Policy { child: child, stdin: stdin, answers: answers })
+    }
+
+    /// Ask the program for the setting of an update, and change the
+    /// setting to its answer
+    ///
+    /// An error means the program is no use any more.
+    pub fn decide(&mut self, query: &Query, setting: &mut ColorSetting) -> ::std::result::Result<(), String> {
+        writeln!(self.stdin, "{}", query.line()).map_err(|e| format!("could not write to it: {}", e))?;
+        let answer = match self.answers.recv_timeout(Duration::from_millis(TIMEOUT_MS)) {
+            Ok(answer) => answer,
+            Err(mpsc::RecvTimeoutError::Timeout) =>
+                return Err(format!("no answer within {} ms", TIMEOUT_MS)),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err("it exited".to_owned()),
+        };
+        let (temp, brightness) = parse(&answer)?;
+        if let Some(t) = temp {
+            setting.temp = t;
+        }
+        if let Some(b) = brightness {
+            setting.brightness = b;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Policy {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn answers() {
+        assert_eq!(parse(""), Ok((None, None)));
+        assert_eq!(parse("temperature=4000 brightness=0.8"), Ok((Some(4000), Some(0.8))));
+        assert_eq!(parse("  brightness=0.5\n"), Ok((None, Some(0.5))));
+        assert!(parse("temperature=100").is_err());
+        assert!(parse("brightness=1.5").is_err());
+        assert!(parse("4000").is_err());
+        assert!(parse("contrast=0.9").is_err());
+    }
+
+    #[test]
+    fn query() {
+        let setting = ColorSetting { temp: 4800, brightness: 1.0, ..ColorSetting::new() };
+        let query = Query {
+            time: 1792040000.4,
+            elevation: -4.2,
+            location: Location::new(55.7, 12.6),
+            period: Period::Transition(0.5),
+            setting: &setting,
+            lux: Some(35.2),
+        };
+        assert_eq!(query.line(), "time=1792040000 elevation=-4.20 latitude=55.70 longitude=12.60 \
+                                  period=transition temperature=4800 brightness=1.00 lux=35");
+    }
+}
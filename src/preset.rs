@@ -0,0 +1,111 @@
+//! Named temperature presets
+//!
+//! Anywhere a temperature in Kelvin is accepted, a symbolic name can
+//! be given instead, or the temperature in mireds (a million divided
+//! by the Kelvin), as in `250mired` for 4000K. The built-in presets
+//! can be extended or overridden in the `[presets]` section of the
+//! config file:
+//!
+//! ```text
+//! [presets]
+//! reading=4200
+//! candle=1800
+//! dim=400mired
+//! ```
+
+use config::Section;
+use super::{Result, RedshiftError};
+
+/// The built-in presets, approximate color temperatures of common
+/// light sources
+const BUILTIN: &'static [(&'static str, i32)] = &[
+    ("candle",   1900),
+    ("tungsten", 2700),
+    ("halogen",  3400),
+    ("daylight", 5500),
+    ("neutral",  6500),
+    ("overcast", 7000),
+];
+
+pub struct Presets {
+    table: Vec<(String, i32)>,
+}
+
+impl Presets {
+    pub fn builtin() -> Presets {
+        Presets {
+            table: BUILTIN.iter().map(|&(n, t)| (n.to_owned(), t)).collect(),
+        }
+    }
+
+    /// Add the presets of a config section, overriding existing ones
+    /// with the same name
+    pub fn extend(&mut self, section: &Section) -> Result<()> {
+        for (name, value) in section.entries.iter().map(|e| (&e.0, &e.1)) {
+            let temp = parse_temperature(value).ok_or_else(|| {
+                RedshiftError::MalformedConfig(
+                    format!("[{}] {}: invalid temperature '{}'", section.name, name, value))
+            })?;
+            match self.table.iter_mut().find(|e| e.0 == *name) {
+                Some(e) => e.1 = temp,
+                None => self.table.push((name.clone(), temp)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Look up a preset by name
+    pub fn get(&self, name: &str) -> Option<i32> {
+        self.table.iter().find(|e| e.0 == name).map(|e| e.1)
+    }
+
+    /// Resolve a temperature given in Kelvin (with an optional `K`
+    /// suffix), in mireds or as the name of a preset
+    pub fn resolve(&self, input: &str) -> Result<i32> {
+        parse_temperature(input)
+            .or_else(|| self.get(&input.to_lowercase()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = self.table.iter().map(|e| &e.0[..]).collect();
+                RedshiftError::MalformedArgument(
+                    format!("temperature: {} (expected Kelvin, mireds or one of: {})",
+                            input, names.join(", ")))
+            })
+    }
+}
+
+/// Parse a temperature in Kelvin, e.g. "4500" or "4500K", or in
+/// mireds, e.g. "250mired", returning it in Kelvin
+pub fn parse_temperature(input: &str) -> Option<i32> {
+    let input = input.trim();
+    let lower = input.to_lowercase();
+    if let Some(mireds) = lower.strip_suffix("mireds").or_else(|| lower.strip_suffix("mired")) {
+        return mireds.trim().parse::<f64>().ok()
+            .filter(|m| *m > 0.0)
+            .map(|m| (1e6 / m).round() as i32);
+    }
+    let digits = input.trim_end_matches(&['K', 'k'][..]);
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Presets;
+    use config::Config;
+
+    #[test]
+    fn resolve_presets() {
+        let mut presets = Presets::builtin();
+        assert_eq!(presets.resolve("4500").unwrap(), 4500);
+        assert_eq!(presets.resolve("4500K").unwrap(), 4500);
+        assert_eq!(presets.resolve("Candle").unwrap(), 1900);
+        assert!(presets.resolve("reading").is_err());
+        assert_eq!(presets.resolve("250mired").unwrap(), 4000);
+        assert_eq!(presets.resolve("153 Mireds").unwrap(), 6536);
+        assert!(presets.resolve("0mired").is_err());
+
+        let config: Config = "[presets]\nreading=4200K\ncandle=1800".parse().unwrap();
+        presets.extend(config.section("presets").unwrap()).unwrap();
+        assert_eq!(presets.resolve("reading").unwrap(), 4200);
+        assert_eq!(presets.resolve("candle").unwrap(), 1800);
+    }
+}
@@ -0,0 +1,89 @@
+//! Printing gamma ramps
+//!
+//! `redshift-rs ramps TEMP` prints the ramps computed for a setting,
+//! exactly as they would be written to the hardware, to look into
+//! what a setting does to the display without applying it:
+//!
+//! ```text
+//! $ redshift-rs ramps 3500 -b 0.8 --size 1024
+//! # 3500K, brightness 0.80, gamma 1.00:1.00:1.00, 1024 entries
+//! #  index    red  green   blue
+//!        0      0      0      0
+//!       64   3280   2557   1792
+//! ...
+//! ```
+//!
+//! Only every so many entries are printed, unless `--all` is given.
+//! The command is hidden from the help, it is meant for debugging.
+
+use clap::ArgMatches;
+
+use colorramp;
+use config::Config;
+use preset::Presets;
+use transition::ColorSetting;
+use super::{check_temperature, parse_brightness, parse_gamma, Result, RedshiftError};
+
+/// Ramp size without `--size`, that of most graphics cards
+const DEFAULT_SIZE: usize = 256;
+
+/// Entries printed without `--all`, besides the last one
+const SAMPLES: usize = 16;
+
+/// Print the ramps of the setting given by the arguments of the
+/// `ramps` subcommand
+pub fn print(matches: &ArgMatches, sub: &ArgMatches) -> Result<i32> {
+    let mut presets = Presets::builtin();
+    if let Some(section) = Config::load(matches.value_of("config"))?.section("presets") {
+        presets.extend(section)?;
+    }
+    let size = match sub.value_of("size") {
+        Some(s) => s.parse::<usize>().ok().filter(|n| *n >= 2 && *n <= 65536)
+            .ok_or_else(|| RedshiftError::MalformedArgument(format!("ramp size: {} (2 to 65536)", s)))?,
+        None => DEFAULT_SIZE,
+    };
+    let setting = ColorSetting {
+        temp: check_temperature(presets.resolve(sub.value_of("temperature").unwrap())?)?,
+        brightness: sub.value_of("brightness").map_or(Ok(1.0), |b| parse_brightness(b).map(|b| b.0))?,
+        gamma: sub.value_of("gamma").map_or(Ok([1.0, 1.0, 1.0]), |g| parse_gamma(g).map(|g| [g.0, g.1, g.2]))?,
+        ..ColorSetting::neutral()
+    };
+
+    println!("# {}K, brightness {:.2}, gamma {:.2}:{:.2}:{:.2}, {} entries",
+             setting.temp, setting.brightness,
+             setting.gamma[0], setting.gamma[1], setting.gamma[2], size);
+    println!("# {:>6} {:>6} {:>6} {:>6}", "index", "red", "green", "blue");
+    let ramps = colorramp::ramps(&setting, size);
+    for i in indices(size, sub.is_present("all")) {
+        println!("  {:>6} {:>6} {:>6} {:>6}", i, ramps.0[i], ramps.1[i], ramps.2[i]);
+    }
+    Ok(0)
+}
+
+/// The entries to print of a ramp of the given size
+fn indices(size: usize, all: bool) -> Vec<usize> {
+    if all || size <= SAMPLES + 1 {
+        return (0..size).collect();
+    }
+    let step = size / SAMPLES;
+    let mut indices: Vec<usize> = (0..size).step_by(step).collect();
+    if indices.last() != Some(&(size - 1)) {
+        indices.push(size - 1);
+    }
+    indices
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sampled_indices() {
+        assert_eq!(indices(8, false), (0..8).collect::<Vec<_>>());
+        let sampled = indices(256, false);
+        assert_eq!(sampled.len(), 17);
+        assert_eq!(sampled[1], 16);
+        assert_eq!(sampled.last(), Some(&255));
+        assert_eq!(indices(256, true).len(), 256);
+    }
+}
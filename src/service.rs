@@ -0,0 +1,202 @@
+//! Installing a service
+//!
+//! `redshift-rs install-service` sets up autostart in one go. On Linux
+//! it writes a systemd user unit running the current binary, with the
+//! configuration file given by `-c` if any, to
+//! `~/.config/systemd/user/redshift-rs.service`:
+//!
+//! ```text
+//! [Unit]
+//! Description=Adjust the color temperature of the screen
+//! PartOf=graphical-session.target
+//! After=graphical-session.target
+//!
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/bin/redshift-rs
+//! WatchdogSec=30
+//! Restart=on-failure
+//!
+//! [Install]
+//! WantedBy=graphical-session.target
+//! ```
+//!
+//! With `--enable` it is enabled and started with `systemctl --user`
+//! right away. On macOS a launchd agent is written to
+//! `~/Library/LaunchAgents` instead, which launchd starts at the next
+//! login, or with `--enable` at once. Windows is not supported, like
+//! the rest of redshift-rs.
+//!
+//! An existing file is only replaced with `--force`, and `--print`
+//! prints the file rather than installing it.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::ArgMatches;
+
+use paths;
+use super::{Result, RedshiftError};
+
+const NAME: &'static str = "redshift-rs";
+/// Label of the launchd agent
+const LABEL: &'static str = "io.github.redshift-rs";
+
+/// The service file for the binary and the arguments, and where it
+/// goes
+struct Service {
+    path: PathBuf,
+    contents: String,
+    /// Command enabling and starting it
+    enable: Vec<Vec<String>>,
+}
+
+pub fn install(matches: &ArgMatches, sub: &ArgMatches) -> Result<i32> {
+    let binary = env::current_exe().map_err(|e| RedshiftError::io("the path of redshift-rs", e))?;
+    let mut args = vec![binary.to_string_lossy().into_owned()];
+    if let Some(config) = matches.value_of("config") {
+        let config = env::current_dir().map(|d| d.join(config)).unwrap_or_else(|_| PathBuf::from(config));
+        args.push("-c".to_owned());
+        args.push(config.to_string_lossy().into_owned());
+    }
+    let service = service(&args)?;
+
+    if sub.is_present("print") {
+        print!("{}", service.contents);
+        return Ok(0);
+    }
+    if service.path.exists() && !sub.is_present("force") {
+        return Err(RedshiftError::MalformedArgument(
+            format!("{} exists, use --force to replace it", service.path.display())));
+    }
+    if let Some(dir) = service.path.parent() {
+        paths::ensure_dir(dir).map_err(|e| RedshiftError::io(dir.display(), e))?;
+    }
+    fs::write(&service.path, &service.contents).map_err(|e| RedshiftError::io(service.path.display(), e))?;
+    println!("Installed {}", service.path.display());
+
+    if sub.is_present("enable") {
+        for command in &service.enable {
+            run(command)?;
+        }
+        println!("Enabled and started the service");
+    } else {
+        println!("Start it now with: {}", service.enable.last().map(|c| c.join(" ")).unwrap_or_default());
+    }
+    Ok(0)
+}
+
+fn run(command: &[String]) -> Result<()> {
+    let status = Command::new(&command[0]).args(&command[1..]).status()
+        .map_err(|e| RedshiftError::io(&command[0], e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(RedshiftError::MalformedArgument(format!("'{}' failed ({})", command.join(" "), status)))
+    }
+}
+
+fn no_home() -> RedshiftError {
+    RedshiftError::MalformedArgument("$HOME is not set".to_owned())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn service(args: &[String]) -> Result<Service> {
+    let dir = paths::config_dir_of("systemd/user").ok_or_else(no_home)?;
+    let unit = format!("{}.service", NAME);
+    let systemctl = |args: &[&str]| -> Vec<String> {
+        ["systemctl", "--user"].iter().chain(args).map(|s| s.to_string()).collect()
+    };
+    Ok(Service {
+        path: dir.join(&unit),
+        contents: systemd_unit(args),
+        enable: vec![systemctl(&["daemon-reload"]), systemctl(&["enable", "--now", &unit])],
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn service(args: &[String]) -> Result<Service> {
+    let home = env::var_os("HOME").map(PathBuf::from).ok_or_else(no_home)?;
+    let path = home.join("Library/LaunchAgents").join(format!("{}.plist", LABEL));
+    let load = vec!["launchctl".to_owned(), "load".to_owned(), path.to_string_lossy().into_owned()];
+    Ok(Service {
+        contents: launchd_plist(args),
+        path: path,
+        enable: vec![load],
+    })
+}
+
+fn systemd_unit(args: &[String]) -> String {
+    let exec: Vec<String> = args.iter().map(|a| systemd_quote(a)).collect();
+    format!("[Unit]\n\
+             Description=Adjust the color temperature of the screen\n\
+             PartOf=graphical-session.target\n\
+             After=graphical-session.target\n\
+             \n\
+             [Service]\n\
+             Type=notify\n\
+             ExecStart={}\n\
+             WatchdogSec=30\n\
+             Restart=on-failure\n\
+             \n\
+             [Install]\n\
+             WantedBy=graphical-session.target\n", exec.join(" "))
+}
+
+/// An argument of `ExecStart=`, quoted if needed and with the
+/// specifiers and variables of systemd escaped
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if escaped.is_empty() || escaped.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '\'') {
+        format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        escaped
+    }
+}
+
+#[cfg_attr(not(any(test, target_os = "macos")), allow(dead_code))]
+fn launchd_plist(args: &[String]) -> String {
+    let arguments: Vec<String> = args.iter()
+        .map(|a| format!("        <string>{}</string>\n", xml_escape(a)))
+        .collect();
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n    \
+                 <key>Label</key>\n    \
+                 <string>{}</string>\n    \
+                 <key>ProgramArguments</key>\n    \
+                 <array>\n{}    </array>\n    \
+                 <key>RunAtLoad</key>\n    \
+                 <true/>\n    \
+                 <key>KeepAlive</key>\n    \
+                 <true/>\n\
+             </dict>\n\
+             </plist>\n", LABEL, arguments.concat())
+}
+
+#[cfg_attr(not(any(test, target_os = "macos")), allow(dead_code))]
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unit_files() {
+        let unit = systemd_unit(&["/usr/bin/redshift-rs".to_owned(), "-c".to_owned(),
+                                  "/home/me/my config%.conf".to_owned()]);
+        assert!(unit.contains("\nExecStart=/usr/bin/redshift-rs -c \"/home/me/my config%%.conf\"\n"));
+        assert!(unit.contains("\nType=notify\n"));
+        assert_eq!(systemd_quote("a\"b"), "\"a\\\"b\"");
+
+        let plist = launchd_plist(&["/opt/bin/redshift-rs".to_owned(), "-c".to_owned(), "a&b".to_owned()]);
+        assert!(plist.contains("        <string>a&amp;b</string>\n    </array>\n"));
+        assert!(plist.contains("    <key>Label</key>\n    <string>io.github.redshift-rs</string>\n"));
+    }
+}
@@ -0,0 +1,140 @@
+//! Real-time signals
+//!
+//! Key bindings of window managers run commands more easily than they
+//! make D-Bus calls. With a `[signals]` section in the config file,
+//! the real-time signals select temperatures, as in
+//! `pkill -RTMIN+1 redshift-rs`:
+//!
+//! ```text
+//! [signals]
+//! RTMIN+1=3000       ; hold 3000K
+//! RTMIN+2=reading    ; hold a preset
+//! RTMIN+3=reset      ; resume the schedule
+//! ```
+//!
+//! A temperature is held like one set through a control interface,
+//! until another one is or the schedule is resumed.
+//!
+//! The daemon blocks all real-time signals and waits for them in a
+//! thread of its own, so that one sent before it is bound, or after
+//! its binding was removed from the config file, is only logged
+//! rather than ending the daemon, which is what they do by default.
+
+use std::mem;
+use std::ptr;
+use std::thread;
+
+use chan;
+use libc;
+
+use config::Section;
+use preset::Presets;
+use super::{check_temperature, Result, RedshiftError};
+
+/// What a signal does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Hold a temperature
+    Temperature(i32),
+    /// Resume the schedule
+    Reset,
+}
+
+/// A real-time signal, by offset from SIGRTMIN, and what it does
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub offset: i32,
+    pub action: Action,
+}
+
+/// Highest offset from SIGRTMIN, that of SIGRTMAX
+fn max_offset() -> i32 {
+    libc::SIGRTMAX() - libc::SIGRTMIN()
+}
+
+/// Parse the name of a real-time signal, as in `RTMIN+1` or
+/// `SIGRTMIN`, into its offset from SIGRTMIN
+fn parse_signal(name: &str) -> Option<i32> {
+    let name = name.trim().to_uppercase();
+    let name = name.trim_start_matches("SIG");
+    if !name.starts_with("RTMIN") {
+        return None;
+    }
+    let offset = match name["RTMIN".len()..].trim() {
+        "" => 0,
+        n if n.starts_with('+') => n[1..].trim().parse().ok()?,
+        _ => return None,
+    };
+    if offset <= max_offset() { Some(offset) } else { None }
+}
+
+/// The bindings of the `[signals]` section
+pub fn from_config(section: &Section, presets: &Presets) -> Result<Vec<Binding>> {
+    section.entries.iter().map(|e| {
+        let (name, value) = (&e.0, &e.1);
+        let offset = parse_signal(name).ok_or_else(|| RedshiftError::MalformedConfig(
+            format!("[{}] {}: not a real-time signal (RTMIN to RTMIN+{})", section.name, name, max_offset())))?;
+        let action = match value.trim() {
+            "reset" => Action::Reset,
+            temp => Action::Temperature(check_temperature(presets.resolve(temp)?)?),
+        };
+        Ok(Binding { offset: offset, action: action })
+    }).collect()
+}
+
+/// Block the real-time signals and receive them on a channel instead,
+/// by offset from SIGRTMIN
+///
+/// Like `chan_signal::notify`, this must be called before any other
+/// threads are spawned.
+pub fn notify() -> chan::Receiver<i32> {
+    let (tx, rx) = chan::async();
+    let rtmin = libc::SIGRTMIN();
+    // Safety: the set is initialized by sigemptyset before use
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for sig in rtmin..libc::SIGRTMAX() + 1 {
+            libc::sigaddset(&mut set, sig);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        set
+    };
+    thread::spawn(move || loop {
+        let mut sig = 0;
+        if unsafe { libc::sigwait(&set, &mut sig) } == 0 {
+            tx.send(sig - rtmin);
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use config::Config;
+
+    #[test]
+    fn signal_names() {
+        assert_eq!(parse_signal("RTMIN"), Some(0));
+        assert_eq!(parse_signal("SIGRTMIN+2"), Some(2));
+        assert_eq!(parse_signal("rtmin+1"), Some(1));
+        assert_eq!(parse_signal("RTMIN+1000"), None);
+        assert_eq!(parse_signal("USR1"), None);
+        assert_eq!(parse_signal("RTMIN-1"), None);
+    }
+
+    #[test]
+    fn bindings() {
+        let config = "[signals]\nRTMIN+1=3000\nRTMIN+2=candle\nRTMIN+3=reset\n".parse::<Config>().unwrap();
+        let bindings = from_config(config.section("signals").unwrap(), &Presets::builtin()).unwrap();
+        assert_eq!(bindings, vec![
+            Binding { offset: 1, action: Action::Temperature(3000) },
+            Binding { offset: 2, action: Action::Temperature(1900) },
+            Binding { offset: 3, action: Action::Reset },
+        ]);
+
+        let config = "[signals]\nUSR2=3000\n".parse::<Config>().unwrap();
+        assert!(from_config(config.section("signals").unwrap(), &Presets::builtin()).is_err());
+    }
+}
@@ -188,6 +188,52 @@ pub fn elevation(t: f64, loc: &location::Location) -> f64 {
     elevation_from_time(jd, loc).to_degrees()
 }
 
+/// Time between the entries of a `Table`, in seconds
+const TABLE_STEP_S: f64 = 60.0;
+
+/// The solar elevations of a stretch of time at one location, computed
+/// at once and then only looked up
+///
+/// Between the entries, a minute apart, the elevation is interpolated,
+/// which is off by well under a hundredth of a degree.
+#[derive(Debug, Clone)]
+pub struct Table {
+    location: location::Location,
+    start: f64,
+    elevations: Vec<f64>,
+}
+
+impl Table {
+    /// The elevations from `start` for `span` seconds
+    pub fn new(loc: &location::Location, start: f64, span: f64) -> Table {
+        let steps = (span / TABLE_STEP_S).ceil() as usize + 1;
+        Table {
+            location: *loc,
+            start: start,
+            elevations: (0..steps).map(|i| elevation(start + i as f64 * TABLE_STEP_S, loc)).collect(),
+        }
+    }
+
+    /// Whether the elevation at the time and location can be looked up
+    pub fn covers(&self, loc: &location::Location, t: f64) -> bool {
+        *loc == self.location && t >= self.start &&
+            t <= self.start + (self.elevations.len() - 1) as f64 * TABLE_STEP_S
+    }
+
+    /// The elevation at the time, computed if the table doesn't cover it
+    pub fn elevation(&self, t: f64) -> f64 {
+        if !self.covers(&self.location, t) {
+            return elevation(t, &self.location);
+        }
+        let i = (t - self.start) / TABLE_STEP_S;
+        let (before, alpha) = (i.floor() as usize, i.fract());
+        match self.elevations.get(before + 1) {
+            Some(after) => self.elevations[before] + (after - self.elevations[before]) * alpha,
+            None => self.elevations[before],
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -218,4 +264,17 @@ mod test {
         println!("eq_time(10000)={:?}", jd2k.to_julian_cent().equation_of_time());
         elevation(1000.0, &location::Location::new(0.0, 0.0));
     }
+
+    #[test]
+    fn table() {
+        let loc = location::Location::new(55.7, 12.6);
+        let start = 1483228800.0;
+        let table = Table::new(&loc, start, 86400.0);
+        assert!(table.covers(&loc, start + 86400.0));
+        assert!(!table.covers(&loc, start + 86401.0));
+        assert!(!table.covers(&location::Location::new(0.0, 0.0), start));
+        for &t in &[start, start + 30.0, start + 12345.6, start + 86400.0, start - 100.0] {
+            assert!((table.elevation(t) - elevation(t, &loc)).abs() < 0.01);
+        }
+    }
 }
@@ -0,0 +1,134 @@
+//! Persistent daemon state
+//!
+//! Choices made at runtime, through SIGUSR1 or a control interface,
+//! survive a restart of the daemon: a display that was disabled stays
+//! disabled, and a held temperature stays held. They are kept for each
+//! display, in `$XDG_STATE_HOME/redshift-rs/DISPLAY/state` as
+//! `key=value` lines, see `instance::state_dir`:
+//!
+//! ```text
+//! enabled=0
+//! temperature=4000
+//! night-vision=1
+//! ```
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use paths;
+use super::{Result, RedshiftError};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct State {
+    pub enabled: bool,
+
+    /// Temperature held instead of following the schedule
+    pub manual: Option<i32>,
+
+    /// Red only, instead of following the schedule
+    pub night_vision: bool,
+}
+
+impl Default for State {
+    fn default() -> State {
+        State {
+            enabled: true,
+            manual: None,
+            night_vision: false,
+        }
+    }
+}
+
+const FILE: &'static str = "state";
+
+impl State {
+    /// The state stored by the last run on the displays of `dir`, or
+    /// the default state
+    ///
+    /// Before the state was kept for each display it was shared by
+    /// all, in the parent directory, which is read while a display
+    /// has none of its own.
+    pub fn load(dir: Option<&Path>) -> State {
+        let path = match dir {
+            Some(dir) if dir.join(FILE).exists() => dir.join(FILE),
+            Some(_) => match paths::state_dir() {
+                Some(shared) => shared.join(FILE),
+                None => return State::default(),
+            },
+            None => return State::default(),
+        };
+        let mut contents = String::new();
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_) => contents.parse().unwrap_or_else(|e| {
+                warn!("Ignoring malformed state in {}: {}", path.display(), e);
+                State::default()
+            }),
+            Err(_) => State::default(),
+        }
+    }
+
+    /// Store the state for the displays of `dir`
+    pub fn store(&self, dir: Option<&Path>) -> Result<()> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let path = dir.join(FILE);
+        let write = || -> ::std::io::Result<()> {
+            paths::ensure_dir(dir)?;
+            File::create(&path)?.write_all(self.contents().as_bytes())
+        };
+        write().map_err(|e| RedshiftError::io(path.display(), e))
+    }
+
+    fn contents(&self) -> String {
+        let mut s = format!("enabled={}\n", self.enabled as i32);
+        if let Some(t) = self.manual {
+            s.push_str(&format!("temperature={}\n", t));
+        }
+        if self.night_vision {
+            s.push_str("night-vision=1\n");
+        }
+        s
+    }
+}
+
+impl FromStr for State {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<State> {
+        let malformed = |line: &str| RedshiftError::MalformedConfig(
+            format!("state: {}", line));
+        let mut state = State::default();
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("enabled"), Some(v)) =>
+                    state.enabled = v.parse::<i32>().map_err(|_| malformed(line))? != 0,
+                (Some("temperature"), Some(v)) =>
+                    state.manual = Some(v.parse().map_err(|_| malformed(line))?),
+                (Some("night-vision"), Some(v)) =>
+                    state.night_vision = v.parse::<i32>().map_err(|_| malformed(line))? != 0,
+                _ => return Err(malformed(line)),
+            }
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::State;
+
+    #[test]
+    fn parse_state() {
+        let state = State { enabled: false, manual: Some(4000), night_vision: true };
+        assert_eq!(state.contents(), "enabled=0\ntemperature=4000\nnight-vision=1\n");
+        assert_eq!(state.contents().parse::<State>().unwrap(), state);
+        assert_eq!("".parse::<State>().unwrap(), State::default());
+        assert!("enabled=yes".parse::<State>().is_err());
+        assert!("brightness=1".parse::<State>().is_err());
+    }
+}
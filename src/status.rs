@@ -0,0 +1,299 @@
+//! Status reports
+//!
+//! The parameters computed for the current time, in either the
+//! human-readable format of print mode or as a JSON object for
+//! scripts and status bars. With `--status-stream` the daemon prints
+//! a line on every change in the format of Waybar's custom modules
+//! (`text`, `tooltip`, `class` and `alt`), which i3status-rust and
+//! similar bars understand too:
+//!
+//! ```text
+//! "custom/redshift": {
+//!     "exec": "redshift-rs --status-stream",
+//!     "return-type": "json"
+//! }
+//! ```
+//!
+//! With `-v`, the daemon also prints the lines C Redshift prints in
+//! verbose mode whenever they change (see `to_classic`), which is what
+//! redshift-gtk and the tray applets built like it read from the
+//! process they start. Together with SIGUSR1 toggling, redshift-rs can
+//! stand in for the `redshift` binary they run.
+//!
+//! The JSON schema is considered stable; new fields may be added but
+//! existing ones keep their name and meaning:
+//!
+//! ```text
+//! {
+//!   "period": "day" | "night" | "transition" | "none",
+//!   "day_fraction": number,         // 1.0 at day, 0.0 at night
+//!   "temperature": number,          // Kelvin
+//!   "brightness": number,
+//!   "gamma": [number, number, number],
+//!   "elevation": number,            // degrees
+//!   "location": { "lat": number, "lon": number },
+//!   "outputs": [string],            // adjusted outputs, empty if none
+//!   "enabled": boolean,             // false while toggled off
+//!   "night_vision": boolean         // red only, overriding the schedule
+//! }
+//! ```
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use i18n;
+use location::Location;
+use transition::{ColorSetting, Period};
+use super::{Result, RedshiftError};
+
+/// Output format of status reports
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(RedshiftError::MalformedArgument(
+                format!("format: {} (expected text or json)", s))),
+        }
+    }
+}
+
+/// The name of the period in the text format, translated as in C
+/// Redshift, which calls the day "Daytime"
+fn period_text(period: &Period) -> &'static str {
+    match *period {
+        Period::None => i18n::tr("None"),
+        Period::Day => i18n::translate("Daytime").unwrap_or("Day"),
+        Period::Night => i18n::tr("Night"),
+        Period::Transition(_) => i18n::tr("Transition"),
+    }
+}
+
+/// The line of C Redshift giving the location, translated
+pub fn location_text(location: &Location) -> String {
+    let (lat, lon) = (location.lat, location.lon);
+    i18n::message("Location: %.2f %s, %.2f %s\n",
+                  &[&format!("{:.2}", lat.abs()), &i18n::tr(if lat >= 0.0 { "N" } else { "S" }),
+                    &format!("{:.2}", lon.abs()), &i18n::tr(if lon >= 0.0 { "E" } else { "W" })])
+}
+
+/// A snapshot of the computed parameters
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub period: Period,
+    pub elevation: f64,
+    pub setting: ColorSetting,
+    pub location: Location,
+    pub outputs: Vec<String>,
+    pub enabled: bool,
+    /// Showing red only, instead of following the schedule
+    pub night_vision: bool,
+}
+
+impl Status {
+    pub fn format(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.to_text(),
+            Format::Json => self.to_json(),
+        }
+    }
+
+    /// The human-readable format, one parameter per line
+    ///
+    /// The lines C Redshift has too are translated, see `i18n`.
+    pub fn to_text(&self) -> String {
+        let mut s = String::new();
+        s.push_str(&i18n::printf(i18n::tr("Solar elevation: %f\n"), &[&self.elevation]));
+        s.push_str(&match self.period {
+            Period::Transition(t) => i18n::printf(i18n::tr("Period: %s (%.2f%% day)\n"),
+                                                  &[&period_text(&self.period), &format!("{:.2}", t * 100.0)]),
+            ref period => i18n::printf(i18n::tr("Period: %s\n"), &[&period_text(period)]),
+        });
+        s.push_str(&i18n::printf(i18n::tr("Color temperature: %uK\n"), &[&self.setting.temp]));
+        s.push_str(i18n::printf(i18n::tr("Brightness: %.2f\n"),
+                                &[&format!("{:.2}", self.setting.brightness)]).trim_end_matches('\n'));
+        if (self.setting.contrast - 1.0).abs() > 1e-6 {
+            let _ = write!(s, "\nContrast: {:.2}", self.setting.contrast);
+        }
+        if self.setting.invert {
+            let _ = write!(s, "\nColors: Inverted");
+        }
+        if self.night_vision {
+            let _ = write!(s, "\nMode: Night vision");
+        }
+        if !self.enabled {
+            s.push('\n');
+            s.push_str(i18n::printf(i18n::tr("Status: %s\n"), &[&i18n::tr("Disabled")]).trim_end_matches('\n'));
+        }
+        if !self.outputs.is_empty() {
+            let _ = write!(s, "\nOutputs: {}", self.outputs.join(", "));
+        }
+        s
+    }
+
+    /// `Key: value` lines as printed by C Redshift in verbose mode,
+    /// by redshift-gtk parsed into its status, temperature, period and
+    /// location
+    pub fn to_classic(&self) -> Vec<String> {
+        vec![
+            format!("Status: {}", if self.enabled { "Enabled" } else { "Disabled" }),
+            format!("Period: {}", self.period),
+            format!("Color temperature: {}K", self.setting.temp),
+            format!("Brightness: {:.2}", self.setting.brightness),
+            format!("Location: {}", self.location),
+        ]
+    }
+
+    /// A single-line JSON object
+    pub fn to_json(&self) -> String {
+        let day_fraction = match self.period {
+            Period::None => ::std::f64::NAN,
+            Period::Day => 1.0,
+            Period::Night => 0.0,
+            Period::Transition(t) => t,
+        };
+        let outputs: Vec<String> = self.outputs.iter().map(|o| json_string(o)).collect();
+        format!("{{\"period\":{},\"day_fraction\":{},\"temperature\":{},\"brightness\":{},\
+                 \"gamma\":[{},{},{}],\"elevation\":{},\"location\":{{\"lat\":{},\"lon\":{}}},\
+                 \"outputs\":[{}],\"enabled\":{},\"night_vision\":{}}}",
+                json_string(self.period.name()), json_number(day_fraction),
+                self.setting.temp, json_number(self.setting.brightness),
+                json_number(self.setting.gamma[0]), json_number(self.setting.gamma[1]),
+                json_number(self.setting.gamma[2]), json_number(self.elevation),
+                json_number(self.location.lat), json_number(self.location.lon),
+                outputs.join(","), self.enabled, self.night_vision)
+    }
+
+    /// `key=value` lines, as written to the status file
+    pub fn to_key_value(&self) -> String {
+        format!("period={}\ntemperature={}\nbrightness={:.2}\nenabled={}\n",
+                self.period.name(), self.setting.temp, self.setting.brightness,
+                self.enabled as i32)
+    }
+
+    /// A single-line JSON object for a custom module of Waybar, with
+    /// the period (or "disabled" or "night-vision") as CSS class
+    pub fn to_waybar(&self) -> String {
+        let class = if self.night_vision {
+            "night-vision"
+        } else if self.enabled {
+            self.period.name()
+        } else {
+            "disabled"
+        };
+        let tooltip = format!("Period: {}\nColor temperature: {}K\nBrightness: {:.2}",
+                              self.period, self.setting.temp, self.setting.brightness);
+        format!("{{\"text\":{},\"tooltip\":{},\"class\":{},\"alt\":{}}}",
+                json_string(&format!("{}K", self.setting.temp)), json_string(&tooltip),
+                json_string(class), json_string(class))
+    }
+}
+
+/// Quote and escape a string as a JSON string literal
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Format a number for JSON, which has no representation of NaN or
+/// infinity
+pub fn json_number(n: f64) -> String {
+    if n.is_finite() { format!("{}", n) } else { "null".to_owned() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_schema() {
+        let status = Status {
+            period: Period::Transition(0.25),
+            elevation: -2.5,
+            setting: ColorSetting { temp: 4500, brightness: 0.9, ..ColorSetting::neutral() },
+            location: Location::new(55.7, 12.6),
+            outputs: vec!["crtc-63".to_owned()],
+            enabled: true,
+            night_vision: false,
+        };
+        assert_eq!(status.to_json(),
+                   "{\"period\":\"transition\",\"day_fraction\":0.25,\"temperature\":4500,\
+                    \"brightness\":0.9,\"gamma\":[1,1,1],\"elevation\":-2.5,\
+                    \"location\":{\"lat\":55.7,\"lon\":12.6},\"outputs\":[\"crtc-63\"],\"enabled\":true,\"night_vision\":false}");
+    }
+
+    #[test]
+    fn key_value() {
+        let status = Status {
+            period: Period::Night,
+            elevation: -20.0,
+            setting: ColorSetting { temp: 3500, brightness: 0.8, ..ColorSetting::neutral() },
+            location: Location::new(55.7, 12.6),
+            outputs: vec![],
+            enabled: false,
+            night_vision: false,
+        };
+        assert_eq!(status.to_key_value(),
+                   "period=night\ntemperature=3500\nbrightness=0.80\nenabled=0\n");
+    }
+
+    #[test]
+    fn waybar() {
+        let status = Status {
+            period: Period::Day,
+            elevation: 30.0,
+            setting: ColorSetting { temp: 6500, ..ColorSetting::neutral() },
+            location: Location::new(55.7, 12.6),
+            outputs: vec![],
+            enabled: false,
+            night_vision: false,
+        };
+        assert_eq!(status.to_waybar(),
+                   "{\"text\":\"6500K\",\"tooltip\":\"Period: Day\\nColor temperature: 6500K\\n\
+                    Brightness: 1.00\",\"class\":\"disabled\",\"alt\":\"disabled\"}");
+    }
+
+    #[test]
+    fn classic() {
+        let status = Status {
+            period: Period::Transition(0.25),
+            elevation: -2.5,
+            setting: ColorSetting { temp: 4500, brightness: 0.9, ..ColorSetting::neutral() },
+            location: Location::new(55.7, -12.6),
+            outputs: vec![],
+            enabled: true,
+            night_vision: false,
+        };
+        assert_eq!(status.to_classic(),
+                   ["Status: Enabled", "Period: Transition (25.00% day)",
+                    "Color temperature: 4500K", "Brightness: 0.90",
+                    "Location: 55.70 N, 12.60 W"]);
+    }
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\n\"");
+        assert_eq!(json_number(::std::f64::NAN), "null");
+    }
+}
@@ -0,0 +1,106 @@
+//! Sweeping through temperatures
+//!
+//! `redshift-rs preview FROM..TO` applies the temperatures from one to
+//! the other in steps, each for a moment, and then restores the
+//! display, to pick a night temperature by eye:
+//!
+//! ```text
+//! $ redshift-rs preview 6500..3000 --step 250 --dwell 2s
+//! 6500K
+//! 6250K
+//! ...
+//! 3000K
+//! ```
+//!
+//! The temperatures are in Kelvin, mireds or preset names, and the
+//! steps 250K apart for 2 seconds by default. The method is selected
+//! as with `-m`, `-d` and `--wayland-display` before the subcommand.
+//! Interrupting the sweep restores the display too.
+
+use chan;
+use chan_signal::{self, Signal};
+use clap::ArgMatches;
+
+use config::Config;
+use gamma;
+use preset::Presets;
+use transition::ColorSetting;
+use super::{check_temperature, parse_interval, Result, RedshiftError};
+
+/// Kelvin between the temperatures without `--step`
+const DEFAULT_STEP: i32 = 250;
+
+/// Time each temperature is shown without `--dwell`, and the range
+/// allowed, in seconds
+const DEFAULT_DWELL_S: f64 = 2.0;
+const DWELL_RANGE_S: (f64, f64) = (0.1, 60.0);
+
+pub fn run(matches: &ArgMatches, sub: &ArgMatches) -> Result<i32> {
+    // Before any threads are spawned, like in the daemon
+    let signals = chan_signal::notify(&[Signal::INT, Signal::TERM]);
+
+    let mut presets = Presets::builtin();
+    if let Some(section) = Config::load(matches.value_of("config"))?.section("presets") {
+        presets.extend(section)?;
+    }
+    let range = sub.value_of("range").unwrap();
+    let (from, to) = match range.find("..") {
+        Some(i) => (&range[..i], &range[i + 2..]),
+        None => return Err(RedshiftError::MalformedArgument(
+            format!("temperature range: {} (expected FROM..TO)", range))),
+    };
+    let (from, to) = (check_temperature(presets.resolve(from.trim())?)?,
+                      check_temperature(presets.resolve(to.trim())?)?);
+    let step = match sub.value_of("step") {
+        Some(s) => s.trim().trim_end_matches('K').parse::<i32>().ok().filter(|s| *s > 0)
+            .ok_or_else(|| RedshiftError::MalformedArgument(format!("step: {}", s)))?,
+        None => DEFAULT_STEP,
+    };
+    let dwell = sub.value_of("dwell")
+        .map_or(Ok(DEFAULT_DWELL_S), |d| parse_interval("dwell", d, DWELL_RANGE_S))?;
+
+    let options = gamma::Options {
+        display: matches.value_of("display").map(ToOwned::to_owned),
+        wayland_display: matches.value_of("wayland-display").map(ToOwned::to_owned),
+        ..gamma::Options::default()
+    };
+    let method = matches.value_of("method").filter(|m| *m != "list");
+    let mut method = gamma::RestoreGuard::new(gamma::init_gamma_method(method, &options)?);
+    method.start()?;
+
+    for temp in temperatures(from, to, step) {
+        method.set_temperature(&ColorSetting { temp: temp, ..ColorSetting::neutral() })?;
+        println!("{}K", temp);
+        let timer = chan::after_ms((dwell * 1000.0) as u32);
+        chan_select! {
+            signals.recv() => {
+                println!("Interrupted, restoring");
+                break;
+            },
+            timer.recv() => {},
+        }
+    }
+    method.restore()?;
+    Ok(0)
+}
+
+/// The temperatures from `from` to `to`, `step` apart, ending with `to`
+fn temperatures(from: i32, to: i32, step: i32) -> Vec<i32> {
+    let mut temps: Vec<i32> = (0..).map(|i| if from <= to { from + i * step } else { from - i * step })
+        .take_while(|t| if from <= to { *t < to } else { *t > to })
+        .collect();
+    temps.push(to);
+    temps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        assert_eq!(temperatures(6500, 5500, 250), [6500, 6250, 6000, 5750, 5500]);
+        assert_eq!(temperatures(3000, 3500, 200), [3000, 3200, 3400, 3500]);
+        assert_eq!(temperatures(4000, 4000, 100), [4000]);
+    }
+}
@@ -0,0 +1,74 @@
+//! systemd service notifications
+//!
+//! Implements the sd_notify(3) protocol without linking libsystemd,
+//! so that redshift-rs can run as a service of `Type=notify`, with
+//! `WatchdogSec=` to have it restarted if the main loop hangs:
+//!
+//! ```text
+//! [Service]
+//! Type=notify
+//! ExecStart=/usr/bin/redshift-rs
+//! WatchdogSec=30
+//! ```
+//!
+//! Outside of such a service `$NOTIFY_SOCKET` is not set and the
+//! notifications are no-ops.
+
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::time::Duration;
+
+/// Send a state change, such as "READY=1", to the service manager
+///
+/// Failures are only logged, a service manager that can't be reached
+/// is no reason to stop adjusting the display.
+pub fn notify(state: &str) {
+    let socket = match env::var_os("NOTIFY_SOCKET") {
+        Some(s) => s,
+        None => return,
+    };
+    let socket = socket.to_string_lossy();
+    if let Err(e) = send(&socket, state) {
+        debug!("Could not notify service manager at {}: {}", socket, e);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let sock = UnixDatagram::unbound()?;
+    // Names starting with '@' are in the abstract namespace
+    let addr = if socket.starts_with('@') {
+        SocketAddr::from_abstract_name(&socket.as_bytes()[1..])?
+    } else {
+        SocketAddr::from_pathname(socket)?
+    };
+    sock.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    UnixDatagram::unbound()?.send_to(state.as_bytes(), socket).map(|_| ())
+}
+
+/// How often the watchdog must be pinged with "WATCHDOG=1", if the
+/// service manager has enabled it for this process
+///
+/// This is half of the configured timeout, as recommended by
+/// sd_watchdog_enabled(3).
+pub fn watchdog_interval() -> Option<Duration> {
+    let pid_matches = env::var("WATCHDOG_PID")
+        .map(|p| p.parse() == Ok(process::id()))
+        .unwrap_or(true);
+    if !pid_matches {
+        return None;
+    }
+    env::var("WATCHDOG_USEC").ok()
+        .and_then(|u| u.parse::<u64>().ok())
+        .filter(|&u| u > 0)
+        .map(|u| Duration::from_micros(u / 2))
+}
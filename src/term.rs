@@ -0,0 +1,111 @@
+//! Terminal output
+//!
+//! When standard output is a terminal, print mode (`-p`) lines up the
+//! fields of the status, adds the unit of the solar elevation and
+//! shows the color of the temperature as a swatch, and `--follow` puts
+//! a swatch before the temperature too. Log records on a terminal get
+//! their level colored. Piped output stays plain, as does all output
+//! with `$NO_COLOR` set or `TERM=dumb`.
+//!
+//! The swatch is the whitepoint of the temperature as a 24-bit
+//! background color, which is about what a white area of the screen
+//! looks like at that temperature.
+
+use std::env;
+
+use libc;
+
+use colorramp;
+use status::Status;
+
+const RESET: &'static str = "\x1b[0m";
+const BOLD: &'static str = "\x1b[1m";
+
+pub const STDOUT: i32 = libc::STDOUT_FILENO;
+pub const STDERR: i32 = libc::STDERR_FILENO;
+
+/// Whether to color what is written to the file descriptor
+pub fn colors(fd: i32) -> bool {
+    env::var_os("NO_COLOR").is_none()
+        && env::var("TERM").map(|t| t != "dumb").unwrap_or(true)
+        && unsafe { libc::isatty(fd) } == 1
+}
+
+/// A block with the color of the temperature
+pub fn swatch(temp: i32) -> String {
+    let white = colorramp::white_point(temp);
+    let c: Vec<u8> = white.iter().map(|w| (w.clamp(0.0, 1.0) * 255.0).round() as u8).collect();
+    format!("\x1b[48;2;{};{};{}m  {}", c[0], c[1], c[2], RESET)
+}
+
+/// The text format of the status, aligned and colored
+///
+/// Each line of `to_text` is split into its label and value, so that
+/// a translated status is laid out the same way.
+pub fn status(status: &Status) -> String {
+    let text = status.to_text();
+    let fields: Vec<(&str, &str)> = text.lines()
+        .map(|line| match line.find(": ") {
+            Some(i) => (&line[..i + 1], &line[i + 2..]),
+            None => ("", line),
+        })
+        .collect();
+    let width = fields.iter().map(|f| f.0.chars().count()).max().unwrap_or(0);
+    let temp = format!("{}K", status.setting.temp);
+    let elevation = status.elevation.to_string();
+    fields.iter()
+        .map(|&(label, value)| {
+            let value = if value == temp {
+                format!("{} {} K", swatch(status.setting.temp), status.setting.temp)
+            } else if value == elevation {
+                format!("{:.2}°", status.elevation)
+            } else {
+                value.to_owned()
+            };
+            let pad = width - label.chars().count();
+            format!("{}{}{}{:pad$} {}", BOLD, label, RESET, "", value, pad = pad)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The escape coloring a log level, from "ERROR" to "TRACE"
+pub fn level_color(level: &str) -> &'static str {
+    match level {
+        "ERROR" => "\x1b[1;31m",
+        "WARN" => "\x1b[33m",
+        "INFO" => "\x1b[32m",
+        _ => "\x1b[2m",
+    }
+}
+
+/// `text` in the color, reset afterwards
+pub fn paint(color: &str, text: &str) -> String {
+    format!("{}{}{}", color, text, RESET)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use location::Location;
+    use transition::{ColorSetting, Period};
+
+    #[test]
+    fn aligned_status() {
+        let night = Status {
+            period: Period::Night,
+            elevation: -20.5,
+            setting: ColorSetting { temp: 3500, ..ColorSetting::neutral() },
+            location: Location { lat: 55.7, lon: 12.6 },
+            outputs: vec![],
+            enabled: true,
+            night_vision: false,
+        };
+        let text = status(&night);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], format!("{}Solar elevation:{}   -20.50°", BOLD, RESET));
+        assert!(lines[2].starts_with(&format!("{}Color temperature:{} \x1b[48;2;255;", BOLD, RESET)));
+        assert!(lines[2].ends_with(" 3500 K"));
+        assert_eq!(lines[3], format!("{}Brightness:{}        1.00", BOLD, RESET));
+    }
+}
@@ -0,0 +1,186 @@
+//! Changes of the time zone of the system
+//!
+//! The zone is set by pointing `/etc/localtime` at another zone file.
+//! timedated, behind `timedatectl set-timezone` and the settings of
+//! desktops, announces each change with `PropertiesChanged` on the
+//! system bus, which builds with the `dbus` feature follow. Without it,
+//! or without a system bus, `/etc` is watched with inotify for the link
+//! being replaced, or `/etc/timezone` of Debian being written.
+//! Elsewhere than on Linux they are looked at every few seconds.
+
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::fs::File;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::io::Read;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::FromRawFd;
+use std::thread;
+
+use chan;
+#[cfg(target_os = "linux")]
+use libc;
+use redshift_rs::location;
+
+/// The directory of the link, and the names of the files giving the
+/// zone in it
+#[cfg(target_os = "linux")]
+const ETC: &'static str = "/etc";
+#[cfg(target_os = "linux")]
+const ZONE_FILES: [&'static [u8]; 2] = [b"localtime", b"timezone"];
+
+/// How often the link is looked at where it can't be watched, in
+/// milliseconds
+#[cfg(not(target_os = "linux"))]
+const POLL_MS: u64 = 10000;
+
+/// The zone last reported, and where to report the next one
+struct Zone {
+    zone: Option<String>,
+    tx: chan::Sender<String>,
+}
+
+impl Zone {
+    /// Look at the zone again, reporting it if it changed
+    fn check(&mut self) {
+        let zone = location::time_zone();
+        if zone != self.zone {
+            if let Some(ref z) = zone {
+                self.tx.send(z.clone());
+            }
+            self.zone = zone;
+        }
+    }
+}
+
+/// Follow the time zone
+///
+/// The channel receives the name of the zone, e.g. "Europe/Berlin",
+/// whenever it changes.
+pub fn watch() -> chan::Receiver<String> {
+    let (tx, rx) = chan::async();
+    let mut zone = Zone { zone: location::time_zone(), tx: tx };
+    thread::spawn(move || {
+        #[cfg(feature = "dbus")]
+        {
+            match timedated::follow(&mut zone) {
+                Ok(()) => return,
+                Err(e) => info!("Not following timedated ({}), watching /etc instead", e),
+            }
+        }
+        if let Err(e) = follow_link(&mut zone) {
+            warn!("Not following the time zone: {}", e);
+        }
+    });
+    rx
+}
+
+#[cfg(target_os = "linux")]
+fn follow_link(zone: &mut Zone) -> io::Result<()> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut inotify = unsafe { File::from_raw_fd(fd) };
+    // The directory, as the link is replaced rather than written to
+    let etc = CString::new(ETC).unwrap();
+    let mask = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_CLOSE_WRITE;
+    if unsafe { libc::inotify_add_watch(fd, etc.as_ptr(), mask) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match inotify.read(&mut buf) {
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if names(&buf[..n]).iter().any(|name| ZONE_FILES.contains(name)) {
+            zone.check();
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn follow_link(zone: &mut Zone) -> io::Result<()> {
+    loop {
+        thread::sleep(::std::time::Duration::from_millis(POLL_MS));
+        zone.check();
+    }
+}
+
+/// The names of the files in a buffer of inotify events
+#[cfg(target_os = "linux")]
+fn names(events: &[u8]) -> Vec<&[u8]> {
+    // wd, mask and cookie come before the length of the name
+    const HEADER: usize = 16;
+    let mut names = vec![];
+    let mut at = 0;
+    while at + HEADER <= events.len() {
+        let mut len = [0; 4];
+        len.copy_from_slice(&events[at + 12..at + HEADER]);
+        let end = (at + HEADER + u32::from_ne_bytes(len) as usize).min(events.len());
+        // Padded with zeros
+        let name = &events[at + HEADER..end];
+        names.push(&name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())]);
+        at = end;
+    }
+    names
+}
+
+#[cfg(feature = "dbus")]
+mod timedated {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use dbus::Message;
+    use dbus::blocking::Connection;
+    use dbus::blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged;
+    use dbus::message::SignalArgs;
+
+    use super::Zone;
+
+    const TIMEDATE1_PATH: &'static str = "/org/freedesktop/timedate1";
+    const TIMEDATE1: &'static str = "org.freedesktop.timedate1";
+
+    /// Follow the signals of timedated, which needn't be running until
+    /// the zone is changed
+    pub fn follow(zone: &mut Zone) -> Result<(), ::dbus::Error> {
+        let conn = Connection::new_system()?;
+        let rule = PropertiesPropertiesChanged::match_rule(None, Some(&TIMEDATE1_PATH.into())).static_clone();
+        let changed = Arc::new(AtomicBool::new(false));
+        let flag = changed.clone();
+        conn.add_match(rule, move |properties: PropertiesPropertiesChanged, _: &Connection, _: &Message| {
+            if properties.interface_name == TIMEDATE1 {
+                flag.store(true, Ordering::SeqCst);
+            }
+            true
+        })?;
+        loop {
+            conn.process(Duration::from_secs(3600))?;
+            if changed.swap(false, Ordering::SeqCst) {
+                zone.check();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn event_names() {
+        let mut events = vec![];
+        for name in &[&b"localtime\0\0\0\0\0\0\0"[..], &b""[..], &b"hosts\0\0\0"[..]] {
+            events.extend_from_slice(&[1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+            events.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+            events.extend_from_slice(name);
+        }
+        assert_eq!(names(&events), [&b"localtime"[..], &b""[..], &b"hosts"[..]]);
+        assert_eq!(names(&events[..10]), Vec::<&[u8]>::new());
+    }
+}
@@ -1,7 +1,14 @@
+use icc::Calibration;
+use location::Location;
 use solar;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+use std::sync::Arc;
+use super::{IDENTITY_MATRIX, NEUTRAL_TEMP, Result, RedshiftError};
 
 /* Periods of day */
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Period {
     None,
     Day,
@@ -10,26 +17,149 @@ pub enum Period {
 }
 
 impl Period {
-    pub fn print(&self) {
+    /// Lower-case name of the period, for machine-readable output
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Period::None => "none",
+            Period::Day => "day",
+            Period::Night => "night",
+            Period::Transition(_) => "transition",
+        }
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Period::None | Period::Day | Period::Night => {
-                println!("Period: {:?}", *self);
+                write!(f, "{:?}", *self)
             }
             Period::Transition(t) => {
-                println!("Period: {} ({:.*}% day)", "Transition", 2, t * 100.0);
+                write!(f, "{} ({:.*}% day)", "Transition", 2, t * 100.0)
+            }
+        }
+    }
+}
+
+/// How temperatures between two others are found, in transitions and
+/// fades
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Interpolation {
+    /// Evenly in Kelvin
+    Kelvin,
+    /// Evenly in mireds, a million divided by the Kelvin
+    ///
+    /// Equal steps in mireds look about equally large, while the same
+    /// number of Kelvin makes a much larger difference at warm
+    /// temperatures than at cool ones. A fade in Kelvin seems to hold
+    /// back and then rush through the warm end.
+    Mired,
+}
+
+impl Interpolation {
+    /// The temperature `alpha` of the way from `from` to `to`
+    pub fn mix(self, from: i32, to: i32, alpha: f64) -> i32 {
+        match self {
+            Interpolation::Kelvin => ((1.0-alpha) * from as f64 + alpha * to as f64) as i32,
+            Interpolation::Mired => {
+                let (from, to) = (1e6 / from as f64, 1e6 / to as f64);
+                (1e6 / ((1.0-alpha) * from + alpha * to)).round() as i32
+            }
+        }
+    }
+}
+
+impl FromStr for Interpolation {
+    type Err = RedshiftError;
+
+    fn from_str(s: &str) -> Result<Interpolation> {
+        match s {
+            "kelvin" => Ok(Interpolation::Kelvin),
+            "mired" => Ok(Interpolation::Mired),
+            _ => Err(RedshiftError::MalformedConfig(
+                format!("interpolation: {} (expected kelvin or mired)", s))),
+        }
+    }
+}
+
+/// A value given at some points, interpolated linearly in between
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve {
+    points: Vec<(f64, f64)>,
+}
+
+impl Curve {
+    /// Parse a list of `X:VALUE` points, separated by commas, in
+    /// increasing order of X
+    pub fn parse(input: &str) -> Result<Curve> {
+        let malformed = |msg: String| RedshiftError::MalformedConfig(msg);
+        let mut points: Vec<(f64, f64)> = vec![];
+        for point in input.split(',').map(str::trim) {
+            let mut parts = point.splitn(2, ':');
+            let (x, value) = match (parts.next().map(str::parse::<f64>), parts.next().map(str::parse::<f64>)) {
+                (Some(Ok(x)), Some(Ok(v))) if x.is_finite() && v.is_finite() => (x, v),
+                _ => return Err(malformed(format!("curve point: {} (of {})", point, input))),
+            };
+            if points.last().map_or(false, |p| p.0 >= x) {
+                return Err(malformed(format!("curve points out of order: {} (of {})", point, input)));
             }
+            points.push((x, value));
         }
+        Ok(Curve { points: points })
+    }
+
+    /// The points, in increasing order of X
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// The value at `x`
+    pub fn at(&self, x: f64) -> f64 {
+        let first = self.points[0];
+        if x <= first.0 {
+            return first.1;
+        }
+        for pair in self.points.windows(2) {
+            let ((x0, v0), (x1, v1)) = (pair[0], pair[1]);
+            if x <= x1 {
+                return v0 + (v1 - v0) * (x - x0) / (x1 - x0);
+            }
+        }
+        self.points[self.points.len() - 1].1
     }
 }
 
 /// A color setting
 ///
-///
-#[derive(PartialEq, Debug)]
+/// Everything in it is applied by `colorramp::fill`, so that every
+/// gamma method supports all of it.
+#[derive(PartialEq, Debug, Clone)]
 pub struct ColorSetting {
+    /// Color temperature in Kelvin
     pub temp: i32,
     pub gamma: [f64; 3],
+    /// Factor scaling all channels, 1.0 leaving them as they are
     pub brightness: f64,
+    /// Invert the colors, before shifting and dimming them, so that
+    /// light backgrounds become dark
+    pub invert: bool,
+    /// Factor scaling the distance from mid-gray, below 1.0 reducing
+    /// the contrast
+    pub contrast: f64,
+    /// Matrix mapping the red, green and blue of each pixel, applied
+    /// last
+    ///
+    /// Ramps only apply the diagonal, the factor of each channel on
+    /// itself; see `colorramp`.
+    pub matrix: [[f64; 3]; 3],
+    /// Matrix mixing the red, green and blue of each pixel, applied
+    /// first, for grayscale and the filters of `vision`
+    ///
+    /// Ramps can't apply any of it; see `colorramp`.
+    pub filter: [[f64; 3]; 3],
+    /// Calibration curves of the display, applied after everything
+    /// else; see `icc`
+    pub calibration: Option<Arc<Calibration>>,
 }
 
 impl ColorSetting {
@@ -39,9 +169,85 @@ impl ColorSetting {
             gamma: [::std::f64::NAN,
                     ::std::f64::NAN,
                     ::std::f64::NAN],
-            brightness: ::std::f64::NAN
+            brightness: ::std::f64::NAN,
+            invert: false,
+            contrast: ::std::f64::NAN,
+            matrix: [[::std::f64::NAN; 3]; 3],
+            filter: [[::std::f64::NAN; 3]; 3],
+            calibration: None,
+        }
+    }
+
+    /// Red only, at the given brightness
+    ///
+    /// Dim red light keeps the eyes adapted to the dark, for
+    /// astronomers and pilots reading charts at night. The whitepoint
+    /// leaves red at full intensity, so the brightness alone sets it.
+    pub fn night_vision(brightness: f64) -> ColorSetting {
+        ColorSetting {
+            brightness: brightness,
+            matrix: [[1.0, 0.0, 0.0],
+                     [0.0, 0.0, 0.0],
+                     [0.0, 0.0, 0.0]],
+            ..ColorSetting::neutral()
+        }
+    }
+
+    /// The setting that leaves the colors unchanged
+    pub fn neutral() -> ColorSetting {
+        ColorSetting {
+            temp: NEUTRAL_TEMP,
+            gamma: [1.0, 1.0, 1.0],
+            brightness: 1.0,
+            invert: false,
+            contrast: 1.0,
+            matrix: IDENTITY_MATRIX,
+            filter: IDENTITY_MATRIX,
+            calibration: None,
+        }
+    }
+}
+
+impl ColorSetting {
+    /// The setting `alpha` of the way from this one to `to`
+    pub fn blend(&self, to: &ColorSetting, alpha: f64, interpolation: Interpolation) -> ColorSetting {
+        let mix = |a: f64, b: f64| (1.0-alpha) * a + alpha * b;
+        ColorSetting {
+            temp: interpolation.mix(self.temp, to.temp, alpha),
+            gamma: [mix(self.gamma[0], to.gamma[0]),
+                    mix(self.gamma[1], to.gamma[1]),
+                    mix(self.gamma[2], to.gamma[2])],
+            brightness: mix(self.brightness, to.brightness),
+            // Switching halfway, like a transition
+            invert: if alpha < 0.5 { self.invert } else { to.invert },
+            contrast: mix(self.contrast, to.contrast),
+            matrix: blend_matrices(&self.matrix, &to.matrix, alpha),
+            filter: blend_matrices(&self.filter, &to.filter, alpha),
+            calibration: if alpha < 0.5 { self.calibration.clone() } else { to.calibration.clone() },
+        }
+    }
+}
+
+/// The matrix `alpha` of the way from `from` to `to`, entry by entry
+pub fn blend_matrices(from: &[[f64; 3]; 3], to: &[[f64; 3]; 3], alpha: f64) -> [[f64; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            m[i][j] = (1.0-alpha) * from[i][j] + alpha * to[i][j];
         }
     }
+    m
+}
+
+/// The product `a` times `b`, which applies `b` first
+pub fn multiply_matrices(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for i in 0..3 {
+        for j in 0..3 {
+            m[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
 }
 
 /**
@@ -58,7 +264,14 @@ pub struct TransitionScheme {
     /* Used for initial and final gradual transition from/to 6500K */
     pub short_trans_delta: i16,
     pub short_trans_len: u16,
-    pub adjustment_alpha: f64
+    pub adjustment_alpha: f64,
+    /// Seconds between the steps of a short transition, which take
+    /// `short_trans_len` seconds in all
+    pub fade_interval: f64,
+    pub interpolation: Interpolation,
+    /// Brightness by solar elevation, instead of blending between that
+    /// of day and night
+    pub brightness_curve: Option<Curve>,
 }
 
 impl TransitionScheme {
@@ -71,7 +284,10 @@ impl TransitionScheme {
 
             short_trans_delta: -1,
             short_trans_len: 10,
-            adjustment_alpha: 1.0
+            adjustment_alpha: 1.0,
+            fade_interval: 0.1,
+            interpolation: Interpolation::Kelvin,
+            brightness_curve: None,
         }
     }
 
@@ -81,21 +297,45 @@ impl TransitionScheme {
     pub fn interpolate_color_settings(&self, elevation: f64) -> ColorSetting {
         let day = &self.day;
         let night = &self.night;
-
-        let al = (self.low - elevation) / (self.low - self.high);
-        let alpha = al.min(1.0).max(0.0); // clamp to [0.0, 1.0]
+        let alpha = self.day_alpha(elevation);
 
         ColorSetting {
-            temp: ((1.0-alpha) * night.temp as f64 + alpha * day.temp as f64) as i32,
-            brightness: (1.0-alpha) * night.brightness + alpha * day.brightness,
+            temp: self.interpolation.mix(night.temp, day.temp, alpha),
+            brightness: match self.brightness_curve {
+                Some(ref curve) => curve.at(elevation),
+                None => (1.0-alpha) * night.brightness + alpha * day.brightness,
+            },
             gamma: [
                 (1.0-alpha) * night.gamma[0] + alpha*day.gamma[0],
                 (1.0-alpha) * night.gamma[1] + alpha*day.gamma[1],
                 (1.0-alpha) * night.gamma[2] + alpha*day.gamma[2]
-            ]
+            ],
+            // Switching halfway through the transition
+            invert: if alpha < 0.5 { night.invert } else { day.invert },
+            contrast: (1.0-alpha) * night.contrast + alpha * day.contrast,
+            matrix: blend_matrices(&night.matrix, &day.matrix, alpha),
+            filter: blend_matrices(&night.filter, &day.filter, alpha),
+            calibration: day.calibration.clone(),
+        }
+    }
+
+    /// Like `interpolate_color_settings`, with other temperatures at
+    /// day and at night
+    pub fn interpolate_with_temperatures(&self, elevation: f64, temperatures: (i32, i32)) -> ColorSetting {
+        let alpha = self.day_alpha(elevation);
+        ColorSetting {
+            temp: self.interpolation.mix(temperatures.1, temperatures.0, alpha),
+            ..self.interpolate_color_settings(elevation)
         }
     }
 
+    /// How far into the day the elevation is, from 0.0 at night to 1.0
+    /// at day
+    fn day_alpha(&self, elevation: f64) -> f64 {
+        let al = (self.low - elevation) / (self.low - self.high);
+        al.min(1.0).max(0.0) // clamp to [0.0, 1.0]
+    }
+
     pub fn get_period(&self, elevation: f64) -> Period {
         if elevation < self.low {
             Period::Night
@@ -107,12 +347,48 @@ impl TransitionScheme {
         }
     }
 
+    /// Seconds from `now` until the period at `location` changes,
+    /// looking at most `limit` seconds ahead
+    ///
+    /// During a transition the color setting changes all the time, so
+    /// this is 0.0 there.
+    pub fn next_period_change(&self, location: &Location, now: f64, limit: f64) -> f64 {
+        self.next_period_change_by(|t| solar::elevation(t, location), now, limit)
+    }
+
+    /// `next_period_change` with the solar elevations of `elevation`,
+    /// e.g. those of a `solar::Table`
+    pub fn next_period_change_by<F: Fn(f64) -> f64>(&self, elevation: F, now: f64, limit: f64) -> f64 {
+        let period_at = |t: f64| self.get_period(elevation(t));
+        let current = period_at(now);
+        if let Period::Transition(_) = current {
+            return 0.0;
+        }
+        let unchanged = |t: f64| mem::discriminant(&period_at(now + t)) == mem::discriminant(&current);
+
+        // Step ahead a minute at a time, then narrow down to a second
+        let mut lo = 0.0;
+        let mut hi = 60.0;
+        while unchanged(hi) {
+            if hi >= limit {
+                return limit;
+            }
+            lo = hi;
+            hi += 60.0;
+        }
+        while hi - lo > 1.0 {
+            let mid = (lo + hi) / 2.0;
+            if unchanged(mid) { lo = mid } else { hi = mid }
+        }
+        lo
+    }
+
     pub fn short_transition(&self) -> bool {
         self.short_trans_delta != 0
     }
 
     pub fn adjust_transition_alpha(&mut self) {
-        self.adjustment_alpha += self.short_trans_delta as f64 * 0.1 / self.short_trans_len as f64;
+        self.adjustment_alpha += self.short_trans_delta as f64 * self.fade_interval / self.short_trans_len as f64;
 
         /* Stop transition when done */
         if self.adjustment_alpha <= 0.0 || self.adjustment_alpha >= 1.0 {
@@ -123,3 +399,71 @@ impl TransitionScheme {
         self.adjustment_alpha = self.adjustment_alpha.max(0.0).min(1.0);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn curves() {
+        let curve = Curve::parse("0:0.6, 50:0.8,300:1.0").unwrap();
+        assert_eq!(curve.at(-5.0), 0.6);
+        assert_eq!(curve.at(25.0), 0.7);
+        assert_eq!(curve.at(175.0), 0.9);
+        assert_eq!(curve.at(1000.0), 1.0);
+        assert!(Curve::parse("50:0.8,0:0.6").is_err());
+        assert!(Curve::parse("0:0.6,dark").is_err());
+
+        let mut scheme = TransitionScheme::new();
+        scheme.brightness_curve = Some(Curve::parse("-18:0.5,3:1.0").unwrap());
+        assert_eq!(scheme.interpolate_color_settings(-30.0).brightness, 0.5);
+        assert_eq!(scheme.interpolate_color_settings(-7.5).brightness, 0.75);
+    }
+
+    #[test]
+    fn next_period_change() {
+        let scheme = TransitionScheme::new();
+        let location = Location::new(55.7, 12.6);
+        // 2017-01-01 00:00 UTC, the middle of the night in Copenhagen
+        let midnight = 1483228800.0;
+
+        let wait = scheme.next_period_change(&location, midnight, 86400.0);
+        assert!(wait > 3600.0 && wait < 86400.0);
+        assert_eq!(scheme.get_period(solar::elevation(midnight + wait, &location)), Period::Night);
+        match scheme.get_period(solar::elevation(midnight + wait + 1.0, &location)) {
+            Period::Transition(_) => {}
+            p => panic!("expected a transition, got {:?}", p),
+        }
+
+        assert_eq!(scheme.next_period_change(&location, midnight, 600.0), 600.0);
+    }
+
+    #[test]
+    fn blend() {
+        let from = ColorSetting { temp: 3500, brightness: 0.8, ..ColorSetting::neutral() };
+        let to = ColorSetting::neutral();
+        let halfway = from.blend(&to, 0.5, Interpolation::Kelvin);
+        assert_eq!(halfway.temp, 5000);
+        assert!((halfway.brightness - 0.9).abs() < 1e-9);
+        assert_eq!(from.blend(&to, 1.0, Interpolation::Kelvin), to);
+
+        // Halfway between 286 and 154 mireds, rather than 5000K
+        assert_eq!(from.blend(&to, 0.5, Interpolation::Mired).temp, 4550);
+        assert_eq!(from.blend(&to, 1.0, Interpolation::Mired).temp, 6500);
+    }
+
+    #[test]
+    fn fade_duration() {
+        // Two seconds of 50ms steps
+        let mut scheme = TransitionScheme::new();
+        scheme.short_trans_len = 2;
+        scheme.fade_interval = 0.05;
+        let mut steps = 0;
+        while scheme.short_transition() {
+            scheme.adjust_transition_alpha();
+            steps += 1;
+        }
+        assert_eq!(scheme.adjustment_alpha, 0.0);
+        assert!((40..=41).contains(&steps), "{}", steps);
+    }
+}
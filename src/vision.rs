@@ -0,0 +1,164 @@
+//! Filters for color vision deficiencies
+//!
+//! People with dichromacy lack one kind of cone: protanopes the one
+//! for long wavelengths (red), deuteranopes the one for medium (green)
+//! and tritanopes the one for short (blue). Each sees colors that
+//! differ only along the axis of the missing cone as the same.
+//!
+//! A filter either simulates that, for checking what others see, or
+//! corrects for it by daltonization: the difference between the colors
+//! and their simulation, which is lost on the viewer, is moved to the
+//! channels they do see. Both are 3x3 matrices over the channels,
+//! applied as `ColorSetting::filter`, which only methods with a color
+//! transformation matrix can do; see `colorramp`.
+//!
+//! The simulations are those of Machado, Oliveira and Fernandes (2009)
+//! at full severity. The correction follows Fidaner, Lin and Ozguven.
+
+use std::str::FromStr;
+
+use transition;
+use super::{IDENTITY_MATRIX, Result, RedshiftError};
+
+/// The kind of cone that is missing
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Deficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+/// A filter for a deficiency, as `color-vision` in the config file
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Filter {
+    /// Show the colors as seen with the deficiency
+    Simulate(Deficiency),
+    /// Move what is lost with the deficiency to the other channels
+    Correct(Deficiency),
+}
+
+impl Deficiency {
+    /// The matrix turning colors into what is seen with the deficiency
+    pub fn simulation(self) -> [[f64; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [[ 0.152286,  1.052583, -0.204868],
+                                       [ 0.114503,  0.786281,  0.099216],
+                                       [-0.003882, -0.048116,  1.051998]],
+            Deficiency::Deuteranopia => [[ 0.367322,  0.860646, -0.227968],
+                                         [ 0.280085,  0.672501,  0.047413],
+                                         [-0.011820,  0.042940,  0.968881]],
+            Deficiency::Tritanopia => [[ 1.255528, -0.076749, -0.178779],
+                                       [-0.078411,  0.930809,  0.147602],
+                                       [ 0.004733,  0.691367,  0.303900]],
+        }
+    }
+
+    /// The matrix adding the error of the channel that is not seen to
+    /// the two others
+    fn shift(self) -> [[f64; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [[0.0, 0.0, 0.0],
+                                       [0.7, 1.0, 0.0],
+                                       [0.7, 0.0, 1.0]],
+            Deficiency::Deuteranopia => [[1.0, 0.7, 0.0],
+                                         [0.0, 0.0, 0.0],
+                                         [0.0, 0.7, 1.0]],
+            Deficiency::Tritanopia => [[1.0, 0.0, 0.7],
+                                       [0.0, 1.0, 0.7],
+                                       [0.0, 0.0, 0.0]],
+        }
+    }
+}
+
+impl Filter {
+    /// The matrix of the filter, for `ColorSetting::filter`
+    pub fn matrix(self) -> [[f64; 3]; 3] {
+        match self {
+            Filter::Simulate(d) => d.simulation(),
+            Filter::Correct(d) => {
+                // The colors plus the shifted error, which is the
+                // colors minus their simulation
+                let simulation = d.simulation();
+                let mut error = [[0.0; 3]; 3];
+                for i in 0..3 {
+                    for j in 0..3 {
+                        error[i][j] = IDENTITY_MATRIX[i][j] - simulation[i][j];
+                    }
+                }
+                let shifted = transition::multiply_matrices(&d.shift(), &error);
+                let mut m = IDENTITY_MATRIX;
+                for i in 0..3 {
+                    for j in 0..3 {
+                        m[i][j] += shifted[i][j];
+                    }
+                }
+                m
+            }
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = RedshiftError;
+
+    /// A deficiency to correct, or one to simulate after `simulate-`
+    fn from_str(s: &str) -> Result<Filter> {
+        let (simulate, name) = match s.strip_prefix("simulate-") {
+            Some(name) => (true, name),
+            None => (false, s),
+        };
+        let deficiency = match name {
+            "protanopia" => Deficiency::Protanopia,
+            "deuteranopia" => Deficiency::Deuteranopia,
+            "tritanopia" => Deficiency::Tritanopia,
+            _ => return Err(RedshiftError::MalformedConfig(
+                format!("color-vision: {} (expected protanopia, deuteranopia or tritanopia, \
+                         optionally after simulate-)", s))),
+        };
+        Ok(if simulate { Filter::Simulate(deficiency) } else { Filter::Correct(deficiency) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALL: [Deficiency; 3] = [Deficiency::Protanopia, Deficiency::Deuteranopia, Deficiency::Tritanopia];
+
+    fn apply(m: &[[f64; 3]; 3], rgb: [f64; 3]) -> [f64; 3] {
+        let mut out = [0.0; 3];
+        for (v, row) in out.iter_mut().zip(m.iter()) {
+            *v = (0..3).map(|j| row[j] * rgb[j]).sum();
+        }
+        out
+    }
+
+    #[test]
+    fn gray_stays_gray() {
+        // White, and every gray, look the same to everyone, so neither
+        // simulating nor correcting changes them
+        for &d in ALL.iter() {
+            for m in [Filter::Simulate(d).matrix(), Filter::Correct(d).matrix()].iter() {
+                for (c, v) in apply(m, [0.5; 3]).iter().enumerate() {
+                    assert!((v - 0.5).abs() < 1e-3, "{:?} channel {}: {}", d, c, v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn correction_moves_the_lost_channel() {
+        // Pure red is mostly lost on protanopes; corrected, it gains
+        // green and blue to tell it apart
+        let red = apply(&Filter::Correct(Deficiency::Protanopia).matrix(), [1.0, 0.0, 0.0]);
+        assert!(red[1] > 0.0 && red[2] > 0.0);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("deuteranopia".parse::<Filter>().unwrap(), Filter::Correct(Deficiency::Deuteranopia));
+        assert_eq!("simulate-tritanopia".parse::<Filter>().unwrap(), Filter::Simulate(Deficiency::Tritanopia));
+        assert!("simulate-".parse::<Filter>().is_err());
+        assert!("achromatopsia".parse::<Filter>().is_err());
+    }
+}
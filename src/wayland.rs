@@ -0,0 +1,197 @@
+//! A minimal Wayland client
+//!
+//! The protocols used here take a handful of requests and events, few
+//! enough to speak the Wayland wire format directly rather than depend
+//! on a client library. Only numbers and strings are supported as
+//! arguments, no file descriptors or arrays.
+
+use std::cell::Cell;
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Ids of the objects that exist from the start
+pub const DISPLAY_ID: u32 = 1;
+pub const REGISTRY_ID: u32 = 2;
+
+// Opcodes of the core requests and events used
+const DISPLAY_SYNC: u16 = 0;
+const DISPLAY_GET_REGISTRY: u16 = 1;
+const DISPLAY_ERROR: u16 = 0;
+const REGISTRY_BIND: u16 = 0;
+pub const REGISTRY_GLOBAL: u16 = 0;
+pub const REGISTRY_GLOBAL_REMOVE: u16 = 1;
+
+/// How long to wait for the compositor to answer
+const TIMEOUT_MS: u64 = 5000;
+
+/// An argument of a request
+pub enum Arg<'a> {
+    Uint(u32),
+    Fixed(f64),
+    Str(&'a str),
+}
+
+/// A request in the wire format: the object, the size and opcode, and
+/// the arguments, each padded to 32 bits
+fn message(object: u32, opcode: u16, args: &[Arg]) -> Vec<u8> {
+    let mut body = vec![];
+    for arg in args {
+        match *arg {
+            Arg::Uint(v) => body.extend_from_slice(&v.to_ne_bytes()),
+            Arg::Fixed(v) => body.extend_from_slice(&((v * 256.0).round() as i32).to_ne_bytes()),
+            Arg::Str(s) => {
+                body.extend_from_slice(&(s.len() as u32 + 1).to_ne_bytes());
+                body.extend_from_slice(s.as_bytes());
+                body.push(0);
+                while body.len() % 4 != 0 {
+                    body.push(0);
+                }
+            }
+        }
+    }
+    let mut msg = Vec::with_capacity(8 + body.len());
+    msg.extend_from_slice(&object.to_ne_bytes());
+    msg.extend_from_slice(&(((8 + body.len() as u32) << 16) | opcode as u32).to_ne_bytes());
+    msg.extend(body);
+    msg
+}
+
+/// An event from the compositor, with its arguments read in order
+pub struct Event {
+    pub object: u32,
+    pub opcode: u16,
+    body: Vec<u8>,
+    /// Read position in the body
+    pos: usize,
+}
+
+impl Event {
+    pub fn uint(&mut self) -> io::Result<u32> {
+        let bytes = self.body.get(self.pos..self.pos + 4)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short event"))?;
+        self.pos += 4;
+        Ok(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        let len = self.uint()? as usize;
+        let bytes = self.body.get(self.pos..self.pos + len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "short event"))?;
+        self.pos += (len + 3) & !3;
+        Ok(String::from_utf8_lossy(bytes).trim_end_matches('\0').to_owned())
+    }
+}
+
+pub struct Connection {
+    stream: UnixStream,
+    next_id: Cell<u32>,
+}
+
+impl Connection {
+    /// Connect to the named Wayland socket, `$WAYLAND_DISPLAY` by
+    /// default, and ask for the registry
+    ///
+    /// Reads time out after a few seconds, with an error of kind
+    /// `WouldBlock` or `TimedOut`.
+    pub fn connect(name: Option<&str>) -> io::Result<Connection> {
+        let name = name.map(ToOwned::to_owned)
+            .or_else(|| env::var("WAYLAND_DISPLAY").ok())
+            .unwrap_or_else(|| "wayland-0".to_owned());
+        let path = if name.starts_with('/') {
+            PathBuf::from(&name)
+        } else {
+            let dir = env::var_os("XDG_RUNTIME_DIR")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))?;
+            PathBuf::from(dir).join(&name)
+        };
+        let stream = UnixStream::connect(&path)?;
+        stream.set_read_timeout(Some(Duration::from_millis(TIMEOUT_MS)))?;
+        info!("Connected to Wayland display {}", name);
+        let conn = Connection { stream: stream, next_id: Cell::new(REGISTRY_ID + 1) };
+        conn.send(DISPLAY_ID, DISPLAY_GET_REGISTRY, &[Arg::Uint(REGISTRY_ID)])?;
+        Ok(conn)
+    }
+
+    /// Change how long reads wait for the compositor, `None` waiting
+    /// for as long as it takes
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// An id for an object created by a request
+    pub fn new_id(&self) -> u32 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    pub fn send(&self, object: u32, opcode: u16, args: &[Arg]) -> io::Result<()> {
+        (&self.stream).write_all(&message(object, opcode, args))
+    }
+
+    pub fn read_event(&self) -> io::Result<Event> {
+        let mut header = [0; 8];
+        (&self.stream).read_exact(&mut header)?;
+        let object = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+        let word = u32::from_ne_bytes([header[4], header[5], header[6], header[7]]);
+        let size = (word >> 16) as usize;
+        if size < 8 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed event"));
+        }
+        let mut body = vec![0; size - 8];
+        (&self.stream).read_exact(&mut body)?;
+        Ok(Event { object: object, opcode: (word & 0xffff) as u16, body: body, pos: 0 })
+    }
+
+    /// Wait until the compositor handled the requests sent so far,
+    /// returning the events it sent meanwhile
+    pub fn roundtrip(&self) -> io::Result<Vec<Event>> {
+        let callback = self.new_id();
+        self.send(DISPLAY_ID, DISPLAY_SYNC, &[Arg::Uint(callback)])?;
+        let mut events = vec![];
+        loop {
+            let mut event = self.read_event()?;
+            if event.object == callback {
+                return Ok(events);
+            }
+            if event.object == DISPLAY_ID && event.opcode == DISPLAY_ERROR {
+                let (object, code, msg) = (event.uint()?, event.uint()?, event.string()?);
+                return Err(io::Error::other(format!("protocol error {} on object {}: {}", code, object, msg)));
+            }
+            events.push(event);
+        }
+    }
+
+    /// Bind a global to a new object
+    pub fn bind(&self, global: u32, interface: &str, version: u32) -> io::Result<u32> {
+        let id = self.new_id();
+        self.send(REGISTRY_ID, REGISTRY_BIND,
+                  &[Arg::Uint(global), Arg::Str(interface), Arg::Uint(version), Arg::Uint(id)])?;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wire_format() {
+        let msg = message(2, REGISTRY_BIND, &[Arg::Uint(7), Arg::Str("wl_output"), Arg::Fixed(0.5)]);
+        assert_eq!(msg.len(), 8 + 4 + 4 + 12 + 4);
+        assert_eq!(&msg[0..4], &2u32.to_ne_bytes());
+        assert_eq!(&msg[4..8], &((32u32 << 16) | REGISTRY_BIND as u32).to_ne_bytes());
+        assert_eq!(&msg[12..16], &10u32.to_ne_bytes());
+        assert_eq!(&msg[16..26], b"wl_output\0");
+        assert_eq!(&msg[28..32], &128i32.to_ne_bytes());
+
+        let mut event = Event { object: 2, opcode: 0, body: msg[8..].to_vec(), pos: 0 };
+        assert_eq!(event.uint().unwrap(), 7);
+        assert_eq!(event.string().unwrap(), "wl_output");
+        assert_eq!(event.uint().unwrap(), 128);
+        assert!(event.uint().is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Profiles by weekday
+//!
+//! A `[weekdays]` section switches to a profile on some days of the
+//! week, such as one that stays warm for longer on weekend nights:
+//!
+//! ```text
+//! [weekdays]
+//! fri,sat=weekend      ; a [profile:weekend] section
+//! mon-thu=workday
+//!
+//! [profile:weekend]
+//! elevation-low=-12    ; night begins later in the evening
+//! temp-night=3000
+//! ```
+//!
+//! Days are given by their English names or the first three letters
+//! of them, alone, in lists or in ranges within a week starting on
+//! Monday. The profile of the day is applied on top of the one
+//! selected otherwise, like the one of the `[battery]` section. Days
+//! go from midnight to midnight in local time, and the daemon looks at
+//! the day with every update, which is at least every half hour.
+
+use time;
+
+use config::{Config, Section};
+use super::{Result, RedshiftError};
+
+/// Names of the days, by the number of days since Sunday as in
+/// `time::Tm::tm_wday`
+const DAYS: [&'static str; 7] = ["sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"];
+
+/// The profiles of the `[weekdays]` section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// By the number of days since Sunday
+    profiles: [Option<String>; 7],
+}
+
+/// Parse the name of a day, e.g. "fri" or "Friday", into the number of
+/// days since Sunday
+fn parse_day(name: &str) -> Option<usize> {
+    let name = name.trim().to_lowercase();
+    if name.len() < 3 {
+        return None;
+    }
+    DAYS.iter().position(|d| d.starts_with(&name[..]))
+}
+
+/// Parse a list of days and ranges of days, e.g. "mon-thu,sat"
+fn parse_days(input: &str) -> Option<Vec<usize>> {
+    let mut days = vec![];
+    for part in input.split(',') {
+        let mut ends = part.splitn(2, '-');
+        let first = parse_day(ends.next()?)?;
+        let last = match ends.next() {
+            Some(last) => parse_day(last)?,
+            None => first,
+        };
+        // Counting from Monday, so that "fri-sun" is three days
+        let (from, to) = ((first + 6) % 7, (last + 6) % 7);
+        if to < from {
+            return None;
+        }
+        days.extend((from..to + 1).map(|d| (d + 1) % 7));
+    }
+    Some(days)
+}
+
+impl Options {
+    pub fn from_config(section: &Section, config: &Config) -> Result<Options> {
+        let mut profiles: [Option<String>; 7] = Default::default();
+        for e in &section.entries {
+            let days = parse_days(&e.0).ok_or_else(|| RedshiftError::MalformedConfig(
+                format!("[{}] {}: not a day of the week or a range of them", section.name, e.0)))?;
+            config.profile(&e.1)?;
+            for day in days {
+                profiles[day] = Some(e.1.clone());
+            }
+        }
+        Ok(Options { profiles: profiles })
+    }
+
+    /// The profile of the day at a time given in seconds since the
+    /// epoch
+    pub fn profile(&self, t: f64) -> Option<&str> {
+        let tm = time::at(time::Timespec::new(t as i64, 0));
+        self.profiles[tm.tm_wday as usize].as_ref().map(|p| &p[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn days() {
+        assert_eq!(parse_day("Fri"), Some(5));
+        assert_eq!(parse_day("sunday"), Some(0));
+        assert_eq!(parse_day("fr"), None);
+        assert_eq!(parse_days("mon-thu,sat"), Some(vec![1, 2, 3, 4, 6]));
+        assert_eq!(parse_days("fri-sun"), Some(vec![5, 6, 0]));
+        assert_eq!(parse_days("sun-mon"), None);
+        assert_eq!(parse_days("weekend"), None);
+    }
+
+    #[test]
+    fn profiles() {
+        let config: Config = "[weekdays]\nfri,sat=weekend\n[profile:weekend]\ntemp-night=3000\n"
+            .parse().unwrap();
+        let options = Options::from_config(config.section("weekdays").unwrap(), &config).unwrap();
+        assert_eq!(options.profiles[5], Some("weekend".to_owned()));
+        assert_eq!(options.profiles[1], None);
+
+        let config: Config = "[weekdays]\nfri=missing\n".parse().unwrap();
+        assert!(Options::from_config(config.section("weekdays").unwrap(), &config).is_err());
+    }
+}